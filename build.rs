@@ -0,0 +1,8 @@
+fn main() {
+	let protoc =
+		protoc_bin_vendored::protoc_bin_path().expect("protoc is bundled for this platform");
+	std::env::set_var("PROTOC", protoc);
+	tonic_build::configure()
+		.compile(&["proto/musubi/admin/v1/admin.proto"], &["proto"])
+		.expect("Could not compile the admin API");
+}
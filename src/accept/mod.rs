@@ -0,0 +1,98 @@
+use std::{
+	io,
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration,
+};
+
+use tracing::*;
+
+const FIRST_BACKOFF_MS: u64 = 5;
+const MAX_BACKOFF_MS: u64 = 1000;
+/// Limit on open files below which a busy server may run out of them.
+const LOW_NOFILE: libc::rlim_t = 4096;
+/// What to raise the limit on open files to when the hard limit is unlimited, which the kernel
+/// doesn't allow for it.
+const MAX_NOFILE: libc::rlim_t = 1 << 20;
+
+/// Connections that couldn't be accepted since the last report.
+static FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// How an accept loop copes with errors. Running out of file descriptors or memory fails every
+/// accept at once until connections close, so it waits, longer each time in a row, instead of
+/// spinning. Other errors are about the one connection, and it goes right on.
+#[derive(Debug, Default)]
+pub struct Backoff {
+	/// How long it waited after the latest failure, while failing.
+	failing: Option<Duration>,
+}
+
+impl Backoff {
+	/// An accept failed with `e`. Returns how long to wait before the next.
+	pub fn failed(&mut self, e: &io::Error) -> Option<Duration> {
+		FAILURES.fetch_add(1, Ordering::Relaxed);
+		if !is_exhaustion(e) {
+			debug!("Could not accept a connection: {}", e);
+			return None;
+		}
+		let wait = match self.failing {
+			Some(wait) => (wait * 2).min(Duration::from_millis(MAX_BACKOFF_MS)),
+			None => {
+				warn!("Could not accept connections, backing off: {}", e);
+				Duration::from_millis(FIRST_BACKOFF_MS)
+			}
+		};
+		self.failing = Some(wait);
+		Some(wait)
+	}
+
+	/// A connection was accepted.
+	pub fn accepted(&mut self) {
+		if self.failing.take().is_some() {
+			info!("Accepting connections again");
+		}
+	}
+}
+
+/// Whether the process or system ran out of something accepting takes.
+fn is_exhaustion(e: &io::Error) -> bool {
+	matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM))
+}
+
+/// Connections that couldn't be accepted since this was last called.
+pub fn take_failures() -> u64 {
+	FAILURES.swap(0, Ordering::Relaxed)
+}
+
+/// Check the limit on open files, which bounds the connections handled at once, and warn if
+/// it's low. With `raise`, raise it to the hard limit first.
+pub fn check_nofile(raise: bool) {
+	let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+	// SAFETY: getrlimit only writes to the struct it's given
+	if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+		warn!("Could not read the limit on open files: {}", io::Error::last_os_error());
+		return;
+	}
+	if raise && limit.rlim_cur < limit.rlim_max {
+		let max = match limit.rlim_max {
+			libc::RLIM_INFINITY => MAX_NOFILE,
+			max => max,
+		};
+		let raised = libc::rlimit { rlim_cur: max, rlim_max: limit.rlim_max };
+		// SAFETY: setrlimit only reads the struct it's given
+		if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raised) } == 0 {
+			info!("Raised the limit on open files from {} to {}", limit.rlim_cur, max);
+			limit = raised;
+		} else {
+			warn!("Could not raise the limit on open files: {}", io::Error::last_os_error());
+		}
+	}
+	if limit.rlim_cur < LOW_NOFILE {
+		warn!(
+			"Only {} files can be open at once, so connections past about that many fail. \
+			Raise it with ulimit -n or LimitNOFILE=, or pass --raise-nofile",
+			limit.rlim_cur
+		);
+	} else {
+		debug!("Up to {} files can be open at once", limit.rlim_cur);
+	}
+}
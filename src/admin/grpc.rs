@@ -0,0 +1,373 @@
+// the service has to answer with tonic's Status, however large
+#![allow(clippy::result_large_err)]
+
+use std::{
+	io,
+	pin::Pin,
+	task::{Context, Poll},
+	time::UNIX_EPOCH,
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{
+	io::{AsyncRead, AsyncWrite, ReadBuf},
+	net::{TcpListener, TcpStream},
+	sync::mpsc,
+	time::timeout,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{
+	transport::{
+		server::{Connected, TcpConnectInfo},
+		Server,
+	},
+	Request, Response, Status,
+};
+use tracing::*;
+
+use super::Admin;
+use crate::{
+	filter::{
+		rules::{Action, RuleConfig, RuleError},
+		Thresholds, ThresholdsPatch,
+	},
+	tls,
+};
+use v1::admin_server::{self, AdminServer};
+
+/// Messages and service of `proto/musubi/admin/v1/admin.proto`, for clients to build on.
+#[allow(clippy::all, clippy::unwrap_used)]
+pub mod v1 {
+	tonic::include_proto!("musubi.admin.v1");
+}
+
+/// Connections past their TLS handshake, waiting for the server to take them.
+const HANDSHAKEN_BACKLOG: usize = 64;
+
+impl Admin {
+	/// Serve the gRPC admin API on `listener` over TLS, to clients sending `token` in an
+	/// `authorization: Bearer <token>` metadata entry.
+	pub fn serve_grpc(
+		self, listener: std::net::TcpListener, tls: TlsAcceptor, token: String,
+	) -> io::Result<()> {
+		let listener = TcpListener::from_std(listener)?;
+		info!("gRPC admin API listening on {}", listener.local_addr()?);
+
+		let (handshaken, incoming) = mpsc::channel(HANDSHAKEN_BACKLOG);
+		tokio::spawn(async move {
+			loop {
+				match listener.accept().await {
+					Ok((stream, peer)) => {
+						let (tls, handshaken) = (tls.clone(), handshaken.clone());
+						tokio::spawn(async move {
+							match timeout(tls::HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+								Ok(Ok(stream)) => {
+									handshaken
+										.send(Ok::<_, io::Error>(Connection(stream)))
+										.await
+										.ok();
+								}
+								Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", peer, e),
+								Err(_) => debug!("Timed out on TLS handshake with {}", peer),
+							}
+						});
+					}
+					Err(e) => warn!("Could not accept gRPC connection: {}", e),
+				}
+			}
+		});
+
+		let token = Sha256::digest(format!("Bearer {}", token));
+		let service =
+			AdminServer::with_interceptor(Service(self), move |request| authorize(request, &token));
+		tokio::spawn(async move {
+			let server = Server::builder().add_service(service);
+			if let Err(e) = server.serve_with_incoming(ReceiverStream::new(incoming)).await {
+				error!("gRPC admin API stopped: {}", e);
+			}
+		});
+		Ok(())
+	}
+}
+
+/// Let calls carrying the token through.
+fn authorize(request: Request<()>, token: &[u8]) -> Result<Request<()>, Status> {
+	let given = request.metadata().get("authorization").map(|value| value.as_bytes());
+	// compared as hashes, so the time taken tells nothing about the token
+	match *Sha256::digest(given.unwrap_or_default()) == *token {
+		true => Ok(request),
+		false => Err(Status::unauthenticated("missing or wrong bearer token")),
+	}
+}
+
+/// The admin operations, as the gRPC service.
+struct Service(Admin);
+
+#[tonic::async_trait]
+impl admin_server::Admin for Service {
+	async fn status(
+		&self, _: Request<v1::StatusRequest>,
+	) -> Result<Response<v1::Overview>, Status> {
+		let overview = self.0.overview();
+		Ok(Response::new(v1::Overview {
+			version: overview.version,
+			uptime_secs: overview.uptime,
+			rules: overview.rules as u64,
+			blocklist: overview.blocklist as u64,
+			allowlist: overview.allowlist as u64,
+			tarpit: overview.tarpit as u64,
+			attachments: overview.attachments as u64,
+			quarantined: overview.quarantined as u64,
+			rejected: overview.rejected,
+		}))
+	}
+
+	async fn reload(
+		&self, _: Request<v1::ReloadRequest>,
+	) -> Result<Response<v1::ReloadResponse>, Status> {
+		let rules = self.0.reload().map_err(Status::failed_precondition)?;
+		Ok(Response::new(v1::ReloadResponse { rules: rules as u64 }))
+	}
+
+	async fn list_rules(
+		&self, _: Request<v1::ListRulesRequest>,
+	) -> Result<Response<v1::Rules>, Status> {
+		Ok(Response::new(self.rules()))
+	}
+
+	async fn add_rule(
+		&self, request: Request<v1::AddRuleRequest>,
+	) -> Result<Response<v1::Rules>, Status> {
+		let request = request.into_inner();
+		let position = request.position.map(|position| size(position, "position")).transpose()?;
+		self.0.add_rule(rule_config(request.rule)?, position).map_err(rule_error)?;
+		Ok(Response::new(self.rules()))
+	}
+
+	async fn update_rule(
+		&self, request: Request<v1::UpdateRuleRequest>,
+	) -> Result<Response<v1::Rules>, Status> {
+		let request = request.into_inner();
+		self.0.update_rule(&request.name, rule_config(request.rule)?).map_err(rule_error)?;
+		Ok(Response::new(self.rules()))
+	}
+
+	async fn remove_rule(
+		&self, request: Request<v1::RemoveRuleRequest>,
+	) -> Result<Response<v1::Rules>, Status> {
+		self.0.remove_rule(&request.into_inner().name).map_err(rule_error)?;
+		Ok(Response::new(self.rules()))
+	}
+
+	async fn get_thresholds(
+		&self, _: Request<v1::GetThresholdsRequest>,
+	) -> Result<Response<v1::Thresholds>, Status> {
+		Ok(Response::new(thresholds(self.0.filter.thresholds())))
+	}
+
+	async fn set_thresholds(
+		&self, request: Request<v1::Thresholds>,
+	) -> Result<Response<v1::Thresholds>, Status> {
+		let request = request.into_inner();
+		let patch = ThresholdsPatch {
+			max_audience: request.max_audience.map(|n| size(n, "max_audience")).transpose()?,
+			reply_flood_max: request
+				.reply_flood_max
+				.map(|n| size(n, "reply_flood_max"))
+				.transpose()?,
+			max_hashtags: request.max_hashtags.map(|n| size(n, "max_hashtags")).transpose()?,
+			max_notes_per_day: request.max_notes_per_day,
+			spam_score_threshold: request.spam_score_threshold,
+		};
+		self.0.set_thresholds(&patch).map_err(rule_error)?;
+		Ok(Response::new(thresholds(self.0.filter.thresholds())))
+	}
+
+	async fn list_entries(
+		&self, request: Request<v1::ListEntriesRequest>,
+	) -> Result<Response<v1::Entries>, Status> {
+		let entries = match v1::List::try_from(request.into_inner().list) {
+			Ok(v1::List::Blocklist) => self.0.blocklist.list(),
+			Ok(v1::List::Allowlist) => self.0.allowlist.list(),
+			Ok(v1::List::Tarpit) => self.0.tarpit.list(),
+			Ok(v1::List::Attachments) => self.0.attachments.list(),
+			Ok(v1::List::Unspecified) | Err(_) => return Err(no_list()),
+		};
+		Ok(Response::new(v1::Entries { entries }))
+	}
+
+	async fn add_entries(
+		&self, request: Request<v1::ChangeEntriesRequest>,
+	) -> Result<Response<v1::Changed>, Status> {
+		let v1::ChangeEntriesRequest { list, entries } = request.into_inner();
+		let changed = match v1::List::try_from(list) {
+			Ok(v1::List::Blocklist) => self.0.blocklist.add(&entries).await,
+			Ok(v1::List::Allowlist) => self.0.allowlist.add(&entries).await,
+			Ok(v1::List::Tarpit) => self.0.tarpit.add(&entries).await,
+			Ok(v1::List::Attachments) => self.0.attachments.add(&entries).await,
+			Ok(v1::List::Unspecified) | Err(_) => return Err(no_list()),
+		};
+		let changed = changed.map_err(|e| Status::internal(e.to_string()))?;
+		Ok(Response::new(v1::Changed { changed: changed as u64 }))
+	}
+
+	async fn remove_entries(
+		&self, request: Request<v1::ChangeEntriesRequest>,
+	) -> Result<Response<v1::Changed>, Status> {
+		let v1::ChangeEntriesRequest { list, entries } = request.into_inner();
+		let changed = match v1::List::try_from(list) {
+			Ok(v1::List::Blocklist) => self.0.blocklist.remove(&entries).await,
+			Ok(v1::List::Allowlist) => self.0.allowlist.remove(&entries).await,
+			Ok(v1::List::Tarpit) => self.0.tarpit.remove(&entries).await,
+			Ok(v1::List::Attachments) => self.0.attachments.remove(&entries).await,
+			Ok(v1::List::Unspecified) | Err(_) => return Err(no_list()),
+		};
+		let changed = changed.map_err(|e| Status::internal(e.to_string()))?;
+		Ok(Response::new(v1::Changed { changed: changed as u64 }))
+	}
+
+	async fn list_quarantined(
+		&self, _: Request<v1::ListQuarantinedRequest>,
+	) -> Result<Response<v1::Quarantined>, Status> {
+		let activities = self.0.filter.quarantine().held().into_iter();
+		let activities = activities
+			.map(|held| v1::HeldActivity {
+				id: held.id,
+				received: held
+					.received
+					.duration_since(UNIX_EPOCH)
+					.map(|d| d.as_secs())
+					.unwrap_or_default(),
+				actor: held.actor,
+				rule: held.rule,
+				header: held.header,
+				body: held.body,
+			})
+			.collect();
+		Ok(Response::new(v1::Quarantined { activities }))
+	}
+
+	async fn release(
+		&self, request: Request<v1::ReleaseRequest>,
+	) -> Result<Response<v1::ReleaseResponse>, Status> {
+		let id = request.into_inner().id;
+		if self.0.filter.quarantine().get(id).is_none() {
+			return Err(Status::not_found("no such quarantined activity"));
+		}
+		let response = self.0.release(id).await.map_err(Status::unavailable)?;
+		Ok(Response::new(v1::ReleaseResponse { response }))
+	}
+
+	async fn top_rejected(
+		&self, request: Request<v1::TopRejectedRequest>,
+	) -> Result<Response<v1::Rejections>, Status> {
+		let limit = request.into_inner().limit as usize;
+		let rejections = self.0.filter.rejections().top(limit).into_iter();
+		let rejections = rejections
+			.map(|r| v1::Rejection { origin: r.origin, kind: r.kind, count: r.count })
+			.collect();
+		Ok(Response::new(v1::Rejections { rejections }))
+	}
+}
+
+impl Service {
+	fn rules(&self) -> v1::Rules {
+		let rules = self.0.filter.rule_configs().into_iter();
+		let rules = rules
+			.map(|rule| v1::Rule {
+				name: rule.name,
+				when: rule.when,
+				action: match rule.action {
+					Action::Reject => v1::Action::Reject,
+					Action::Quarantine => v1::Action::Quarantine,
+					Action::Tag => v1::Action::Tag,
+					Action::Throttle => v1::Action::Throttle,
+					Action::Log => v1::Action::Log,
+				} as i32,
+				limit: rule.limit,
+				window_secs: rule.window,
+			})
+			.collect();
+		v1::Rules { rules }
+	}
+}
+
+fn rule_config(rule: Option<v1::Rule>) -> Result<RuleConfig, Status> {
+	let rule = rule.ok_or_else(|| Status::invalid_argument("no rule given"))?;
+	let action = match v1::Action::try_from(rule.action) {
+		Ok(v1::Action::Reject) => Action::Reject,
+		Ok(v1::Action::Quarantine) => Action::Quarantine,
+		Ok(v1::Action::Tag) => Action::Tag,
+		Ok(v1::Action::Throttle) => Action::Throttle,
+		Ok(v1::Action::Log) => Action::Log,
+		Ok(v1::Action::Unspecified) | Err(_) => {
+			return Err(Status::invalid_argument("the rule has no action"))
+		}
+	};
+	Ok(RuleConfig {
+		name: rule.name,
+		when: rule.when,
+		action,
+		limit: rule.limit,
+		window: rule.window_secs,
+	})
+}
+
+fn thresholds(thresholds: Thresholds) -> v1::Thresholds {
+	v1::Thresholds {
+		max_audience: Some(thresholds.max_audience as u64),
+		reply_flood_max: Some(thresholds.reply_flood_max as u64),
+		max_hashtags: Some(thresholds.max_hashtags as u64),
+		max_notes_per_day: Some(thresholds.max_notes_per_day),
+		spam_score_threshold: Some(thresholds.spam_score_threshold),
+	}
+}
+
+fn size(n: u64, field: &str) -> Result<usize, Status> {
+	usize::try_from(n).map_err(|_| Status::invalid_argument(format!("{} is too large", field)))
+}
+
+fn rule_error(e: RuleError) -> Status {
+	match e {
+		RuleError::NotFound(_) => Status::not_found(e.to_string()),
+		_ => Status::invalid_argument(e.to_string()),
+	}
+}
+
+fn no_list() -> Status {
+	Status::invalid_argument("no such list")
+}
+
+/// A connection to the gRPC admin API, past its TLS handshake.
+struct Connection(TlsStream<TcpStream>);
+
+impl Connected for Connection {
+	type ConnectInfo = TcpConnectInfo;
+
+	fn connect_info(&self) -> TcpConnectInfo {
+		self.0.get_ref().0.connect_info()
+	}
+}
+
+impl AsyncRead for Connection {
+	fn poll_read(
+		self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf,
+	) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().0).poll_read(cx, buf)
+	}
+}
+
+impl AsyncWrite for Connection {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+		Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().0).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+	}
+}
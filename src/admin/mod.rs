@@ -0,0 +1,390 @@
+use std::{
+	io,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+	io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+	net::{UnixListener, UnixStream},
+	time::timeout,
+};
+use tracing::*;
+
+use crate::{
+	attachments::AttachmentList,
+	config::Config,
+	domains::DomainList,
+	filter::{
+		headers::Headers,
+		rejections::RejectionCount,
+		rules::{self, canary::Canary, pack::Packs, Action, RuleConfig, RuleError, RuleSet},
+		strict::StrictPin,
+		Filter, Report, Thresholds, ThresholdsPatch,
+	},
+	query::Backend,
+	reputation::Subject,
+	upstream::Routes,
+};
+use state::Snapshot;
+
+pub mod grpc;
+pub mod review;
+pub mod state;
+
+/// Longest request line accepted, in bytes.
+const MAX_REQUEST_LEN: u64 = 16 * 1024 * 1024;
+/// How long the AP server gets to answer a released activity.
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest status line read back from the AP server, in bytes.
+const MAX_STATUS_LEN: u64 = 1024;
+
+/// A request to the running process, sent as one JSON line over the admin socket.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Request {
+	BlocklistAdd { domains: Vec<String> },
+	BlocklistRemove { domains: Vec<String> },
+	BlocklistList,
+	AllowlistAdd { domains: Vec<String> },
+	AllowlistRemove { domains: Vec<String> },
+	AllowlistList,
+	TarpitAdd { domains: Vec<String> },
+	TarpitRemove { domains: Vec<String> },
+	TarpitList,
+	/// Give every activity from these domains `action` for `hours`, instead of judging them.
+	StrictAdd { domains: Vec<String>, action: Action, hours: u64 },
+	StrictRemove { domains: Vec<String> },
+	StrictList,
+	/// Entries are attachment URLs, domains hosting them, or SHA-256 hashes of either.
+	AttachmentsAdd { entries: Vec<String> },
+	AttachmentsRemove { entries: Vec<String> },
+	AttachmentsList,
+	RulesList,
+	/// Insert a rule at `position`, or append it.
+	RulesAdd { rule: RuleConfig, position: Option<usize> },
+	/// Replace the rule called `name`.
+	RulesUpdate { name: String, rule: RuleConfig },
+	RulesRemove { name: String },
+	ThresholdsGet,
+	ThresholdsSet(ThresholdsPatch),
+	/// Replace the whole ruleset and change thresholds in one go, or not at all.
+	Apply { rules: Option<Vec<RuleConfig>>, thresholds: Option<ThresholdsPatch> },
+	ExportState,
+	/// Merge a snapshot from `export-state` into the current state.
+	ImportState { state: Snapshot },
+	InspectActor { actor: String },
+	InspectInstance { host: String },
+	Status,
+	/// Read the rules from the config file again.
+	Reload,
+	/// Forward a quarantined activity to the AP server after all.
+	Release { id: u64 },
+	TopRejected { limit: usize },
+}
+
+/// Answer to a [`Request`], sent back as one JSON line.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum Response {
+	/// How many entries the request changed.
+	Done { changed: usize },
+	Domains { domains: Vec<String> },
+	Hashes { hashes: Vec<String> },
+	Pins { pins: Vec<StrictPin> },
+	Rules { rules: Vec<RuleConfig> },
+	Thresholds { thresholds: Thresholds },
+	Report { report: Report },
+	State { state: Snapshot },
+	Overview { overview: Overview },
+	/// How many rules are in effect after a reload.
+	Reloaded { rules: usize },
+	/// Status line the AP server answered a released activity with.
+	Released { response: String },
+	Rejections { rejections: Vec<RejectionCount> },
+	Error { message: String },
+}
+
+/// Overview of the running process.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Overview {
+	pub version: String,
+	/// seconds
+	pub uptime: u64,
+	pub rules: usize,
+	pub blocklist: usize,
+	pub allowlist: usize,
+	pub tarpit: usize,
+	pub attachments: usize,
+	pub quarantined: usize,
+	/// Rejections over the last hour or two.
+	pub rejected: u64,
+}
+
+/// What the admin socket can look at and change.
+///
+/// Rule and threshold changes last until restart; edit the config file to keep them.
+#[derive(Clone)]
+pub struct Admin {
+	pub blocklist: DomainList,
+	pub allowlist: DomainList,
+	pub tarpit: DomainList,
+	pub attachments: AttachmentList,
+	pub filter: Filter,
+	/// DB of the default AP server, to look up stats in.
+	pub query: Arc<dyn Backend>,
+	/// Where released activities are forwarded to.
+	pub routes: Routes,
+	/// Config file to reload rules from.
+	pub config: Option<PathBuf>,
+	pub started: Instant,
+}
+
+impl Admin {
+	/// Listen for requests on a Unix socket at `path`, replacing a stale socket file.
+	///
+	/// Anyone who can connect can change the firewall, so keep the socket's directory private.
+	pub async fn serve(self, path: &Path) -> io::Result<()> {
+		match std::fs::remove_file(path) {
+			Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+			_ => {}
+		}
+		let listener = UnixListener::bind(path)?;
+		info!("Admin socket listening on {}", path.display());
+
+		tokio::spawn(async move {
+			loop {
+				match listener.accept().await {
+					Ok((stream, _)) => {
+						let admin = self.clone();
+						tokio::spawn(async move {
+							if let Err(e) = admin.connection(stream).await {
+								debug!("Admin connection failed: {}", e);
+							}
+						});
+					}
+					Err(e) => warn!("Could not accept admin connection: {}", e),
+				}
+			}
+		});
+		Ok(())
+	}
+
+	async fn connection(&self, stream: UnixStream) -> io::Result<()> {
+		let (read, mut write) = stream.into_split();
+		let mut line = String::new();
+		BufReader::new(read).take(MAX_REQUEST_LEN).read_line(&mut line).await?;
+
+		let response = match sonic_rs::from_str::<Request>(&line) {
+			Ok(request) => self.handle(request).await,
+			Err(e) => Response::Error { message: format!("bad request: {}", e) },
+		};
+
+		let mut out = sonic_rs::to_string(&response).map_err(io::Error::other)?;
+		out.push('\n');
+		write.write_all(out.as_bytes()).await
+	}
+
+	async fn handle(&self, request: Request) -> Response {
+		let domains = |list: &DomainList| Response::Domains { domains: list.list() };
+		let done = |changed| Response::Done { changed };
+		let error = |e: &dyn std::error::Error| Response::Error { message: e.to_string() };
+		let retuned = |result: Result<(), RuleError>| match result {
+			Ok(()) => done(1),
+			Err(e) => error(&e),
+		};
+
+		match request {
+			Request::BlocklistAdd { domains } => {
+				self.blocklist.add(&domains).await.map_or_else(|e| error(&e), done)
+			}
+			Request::BlocklistRemove { domains } => {
+				self.blocklist.remove(&domains).await.map_or_else(|e| error(&e), done)
+			}
+			Request::BlocklistList => domains(&self.blocklist),
+			Request::AllowlistAdd { domains } => {
+				self.allowlist.add(&domains).await.map_or_else(|e| error(&e), done)
+			}
+			Request::AllowlistRemove { domains } => {
+				self.allowlist.remove(&domains).await.map_or_else(|e| error(&e), done)
+			}
+			Request::AllowlistList => domains(&self.allowlist),
+			Request::TarpitAdd { domains } => {
+				self.tarpit.add(&domains).await.map_or_else(|e| error(&e), done)
+			}
+			Request::TarpitRemove { domains } => {
+				self.tarpit.remove(&domains).await.map_or_else(|e| error(&e), done)
+			}
+			Request::TarpitList => domains(&self.tarpit),
+			Request::StrictAdd { domains, action, hours } => {
+				let length = Duration::from_secs(hours.saturating_mul(3600));
+				done(self.filter.strict().pin(&domains, action, length))
+			}
+			Request::StrictRemove { domains } => done(self.filter.strict().unpin(&domains)),
+			Request::StrictList => Response::Pins { pins: self.filter.strict().list() },
+			Request::AttachmentsAdd { entries } => {
+				self.attachments.add(&entries).await.map_or_else(|e| error(&e), done)
+			}
+			Request::AttachmentsRemove { entries } => {
+				self.attachments.remove(&entries).await.map_or_else(|e| error(&e), done)
+			}
+			Request::AttachmentsList => Response::Hashes { hashes: self.attachments.list() },
+			Request::RulesList => Response::Rules { rules: self.filter.rule_configs() },
+			Request::RulesAdd { rule, position } => retuned(self.add_rule(rule, position)),
+			Request::RulesUpdate { name, rule } => retuned(self.update_rule(&name, rule)),
+			Request::RulesRemove { name } => retuned(self.remove_rule(&name)),
+			Request::ThresholdsGet => Response::Thresholds { thresholds: self.filter.thresholds() },
+			Request::ThresholdsSet(patch) => retuned(self.set_thresholds(&patch)),
+			Request::Apply { rules: new_rules, thresholds: patch } => {
+				retuned(self.filter.retune(|rules, thresholds| {
+					if let Some(new_rules) = new_rules {
+						*rules = new_rules;
+					}
+					if let Some(patch) = patch {
+						patch.apply(thresholds);
+					}
+					Ok(())
+				}))
+			}
+			Request::ExportState => Response::State { state: self.export_state() },
+			Request::ImportState { state } => match self.import_state(state).await {
+				Ok(changed) => done(changed),
+				Err(message) => Response::Error { message },
+			},
+			Request::InspectActor { actor } => self.report(Subject::Actor, &actor).await,
+			Request::InspectInstance { host } => self.report(Subject::Instance, &host).await,
+			Request::Status => Response::Overview { overview: self.overview() },
+			Request::Reload => match self.reload() {
+				Ok(rules) => Response::Reloaded { rules },
+				Err(message) => Response::Error { message },
+			},
+			Request::Release { id } => match self.release(id).await {
+				Ok(response) => Response::Released { response },
+				Err(message) => Response::Error { message },
+			},
+			Request::TopRejected { limit } => {
+				Response::Rejections { rejections: self.filter.rejections().top(limit) }
+			}
+		}
+	}
+
+	/// Insert `rule` at `position`, or append it.
+	fn add_rule(&self, rule: RuleConfig, position: Option<usize>) -> Result<(), RuleError> {
+		self.filter.retune(|rules, _| {
+			rules.insert(position.unwrap_or(rules.len()).min(rules.len()), rule);
+			Ok(())
+		})
+	}
+
+	/// Replace the rule called `name`.
+	fn update_rule(&self, name: &str, rule: RuleConfig) -> Result<(), RuleError> {
+		self.filter.retune(|rules, _| {
+			let i = rules::position(rules, name)?;
+			rules[i] = rule;
+			Ok(())
+		})
+	}
+
+	fn remove_rule(&self, name: &str) -> Result<(), RuleError> {
+		self.filter.retune(|rules, _| {
+			rules.remove(rules::position(rules, name)?);
+			Ok(())
+		})
+	}
+
+	fn set_thresholds(&self, patch: &ThresholdsPatch) -> Result<(), RuleError> {
+		self.filter.retune(|_, thresholds| {
+			patch.apply(thresholds);
+			Ok(())
+		})
+	}
+
+	fn overview(&self) -> Overview {
+		Overview {
+			version: env!("CARGO_PKG_VERSION").to_string(),
+			uptime: self.started.elapsed().as_secs(),
+			rules: self.filter.rule_configs().len(),
+			blocklist: self.blocklist.list().len(),
+			allowlist: self.allowlist.list().len(),
+			tarpit: self.tarpit.list().len(),
+			attachments: self.attachments.list().len(),
+			quarantined: self.filter.quarantine().held().len(),
+			rejected: self.filter.rejections().total(),
+		}
+	}
+
+	/// Replace the rules with the config file's, or the default ones if it has none, followed
+	/// by those of the rule packs, along with the candidate ruleset, and return how many there
+	/// are now. Other settings take a restart.
+	fn reload(&self) -> Result<usize, String> {
+		let path = self.config.as_ref().ok_or("no config file to reload")?;
+		let config = Config::load_without_secrets(path).map_err(|e| e.to_string())?;
+		let mut new_rules = match config.rules {
+			Some(rules) => rules,
+			None => RuleSet::default_rules().configs().to_vec(),
+		};
+		let packs = match &config.rule_packs {
+			Some(dir) => Packs::load_dir(dir).map_err(|e| e.to_string())?,
+			None => Packs::default(),
+		};
+		let canary = match &config.canary {
+			Some(canary) => Some(Canary::compile(canary, &packs.rules).map_err(|e| e.to_string())?),
+			None => None,
+		};
+		new_rules.extend(packs.rules);
+		let count = new_rules.len();
+		self.filter.reload(new_rules, canary, packs.lists).map_err(|e| e.to_string())?;
+		info!("Reloaded {} rules from {}", count, path.display());
+		Ok(count)
+	}
+
+	/// Forward the activity quarantined under `id` to its AP server, and stop holding it once
+	/// the server answered. Its signature may have expired if it was held for long.
+	async fn release(&self, id: u64) -> Result<String, String> {
+		let held = self.filter.quarantine().get(id).ok_or("no such quarantined activity")?;
+		let host = Headers::parse(&held.header).ok().and_then(|h| h.get("host").ok().flatten());
+		let picked = self.routes.pick(self.routes.route(host));
+		let address = &picked.address;
+
+		let forward = async {
+			let mut stream = address.connect().await?;
+			stream.write_all(&held.header).await?;
+			stream.write_all(&held.body).await?;
+			let mut status = String::new();
+			BufReader::new(stream).take(MAX_STATUS_LEN).read_line(&mut status).await?;
+			io::Result::Ok(status.trim_end().to_string())
+		};
+		let status = match timeout(RELEASE_TIMEOUT, forward).await {
+			Ok(Ok(status)) if !status.is_empty() => status,
+			Ok(Ok(_)) => return Err(format!("{} closed the connection", address)),
+			Ok(Err(e)) => return Err(format!("Could not forward to {}: {}", address, e)),
+			Err(_) => return Err(format!("{} did not answer in time", address)),
+		};
+		self.filter.quarantine().remove(id);
+		info!("Released quarantined activity {} from {}: {}", id, held.actor, status);
+		Ok(status)
+	}
+
+	async fn report(&self, kind: Subject, subject: &str) -> Response {
+		match self.filter.report(kind, subject, self.query.as_ref()).await {
+			Ok(report) => Response::Report { report },
+			Err(e) => Response::Error { message: e.to_string() },
+		}
+	}
+}
+
+/// Send a request to the process listening on the admin socket at `path`.
+pub async fn request(path: &Path, request: &Request) -> io::Result<Response> {
+	let stream = UnixStream::connect(path).await?;
+	let (read, mut write) = stream.into_split();
+
+	let mut out = sonic_rs::to_string(request).map_err(io::Error::other)?;
+	out.push('\n');
+	write.write_all(out.as_bytes()).await?;
+
+	let mut line = String::new();
+	BufReader::new(read).read_line(&mut line).await?;
+	sonic_rs::from_str(&line).map_err(io::Error::other)
+}
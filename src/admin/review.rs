@@ -0,0 +1,411 @@
+use std::{
+	fmt::Write as _,
+	time::{Duration, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use sonic_rs::{JsonContainerTrait, JsonValueTrait, Value};
+use tokio::{
+	io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+	time::timeout,
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::*;
+use url::{form_urlencoded, Url};
+
+use super::Admin;
+use crate::{
+	attachments::attachment_urls,
+	filter::{headers::Headers, text::note_text},
+	http::http_date,
+	quarantine::Held,
+	reputation::Subject,
+	tls::HANDSHAKE_TIMEOUT,
+};
+
+/// Longest request accepted, in bytes. Forms only ever carry a token.
+const MAX_REQUEST_LEN: usize = 16 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Nothing from notes may run, load or submit anywhere.
+const CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; form-action 'self'";
+/// Activities shown at once, newest first, as each takes a lookup of its actor.
+const PAGE_LEN: usize = 50;
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:50em;margin:auto;padding:1em}\
+	section{border-top:1px solid #ccc;padding:.5em 0}.meta{color:#666;font-size:.9em}\
+	blockquote{white-space:pre-wrap;background:#f6f6f6;margin:0;padding:.5em}\
+	form{display:inline}.notice{background:#ffd;padding:.5em}";
+
+/// What reviewers can do with a quarantined activity.
+enum Decision {
+	/// Forward it to the AP server after all.
+	Release,
+	/// Drop it.
+	Reject,
+	/// Drop it and block the actor's domain.
+	Block,
+}
+
+impl Admin {
+	/// Serve the quarantine review page on `listener`, to browsers logging in with `password`
+	/// over HTTP Basic auth. It's served over HTTPS with `tls` if given.
+	pub fn serve_review(
+		self, listener: std::net::TcpListener, password: String, tls: Option<TlsAcceptor>,
+	) -> io::Result<()> {
+		let listener = TcpListener::from_std(listener)?;
+		info!("Quarantine review page listening on {}", listener.local_addr()?);
+
+		let form_token = {
+			#[allow(clippy::unwrap_used)] // HMAC takes keys of any length
+			let mut mac = Hmac::<Sha256>::new_from_slice(password.as_bytes()).unwrap();
+			mac.update(b"quarantine review form");
+			hex::encode(mac.finalize().into_bytes())
+		};
+		let password = Sha256::digest(password.as_bytes());
+		tokio::spawn(async move {
+			loop {
+				match listener.accept().await {
+					Ok((stream, _)) => {
+						let (admin, form_token) = (self.clone(), form_token.clone());
+						let tls = tls.clone();
+						tokio::spawn(async move {
+							let result = match tls {
+								Some(tls) => {
+									admin.review_tls(tls, stream, &password, &form_token).await
+								}
+								None => {
+									admin.review_connection(stream, &password, &form_token).await
+								}
+							};
+							if let Err(e) = result {
+								debug!("Review connection failed: {}", e);
+							}
+						});
+					}
+					Err(e) => warn!("Could not accept review connection: {}", e),
+				}
+			}
+		});
+		Ok(())
+	}
+
+	/// Like [`Self::review_connection`], after the TLS handshake.
+	async fn review_tls(
+		&self, tls: TlsAcceptor, stream: TcpStream, password: &[u8], form_token: &str,
+	) -> io::Result<()> {
+		match timeout(HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+			Ok(stream) => self.review_connection(stream?, password, form_token).await,
+			// too slow to say hello
+			Err(_) => Ok(()),
+		}
+	}
+
+	async fn review_connection(
+		&self, mut stream: impl AsyncRead + AsyncWrite + Unpin, password: &[u8], form_token: &str,
+	) -> io::Result<()> {
+		self.review_request(&mut stream, password, form_token).await?;
+		// sends on what TLS holds back, and closes it properly
+		stream.shutdown().await
+	}
+
+	async fn review_request(
+		&self, stream: &mut (impl AsyncRead + AsyncWrite + Unpin), password: &[u8],
+		form_token: &str,
+	) -> io::Result<()> {
+		let Ok(request) = timeout(REQUEST_TIMEOUT, read_request(stream)).await else {
+			return Ok(());
+		};
+		let Some((header, body)) = request? else {
+			return respond(stream, "400 Bad Request", "text/plain", "Bad request").await;
+		};
+		let Ok(headers) = Headers::parse(&header) else {
+			return respond(stream, "400 Bad Request", "text/plain", "Bad request").await;
+		};
+		if !authorized(&headers, password) {
+			let response = "HTTP/1.1 401 Unauthorized\r\n\
+				WWW-Authenticate: Basic realm=\"spam-musubi\", charset=\"UTF-8\"\r\n\
+				Content-Length: 0\r\nConnection: close\r\n\r\n";
+			return stream.write_all(response.as_bytes()).await;
+		}
+
+		let line = header.split(|&b| b == b'\r').next().unwrap_or_default();
+		let line = String::from_utf8_lossy(line);
+		let mut parts = line.split(' ');
+		let method = parts.next().unwrap_or_default();
+		let target = parts.next().unwrap_or_default();
+		let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+		match (method, path.trim_start_matches('/').split_once('/')) {
+			("GET", None) if path == "/" => {
+				let notice = form_urlencoded::parse(query.as_bytes())
+					.find(|(key, _)| key == "notice")
+					.map(|(_, value)| value.into_owned());
+				let page = self.review_page(notice.as_deref(), form_token).await;
+				respond(stream, "200 OK", "text/html; charset=utf-8", &page).await
+			}
+			("POST", Some((id, decision))) => {
+				let token = form_urlencoded::parse(&body)
+					.find(|(key, _)| key == "token")
+					.map(|(_, value)| value.into_owned());
+				if token.as_deref() != Some(form_token) {
+					return respond(stream, "403 Forbidden", "text/plain", "Bad form").await;
+				}
+				let decision = match decision {
+					"release" => Decision::Release,
+					"reject" => Decision::Reject,
+					"block" => Decision::Block,
+					_ => return respond(stream, "404 Not Found", "text/plain", "").await,
+				};
+				let notice = match id.parse() {
+					Ok(id) => self.decide(id, decision).await,
+					Err(_) => "No such quarantined activity".to_string(),
+				};
+				let location = form_urlencoded::Serializer::for_suffix(String::from("/?"), 2)
+					.append_pair("notice", &notice)
+					.finish();
+				let response = format!(
+					"HTTP/1.1 303 See Other\r\nLocation: {}\r\nContent-Length: 0\r\n\
+					Connection: close\r\n\r\n",
+					location
+				);
+				stream.write_all(response.as_bytes()).await
+			}
+			_ => respond(stream, "404 Not Found", "text/plain", "Not found").await,
+		}
+	}
+
+	/// Act on a reviewer's decision, and describe what happened.
+	async fn decide(&self, id: u64, decision: Decision) -> String {
+		let quarantine = self.filter.quarantine();
+		let Some(held) = quarantine.get(id) else {
+			return format!("#{} is no longer quarantined", id);
+		};
+		match decision {
+			Decision::Release => match self.release(id).await {
+				Ok(response) => format!("Forwarded #{}: {}", id, response),
+				Err(message) => format!("Could not forward #{}: {}", id, message),
+			},
+			Decision::Reject => {
+				quarantine.remove(id);
+				info!("Rejected quarantined activity {} from {} on review", id, held.actor);
+				format!("Rejected #{}", id)
+			}
+			Decision::Block => {
+				let actor = Url::parse(&held.actor).ok();
+				let Some(host) = actor.as_ref().and_then(|u| u.host_str()).map(str::to_string)
+				else {
+					return format!("#{} has no actor domain to block", id);
+				};
+				if let Err(e) = self.blocklist.add(std::slice::from_ref(&host)).await {
+					return format!("Could not block {}: {}", host, e);
+				}
+				quarantine.remove(id);
+				info!("Blocked {} on review of quarantined activity {}", host, id);
+				format!("Rejected #{} and blocked {}", id, host)
+			}
+		}
+	}
+
+	async fn review_page(&self, notice: Option<&str>, form_token: &str) -> String {
+		let held = self.filter.quarantine().held();
+		let mut page = format!(
+			"<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Quarantine review</title>\
+			<style>{}</style></head><body><h1>Quarantine</h1>",
+			STYLE
+		);
+		if let Some(notice) = notice {
+			let _ = write!(page, "<p class=\"notice\">{}</p>", escape(notice));
+		}
+		let _ = match held.len() {
+			0 => write!(page, "<p>Nothing is quarantined.</p>"),
+			n if n > PAGE_LEN => {
+				write!(page, "<p>{} held, showing the newest {}.</p>", n, PAGE_LEN)
+			}
+			n => write!(page, "<p>{} held.</p>", n),
+		};
+		for held in held.iter().rev().take(PAGE_LEN) {
+			self.render(&mut page, held, form_token).await;
+		}
+		page.push_str("</body></html>");
+		page
+	}
+
+	async fn render(&self, page: &mut String, held: &Held, form_token: &str) {
+		let received = held.received.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+		let _ = write!(
+			page,
+			"<section><h2>#{} held by rule \u{201c}{}\u{201d}</h2><p class=\"meta\">{} from {}",
+			held.id,
+			escape(&held.rule),
+			http_date(received),
+			link(&held.actor)
+		);
+		match self.query.get_user(&held.actor).await {
+			Ok(Some(user)) => {
+				let _ = write!(
+					page,
+					"<br>{} followers, {} following, {} notes, known for {} days",
+					user.followers,
+					user.following,
+					user.notes,
+					user.age.as_secs() / 86400
+				);
+			}
+			Ok(None) => page.push_str("<br>unknown to the AP server"),
+			Err(e) => {
+				let _ = write!(page, "<br>could not look the actor up: {}", escape(&e.to_string()));
+			}
+		}
+		let reputation = self.filter.reputation().get(Subject::Actor, &held.actor);
+		let _ = write!(page, ", reputation {:.1}</p>", reputation);
+
+		match sonic_rs::from_slice::<Value>(&held.body) {
+			Ok(ap_json) => {
+				let object = ap_json.get("object");
+				let summary = object.and_then(|o| o.get("summary")).and_then(|s| s.as_str());
+				if let Some(summary) = summary.filter(|s| !s.is_empty()) {
+					let _ = write!(page, "<p><b>CW:</b> {}</p>", escape(summary));
+				}
+				match note_text(&ap_json) {
+					Some(text) => {
+						let _ = write!(page, "<blockquote>{}</blockquote>", escape(&text));
+					}
+					None => {
+						let _ = write!(
+							page,
+							"<p>{} of {} without text</p>",
+							escape(type_of(Some(&ap_json))),
+							escape(type_of(object))
+						);
+					}
+				}
+				let urls = attachment_urls(&ap_json);
+				if !urls.is_empty() {
+					page.push_str("<ul>");
+					for url in urls {
+						let _ = write!(page, "<li>{}</li>", link(url));
+					}
+					page.push_str("</ul>");
+				}
+				let tags = object.and_then(|o| o.get("tag")).and_then(|t| t.as_array());
+				let hashtags: Vec<_> = tags
+					.into_iter()
+					.flat_map(|tags| tags.iter())
+					.filter(|tag| tag.get("type").and_then(|t| t.as_str()) == Some("Hashtag"))
+					.filter_map(|tag| tag.get("name").and_then(|n| n.as_str()))
+					.collect();
+				if !hashtags.is_empty() {
+					let _ = write!(page, "<p class=\"meta\">{}</p>", escape(&hashtags.join(" ")));
+				}
+			}
+			Err(_) => page.push_str("<p>Not JSON</p>"),
+		}
+
+		for (decision, label) in
+			[("release", "Forward"), ("reject", "Reject"), ("block", "Reject and block domain")]
+		{
+			let _ = write!(
+				page,
+				"<form method=\"post\" action=\"/{}/{}\"><input type=\"hidden\" name=\"token\" \
+				value=\"{}\"><button>{}</button></form> ",
+				held.id, decision, form_token, label
+			);
+		}
+		page.push_str("</section>");
+	}
+}
+
+/// Read a request's header and body, or `None` if it's too long or malformed.
+async fn read_request(
+	stream: &mut (impl AsyncRead + Unpin),
+) -> io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+	let mut buf = Vec::new();
+	let end = loop {
+		if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+			break i + 4;
+		}
+		if buf.len() >= MAX_REQUEST_LEN || stream.read_buf(&mut buf).await? == 0 {
+			return Ok(None);
+		}
+	};
+	let mut body = buf.split_off(end);
+	let Ok(headers) = Headers::parse(&buf) else {
+		return Ok(None);
+	};
+	let length = match headers.content_length() {
+		Ok(Some(length)) if length <= MAX_REQUEST_LEN => length,
+		Ok(None) => 0,
+		_ => return Ok(None),
+	};
+	while body.len() < length {
+		if stream.read_buf(&mut body).await? == 0 {
+			return Ok(None);
+		}
+	}
+	body.truncate(length);
+	Ok(Some((buf, body)))
+}
+
+/// Whether the request logs in with the password, as any user.
+fn authorized(headers: &Headers, password: &[u8]) -> bool {
+	let Ok(Some(authorization)) = headers.get("authorization") else {
+		return false;
+	};
+	let Some(credentials) = authorization.strip_prefix("Basic ") else {
+		return false;
+	};
+	let Ok(credentials) = STANDARD.decode(credentials.trim()) else {
+		return false;
+	};
+	let Some(colon) = credentials.iter().position(|&b| b == b':') else {
+		return false;
+	};
+	// compared as hashes, so the time taken tells nothing about the password
+	*Sha256::digest(&credentials[colon + 1..]) == *password
+}
+
+async fn respond(
+	stream: &mut (impl AsyncWrite + Unpin), status: &str, content_type: &str, body: &str,
+) -> io::Result<()> {
+	let response = format!(
+		"HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\n\
+		Content-Security-Policy: {}\r\nX-Frame-Options: DENY\r\nConnection: close\r\n\r\n{}",
+		status,
+		content_type,
+		body.len(),
+		CSP,
+		body
+	);
+	stream.write_all(response.as_bytes()).await
+}
+
+fn type_of(ap_json: Option<&Value>) -> &str {
+	ap_json.and_then(|v| v.get("type")).and_then(|t| t.as_str()).unwrap_or("?")
+}
+
+/// `url` as a link if it's http(s), or as text otherwise.
+fn link(url: &str) -> String {
+	if url.starts_with("https://") || url.starts_with("http://") {
+		format!("<a href=\"{0}\" rel=\"noreferrer\">{0}</a>", escape(url))
+	} else {
+		escape(url)
+	}
+}
+
+fn escape(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'&' => escaped.push_str("&amp;"),
+			'<' => escaped.push_str("&lt;"),
+			'>' => escaped.push_str("&gt;"),
+			'"' => escaped.push_str("&quot;"),
+			'\'' => escaped.push_str("&#39;"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
@@ -0,0 +1,111 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::Admin;
+use crate::{quarantine::Held, reputation::Subject};
+
+/// Version of the snapshot format, bumped on incompatible changes.
+const VERSION: u32 = 1;
+
+/// Runtime state that changes while spam-musubi runs, to carry it over to another host or
+/// seed a new replica with.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+	pub version: u32,
+	pub blocklist: Vec<String>,
+	pub allowlist: Vec<String>,
+	pub tarpit: Vec<String>,
+	/// Hashes of attachment URLs and domains.
+	pub attachments: Vec<String>,
+	pub reputation: Vec<ReputationEntry>,
+	pub quarantine: Vec<HeldEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReputationEntry {
+	pub kind: Subject,
+	pub subject: String,
+	pub score: f64,
+	/// unix time in seconds
+	pub updated: u64,
+}
+
+/// A quarantined activity. Header and body are stored as text, replacing bytes that aren't
+/// UTF-8.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HeldEntry {
+	/// unix time in seconds
+	pub received: u64,
+	pub actor: String,
+	pub rule: String,
+	pub header: String,
+	pub body: String,
+}
+
+impl Admin {
+	pub fn export_state(&self) -> Snapshot {
+		let reputation = self.filter.reputation().entries().into_iter();
+		let quarantine = self.filter.quarantine().held().into_iter();
+		Snapshot {
+			version: VERSION,
+			blocklist: self.blocklist.list(),
+			allowlist: self.allowlist.list(),
+			tarpit: self.tarpit.list(),
+			attachments: self.attachments.list(),
+			reputation: reputation
+				.map(|(kind, subject, score, updated)| ReputationEntry {
+					kind,
+					subject,
+					score,
+					updated,
+				})
+				.collect(),
+			quarantine: quarantine
+				.map(|held| HeldEntry {
+					received: held
+						.received
+						.duration_since(UNIX_EPOCH)
+						.map(|d| d.as_secs())
+						.unwrap_or_default(),
+					actor: held.actor,
+					rule: held.rule,
+					header: String::from_utf8_lossy(&held.header).to_string(),
+					body: String::from_utf8_lossy(&held.body).to_string(),
+				})
+				.collect(),
+		}
+	}
+
+	/// Merge a snapshot into the current state and return how many entries changed. Lists gain
+	/// the snapshot's entries, reputation scores are taken unless newer ones are known, and
+	/// quarantined activities are held again under new ids.
+	pub async fn import_state(&self, snapshot: Snapshot) -> Result<usize, String> {
+		if snapshot.version != VERSION {
+			return Err(format!("unsupported snapshot version {}", snapshot.version));
+		}
+		let mut changed = 0;
+		changed += self.blocklist.add(&snapshot.blocklist).await.map_err(|e| e.to_string())?;
+		changed += self.allowlist.add(&snapshot.allowlist).await.map_err(|e| e.to_string())?;
+		changed += self.tarpit.add(&snapshot.tarpit).await.map_err(|e| e.to_string())?;
+		changed += self.attachments.add(&snapshot.attachments).await.map_err(|e| e.to_string())?;
+		for e in snapshot.reputation {
+			if self.filter.reputation().restore(e.kind, &e.subject, e.score, e.updated) {
+				changed += 1;
+			}
+		}
+		for e in snapshot.quarantine {
+			self.filter.quarantine().restore(Held {
+				id: 0,
+				received: UNIX_EPOCH + Duration::from_secs(e.received),
+				actor: e.actor,
+				rule: e.rule,
+				header: e.header.into_bytes(),
+				body: e.body.into_bytes(),
+			});
+			changed += 1;
+		}
+		Ok(changed)
+	}
+}
@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use dashmap::DashSet;
+use sha2::{Digest, Sha256};
+use sonic_rs::{JsonContainerTrait, JsonValueTrait, Value};
+use tracing::*;
+
+use crate::db::StateDb;
+
+/// Hashes of files hosted for spam campaigns, and of domains hosting nothing else, so notes
+/// reusing the same image or file are caught however their text is reworded. Listing a domain
+/// also lists its subdomains.
+///
+/// URLs are hashed without their query and fragment, which often vary between copies. When a
+/// state DB is configured, the list survives restarts.
+#[derive(Debug, Clone)]
+pub struct AttachmentList {
+	hashes: Arc<DashSet<String>>,
+	db: Option<StateDb>,
+}
+
+impl AttachmentList {
+	pub async fn init(db: Option<StateDb>) -> Result<Self, sqlx::Error> {
+		let list = AttachmentList { hashes: Arc::new(DashSet::new()), db };
+		if let Some(db) = &list.db {
+			let rows = sqlx::query_as::<_, (String,)>("SELECT hash FROM attachment_blocklist")
+				.fetch_all(db.pool())
+				.await?;
+			for (hash,) in rows {
+				list.hashes.insert(hash);
+			}
+			info!("Loaded {} hashes into attachment_blocklist", list.hashes.len());
+		}
+		Ok(list)
+	}
+
+	/// Whether any attachment of the activity's object is listed, by URL or by domain.
+	pub fn matches(&self, ap_json: &Value) -> bool {
+		attachment_urls(ap_json)
+			.into_iter()
+			.any(|url| url_hashes(url).iter().any(|hash| self.hashes.contains(hash)))
+	}
+
+	/// List URLs, domains or hashes of either, and return how many weren't listed already.
+	pub async fn add(&self, entries: &[String]) -> Result<usize, sqlx::Error> {
+		let mut added = 0;
+		for hash in entries.iter().filter_map(|e| entry_hash(e)) {
+			if let Some(db) = &self.db {
+				sqlx::query("INSERT OR IGNORE INTO attachment_blocklist (hash) VALUES (?)")
+					.bind(&hash)
+					.execute(db.pool())
+					.await?;
+			}
+			if self.hashes.insert(hash) {
+				added += 1;
+			}
+		}
+		Ok(added)
+	}
+
+	/// Unlist URLs, domains or hashes of either, and return how many were listed.
+	pub async fn remove(&self, entries: &[String]) -> Result<usize, sqlx::Error> {
+		let mut removed = 0;
+		for hash in entries.iter().filter_map(|e| entry_hash(e)) {
+			if let Some(db) = &self.db {
+				sqlx::query("DELETE FROM attachment_blocklist WHERE hash = ?")
+					.bind(&hash)
+					.execute(db.pool())
+					.await?;
+			}
+			if self.hashes.remove(&hash).is_some() {
+				removed += 1;
+			}
+		}
+		Ok(removed)
+	}
+
+	pub fn list(&self) -> Vec<String> {
+		let mut hashes: Vec<_> = self.hashes.iter().map(|h| h.clone()).collect();
+		hashes.sort();
+		hashes
+	}
+}
+
+/// URLs of every `object.attachment`, whether given as strings or as links.
+pub fn attachment_urls(ap_json: &Value) -> Vec<&str> {
+	let Some(attachments) = ap_json.get("object").and_then(|o| o.get("attachment")) else {
+		return Vec::new();
+	};
+	one_or_many(attachments)
+		.into_iter()
+		.filter_map(|a| a.get("url"))
+		.flat_map(one_or_many)
+		.filter_map(|url| url.as_str().or_else(|| url.get("href").and_then(|h| h.as_str())))
+		.collect()
+}
+
+/// Values of a property that may be a single value or an array of them.
+fn one_or_many(value: &Value) -> Vec<&Value> {
+	match value.as_array() {
+		Some(values) => values.iter().collect(),
+		None => vec![value],
+	}
+}
+
+/// Hash an entry is listed under: a SHA-256 hash as is, or else the hash of a URL or domain.
+fn entry_hash(entry: &str) -> Option<String> {
+	let entry = entry.trim();
+	if entry.len() == 64 && entry.bytes().all(|b| b.is_ascii_hexdigit()) {
+		return Some(entry.to_ascii_lowercase());
+	}
+	if entry.contains("://") {
+		return normalize_url(entry).map(|(url, _)| hash(&url));
+	}
+	let domain = entry.trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase();
+	if domain.is_empty() {
+		None
+	} else {
+		Some(hash(&domain))
+	}
+}
+
+/// Hashes a file at `url` could be listed under: its URL's, and its host's and parent domains'.
+fn url_hashes(url: &str) -> Vec<String> {
+	let Some((url, host)) = normalize_url(url) else {
+		return Vec::new();
+	};
+	let mut hashes = vec![hash(&url)];
+	let mut domain = host.as_str();
+	loop {
+		hashes.push(hash(domain));
+		match domain.split_once('.') {
+			// stop short of the bare TLD
+			Some((_, parent)) if parent.contains('.') => domain = parent,
+			_ => return hashes,
+		}
+	}
+}
+
+/// URL without query and fragment, with scheme and host in lowercase, and its host.
+fn normalize_url(url: &str) -> Option<(String, String)> {
+	let url = url.trim().split(['?', '#']).next().unwrap_or_default();
+	let (scheme, rest) = url.split_once("://")?;
+	let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+	let authority = authority.rsplit('@').next().unwrap_or_default().to_ascii_lowercase();
+	let host = match authority.rsplit_once(':') {
+		Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+		_ => &authority,
+	};
+	let host = host.trim_end_matches('.').to_string();
+	if host.is_empty() {
+		return None;
+	}
+	Some((format!("{}://{}/{}", scheme.to_ascii_lowercase(), authority, path), host))
+}
+
+fn hash(s: &str) -> String {
+	format!("{:x}", Sha256::digest(s.as_bytes()))
+}
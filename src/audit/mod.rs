@@ -0,0 +1,119 @@
+use std::{
+	io::{self, Write},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		mpsc::{self, SyncSender, TrySendError},
+		Arc,
+	},
+	thread,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tracing::*;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{
+	filter::score::Score,
+	logging::file::{FileSink, LogFile},
+};
+
+/// How many records can be waiting for the writer before we start dropping them.
+const QUEUE_LEN: usize = 16384;
+
+/// What was learned about a request while judging it, for its audit record.
+#[derive(Debug, Default, Serialize)]
+pub struct Trail {
+	pub actor: Option<String>,
+	pub host: Option<String>,
+	pub note: Option<String>,
+	/// Stages and rules that acted on the note, in order.
+	pub rules: Vec<String>,
+	pub score: Option<u32>,
+	pub signals: Vec<String>,
+}
+
+impl Trail {
+	/// Note the score a note had when it was decided on.
+	pub fn scored(&mut self, score: &Score) {
+		self.score = Some(score.total());
+		self.signals = score.signals().into_iter().map(str::to_string).collect();
+	}
+
+	/// Note a stage or rule acting on the note, with its score at the time.
+	pub fn matched(&mut self, rule: &str, score: &Score) {
+		self.rules.push(rule.to_string());
+		self.scored(score);
+	}
+}
+
+/// A decision, as written to the audit log along with its unix time in milliseconds `ts_ms`.
+#[derive(Debug, Serialize)]
+pub struct Record<'a> {
+	pub request_id: &'a str,
+	/// `accepted`, or the kind of rejection.
+	pub verdict: &'a str,
+	/// `forward`, `reject`, `quarantine`, `throttle` or `tarpit`.
+	pub action: &'a str,
+	#[serde(flatten)]
+	pub trail: &'a Trail,
+	/// Time taken to decide, in microseconds.
+	pub latency_us: u64,
+}
+
+/// Append-only log of every decision, one JSON object per line, kept apart from the debug log
+/// for compliance and later analysis. Rotated like `--log-file`.
+///
+/// Records are written by a thread of their own so a slow disk never slows down the filter.
+/// While it can't keep up, records are dropped and a warning is logged.
+#[derive(Debug, Clone)]
+pub struct Audit {
+	tx: SyncSender<String>,
+	/// Whether records are being dropped, so it's only warned about once.
+	behind: Arc<AtomicBool>,
+}
+
+impl Audit {
+	pub fn open(config: LogFile) -> io::Result<Self> {
+		let path = config.path.clone();
+		let sink = FileSink::open(config)?;
+		let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_LEN);
+		thread::Builder::new().name("audit".to_string()).spawn(move || {
+			for line in rx {
+				if let Err(e) = sink.make_writer().write_all(line.as_bytes()) {
+					warn!("Could not write to audit log {}: {}", path.display(), e);
+				}
+			}
+		})?;
+		Ok(Audit { tx, behind: Arc::new(AtomicBool::new(false)) })
+	}
+
+	pub fn record(&self, record: &Record) {
+		#[derive(Serialize)]
+		struct Stamped<'a> {
+			ts_ms: u64,
+			#[serde(flatten)]
+			record: &'a Record<'a>,
+		}
+		let ts_ms = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_or(0, |d| d.as_millis().try_into().unwrap_or(u64::MAX));
+		let Ok(mut line) = sonic_rs::to_string(&Stamped { ts_ms, record }) else {
+			return;
+		};
+		line.push('\n');
+		match self.tx.try_send(line) {
+			Ok(()) => {
+				if self.behind.swap(false, Ordering::Relaxed) {
+					info!("Audit log caught up");
+				}
+			}
+			Err(TrySendError::Full(_)) => {
+				if !self.behind.swap(true, Ordering::Relaxed) {
+					warn!("Audit log can't keep up, dropping records");
+				}
+			}
+			Err(TrySendError::Disconnected(_)) => {}
+		}
+	}
+}
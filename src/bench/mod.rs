@@ -0,0 +1,284 @@
+use std::{
+	io,
+	net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+	task::JoinSet,
+	time::Instant,
+};
+
+use crate::{
+	cache::CacheConfig,
+	filter::Filter,
+	query::{InstanceStats, MemoryBackend, User},
+	upstream::{Routes, Upstream},
+};
+
+const LOCAL_HOST: &str = "local.example";
+const ACTOR: &str = "https://big.example/users/alice";
+
+/// What the stub AP server answers every request with.
+const UPSTREAM_RESPONSE: &[u8] =
+	b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// A request the filter doesn't look into.
+const PASSTHROUGH: &str =
+	"GET /nodeinfo/2.0 HTTP/1.1\r\nHost: local.example\r\nAccept: application/json\r\n\r\n";
+
+/// A note mentioning a local user, which goes through every check before being let through.
+const DELIVERY: &str = r##"{"@context":"https://www.w3.org/ns/activitystreams","id":"https://big.example/notes/1/activity","type":"Create","actor":"https://big.example/users/alice","object":{"id":"https://big.example/notes/1","type":"Note","attributedTo":"https://big.example/users/alice","content":"<p>@me lunch tomorrow? #food</p>","to":["https://www.w3.org/ns/activitystreams#Public"],"cc":["https://big.example/users/alice/followers","https://local.example/users/me"],"tag":[{"type":"Mention","href":"https://local.example/users/me","name":"@me@local.example"},{"type":"Hashtag","name":"#food"}]},"to":["https://www.w3.org/ns/activitystreams#Public"],"cc":["https://big.example/users/alice/followers","https://local.example/users/me"]}"##;
+
+/// How the requests of a scenario are sent.
+#[derive(Debug, Clone, Copy)]
+pub enum Scenario {
+	/// Straight to the stub AP server, as the baseline.
+	Direct,
+	/// Through the proxy, with requests it passes along without inspecting.
+	Passthrough,
+	/// Through the proxy, with inbox deliveries it has to inspect.
+	Filtered,
+}
+
+impl Scenario {
+	pub const ALL: [Scenario; 3] = [Scenario::Direct, Scenario::Passthrough, Scenario::Filtered];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Scenario::Direct => "direct",
+			Scenario::Passthrough => "passthrough",
+			Scenario::Filtered => "filtered",
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+	pub scenario: Scenario,
+	pub requests: usize,
+	/// Requests that failed or weren't answered by the AP server, e.g. because they were
+	/// rejected.
+	pub errors: usize,
+	pub elapsed: Duration,
+	pub mean: Duration,
+	pub p50: Duration,
+	pub p99: Duration,
+}
+
+impl Report {
+	/// Requests per second.
+	pub fn throughput(&self) -> f64 {
+		self.requests as f64 / self.elapsed.as_secs_f64()
+	}
+}
+
+/// Run every scenario against a proxy in front of a stub AP server and a stub DB, all within
+/// this process, sending `requests` requests over `concurrency` connections at a time.
+pub async fn run(requests: usize, concurrency: usize) -> io::Result<Vec<Report>> {
+	crate::HOST.set(LOCAL_HOST.to_string()).ok();
+
+	let upstream = stub_upstream().await?;
+	let routes = Routes::init(
+		Upstream {
+			backends: upstream.into(),
+			query: Arc::new(stub_backend()),
+			host: None,
+			tenant: Arc::default(),
+		},
+		&[],
+		&CacheConfig::default(),
+	)
+	.await
+	.map_err(io::Error::other)?;
+	// every delivery is the same, so don't let remembered verdicts skip the checks
+	let filter = Filter::builder().decision_ttl(Duration::ZERO).build();
+	let proxy = proxy(filter, routes).await?;
+
+	let delivery = format!(
+		"POST /inbox HTTP/1.1\r\nHost: {}\r\nContent-Type: application/activity+json\r\n\
+		Content-Length: {}\r\n\r\n{}",
+		LOCAL_HOST,
+		DELIVERY.len(),
+		DELIVERY
+	);
+
+	let mut reports = Vec::new();
+	for scenario in Scenario::ALL {
+		let (target, request) = match scenario {
+			Scenario::Direct => (upstream, delivery.clone()),
+			Scenario::Passthrough => (proxy, PASSTHROUGH.to_string()),
+			Scenario::Filtered => (proxy, delivery.clone()),
+		};
+		reports.push(measure(scenario, target, request.into_bytes(), requests, concurrency).await);
+	}
+	Ok(reports)
+}
+
+fn stub_backend() -> MemoryBackend {
+	MemoryBackend::new()
+		.instance("big.example", InstanceStats { followers: 500, following: 500, notes: 10000 })
+		.user(
+			ACTOR,
+			User {
+				followers: 50,
+				following: 40,
+				notes: 300,
+				age: Duration::from_secs(400 * 24 * 60 * 60),
+			},
+		)
+}
+
+/// Listen as an AP server that reads whole requests and accepts all of them.
+async fn stub_upstream() -> io::Result<SocketAddrV4> {
+	let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+	let address = local_v4(listener.local_addr()?);
+	tokio::spawn(async move {
+		loop {
+			let Ok((mut stream, _)) = listener.accept().await else {
+				continue;
+			};
+			tokio::spawn(async move {
+				if read_request(&mut stream).await.is_ok() {
+					stream.write_all(UPSTREAM_RESPONSE).await.ok();
+				}
+			});
+		}
+	});
+	Ok(address)
+}
+
+/// Read up to the end of the body, going by Content-Length.
+async fn read_request(stream: &mut TcpStream) -> io::Result<()> {
+	let mut buf = Vec::with_capacity(4096);
+	let mut chunk = [0u8; 4096];
+	let header_end = loop {
+		if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+			break end + 4;
+		}
+		let n = stream.read(&mut chunk).await?;
+		if n == 0 {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		buf.extend_from_slice(&chunk[..n]);
+	};
+	let length = String::from_utf8_lossy(&buf[..header_end])
+		.lines()
+		.filter_map(|line| line.split_once(':'))
+		.find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+		.and_then(|(_, value)| value.trim().parse::<usize>().ok())
+		.unwrap_or(0);
+	while buf.len() < header_end + length {
+		let n = stream.read(&mut chunk).await?;
+		if n == 0 {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		buf.extend_from_slice(&chunk[..n]);
+	}
+	Ok(())
+}
+
+/// Listen as spam-musubi does, minus logging and dumping of rejected requests.
+async fn proxy(filter: Filter, routes: Routes) -> io::Result<SocketAddrV4> {
+	let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+	let address = local_v4(listener.local_addr()?);
+	tokio::spawn(async move {
+		loop {
+			let Ok((stream, _)) = listener.accept().await else {
+				continue;
+			};
+			let filter = filter.clone();
+			let routes = routes.clone();
+			tokio::spawn(async move {
+				let Ok(mut admit) = filter.handler(stream, &routes).await else {
+					return;
+				};
+				let Ok(mut server_stream) = admit.upstream.address.connect().await else {
+					return;
+				};
+				if server_stream.write_all(&admit.pending_header).await.is_err()
+					|| server_stream.write_all(&admit.pending_body).await.is_err()
+				{
+					return;
+				}
+				tokio::io::copy_bidirectional(&mut admit.incoming_stream, &mut server_stream)
+					.await
+					.ok();
+			});
+		}
+	});
+	Ok(address)
+}
+
+async fn measure(
+	scenario: Scenario, target: SocketAddrV4, request: Vec<u8>, requests: usize,
+	concurrency: usize,
+) -> Report {
+	let request = Arc::new(request);
+	let remaining = Arc::new(AtomicUsize::new(requests));
+	let started = Instant::now();
+
+	let mut workers = JoinSet::new();
+	for _ in 0..concurrency.max(1) {
+		let request = request.clone();
+		let remaining = remaining.clone();
+		workers.spawn(async move {
+			let mut latencies = Vec::new();
+			let mut errors = 0;
+			let take = |n: usize| n.checked_sub(1);
+			while remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, take).is_ok() {
+				let now = Instant::now();
+				match send(target, &request).await {
+					Ok(true) => latencies.push(now.elapsed()),
+					_ => errors += 1,
+				}
+			}
+			(latencies, errors)
+		});
+	}
+
+	let mut latencies = Vec::with_capacity(requests);
+	let mut errors = 0;
+	while let Some(Ok((l, e))) = workers.join_next().await {
+		latencies.extend(l);
+		errors += e;
+	}
+	let elapsed = started.elapsed();
+
+	latencies.sort();
+	let percentile = |p: usize| {
+		latencies.get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1))).copied()
+	};
+	let total: Duration = latencies.iter().sum();
+	Report {
+		scenario,
+		requests,
+		errors,
+		elapsed,
+		mean: total.checked_div(latencies.len() as u32).unwrap_or_default(),
+		p50: percentile(50).unwrap_or_default(),
+		p99: percentile(99).unwrap_or_default(),
+	}
+}
+
+/// Send a request on a fresh connection, and return whether the AP server answered it.
+async fn send(target: SocketAddrV4, request: &[u8]) -> io::Result<bool> {
+	let mut stream = TcpStream::connect(target).await?;
+	stream.write_all(request).await?;
+	let mut response = Vec::new();
+	stream.read_to_end(&mut response).await?;
+	Ok(response.starts_with(UPSTREAM_RESPONSE))
+}
+
+fn local_v4(address: SocketAddr) -> SocketAddrV4 {
+	match address {
+		SocketAddr::V4(address) => address,
+		SocketAddr::V6(_) => unreachable!("bound to an IPv4 address"),
+	}
+}
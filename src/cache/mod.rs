@@ -0,0 +1,159 @@
+use std::{
+	hash::Hash,
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		Arc, Mutex, Weak,
+	},
+};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+
+/// Every cache still around, to report on.
+static REGISTRY: Mutex<Vec<Weak<CacheStats>>> = Mutex::new(Vec::new());
+
+/// `[caches]` in the config file: how much memory each cache may take, in MiB. Caches without
+/// a budget grow with traffic, bounded only by how long they keep entries.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+	/// Verdicts on recently judged notes.
+	pub decisions_mb: Option<u64>,
+	/// Recently forwarded deliveries, to drop exact repeats of.
+	pub dedup_mb: Option<u64>,
+	/// Actors and instances looked up through the AP server's API.
+	pub lookups_mb: Option<u64>,
+	/// Recent `Move`s, to tell migrations from follower harvesting.
+	pub moves_mb: Option<u64>,
+}
+
+impl CacheConfig {
+	pub fn validate(&self) -> Result<(), String> {
+		let budgets = [self.decisions_mb, self.dedup_mb, self.lookups_mb, self.moves_mb];
+		if budgets.iter().flatten().any(|mb| *mb == 0) {
+			return Err("cache budgets must be at least 1 MiB".to_string());
+		}
+		Ok(())
+	}
+}
+
+/// Entries fitting in `mb` MiB at about `entry_bytes` each, or no limit.
+pub fn max_entries(mb: Option<u64>, entry_bytes: usize) -> usize {
+	mb.map_or(usize::MAX, |mb| (mb as usize * 1024 * 1024 / entry_bytes).max(1))
+}
+
+/// Hits, misses and evictions of one cache, reported as metrics.
+#[derive(Debug)]
+pub struct CacheStats {
+	pub name: &'static str,
+	/// Rough size of an entry with its key, for the memory estimate.
+	pub entry_bytes: usize,
+	entries: AtomicUsize,
+	hits: AtomicU64,
+	misses: AtomicU64,
+	evictions: AtomicU64,
+}
+
+/// What a cache counted since it was last reported.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheReport {
+	pub name: &'static str,
+	pub entries: usize,
+	/// Estimated from the entry count.
+	pub bytes: usize,
+	pub hits: u64,
+	pub misses: u64,
+	pub evictions: u64,
+}
+
+impl CacheStats {
+	/// Counters for a new cache, reported along with every other one.
+	pub fn register(name: &'static str, entry_bytes: usize) -> Arc<Self> {
+		let stats = Arc::new(CacheStats {
+			name,
+			entry_bytes,
+			entries: AtomicUsize::new(0),
+			hits: AtomicU64::new(0),
+			misses: AtomicU64::new(0),
+			evictions: AtomicU64::new(0),
+		});
+		#[allow(clippy::unwrap_used)]
+		REGISTRY.lock().unwrap().push(Arc::downgrade(&stats));
+		stats
+	}
+
+	pub fn hit(&self) {
+		self.hits.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn miss(&self) {
+		self.misses.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Count a lookup, and pass on what it found.
+	pub fn count<T>(&self, found: Option<T>) -> Option<T> {
+		match found {
+			Some(_) => self.hit(),
+			None => self.miss(),
+		}
+		found
+	}
+
+	pub fn set_entries(&self, entries: usize) {
+		self.entries.store(entries, Ordering::Relaxed);
+	}
+
+	/// Counts since the last call, and the current size.
+	fn take(&self) -> CacheReport {
+		let entries = self.entries.load(Ordering::Relaxed);
+		CacheReport {
+			name: self.name,
+			entries,
+			bytes: entries * self.entry_bytes,
+			hits: self.hits.swap(0, Ordering::Relaxed),
+			misses: self.misses.swap(0, Ordering::Relaxed),
+			evictions: self.evictions.swap(0, Ordering::Relaxed),
+		}
+	}
+}
+
+/// Counts of every cache since the last call. Caches of the same name, like those of several
+/// upstreams, are added up.
+pub fn reports() -> Vec<CacheReport> {
+	#[allow(clippy::unwrap_used)]
+	let mut registry = REGISTRY.lock().unwrap();
+	registry.retain(|stats| stats.strong_count() > 0);
+	let mut reports: Vec<CacheReport> = Vec::new();
+	for report in registry.iter().filter_map(Weak::upgrade).map(|stats| stats.take()) {
+		match reports.iter_mut().find(|r| r.name == report.name) {
+			Some(sum) => {
+				sum.entries += report.entries;
+				sum.bytes += report.bytes;
+				sum.hits += report.hits;
+				sum.misses += report.misses;
+				sum.evictions += report.evictions;
+			}
+			None => reports.push(report),
+		}
+	}
+	reports
+}
+
+/// Make room for one more entry in a cache of at most `max` entries, dropping the oldest ones
+/// by `at` if it's full. Counted as evictions, unlike entries dropped for expiring.
+pub fn make_room<K: Eq + Hash + Clone, V, A: Ord + Copy>(
+	map: &DashMap<K, V>, max: usize, stats: &CacheStats, at: impl Fn(&V) -> A,
+) {
+	if map.len() < max {
+		return;
+	}
+	let mut ages: Vec<_> = map.iter().map(|e| (at(e.value()), e.key().clone())).collect();
+	ages.sort_unstable_by_key(|(at, _)| *at);
+	// down to 90%, so it isn't full again on the very next insert
+	let keep = max - max / 10;
+	let excess = ages.len().saturating_sub(keep);
+	for (_, key) in ages.into_iter().take(excess) {
+		map.remove(&key);
+	}
+	stats.evictions.fetch_add(excess as u64, Ordering::Relaxed);
+}
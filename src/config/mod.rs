@@ -0,0 +1,172 @@
+use std::{
+	collections::HashMap,
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+use serde::{de, Deserialize};
+use thiserror::Error;
+use toml::{Table, Value};
+
+use crate::{
+	cache::CacheConfig,
+	events::EventsConfig,
+	flag::FlagConfig,
+	filter::{
+		asn::AsnConfig,
+		classifier::ClassifierConfig,
+		digest::DigestConfig,
+		domain_block::DomainBlockConfig,
+		followers::FollowersConfig,
+		honeypot::HoneypotConfig,
+		panic::PanicConfig,
+		pow::PowConfig,
+		responses::ResponseConfig,
+		rules::{canary::CanaryConfig, RuleConfig},
+		shadow::ShadowConfig,
+		suspend::SuspendConfig,
+		text::TextConfig,
+		user_agent::UserAgentConfig,
+		webfinger::WebFingerConfig,
+	},
+	statsd::StatsdConfig,
+	subscriptions::SubscriptionConfig,
+	telemetry::TelemetryConfig,
+	upstream::UpstreamConfig,
+};
+
+pub mod effective;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+	#[error("Could not read config file: {0}")]
+	Read(#[from] io::Error),
+	#[error("Invalid config file: {0}")]
+	Parse(#[from] toml::de::Error),
+	#[error("Could not read {key}_file {path}: {error}")]
+	Secret { key: String, path: String, error: io::Error },
+	#[error("Invalid config file: {0} and {0}_file are both set")]
+	BothSecrets(String),
+}
+
+/// Keys whose value may be read from the file named by `<key>_file` instead, as with docker and
+/// Kubernetes secrets.
+pub const SECRET_FILE_KEYS: &[&str] = &["password", "token", "salt", "smtp"];
+
+/// Settings read from the `--config` TOML file.
+///
+/// Anything not set here keeps its built-in default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+	/// Replaces the default ruleset when present.
+	pub rules: Option<Vec<RuleConfig>>,
+	/// A candidate ruleset to try out on a share of actors, and compare with the active one.
+	pub canary: Option<CanaryConfig>,
+	/// Pipeline B, judging every note without acting on it, to compare with.
+	pub shadow: Option<ShadowConfig>,
+	/// Directory of rule pack files, whose rules are evaluated after these.
+	pub rule_packs: Option<PathBuf>,
+	/// Upstream AP servers routed to by Host header, besides the one given by flags.
+	pub upstreams: Option<Vec<UpstreamConfig>>,
+	/// Responses to rejections by reason, overriding the built-in ones.
+	pub responses: Option<HashMap<String, ResponseConfig>>,
+	/// Stricter rules to switch to during waves of spam.
+	pub panic: Option<PanicConfig>,
+	/// The local Misskey's admin API, to suspend or report actors that keep sending spam.
+	pub suspend: Option<SuspendConfig>,
+	/// The local Mastodon's admin API, to block instances that send a lot of spam.
+	pub domain_block: Option<DomainBlockConfig>,
+	/// A system actor to report spam to the AP server's moderators as.
+	pub flag: Option<FlagConfig>,
+	/// An external service to ask for spam scores.
+	pub classifier: Option<ClassifierConfig>,
+	/// Decoy paths that get whoever asks for them blocked.
+	pub honeypot: Option<HoneypotConfig>,
+	/// User-Agents of deliveries to reject or be wary of.
+	pub user_agents: Option<UserAgentConfig>,
+	/// Networks to reject or be wary of deliveries from.
+	pub asn: Option<AsnConfig>,
+	/// A proxy to ask instances whether actors new to the AP server exist there through.
+	pub webfinger: Option<WebFingerConfig>,
+	/// A proxy to ask instances how many followers actors with only a handful here have through.
+	pub followers: Option<FollowersConfig>,
+	/// How notes' HTML is read as text.
+	pub text: Option<TextConfig>,
+	/// Proof-of-work challenges for first deliveries from unknown instances.
+	pub pow: Option<PowConfig>,
+	/// A NATS server to publish every decision to.
+	pub events: Option<EventsConfig>,
+	/// Emails summing up what was filtered.
+	pub digest: Option<DigestConfig>,
+	/// A StatsD or DogStatsD agent to push metrics to.
+	pub statsd: Option<StatsdConfig>,
+	/// A community endpoint to upload salted hashes of confirmed spam to.
+	pub telemetry: Option<TelemetryConfig>,
+	/// Memory budgets of the caches.
+	pub caches: Option<CacheConfig>,
+	/// Signed blocklists and fingerprint packs published elsewhere, to pull periodically.
+	pub subscriptions: Option<Vec<SubscriptionConfig>>,
+}
+
+impl Config {
+	/// The config file, with secrets read from the files named by `*_file` keys.
+	pub fn load(path: &Path) -> Result<Self, ConfigError> {
+		Self::parse(path, true)
+	}
+
+	/// The config file without reading secret files, for reloads, which only take the rules
+	/// and may be kept from reading them by the sandbox.
+	pub fn load_without_secrets(path: &Path) -> Result<Self, ConfigError> {
+		Self::parse(path, false)
+	}
+
+	fn parse(path: &Path, secrets: bool) -> Result<Self, ConfigError> {
+		let mut table: Table = toml::from_str(&fs::read_to_string(path)?)?;
+		read_secret_files(&mut table, secrets)?;
+		Ok(Value::Table(table).try_into()?)
+	}
+}
+
+/// Replace `<key>_file` with `<key>` and the contents of the file, in every table. Without
+/// `read`, with an empty value instead.
+fn read_secret_files(table: &mut Table, read: bool) -> Result<(), ConfigError> {
+	for key in SECRET_FILE_KEYS {
+		let file_key = format!("{}_file", key);
+		let Some(path) = table.remove(&file_key) else {
+			continue;
+		};
+		if table.contains_key(*key) {
+			return Err(ConfigError::BothSecrets(key.to_string()));
+		}
+		let Value::String(path) = path else {
+			let error = de::Error::custom(format!("{} must be a path", file_key));
+			return Err(ConfigError::Parse(error));
+		};
+		let value = match read {
+			true => fs::read_to_string(&path).map_err(|error| ConfigError::Secret {
+				key: key.to_string(),
+				path: path.clone(),
+				error,
+			})?,
+			false => String::new(),
+		};
+		// secrets are often written with a trailing newline
+		let value = value.trim_end_matches(['\r', '\n']).to_string();
+		table.insert(key.to_string(), Value::String(value));
+	}
+	for (_, value) in table.iter_mut() {
+		match value {
+			Value::Table(table) => read_secret_files(table, read)?,
+			Value::Array(values) => {
+				for value in values {
+					if let Value::Table(table) = value {
+						read_secret_files(table, read)?;
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+	Ok(())
+}
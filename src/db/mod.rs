@@ -1 +1,65 @@
+use std::path::Path;
 
+use sqlx::{
+	sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+	SqlitePool,
+};
+
+/// SQLite database holding spam-musubi's own state, as opposed to the AP server's database
+/// behind [`crate::query::Query`].
+#[derive(Debug, Clone)]
+pub struct StateDb {
+	pool: SqlitePool,
+}
+
+const SCHEMA: &[&str] = &[
+	r#"CREATE TABLE IF NOT EXISTS reputation (
+		kind TEXT NOT NULL,
+		subject TEXT NOT NULL,
+		score REAL NOT NULL,
+		updated INTEGER NOT NULL,
+		PRIMARY KEY (kind, subject)
+	)"#,
+	r#"CREATE TABLE IF NOT EXISTS blocklist (domain TEXT PRIMARY KEY)"#,
+	r#"CREATE TABLE IF NOT EXISTS allowlist (domain TEXT PRIMARY KEY)"#,
+	r#"CREATE TABLE IF NOT EXISTS tarpit (domain TEXT PRIMARY KEY)"#,
+	r#"CREATE TABLE IF NOT EXISTS attachment_blocklist (hash TEXT PRIMARY KEY)"#,
+	r#"CREATE TABLE IF NOT EXISTS throttle (
+		rule TEXT NOT NULL,
+		actor TEXT NOT NULL,
+		start INTEGER NOT NULL,
+		length INTEGER NOT NULL,
+		count INTEGER NOT NULL,
+		PRIMARY KEY (rule, actor)
+	)"#,
+	r#"CREATE TABLE IF NOT EXISTS seen_deliveries (key TEXT PRIMARY KEY, at INTEGER NOT NULL)"#,
+	r#"CREATE TABLE IF NOT EXISTS replies (
+		actor TEXT NOT NULL,
+		target TEXT NOT NULL,
+		at INTEGER NOT NULL,
+		PRIMARY KEY (actor, target)
+	)"#,
+	r#"CREATE TABLE IF NOT EXISTS fingerprints (fingerprint TEXT PRIMARY KEY, at INTEGER NOT NULL)"#,
+	r#"CREATE TABLE IF NOT EXISTS subscription_serials (url TEXT PRIMARY KEY, serial INTEGER NOT NULL)"#,
+	r#"CREATE TABLE IF NOT EXISTS subscription_entries (
+		url TEXT NOT NULL,
+		entry TEXT NOT NULL,
+		PRIMARY KEY (url, entry)
+	)"#,
+];
+
+impl StateDb {
+	pub async fn open(path: &Path) -> Result<Self, sqlx::Error> {
+		let pool = SqlitePoolOptions::new()
+			.connect_with(SqliteConnectOptions::new().filename(path).create_if_missing(true))
+			.await?;
+		for statement in SCHEMA {
+			sqlx::query(statement).execute(&pool).await?;
+		}
+		Ok(StateDb { pool })
+	}
+
+	pub fn pool(&self) -> &SqlitePool {
+		&self.pool
+	}
+}
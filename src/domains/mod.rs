@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use dashmap::DashSet;
+use tracing::*;
+
+use crate::db::StateDb;
+
+/// Which operator-managed domain list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+	/// Everything from these domains is rejected.
+	Block,
+	/// Everything from these domains skips spam detection.
+	Allow,
+	/// Deliveries from these domains are confirmed spam and get tarpitted.
+	Tarpit,
+}
+
+/// Domains the operator blocked, allowed or tarpitted outright. Listing a domain also lists its
+/// subdomains.
+///
+/// When a state DB is configured, the list survives restarts.
+#[derive(Debug, Clone)]
+pub struct DomainList {
+	kind: Kind,
+	domains: Arc<DashSet<String>>,
+	db: Option<StateDb>,
+}
+
+impl Kind {
+	fn table(self) -> &'static str {
+		match self {
+			Kind::Block => "blocklist",
+			Kind::Allow => "allowlist",
+			Kind::Tarpit => "tarpit",
+		}
+	}
+}
+
+impl DomainList {
+	pub async fn init(kind: Kind, db: Option<StateDb>) -> Result<Self, sqlx::Error> {
+		let list = DomainList { kind, domains: Arc::new(DashSet::new()), db };
+		if let Some(db) = &list.db {
+			let statement = format!("SELECT domain FROM {}", kind.table());
+			let rows = sqlx::query_as::<_, (String,)>(&statement).fetch_all(db.pool()).await?;
+			for (domain,) in rows {
+				list.domains.insert(domain);
+			}
+			info!("Loaded {} domains into {}", list.domains.len(), kind.table());
+		}
+		Ok(list)
+	}
+
+	/// Whether `host` or any domain it is under is listed.
+	pub fn contains(&self, host: &str) -> bool {
+		let mut host = host.trim_end_matches('.');
+		loop {
+			if self.domains.contains(host) {
+				return true;
+			}
+			match host.split_once('.') {
+				Some((_, parent)) => host = parent,
+				None => return false,
+			}
+		}
+	}
+
+	/// List domains and return how many weren't listed already.
+	pub async fn add(&self, domains: &[String]) -> Result<usize, sqlx::Error> {
+		let mut added = 0;
+		for domain in domains.iter().filter_map(|d| normalize(d)) {
+			if let Some(db) = &self.db {
+				let statement =
+					format!("INSERT OR IGNORE INTO {} (domain) VALUES (?)", self.kind.table());
+				sqlx::query(&statement).bind(&domain).execute(db.pool()).await?;
+			}
+			if self.domains.insert(domain) {
+				added += 1;
+			}
+		}
+		Ok(added)
+	}
+
+	/// Unlist domains and return how many were listed.
+	pub async fn remove(&self, domains: &[String]) -> Result<usize, sqlx::Error> {
+		let mut removed = 0;
+		for domain in domains.iter().filter_map(|d| normalize(d)) {
+			if let Some(db) = &self.db {
+				let statement = format!("DELETE FROM {} WHERE domain = ?", self.kind.table());
+				sqlx::query(&statement).bind(&domain).execute(db.pool()).await?;
+			}
+			if self.domains.remove(&domain).is_some() {
+				removed += 1;
+			}
+		}
+		Ok(removed)
+	}
+
+	pub fn list(&self) -> Vec<String> {
+		let mut domains: Vec<_> = self.domains.iter().map(|d| d.clone()).collect();
+		domains.sort();
+		domains
+	}
+}
+
+/// A domain as listed: lowercase, without a leading `*.` or trailing dot.
+pub fn normalize(domain: &str) -> Option<String> {
+	let domain = domain.trim().trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase();
+	if domain.is_empty() {
+		None
+	} else {
+		Some(domain)
+	}
+}
+
+/// Domains in a domain list file: one per line, or a CSV like Mastodon's domain block export
+/// with the domain in the first column. Empty lines and lines starting with `#` are skipped.
+pub fn parse_import(src: &str) -> Vec<String> {
+	src.lines()
+		.map(str::trim)
+		.filter(|l| !l.is_empty() && !l.starts_with('#'))
+		.filter_map(|l| l.split(',').next())
+		.map(|d| d.trim().to_string())
+		.collect()
+}
@@ -0,0 +1,123 @@
+use std::{
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::{
+	fs::{self, File, OpenOptions},
+	io::{self, AsyncWriteExt},
+	sync::mpsc,
+};
+use tracing::*;
+
+/// Number of segment files the ring buffer is split into.
+const SEGMENTS: u64 = 8;
+/// How many records can be waiting for the writer before we start dropping them.
+const QUEUE_LEN: usize = 1024;
+
+/// Bounded on-disk ring buffer of rejected payloads.
+///
+/// Records are appended as JSONL to `rejected.<n>.jsonl` segments inside the directory.
+/// Once a segment is full the next one is truncated and written to, so the directory never
+/// grows past the configured size and the newest samples overwrite the oldest.
+#[derive(Debug, Clone)]
+pub struct RejectDump {
+	tx: mpsc::Sender<Record>,
+}
+
+#[derive(Debug, Serialize)]
+struct Record {
+	ts: u64,
+	reason: String,
+	actor: Option<String>,
+	body: String,
+}
+
+struct Writer {
+	dir: PathBuf,
+	segment_size: u64,
+	segment: u64,
+	written: u64,
+	file: File,
+}
+
+impl RejectDump {
+	pub async fn init(dir: &Path, max_bytes: u64) -> io::Result<Self> {
+		fs::create_dir_all(dir).await?;
+		let mut writer = Writer::open(dir.to_path_buf(), max_bytes / SEGMENTS).await?;
+
+		let (tx, mut rx) = mpsc::channel::<Record>(QUEUE_LEN);
+		tokio::spawn(async move {
+			while let Some(record) = rx.recv().await {
+				if let Err(e) = writer.write(&record).await {
+					warn!("Could not write rejected payload to disk: {}", e);
+				}
+			}
+		});
+
+		Ok(RejectDump { tx })
+	}
+
+	/// Queue a rejected payload for writing. Never blocks; drops the sample if the writer is
+	/// lagging behind.
+	pub fn record(&self, reason: &str, actor: Option<&str>, body: &str) {
+		let record = Record {
+			ts: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+			reason: reason.to_string(),
+			actor: actor.map(|a| a.to_string()),
+			body: body.to_string(),
+		};
+		if self.tx.try_send(record).is_err() {
+			trace!("Rejected payload dump queue is full, dropping sample");
+		}
+	}
+}
+
+impl Writer {
+	async fn open(dir: PathBuf, segment_size: u64) -> io::Result<Self> {
+		// continue from the most recently written segment, so restarts don't clobber samples
+		let mut segment = 0;
+		let mut newest = None;
+		for n in 0..SEGMENTS {
+			if let Ok(modified) =
+				fs::metadata(segment_path(&dir, n)).await.and_then(|m| m.modified())
+			{
+				if newest < Some(modified) {
+					newest = Some(modified);
+					segment = n;
+				}
+			}
+		}
+
+		let file =
+			OpenOptions::new().create(true).append(true).open(segment_path(&dir, segment)).await?;
+		let written = file.metadata().await?.len();
+
+		Ok(Writer { dir, segment_size, segment, written, file })
+	}
+
+	async fn write(&mut self, record: &Record) -> io::Result<()> {
+		let mut line = sonic_rs::to_vec(record).map_err(io::Error::other)?;
+		line.push(b'\n');
+
+		if self.written > 0 && self.written + line.len() as u64 > self.segment_size {
+			self.segment = (self.segment + 1) % SEGMENTS;
+			self.file = OpenOptions::new()
+				.create(true)
+				.write(true)
+				.truncate(true)
+				.open(segment_path(&self.dir, self.segment))
+				.await?;
+			self.written = 0;
+		}
+
+		self.file.write_all(&line).await?;
+		self.written += line.len() as u64;
+		Ok(())
+	}
+}
+
+fn segment_path(dir: &Path, n: u64) -> PathBuf {
+	dir.join(format!("rejected.{}.jsonl", n))
+}
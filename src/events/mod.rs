@@ -0,0 +1,177 @@
+use std::{
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sonic_rs::{JsonValueTrait, Value};
+use tokio::{
+	io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+	net::TcpStream,
+	sync::mpsc,
+};
+use tracing::*;
+use url::Url;
+
+use crate::tls::{self, Stream};
+
+/// How many events can be waiting for the connection before we start dropping them.
+const QUEUE_LEN: usize = 4096;
+const RECONNECT_SECS: u64 = 5;
+const DEFAULT_SUBJECT: &str = "musubi.decisions";
+const DEFAULT_PORT: u16 = 4222;
+
+/// `[events]` in the config file: a NATS server to publish every decision to, for analytics or
+/// collecting training data outside spam-musubi.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventsConfig {
+	/// NATS server, like `nats://127.0.0.1:4222`. The connection is upgraded to TLS if the
+	/// server requires it, and always for `tls://` URLs.
+	pub nats: String,
+	#[serde(default = "default_subject")]
+	pub subject: String,
+	/// Token to authenticate with, if the server asks for one.
+	pub token: Option<String>,
+}
+
+fn default_subject() -> String {
+	DEFAULT_SUBJECT.to_string()
+}
+
+impl EventsConfig {
+	/// Check the server URL and subject at startup rather than on the first decision.
+	pub fn validate(&self) -> Result<(), String> {
+		let url = Url::parse(&self.nats).map_err(|e| format!("invalid events nats: {}", e))?;
+		if !matches!(url.scheme(), "nats" | "tls") || url.host_str().is_none() {
+			return Err("events nats must be a nats://host:port or tls://host:port URL".to_string());
+		}
+		let valid = !self.subject.is_empty()
+			&& !self.subject.contains(|c: char| c.is_whitespace() || c == '*' || c == '>');
+		if !valid {
+			return Err("events subject must be a NATS subject without wildcards".to_string());
+		}
+		Ok(())
+	}
+
+	/// Port the NATS server is reached on.
+	pub fn nats_port(&self) -> Option<u16> {
+		Some(Url::parse(&self.nats).ok()?.port().unwrap_or(DEFAULT_PORT))
+	}
+}
+
+/// A decision on a delivery, as published along with its unix time `ts`.
+#[derive(Debug, Serialize)]
+pub struct Event<'a> {
+	/// `accepted`, or the kind of rejection.
+	pub verdict: &'a str,
+	pub actor: Option<&'a str>,
+	pub host: Option<&'a str>,
+	/// AP id of the note judged, if it got that far.
+	pub note: Option<&'a str>,
+	/// Total weight of spam signals, and their names, for notes that were scored.
+	pub score: Option<u32>,
+	pub signals: Vec<&'a str>,
+	/// ID of the request, as sent to the AP server in X-Request-Id.
+	pub request_id: &'a str,
+}
+
+/// Publishes decisions to NATS in the background. Never slows down the filter: events are
+/// dropped while the server can't keep up or can't be reached.
+#[derive(Debug, Clone)]
+pub struct Events {
+	tx: mpsc::Sender<String>,
+}
+
+impl Events {
+	pub fn new(config: EventsConfig) -> Self {
+		let (tx, rx) = mpsc::channel(QUEUE_LEN);
+		tokio::spawn(run(Arc::new(config), rx));
+		Events { tx }
+	}
+
+	pub fn publish(&self, event: &Event) {
+		#[derive(Serialize)]
+		struct Stamped<'a> {
+			ts: u64,
+			#[serde(flatten)]
+			event: &'a Event<'a>,
+		}
+		let ts = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+		let Ok(payload) = sonic_rs::to_string(&Stamped { ts, event }) else {
+			return;
+		};
+		if self.tx.try_send(payload).is_err() {
+			trace!("Event queue full, dropping event");
+		}
+	}
+}
+
+/// Keep a connection to the server up and publish queued events on it, until the filter is
+/// gone.
+async fn run(config: Arc<EventsConfig>, mut rx: mpsc::Receiver<String>) {
+	loop {
+		match publish(&config, &mut rx).await {
+			Ok(()) => return,
+			Err(e) => warn!("Lost connection to NATS at {}: {}", config.nats, e),
+		}
+		tokio::time::sleep(Duration::from_secs(RECONNECT_SECS)).await;
+	}
+}
+
+async fn publish(config: &EventsConfig, rx: &mut mpsc::Receiver<String>) -> io::Result<()> {
+	let url = Url::parse(&config.nats).map_err(io::Error::other)?;
+	let host = url.host_str().unwrap_or_default();
+	let stream = TcpStream::connect((host, url.port().unwrap_or(DEFAULT_PORT))).await?;
+
+	// the server introduces itself first, in plain text even when it wants TLS
+	let mut reader = BufReader::new(stream);
+	let mut info = String::new();
+	reader.read_line(&mut info).await?;
+	let Some(info) = info.strip_prefix("INFO ") else {
+		return Err(io::Error::other("not a NATS server"));
+	};
+	let info: Value = sonic_rs::from_str(info).map_err(io::Error::other)?;
+	let tls_required = info.get("tls_required").and_then(|t| t.as_bool()).unwrap_or(false);
+	let stream = match url.scheme() == "tls" || tls_required {
+		true => tls::connect(host, reader.into_inner()).await?,
+		false => Stream::Plain(reader.into_inner()),
+	};
+	let (read, mut write) = io::split(stream);
+	let mut lines = BufReader::new(read).lines();
+	let connect = sonic_rs::json!({
+		"verbose": false,
+		"pedantic": false,
+		"name": "spam-musubi",
+		"lang": "rust",
+		"version": env!("CARGO_PKG_VERSION"),
+		"auth_token": config.token.as_deref(),
+	});
+	write.write_all(format!("CONNECT {}\r\nPING\r\n", connect).as_bytes()).await?;
+	write.flush().await?;
+	info!("Publishing decisions to NATS at {}", config.nats);
+
+	loop {
+		tokio::select! {
+			payload = rx.recv() => {
+				let Some(payload) = payload else {
+					return Ok(());
+				};
+				let message =
+					format!("PUB {} {}\r\n{}\r\n", config.subject, payload.len(), payload);
+				write.write_all(message.as_bytes()).await?;
+				write.flush().await?;
+			}
+			line = lines.next_line() => match line? {
+				// the server hangs up on clients that don't answer
+				Some(line) if line.starts_with("PING") => {
+					write.write_all(b"PONG\r\n").await?;
+					write.flush().await?;
+				}
+				Some(line) if line.starts_with("-ERR") => return Err(io::Error::other(line)),
+				Some(_) => {}
+				None => return Err(io::ErrorKind::UnexpectedEof.into()),
+			},
+		}
+	}
+}
@@ -0,0 +1,323 @@
+use std::{
+	fs, io,
+	net::IpAddr,
+	path::{Path, PathBuf},
+	sync::{Arc, RwLock},
+	time::Duration,
+};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use tokio::{net::lookup_host, time::Instant};
+use tracing::*;
+
+use super::score;
+
+const RESOLVE_TIMEOUT_MS: u64 = 1000;
+/// How long a host's ASNs are remembered, including hosts that couldn't be resolved.
+const CACHE_SECS: u64 = 60 * 60;
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+/// How often the database file is checked for changes.
+const RELOAD_INTERVAL_SECS: u64 = 5 * 60;
+
+/// `[asn]` in the config file: the networks actor hosts live in, since spam instances cluster on
+/// a few bulletproof hosters.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AsnConfig {
+	/// IP-to-ASN database in the TSV format of iptoasn.com: range start, range end, AS number,
+	/// and whatever else on each line. Read again whenever it's replaced.
+	pub database: PathBuf,
+	/// AS numbers to reject deliveries from.
+	#[serde(default)]
+	pub block: Vec<u32>,
+	/// AS numbers whose deliveries raise an `asn` signal.
+	#[serde(default)]
+	pub suspect: Vec<u32>,
+	/// Weight of the `asn` signal for suspect networks.
+	#[serde(default = "default_weight")]
+	pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+	score::WEAK
+}
+
+impl AsnConfig {
+	pub fn validate(&self) -> Result<(), String> {
+		if !self.database.is_file() {
+			return Err(format!("asn database {} is not a file", self.database.display()));
+		}
+		if self.block.is_empty() && self.suspect.is_empty() {
+			return Err("asn needs AS numbers to block or suspect".to_string());
+		}
+		Ok(())
+	}
+}
+
+/// What to make of the network a host lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+	Fine,
+	/// In a suspect network, with its AS number.
+	Suspect(u32),
+	/// In a blocked network, with its AS number.
+	Blocked(u32),
+}
+
+/// Address ranges and the AS announcing them, sorted by range start.
+#[derive(Debug, Default)]
+struct Table {
+	v4: Vec<(u32, u32, u32)>,
+	v6: Vec<(u128, u128, u32)>,
+}
+
+impl Table {
+	fn load(path: &Path) -> io::Result<Self> {
+		let mut table = Table::default();
+		for line in fs::read_to_string(path)?.lines() {
+			let mut fields = line.split('\t');
+			let (Some(start), Some(end), Some(asn)) = (fields.next(), fields.next(), fields.next())
+			else {
+				continue;
+			};
+			let (Ok(start), Ok(end), Ok(asn)) =
+				(start.parse::<IpAddr>(), end.parse::<IpAddr>(), asn.parse::<u32>())
+			else {
+				continue;
+			};
+			// AS 0 marks space nobody announces
+			match (start, end, asn) {
+				(_, _, 0) => {}
+				(IpAddr::V4(start), IpAddr::V4(end), _) => {
+					table.v4.push((start.into(), end.into(), asn));
+				}
+				(IpAddr::V6(start), IpAddr::V6(end), _) => {
+					table.v6.push((start.into(), end.into(), asn));
+				}
+				_ => {}
+			}
+		}
+		if table.v4.is_empty() && table.v6.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "no ranges"));
+		}
+		table.v4.sort_unstable();
+		table.v6.sort_unstable();
+		Ok(table)
+	}
+
+	fn asn(&self, ip: IpAddr) -> Option<u32> {
+		fn find<T: Ord + Copy>(ranges: &[(T, T, u32)], ip: T) -> Option<u32> {
+			let i = ranges.partition_point(|(start, _, _)| *start <= ip).checked_sub(1)?;
+			let (_, end, asn) = ranges[i];
+			(ip <= end).then_some(asn)
+		}
+		match ip {
+			IpAddr::V4(ip) => find(&self.v4, ip.into()),
+			IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+				Some(ip) => find(&self.v4, ip.into()),
+				None => find(&self.v6, ip.into()),
+			},
+		}
+	}
+}
+
+/// Resolves actor hosts and judges them by the networks they live in. Fails open: a host that
+/// doesn't resolve, or isn't in the database, is fine.
+#[derive(Debug, Clone)]
+pub struct Asns {
+	config: Arc<AsnConfig>,
+	table: Arc<RwLock<Arc<Table>>>,
+	hosts: Arc<DashMap<String, (Instant, Vec<u32>)>>,
+}
+
+impl Asns {
+	pub fn new(config: AsnConfig) -> Self {
+		let table = match Table::load(&config.database) {
+			Ok(table) => table,
+			Err(e) => {
+				warn!("Could not load ASN database {}: {}", config.database.display(), e);
+				Table::default()
+			}
+		};
+		let asns = Asns {
+			config: Arc::new(config),
+			table: Arc::new(RwLock::new(Arc::new(table))),
+			hosts: Arc::new(DashMap::new()),
+		};
+
+		let hosts = asns.hosts.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				let expiry = Duration::from_secs(CACHE_SECS);
+				hosts.retain(|_, (resolved, _)| resolved.elapsed() < expiry);
+			}
+		});
+		tokio::spawn(asns.clone().watch());
+
+		asns
+	}
+
+	/// Weight of the `asn` signal for suspect networks.
+	pub fn weight(&self) -> u32 {
+		self.config.weight
+	}
+
+	/// Judge `host` by the networks its addresses are in.
+	pub async fn check(&self, host: &str) -> Verdict {
+		let asns = self.resolve(host).await;
+		if let Some(asn) = asns.iter().find(|asn| self.config.block.contains(asn)) {
+			return Verdict::Blocked(*asn);
+		}
+		if let Some(asn) = asns.iter().find(|asn| self.config.suspect.contains(asn)) {
+			return Verdict::Suspect(*asn);
+		}
+		Verdict::Fine
+	}
+
+	/// AS numbers of the addresses `host` resolves to.
+	async fn resolve(&self, host: &str) -> Vec<u32> {
+		if let Some(cached) = self.hosts.get(host) {
+			if cached.0.elapsed() < Duration::from_secs(CACHE_SECS) {
+				return cached.1.clone();
+			}
+		}
+		let limit = Duration::from_millis(RESOLVE_TIMEOUT_MS);
+		let addresses = match tokio::time::timeout(limit, lookup_host((host, 443))).await {
+			Ok(Ok(addresses)) => addresses.map(|a| a.ip()).collect(),
+			Ok(Err(e)) => {
+				debug!("Could not resolve {}: {}", host, e);
+				Vec::new()
+			}
+			Err(_) => {
+				debug!("Timed out resolving {}", host);
+				Vec::new()
+			}
+		};
+		let asns = {
+			#[allow(clippy::unwrap_used)]
+			let table = self.table.read().unwrap().clone();
+			let mut asns: Vec<u32> = addresses.into_iter().filter_map(|ip| table.asn(ip)).collect();
+			asns.sort_unstable();
+			asns.dedup();
+			asns
+		};
+		self.hosts.insert(host.to_string(), (Instant::now(), asns.clone()));
+		asns
+	}
+
+	/// Load the database again whenever the file changes, like when a cron job fetches a new
+	/// one.
+	async fn watch(self) {
+		let path = self.config.database.clone();
+		let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+		let mut loaded = modified(&path);
+		let mut interval = tokio::time::interval(Duration::from_secs(RELOAD_INTERVAL_SECS));
+		interval.tick().await;
+		loop {
+			interval.tick().await;
+			let now = modified(&path);
+			if now.is_none() || now == loaded {
+				continue;
+			}
+			loaded = now;
+			let load = {
+				let path = path.clone();
+				tokio::task::spawn_blocking(move || Table::load(&path))
+			};
+			match load.await {
+				Ok(Ok(table)) => {
+					info!(
+						"Reloaded ASN database {} ({} ranges)",
+						path.display(),
+						table.v4.len() + table.v6.len()
+					);
+					#[allow(clippy::unwrap_used)]
+					let mut current = self.table.write().unwrap();
+					*current = Arc::new(table);
+					drop(current);
+					// hosts were judged by the old one
+					self.hosts.clear();
+				}
+				Ok(Err(e)) => warn!("Could not reload ASN database {}: {}", path.display(), e),
+				Err(e) => warn!("Could not reload ASN database {}: {}", path.display(), e),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const DATABASE: &str = "\
+		1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+		1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n\
+		1.0.4.0\t1.0.7.255\t38803\tAU\tWPL-AS-AP\n\
+		127.0.0.0\t127.255.255.255\t64500\tZZ\tLOOPBACK\n\
+		not\tan\tentry\n\
+		2001:db8::\t2001:db8::ffff\t64501\tZZ\tDOCUMENTATION\n";
+
+	/// The database written to a file of its own.
+	fn database(name: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("musubi-asn-{}-{}", std::process::id(), name));
+		#[allow(clippy::unwrap_used)]
+		fs::write(&path, DATABASE).unwrap();
+		path
+	}
+
+	#[test]
+	fn looks_up_ranges() {
+		let path = database("ranges");
+		let table = Table::load(&path);
+		fs::remove_file(&path).ok();
+		let Ok(table) = table else {
+			panic!("database didn't load");
+		};
+		let asn = |ip: &str| ip.parse().ok().and_then(|ip| table.asn(ip));
+
+		assert_eq!(asn("1.0.0.0"), Some(13335));
+		assert_eq!(asn("1.0.0.255"), Some(13335));
+		assert_eq!(asn("1.0.5.1"), Some(38803));
+		assert_eq!(asn("2001:db8::1"), Some(64501));
+		assert_eq!(asn("::ffff:1.0.4.1"), Some(38803));
+		// AS 0, gaps, and beyond the ends
+		assert_eq!(asn("1.0.2.1"), None);
+		assert_eq!(asn("1.0.8.0"), None);
+		assert_eq!(asn("0.255.255.255"), None);
+		assert_eq!(asn("255.255.255.255"), None);
+		assert_eq!(asn("2001:db8::1:0"), None);
+	}
+
+	#[test]
+	fn refuses_databases_without_ranges() {
+		let path = std::env::temp_dir().join(format!("musubi-asn-{}-empty", std::process::id()));
+		fs::write(&path, "not\tan\tentry\n").ok();
+		let table = Table::load(&path);
+		fs::remove_file(&path).ok();
+		assert!(table.is_err());
+	}
+
+	#[tokio::test]
+	async fn judges_hosts_by_network() {
+		let path = database("judge");
+		let config = |block: Vec<u32>, suspect: Vec<u32>| AsnConfig {
+			database: path.clone(),
+			block,
+			suspect,
+			weight: score::WEAK,
+		};
+		let blocking = Asns::new(config(vec![64500], vec![]));
+		let suspecting = Asns::new(config(vec![13335], vec![64500]));
+		let ignoring = Asns::new(config(vec![13335], vec![38803]));
+		fs::remove_file(&path).ok();
+
+		assert_eq!(blocking.check("127.0.0.1").await, Verdict::Blocked(64500));
+		assert_eq!(suspecting.check("127.0.0.1").await, Verdict::Suspect(64500));
+		assert_eq!(ignoring.check("127.0.0.1").await, Verdict::Fine);
+		// addresses that aren't in the database at all
+		assert_eq!(blocking.check("10.0.0.1").await, Verdict::Fine);
+	}
+}
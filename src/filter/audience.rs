@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+
+use sonic_rs::{JsonValueTrait, Value};
+
+use super::uris;
+
+const PUBLIC_COLLECTIONS: [&str; 3] =
+	["https://www.w3.org/ns/activitystreams#Public", "as:Public", "Public"];
+
+/// Number of distinct recipients a note addresses directly in `to` and `cc`.
+///
+/// The public collection and followers collections are not counted, so this is roughly the
+/// number of people being mentioned or DMed.
+pub fn audience_size(ap_json: &Value) -> usize {
+	direct_recipients(ap_json).len()
+}
+
+/// Who can see a note, going by how it's addressed, the way Mastodon tells them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+	/// Addressed to the public in `to`: shows up on public timelines.
+	Public,
+	/// Addressed to the public in `cc` only: visible to anyone, but kept off public timelines.
+	Unlisted,
+	/// Addressed to followers collections but not the public.
+	Followers,
+	/// Addressed to people only, like a DM.
+	Direct,
+}
+
+impl Visibility {
+	/// Name rules compare `audience.visibility` with.
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Visibility::Public => "public",
+			Visibility::Unlisted => "unlisted",
+			Visibility::Followers => "followers",
+			Visibility::Direct => "direct",
+		}
+	}
+}
+
+/// Who can see a note. Notes addressed to nobody count as direct, like Mastodon has it.
+pub fn visibility(ap_json: &Value) -> Visibility {
+	let is_public = |uri: &&str| PUBLIC_COLLECTIONS.contains(uri);
+	if addressed_in(ap_json, "to").iter().any(is_public) {
+		Visibility::Public
+	} else if addressed_in(ap_json, "cc").iter().any(is_public) {
+		Visibility::Unlisted
+	} else if addressed(ap_json).iter().any(|uri| is_collection(uri)) {
+		Visibility::Followers
+	} else {
+		Visibility::Direct
+	}
+}
+
+/// Whether a note is addressed to people only, neither to the public nor to anyone's followers,
+/// like a DM.
+pub fn is_direct(ap_json: &Value) -> bool {
+	!addressed(ap_json).is_empty() && visibility(ap_json) == Visibility::Direct
+}
+
+/// Recipients a note addresses directly in `to` and `cc`.
+pub fn direct_recipients(ap_json: &Value) -> HashSet<&str> {
+	addressed(ap_json).into_iter().filter(|uri| !is_collection(uri)).collect()
+}
+
+/// Everyone and everything in `to` and `cc`, of the note, or of the activity if the note has
+/// none.
+fn addressed(ap_json: &Value) -> Vec<&str> {
+	let mut addressed = addressed_in(ap_json, "to");
+	addressed.extend(addressed_in(ap_json, "cc"));
+	addressed
+}
+
+/// Everyone and everything in `field` of the note, or of the activity if the note has none.
+fn addressed_in<'a>(ap_json: &'a Value, field: &str) -> Vec<&'a str> {
+	let object = ap_json.get("object");
+	object.and_then(|o| o.get(field)).or_else(|| ap_json.get(field)).map(uris).unwrap_or_default()
+}
+
+fn is_collection(uri: &str) -> bool {
+	PUBLIC_COLLECTIONS.contains(&uri) || uri.trim_end_matches('/').ends_with("/followers")
+}
@@ -0,0 +1,58 @@
+use std::collections::HashSet;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use sonic_rs::{JsonContainerTrait, Value};
+
+use super::{append_header, strip_headers};
+
+/// What to do about delivered JSON the AP server's parser might read differently than ours.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CanonicalBody {
+	/// Forward bodies as they came.
+	#[default]
+	Off,
+	/// Reject bodies with an object key given twice, which parsers resolve differently: ours
+	/// goes by the first, most others by the last.
+	Check,
+	/// Reject those too, and forward the body as we parsed it, written out again, instead of
+	/// the sender's bytes. This breaks signatures covering the body's digest.
+	Rewrite,
+}
+
+/// A key given twice in the same object, anywhere in `value`.
+pub fn duplicate_key(value: &Value) -> Option<&str> {
+	if let Some(object) = value.as_object() {
+		let mut keys = HashSet::new();
+		for (key, value) in object.iter() {
+			if !keys.insert(key) {
+				return Some(key);
+			}
+			if let Some(key) = duplicate_key(value) {
+				return Some(key);
+			}
+		}
+	} else if let Some(array) = value.as_array() {
+		return array.iter().find_map(duplicate_key);
+	}
+	None
+}
+
+/// Write `value` out again as the body to forward, and fix up the Content-Length and Digest
+/// headers for it.
+pub fn rewrite(header: &mut Vec<u8>, value: &Value) -> sonic_rs::Result<Vec<u8>> {
+	let body = sonic_rs::to_vec(value)?;
+	let digested = header
+		.split(|&b| b == b'\n')
+		.skip(1)
+		.any(|line| line.len() >= 7 && line[..7].eq_ignore_ascii_case(b"digest:"));
+	strip_headers(header, b"content-length:");
+	append_header(header, "Content-Length", &body.len().to_string());
+	if digested {
+		strip_headers(header, b"digest:");
+		let digest = format!("SHA-256={}", BASE64.encode(Sha256::digest(&body)));
+		append_header(header, "Digest", &digest);
+	}
+	Ok(body)
+}
@@ -0,0 +1,82 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use sonic_rs::{JsonValueTrait, Value};
+use tokio::time::timeout;
+use tracing::*;
+use url::Url;
+
+use crate::http::{self, HttpError};
+
+const DEFAULT_TIMEOUT_MS: u64 = 500;
+const ACTIVITY_JSON: &str = "application/activity+json";
+
+/// `[classifier]` in the config file: an external service to ask for a spam score, like an
+/// rspamd-style filter or an ML model behind an HTTP endpoint.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClassifierConfig {
+	/// HTTP(S) URL the activity is POSTed to. It answers with `{"score": <number>}`.
+	pub url: String,
+	/// Milliseconds to wait for an answer before letting the note through without one.
+	#[serde(default = "default_timeout_ms")]
+	pub timeout_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+	DEFAULT_TIMEOUT_MS
+}
+
+impl ClassifierConfig {
+	/// Check the URL at startup rather than on the first note.
+	pub fn validate(&self) -> Result<(), String> {
+		let url = Url::parse(&self.url).map_err(|e| format!("invalid classifier url: {}", e))?;
+		if !http::supports(&url) {
+			return Err("classifier url must be an http:// or https:// URL".to_string());
+		}
+		if self.timeout_ms == 0 {
+			return Err("classifier timeout_ms must be at least 1".to_string());
+		}
+		Ok(())
+	}
+
+	/// Port the classifier is reached on.
+	pub fn url_port(&self) -> Option<u16> {
+		Url::parse(&self.url).ok()?.port_or_known_default()
+	}
+}
+
+/// Asks the external classifier to score notes. Fails open: a classifier that's down, slow or
+/// talking nonsense scores nothing, and the note is judged as if there was none.
+#[derive(Debug, Clone)]
+pub struct Classifier {
+	config: Arc<ClassifierConfig>,
+}
+
+impl Classifier {
+	pub fn new(config: ClassifierConfig) -> Self {
+		Classifier { config: Arc::new(config) }
+	}
+
+	/// Spam score the classifier gives the activity in `body`, if it gave one in time.
+	pub async fn score(&self, body: &[u8]) -> Option<u32> {
+		let limit = Duration::from_millis(self.config.timeout_ms);
+		let score = match timeout(limit, self.ask(body)).await {
+			Ok(score) => score,
+			Err(e) => Err(e.into()),
+		};
+		score.inspect_err(|e| warn!("Classifier gave no score: {}", e)).ok()
+	}
+
+	async fn ask(&self, body: &[u8]) -> Result<u32, HttpError> {
+		let url = &self.config.url;
+		let url = Url::parse(url).map_err(|_| HttpError::NoHost(url.clone()))?;
+		let body = String::from_utf8_lossy(body);
+		let response = http::post(&url, ACTIVITY_JSON, &[], &body).await?;
+		let response: Value =
+			sonic_rs::from_str(&response).map_err(|_| HttpError::MalformedResponse)?;
+		let score = response.get("score").and_then(|s| s.as_f64());
+		let score = score.filter(|s| s.is_finite()).ok_or(HttpError::MalformedResponse)?;
+		Ok(score.round().clamp(0.0, f64::from(u32::MAX)) as u32)
+	}
+}
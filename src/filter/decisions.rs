@@ -0,0 +1,99 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+
+use super::Marks;
+use crate::{
+	cache::{self, CacheStats},
+	upstream::Address,
+};
+
+/// Rough size of a verdict with its key and signals.
+const ENTRY_BYTES: usize = 256;
+
+/// Verdicts on notes judged recently, so the same note delivered to many inboxes, or retried,
+/// is scored once instead of going through every check and DB query again.
+#[derive(Debug, Clone)]
+pub struct DecisionCache {
+	ttl: Duration,
+	max_entries: usize,
+	decisions: Arc<DashMap<String, (Instant, Decision)>>,
+	stats: Arc<CacheStats>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Decision {
+	/// Let through, with the signals found before any rule acted on them. Rules, throttles and
+	/// the score threshold are applied to them again on every delivery.
+	Accept(Marks),
+	/// Rejected as spam.
+	Reject,
+}
+
+impl DecisionCache {
+	/// Remember verdicts for `ttl`, or nothing if it's zero, in at most `budget_mb` MiB.
+	pub fn new(ttl: Duration, budget_mb: Option<u64>) -> Self {
+		let cache = DecisionCache {
+			ttl,
+			max_entries: cache::max_entries(budget_mb, ENTRY_BYTES),
+			decisions: Arc::new(DashMap::new()),
+			stats: CacheStats::register("decisions", ENTRY_BYTES),
+		};
+		if ttl.is_zero() {
+			return cache;
+		}
+
+		let decisions = cache.decisions.clone();
+		let stats = cache.stats.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(ttl);
+			loop {
+				interval.tick().await;
+				decisions.retain(|_, (at, _)| at.elapsed() < ttl);
+				stats.set_entries(decisions.len());
+			}
+		});
+
+		cache
+	}
+
+	/// Key of a note from `actor` for the AP server at `upstream`. The whole body is hashed, so
+	/// only identical deliveries share a verdict.
+	pub fn key(upstream: &Address, actor: &str, body: &[u8]) -> String {
+		let mut hasher = Sha256::new();
+		hasher.update(upstream.to_string());
+		hasher.update(b"\n");
+		hasher.update(actor);
+		hasher.update(b"\n");
+		hasher.update(body);
+		format!("{:x}", hasher.finalize())
+	}
+
+	pub fn get(&self, key: &str) -> Option<Decision> {
+		let found = self.decisions.get(key).and_then(|entry| {
+			let (at, decision) = entry.value();
+			(at.elapsed() < self.ttl).then(|| decision.clone())
+		});
+		self.stats.count(found)
+	}
+
+	pub fn insert(&self, key: String, decision: Decision) {
+		if self.ttl.is_zero() {
+			return;
+		}
+		if self.decisions.len() >= self.max_entries {
+			self.decisions.retain(|_, (at, _)| at.elapsed() < self.ttl);
+			cache::make_room(&self.decisions, self.max_entries, &self.stats, |(at, _)| *at);
+		}
+		self.decisions.insert(key, (Instant::now(), decision));
+		self.stats.set_entries(self.decisions.len());
+	}
+
+	/// Forget every verdict, e.g. after the rules changed.
+	pub fn clear(&self) {
+		self.decisions.clear();
+		self.stats.set_entries(0);
+	}
+}
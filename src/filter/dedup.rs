@@ -0,0 +1,105 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+
+use crate::{
+	cache::{self, CacheStats},
+	upstream::Address,
+};
+
+/// Rough size of a remembered delivery: its hash and when it was seen.
+const ENTRY_BYTES: usize = 128;
+
+/// Deliveries forwarded recently, so exact repeats of them, like replay floods and senders
+/// stuck retrying, are dropped instead of reaching the AP server again.
+#[derive(Debug, Clone)]
+pub struct SeenDeliveries {
+	ttl: Duration,
+	max_entries: usize,
+	seen: Arc<DashMap<String, Instant>>,
+	stats: Arc<CacheStats>,
+}
+
+impl SeenDeliveries {
+	/// Remember deliveries for `ttl`, or nothing if it's zero, in at most `budget_mb` MiB.
+	pub fn new(ttl: Duration, budget_mb: Option<u64>) -> Self {
+		let seen = SeenDeliveries {
+			ttl,
+			max_entries: cache::max_entries(budget_mb, ENTRY_BYTES),
+			seen: Arc::new(DashMap::new()),
+			stats: CacheStats::register("dedup", ENTRY_BYTES),
+		};
+		if ttl.is_zero() {
+			return seen;
+		}
+
+		let entries = seen.seen.clone();
+		let stats = seen.stats.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(ttl);
+			loop {
+				interval.tick().await;
+				entries.retain(|_, at| at.elapsed() < ttl);
+				stats.set_entries(entries.len());
+			}
+		});
+
+		seen
+	}
+
+	/// Key of a delivery of an activity with an id to `upstream`. The request line, host and
+	/// whole body are hashed, so the same activity delivered to several inboxes isn't a repeat.
+	pub fn key(upstream: &Address, header: &[u8], host: Option<&str>, body: &[u8]) -> String {
+		let request_line = header.split(|&b| b == b'\r').next().unwrap_or_default();
+		let mut hasher = Sha256::new();
+		hasher.update(upstream.to_string());
+		hasher.update(b"\n");
+		hasher.update(request_line);
+		hasher.update(b"\n");
+		hasher.update(host.unwrap_or_default());
+		hasher.update(b"\n");
+		hasher.update(body);
+		format!("{:x}", hasher.finalize())
+	}
+
+	pub fn contains(&self, key: &str) -> bool {
+		let seen = self.seen.get(key).is_some_and(|at| at.elapsed() < self.ttl);
+		if seen {
+			self.stats.hit();
+		} else {
+			self.stats.miss();
+		}
+		seen
+	}
+
+	pub fn insert(&self, key: String) {
+		if self.ttl.is_zero() {
+			return;
+		}
+		if self.seen.len() >= self.max_entries {
+			self.seen.retain(|_, at| at.elapsed() < self.ttl);
+			cache::make_room(&self.seen, self.max_entries, &self.stats, |at| *at);
+		}
+		self.seen.insert(key, Instant::now());
+		self.stats.set_entries(self.seen.len());
+	}
+
+	/// Deliveries still remembered, with when they were forwarded.
+	pub fn entries(&self) -> Vec<(String, Instant)> {
+		self.seen
+			.iter()
+			.filter(|entry| entry.elapsed() < self.ttl)
+			.map(|entry| (entry.key().clone(), *entry.value()))
+			.collect()
+	}
+
+	/// Remember a delivery from [`SeenDeliveries::entries`] again.
+	pub fn restore(&self, key: String, at: Instant) {
+		if at.elapsed() < self.ttl {
+			self.seen.insert(key, at);
+			self.stats.set_entries(self.seen.len());
+		}
+	}
+}
@@ -0,0 +1,221 @@
+use std::{
+	collections::HashMap,
+	fmt::Write,
+	sync::{Arc, Mutex, MutexGuard},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use tracing::*;
+
+use super::{text::note_text, Filter};
+use crate::{
+	http::http_date,
+	reputation::{self, Subject},
+	smtp::{self, Message},
+};
+
+const DAY: u64 = 24 * 60 * 60;
+const DEFAULT_HOUR: u64 = 8;
+/// Instances and quarantined activities listed.
+const TOP: usize = 10;
+/// Origins counted per digest, so a wave from random domains can't grow the map without bound.
+const MAX_ORIGINS: usize = 100_000;
+/// Characters of a quarantined note quoted.
+const EXCERPT_LEN: usize = 120;
+
+/// `[digest]` in the config file: an email summing up what was filtered, for moderators who
+/// don't watch logs or dashboards.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DigestConfig {
+	/// SMTP server to send through, like `smtp://127.0.0.1:25`, or `smtps://` for TLS from the
+	/// start, with `user:password@` if it wants a login.
+	pub smtp: String,
+	pub from: String,
+	pub to: Vec<String>,
+	#[serde(default)]
+	pub every: Period,
+	/// Hour of the day to send at, in UTC.
+	#[serde(default = "default_hour")]
+	pub hour: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+	#[default]
+	Day,
+	/// Sent on Mondays.
+	Week,
+}
+
+fn default_hour() -> u64 {
+	DEFAULT_HOUR
+}
+
+impl DigestConfig {
+	/// Check the server and addresses at startup rather than when the first digest is due.
+	pub fn validate(&self) -> Result<(), String> {
+		smtp::validate(&self.smtp).map_err(|e| format!("invalid digest smtp: {}", e))?;
+		let address = |a: &String| a.contains('@') && !a.contains(['\r', '\n', '<', '>', ',']);
+		if !address(&self.from) || self.to.is_empty() || !self.to.iter().all(address) {
+			return Err("digest from and to must be plain email addresses".to_string());
+		}
+		if self.hour > 23 {
+			return Err("digest hour must be 0 to 23".to_string());
+		}
+		Ok(())
+	}
+
+	/// Port the SMTP server is reached on.
+	pub fn smtp_port(&self) -> Option<u16> {
+		smtp::validate(&self.smtp).ok().map(|url| smtp::port(&url))
+	}
+}
+
+/// Decisions counted since the last digest.
+#[derive(Debug, Clone)]
+pub struct Digest {
+	config: Arc<DigestConfig>,
+	tally: Arc<Mutex<Tally>>,
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+	/// unix time in seconds
+	since: u64,
+	accepted: u64,
+	rejected: HashMap<&'static str, u64>,
+	origins: HashMap<String, u64>,
+}
+
+impl Digest {
+	pub fn new(config: DigestConfig) -> Self {
+		let tally = Tally { since: now(), ..Tally::default() };
+		Digest { config: Arc::new(config), tally: Arc::new(Mutex::new(tally)) }
+	}
+
+	/// Count a note let through.
+	pub fn accepted(&self) {
+		self.tally().accepted += 1;
+	}
+
+	/// Count a rejection of any kind.
+	pub fn rejected(&self, kind: &'static str, origin: Option<&str>) {
+		let mut tally = self.tally();
+		*tally.rejected.entry(kind).or_default() += 1;
+		let Some(origin) = origin else {
+			return;
+		};
+		let len = tally.origins.len();
+		match tally.origins.get_mut(origin) {
+			Some(count) => *count += 1,
+			None if len < MAX_ORIGINS => {
+				tally.origins.insert(origin.to_string(), 1);
+			}
+			None => {}
+		}
+	}
+
+	/// Send a digest of `filter`'s decisions every period, starting over the count each time.
+	pub async fn watch(self, filter: Filter) {
+		let Ok(server) = smtp::validate(&self.config.smtp) else {
+			return;
+		};
+		loop {
+			let at = now();
+			let next = next_send(at, self.config.every, self.config.hour);
+			tokio::time::sleep(Duration::from_secs(next - at)).await;
+
+			let fresh = Tally { since: now(), ..Tally::default() };
+			let tally = std::mem::replace(&mut *self.tally(), fresh);
+			let subject = match self.config.every {
+				Period::Day => "spam-musubi daily digest",
+				Period::Week => "spam-musubi weekly digest",
+			};
+			let body = report(&tally, &filter);
+			let message =
+				Message { from: &self.config.from, to: &self.config.to, subject, body: &body };
+			match smtp::send(&server, &message).await {
+				Ok(()) => info!("Sent digest to {}", self.config.to.join(", ")),
+				Err(e) => warn!("Could not send digest through {}: {}", server, e),
+			}
+		}
+	}
+
+	fn tally(&self) -> MutexGuard<'_, Tally> {
+		// counts are still good after a panic elsewhere
+		self.tally.lock().unwrap_or_else(|e| e.into_inner())
+	}
+}
+
+/// Unix time of the next digest after `now`: at `hour` UTC, on a Monday for weekly ones.
+fn next_send(now: u64, every: Period, hour: u64) -> u64 {
+	let mut next = now / DAY * DAY + hour * 3600;
+	if next <= now {
+		next += DAY;
+	}
+	// the unix epoch was a Thursday, so Mondays are 4 days on
+	while every == Period::Week && next / DAY % 7 != 4 {
+		next += DAY;
+	}
+	next
+}
+
+fn report(tally: &Tally, filter: &Filter) -> String {
+	let mut out = String::new();
+	let rejected: u64 = tally.rejected.values().sum();
+	let _ = writeln!(out, "Since {}:", http_date(tally.since));
+	let _ = writeln!(out);
+	let _ = writeln!(out, "{} notes accepted", tally.accepted);
+	let _ = writeln!(out, "{} deliveries rejected", rejected);
+	let mut kinds: Vec<_> = tally.rejected.iter().collect();
+	kinds.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+	for (kind, count) in kinds {
+		let _ = writeln!(out, "  {:>8}  {}", count, kind);
+	}
+
+	if !tally.origins.is_empty() {
+		let _ = writeln!(out);
+		let _ = writeln!(out, "Instances rejected the most:");
+		let mut origins: Vec<_> = tally.origins.iter().collect();
+		origins.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+		for (origin, count) in origins.into_iter().take(TOP) {
+			let _ = writeln!(out, "  {:>8}  {}", count, origin);
+		}
+	}
+
+	// held from actors with nothing against them but the hold itself, who may well be legitimate
+	let held = filter.quarantine().held();
+	let reputation = filter.reputation();
+	let mut candidates: Vec<_> = held
+		.iter()
+		.map(|held| (reputation.get(Subject::Actor, &held.actor), held))
+		.filter(|(score, _)| *score >= reputation::SPAM)
+		.collect();
+	candidates.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.id.cmp(&a.1.id)));
+	let _ = writeln!(out);
+	let _ = writeln!(out, "{} activities quarantined", held.len());
+	if !candidates.is_empty() {
+		let _ = writeln!(out, "Held from actors with nothing else against them:");
+	}
+	for (score, held) in candidates.into_iter().take(TOP) {
+		let _ = writeln!(
+			out,
+			"  #{} from {} by rule \"{}\", reputation {:.1}",
+			held.id, held.actor, held.rule, score
+		);
+		let text = sonic_rs::from_slice(&held.body).ok().and_then(|json| note_text(&json));
+		if let Some(text) = text.filter(|t| !t.is_empty()) {
+			let excerpt: String = text.chars().take(EXCERPT_LEN).collect();
+			let more = if excerpt.len() < text.len() { "..." } else { "" };
+			let _ = writeln!(out, "    {}{}", excerpt, more);
+		}
+	}
+	out
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,240 @@
+use std::{
+	path::PathBuf,
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tracing::*;
+use url::Url;
+
+use super::signature::Signed;
+use crate::http::{self, HttpError};
+
+const DEFAULT_AFTER: u32 = 50;
+const DEFAULT_WINDOW_HOURS: u64 = 24;
+
+/// `[domain_block]` in the config file: the local Mastodon's admin API, to limit instances that
+/// send a lot of spam inside the AP server too.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DomainBlockConfig {
+	/// HTTP(S) base URL of the Mastodon server, like `http://127.0.0.1:3000`.
+	pub api: String,
+	/// Access token with the `admin:write:domain_blocks` scope.
+	pub token: String,
+	/// Spam rejections from an instance within `window_hours` before it's blocked.
+	#[serde(default = "default_after")]
+	pub after: u32,
+	#[serde(default = "default_window_hours")]
+	pub window_hours: u64,
+	#[serde(default)]
+	pub severity: Severity,
+	/// Only write what would be done to the audit log, without calling the API.
+	#[serde(default = "default_dry_run")]
+	pub dry_run: bool,
+	/// JSONL file every block, or would-be block, is appended to.
+	pub audit_log: Option<PathBuf>,
+}
+
+/// How hard Mastodon limits the instance.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+	/// Hide its accounts from everyone not following them.
+	#[default]
+	Silence,
+	/// Drop everything from it.
+	Suspend,
+}
+
+impl Severity {
+	fn as_str(self) -> &'static str {
+		match self {
+			Severity::Silence => "silence",
+			Severity::Suspend => "suspend",
+		}
+	}
+}
+
+fn default_after() -> u32 {
+	DEFAULT_AFTER
+}
+
+fn default_window_hours() -> u64 {
+	DEFAULT_WINDOW_HOURS
+}
+
+fn default_dry_run() -> bool {
+	true
+}
+
+impl DomainBlockConfig {
+	/// Check the API URL at startup rather than on the first wave.
+	pub fn validate(&self) -> Result<(), String> {
+		let url = Url::parse(&self.api).map_err(|e| format!("invalid domain_block api: {}", e))?;
+		if !http::supports(&url) {
+			return Err("domain_block api must be an http:// or https:// URL".to_string());
+		}
+		if self.after == 0 || self.window_hours == 0 {
+			return Err("domain_block after and window_hours must be at least 1".to_string());
+		}
+		Ok(())
+	}
+
+	/// Port the API is reached on.
+	pub fn api_port(&self) -> Option<u16> {
+		Url::parse(&self.api).ok()?.port_or_known_default()
+	}
+}
+
+/// Entry of the audit log.
+#[derive(Debug, Serialize)]
+struct Audit<'a> {
+	ts: u64,
+	domain: &'a str,
+	severity: Severity,
+	/// Spam rejections that led to it.
+	spam: u32,
+	dry_run: bool,
+	/// `null` when the block was added, or would have been.
+	error: Option<String>,
+}
+
+/// Spam rejections counted per instance, to have Mastodon block the ones sending the most.
+#[derive(Debug, Clone)]
+pub struct DomainBlocker {
+	config: Arc<DomainBlockConfig>,
+	/// Spam rejections and the unix time the window started, per instance.
+	counts: Arc<DashMap<String, (u32, u64)>>,
+	/// Instances already blocked, or that would have been.
+	blocked: Arc<DashSet<String>>,
+}
+
+impl DomainBlocker {
+	pub fn new(config: DomainBlockConfig) -> Self {
+		let blocker = DomainBlocker {
+			config: Arc::new(config),
+			counts: Arc::new(DashMap::new()),
+			blocked: Arc::new(DashSet::new()),
+		};
+
+		let counts = blocker.counts.clone();
+		let window = blocker.config.window_hours * 3600;
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(3600));
+			loop {
+				interval.tick().await;
+				let now = now();
+				counts.retain(|_, (_, since)| now - *since < window);
+			}
+		});
+
+		blocker
+	}
+
+	/// Count a spam rejection from `host`, and block it once there are enough. Only deliveries
+	/// signed with a key of an actor on `host` count, so one instance can't forge its way to
+	/// another being blocked.
+	pub fn rejected(&self, host: &str, signed: Signed) {
+		if self.blocked.contains(host) {
+			return;
+		}
+		let blocker = self.clone();
+		let host = host.to_string();
+		tokio::spawn(async move {
+			let signer = signed.signer().await;
+			let signer_host = signer.as_deref().and_then(|s| Url::parse(s).ok());
+			if signer_host.as_ref().and_then(|u| u.host_str()) != Some(host.as_str()) {
+				debug!("Not counting spam from {} toward blocking it, not signed there", host);
+				return;
+			}
+			blocker.count(&host).await;
+		});
+	}
+
+	async fn count(&self, host: &str) {
+		let window = self.config.window_hours * 3600;
+		let now = now();
+		let count = {
+			let mut entry = self.counts.entry(host.to_string()).or_insert((0, now));
+			if now - entry.1 >= window {
+				*entry = (0, now);
+			}
+			entry.0 += 1;
+			entry.0
+		};
+		if count < self.config.after || !self.blocked.insert(host.to_string()) {
+			return;
+		}
+		self.counts.remove(host);
+
+		let config = &self.config;
+		let severity = config.severity.as_str();
+		let error = if config.dry_run {
+			info!("Would have Mastodon {} {} after {} rejections", severity, host, count);
+			None
+		} else {
+			match block(config, host, count).await {
+				Ok(()) => {
+					warn!("Had Mastodon {} {} after {} rejections", severity, host, count);
+					None
+				}
+				Err(e) => {
+					error!("Could not have Mastodon block {}: {}", host, e);
+					Some(e.to_string())
+				}
+			}
+		};
+		let audit = Audit {
+			ts: now,
+			domain: host,
+			severity: config.severity,
+			spam: count,
+			dry_run: config.dry_run,
+			error,
+		};
+		if let Err(e) = write_audit(config, &audit).await {
+			warn!("Could not write to the domain block audit log: {}", e);
+		}
+	}
+}
+
+async fn block(config: &DomainBlockConfig, host: &str, spam: u32) -> Result<(), HttpError> {
+	let url = Url::parse(&format!("{}/", config.api.trim_end_matches('/')))
+		.and_then(|base| base.join("api/v1/admin/domain_blocks"))
+		.map_err(|_| HttpError::NoHost(config.api.clone()))?;
+	let comment = format!(
+		"{} spam rejections within {} hours by spam-musubi",
+		spam, config.window_hours
+	);
+	let body = sonic_rs::json!({
+		"domain": host,
+		"severity": config.severity.as_str(),
+		"private_comment": comment.as_str(),
+	});
+	let authorization = format!("Bearer {}", config.token);
+	let headers = [
+		("Authorization", authorization.as_str()),
+		// spares the redirect to HTTPS Mastodon answers plain requests with
+		("X-Forwarded-Proto", "https"),
+	];
+	http::post_json(&url, &headers, &body.to_string()).await?;
+	Ok(())
+}
+
+async fn write_audit(config: &DomainBlockConfig, audit: &Audit<'_>) -> std::io::Result<()> {
+	let Some(path) = &config.audit_log else {
+		return Ok(());
+	};
+	let mut line = sonic_rs::to_string(audit).map_err(std::io::Error::other)?;
+	line.push('\n');
+	let mut file = OpenOptions::new().create(true).append(true).open(path).await?;
+	file.write_all(line.as_bytes()).await
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,59 @@
+use sonic_rs::{JsonContainerTrait, JsonValueTrait, Value};
+
+use super::text;
+
+/// Emoji in a note's text.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Emoji {
+	/// Custom emoji, by their `:shortcode:` from `object.tag`, and unicode emoji.
+	pub count: usize,
+	/// Share of the text's visible characters that are emoji, in percent. Mentions don't count
+	/// as text, and a custom emoji counts as one character.
+	pub percent: u32,
+}
+
+pub fn emoji(ap_json: &Value) -> Emoji {
+	let Some(text) = text::note_text(ap_json) else {
+		return Emoji::default();
+	};
+	let shortcodes: Vec<&str> = ap_json
+		.get("object")
+		.and_then(|o| o.get("tag"))
+		.and_then(|t| t.as_array())
+		.map(|tags| {
+			tags.iter()
+				.filter(|t| t.get("type").and_then(|t| t.as_str()) == Some("Emoji"))
+				.filter_map(|t| t.get("name").and_then(|n| n.as_str()))
+				.filter(|n| n.len() > 2 && n.starts_with(':') && n.ends_with(':'))
+				.collect()
+		})
+		.unwrap_or_default();
+
+	let (mut count, mut other) = (0, 0);
+	for word in text.split_whitespace().filter(|w| !w.starts_with('@')) {
+		let mut word = word.to_string();
+		for shortcode in &shortcodes {
+			count += word.matches(shortcode).count();
+			word = word.replace(shortcode, "");
+		}
+		for c in word.chars().filter(|&c| !is_modifier(c)) {
+			if is_emoji(c) {
+				count += 1;
+			} else {
+				other += 1;
+			}
+		}
+	}
+	let percent = if count == 0 { 0 } else { (count * 100 / (count + other)) as u32 };
+	Emoji { count, percent }
+}
+
+/// Pictographs, symbols and flag letters, roughly what renders as emoji.
+fn is_emoji(c: char) -> bool {
+	matches!(c, '\u{1F000}'..='\u{1FAFF}' | '\u{2600}'..='\u{27BF}' | '\u{2B50}' | '\u{2B55}')
+}
+
+/// Characters that only change how the emoji before them looks, or join them into one.
+fn is_modifier(c: char) -> bool {
+	matches!(c, '\u{200D}' | '\u{FE0F}' | '\u{1F3FB}'..='\u{1F3FF}' | '\u{E0020}'..='\u{E007F}')
+}
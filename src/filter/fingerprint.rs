@@ -0,0 +1,71 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use sonic_rs::Value;
+use tokio::time::Instant;
+
+use super::text;
+
+/// How long a spam fingerprint is remembered after it was last seen.
+const TTL_SECS: u64 = 7 * 24 * 3600;
+const CLEANUP_INTERVAL_SECS: u64 = 3600;
+
+/// Hash of the text of `object.content`, lowercased, so copies of the same spam match regardless
+/// of case, whitespace and markup.
+pub fn content_fingerprint(ap_json: &Value) -> Option<String> {
+	let normalized = text::note_text(ap_json)?.to_lowercase();
+	if normalized.is_empty() {
+		return None;
+	}
+	Some(format!("{:x}", Sha256::digest(normalized.as_bytes())))
+}
+
+/// Fingerprints of content known to be spam.
+#[derive(Debug, Clone)]
+pub struct Fingerprints {
+	seen: Arc<DashMap<String, Instant>>,
+}
+
+impl Fingerprints {
+	#[allow(clippy::new_without_default)] // spawns a cleanup task
+	pub fn new() -> Self {
+		let fingerprints = Fingerprints { seen: Arc::new(DashMap::new()) };
+
+		let seen = fingerprints.seen.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				seen.retain(|_, at| at.elapsed() < Duration::from_secs(TTL_SECS));
+			}
+		});
+
+		fingerprints
+	}
+
+	pub fn insert(&self, fingerprint: &str) {
+		self.seen.insert(fingerprint.to_string(), Instant::now());
+	}
+
+	/// Forget a fingerprint, and return whether it was known.
+	pub fn remove(&self, fingerprint: &str) -> bool {
+		self.seen.remove(fingerprint).is_some()
+	}
+
+	pub fn contains(&self, fingerprint: &str) -> bool {
+		self.seen.contains_key(fingerprint)
+	}
+
+	/// Every known fingerprint, with when it was last seen.
+	pub fn entries(&self) -> Vec<(String, Instant)> {
+		self.seen.iter().map(|entry| (entry.key().clone(), *entry.value())).collect()
+	}
+
+	/// Put back a fingerprint from [`Fingerprints::entries`], unless it has expired since.
+	pub fn restore(&self, fingerprint: String, at: Instant) {
+		if at.elapsed() < Duration::from_secs(TTL_SECS) {
+			self.seen.insert(fingerprint, at);
+		}
+	}
+}
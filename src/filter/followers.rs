@@ -0,0 +1,139 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use sonic_rs::JsonValueTrait;
+use tokio::time::Instant;
+use tracing::*;
+use url::Url;
+
+use super::remote::{self, Remote};
+use crate::http::HttpError;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_MAX_PER_MINUTE: u32 = 30;
+/// How long a count from an actor's instance is used for.
+const COUNTED_SECS: u64 = 6 * 60 * 60;
+/// How long an actor whose instance wouldn't say is left alone.
+const UNCOUNTED_SECS: u64 = 60 * 60;
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+const ACTIVITY_JSON: &str = "application/activity+json";
+
+/// `[followers]` in the config file: ask the instances of actors with only a handful of followers
+/// here how many they have there.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FollowersConfig {
+	/// HTTP proxy to send requests through, with the instance in the Host header, like nginx
+	/// with `proxy_pass https://$host`. Instances are asked over HTTPS directly without one.
+	#[serde(default)]
+	pub via: Option<String>,
+	/// Milliseconds to wait for both answers before going by the AP server's count.
+	#[serde(default = "default_timeout_ms")]
+	pub timeout_ms: u64,
+	/// Lookups per instance per minute. Past that, actors from it go by the AP server's count.
+	#[serde(default = "default_max_per_minute")]
+	pub max_per_minute: u32,
+}
+
+fn default_timeout_ms() -> u64 {
+	DEFAULT_TIMEOUT_MS
+}
+
+fn default_max_per_minute() -> u32 {
+	DEFAULT_MAX_PER_MINUTE
+}
+
+impl FollowersConfig {
+	/// Check the settings at startup rather than on the first note.
+	pub fn validate(&self) -> Result<(), String> {
+		remote::validate("followers", self.via.as_deref(), self.timeout_ms, self.max_per_minute)
+	}
+
+	/// Port connections go out to: the proxy's, or HTTPS on the instances.
+	pub fn connect_port(&self) -> Option<u16> {
+		remote::connect_port(self.via.as_deref())
+	}
+}
+
+/// Reads the `totalItems` of actors' followers collections on their instances, for actors the
+/// AP server counts only a handful of followers for, which may be stale for accounts popular
+/// elsewhere but new here. Fails open: an instance that's down, slow, hides the count, or was
+/// asked too often already leaves the AP server's count as it is.
+#[derive(Debug, Clone)]
+pub struct Followers {
+	/// Actors looked up, what their instance said, and when.
+	actors: Arc<DashMap<String, (Instant, Option<i32>)>>,
+	remote: Remote,
+}
+
+impl Followers {
+	pub fn new(config: FollowersConfig) -> Self {
+		let followers = Followers {
+			actors: Arc::new(DashMap::new()),
+			remote: Remote::new(config.via.as_deref(), config.timeout_ms, config.max_per_minute),
+		};
+
+		let actors = followers.actors.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				actors.retain(|_, (checked, count)| !expired(*checked, *count));
+			}
+		});
+
+		followers
+	}
+
+	/// Followers the instance of `actor`, at `host`, counts for it, if it says.
+	pub async fn count(&self, actor: &str, host: &str) -> Option<i32> {
+		if let Some(cached) = self.actors.get(actor) {
+			let (checked, count) = *cached;
+			if !expired(checked, count) {
+				return count;
+			}
+		}
+		if !self.remote.allow(host) {
+			debug!("Too many follower lookups on {}, not counting for {}", host, actor);
+			return None;
+		}
+
+		let lookup = self.remote.limit(self.lookup(actor, host)).await;
+		let count = match lookup {
+			Ok(count) => count,
+			Err(e) => {
+				debug!("Could not count followers of {}: {}", actor, e);
+				return None;
+			}
+		};
+		self.actors.insert(actor.to_string(), (Instant::now(), count));
+		count
+	}
+
+	/// Fetch the actor document, then the followers collection it names, which has to be on the
+	/// same instance, and read its size. Instances hiding it answer without `totalItems`.
+	async fn lookup(&self, actor: &str, host: &str) -> Result<Option<i32>, HttpError> {
+		let actor = Url::parse(actor).map_err(|_| HttpError::NoHost(actor.to_string()))?;
+		let document = self.remote.fetch(&actor, ACTIVITY_JSON).await?;
+		let followers = match document.get("followers") {
+			Some(followers) if followers.is_str() => followers.as_str(),
+			Some(followers) => followers.get("id").and_then(|id| id.as_str()),
+			None => None,
+		};
+		let Some(followers) = followers.and_then(|f| Url::parse(f).ok()) else {
+			return Ok(None);
+		};
+		if followers.host_str() != Some(host) {
+			return Ok(None);
+		}
+		let collection = self.remote.fetch(&followers, ACTIVITY_JSON).await?;
+		let total = collection.get("totalItems").and_then(|t| t.as_i64());
+		Ok(total.map(|t| t.clamp(0, i64::from(i32::MAX)) as i32))
+	}
+}
+
+fn expired(checked: Instant, count: Option<i32>) -> bool {
+	let ttl = if count.is_some() { COUNTED_SECS } else { UNCOUNTED_SECS };
+	checked.elapsed() >= Duration::from_secs(ttl)
+}
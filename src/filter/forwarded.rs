@@ -0,0 +1,88 @@
+use std::{
+	net::{IpAddr, Ipv4Addr},
+	str::FromStr,
+};
+
+use super::headers::Headers;
+
+/// Peers whose forwarded metadata is trusted, unless configured otherwise: a reverse proxy on
+/// the same host.
+pub const DEFAULT_TRUSTED_PROXIES: [&str; 1] = ["127.0.0.0/8"];
+
+/// Header fields telling the AP server who the client is, which only a trusted proxy may set.
+const FIELDS: [&str; 3] = ["x-forwarded-for", "x-real-ip", "forwarded"];
+
+/// An IPv4 network, like `10.0.0.0/8`. A lone address is a network of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+	network: u32,
+	prefix: u8,
+}
+
+impl Cidr {
+	pub fn contains(&self, ip: Ipv4Addr) -> bool {
+		u32::from(ip) & self.mask() == self.network
+	}
+
+	fn mask(&self) -> u32 {
+		u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+	}
+}
+
+impl FromStr for Cidr {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (ip, prefix) = s.split_once('/').unwrap_or((s, "32"));
+		let ip: Ipv4Addr = ip.parse().map_err(|_| "invalid IPv4 address")?;
+		let prefix: u8 = prefix.parse().map_err(|_| "invalid prefix length")?;
+		if prefix > 32 {
+			return Err("invalid prefix length");
+		}
+		let mut cidr = Cidr { network: 0, prefix };
+		cidr.network = u32::from(ip) & cidr.mask();
+		Ok(cidr)
+	}
+}
+
+/// Remove forwarded metadata from a request header, whatever its case or spacing, along with
+/// any folded continuation lines.
+pub fn strip(header: &mut Vec<u8>) {
+	let mut kept = Vec::with_capacity(header.len());
+	let mut stripping = false;
+	for (i, line) in header.split_inclusive(|&b| b == b'\n').enumerate() {
+		if stripping && (line.starts_with(b" ") || line.starts_with(b"\t")) {
+			continue;
+		}
+		let name = line.split(|&b| b == b':').next().unwrap_or_default();
+		let name = std::str::from_utf8(name).unwrap_or_default().trim();
+		stripping = i > 0 && FIELDS.iter().any(|f| f.eq_ignore_ascii_case(name));
+		if !stripping {
+			kept.extend_from_slice(line);
+		}
+	}
+	*header = kept;
+}
+
+/// The client a trusted proxy forwarded the request for: X-Real-IP, else the first hop of
+/// X-Forwarded-For, else the first `for=` of Forwarded.
+pub fn client(headers: &Headers) -> Option<IpAddr> {
+	if let Some(ip) = headers.all("x-real-ip").next() {
+		return ip.parse().ok();
+	}
+	if let Some(hops) = headers.all("x-forwarded-for").next() {
+		return hops.split(',').next()?.trim().parse().ok();
+	}
+	let forwarded = headers.all("forwarded").next()?;
+	let node = forwarded.split([',', ';']).find_map(|pair| {
+		let (name, value) = pair.trim().split_once('=')?;
+		name.eq_ignore_ascii_case("for").then_some(value)
+	})?;
+	// for="[2001:db8::1]:4711", or for=192.0.2.43
+	let node = node.trim_matches('"');
+	let ip = match node.strip_prefix('[') {
+		Some(v6) => v6.split(']').next()?,
+		None => node.split(':').next()?,
+	};
+	ip.parse().ok()
+}
@@ -0,0 +1,47 @@
+use std::collections::{HashMap, HashSet};
+
+use sonic_rs::Value;
+
+use super::text;
+
+/// Characters of text below which there's too little to judge.
+const MIN_LEN: usize = 20;
+/// Bits per character below which text uses too few characters to be written by a person.
+/// English prose has about 4.
+const MIN_ENTROPY: f64 = 2.0;
+/// Share of repeated substrings above which text is a template stamped over and over.
+const MAX_REPETITION: f64 = 0.7;
+/// Length of the substrings compared for repetition.
+const NGRAM: usize = 4;
+
+/// Whether `object.content` is long enough to judge, and either made of a handful of
+/// characters (aaaaaa…) or mostly repeats itself.
+pub fn is_gibberish(ap_json: &Value) -> bool {
+	let Some(text) = text::note_text(ap_json) else {
+		return false;
+	};
+	let chars: Vec<char> = text.chars().collect();
+	chars.len() >= MIN_LEN && (entropy(&chars) < MIN_ENTROPY || repetition(&chars) > MAX_REPETITION)
+}
+
+/// Shannon entropy, in bits per character.
+fn entropy(chars: &[char]) -> f64 {
+	let mut counts = HashMap::new();
+	for c in chars {
+		*counts.entry(c).or_insert(0usize) += 1;
+	}
+	let len = chars.len() as f64;
+	counts.values().map(|&n| n as f64 / len).map(|p| -p * p.log2()).sum()
+}
+
+/// Share of substrings of [`NGRAM`] characters that already appeared earlier in the text,
+/// from 0 when all differ to nearly 1 when one is repeated throughout.
+fn repetition(chars: &[char]) -> f64 {
+	let windows = chars.windows(NGRAM);
+	let total = windows.len();
+	if total == 0 {
+		return 0.0;
+	}
+	let distinct: HashSet<&[char]> = windows.collect();
+	(total - distinct.len()) as f64 / total as f64
+}
@@ -0,0 +1,155 @@
+use std::str::FromStr;
+
+/// Fields of a request header, past the request line. Names are matched in any case, and values
+/// are trimmed of surrounding whitespace.
+#[derive(Debug)]
+pub struct Headers<'a> {
+	fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Headers<'a> {
+	/// Parse a complete request header, ending in an empty line.
+	///
+	/// Obsolete line folding and whitespace before the colon are rejected, as RFC 9112 asks of
+	/// servers: they're the stuff of request smuggling, and no AP server sends them. Lines that
+	/// aren't UTF-8 are skipped, since no field we look at can have such a value.
+	pub fn parse(header: &'a [u8]) -> Result<Self, &'static str> {
+		let mut fields = Vec::new();
+		for line in header.split(|&b| b == b'\n').skip(1) {
+			let line = line.strip_suffix(b"\r").unwrap_or(line);
+			if line.is_empty() {
+				break;
+			}
+			if line[0] == b' ' || line[0] == b'\t' {
+				return Err("obsolete line folding");
+			}
+			let Ok(line) = std::str::from_utf8(line) else {
+				continue;
+			};
+			let (name, value) = line.split_once(':').ok_or("header line without colon")?;
+			if name.ends_with([' ', '\t']) {
+				return Err("whitespace before colon");
+			}
+			fields.push((name, value.trim_matches([' ', '\t'])));
+		}
+		Ok(Headers { fields })
+	}
+
+	/// Values of every field with this name, in order.
+	pub fn all<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'a str> + 's {
+		self.fields.iter().filter(move |(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| *v)
+	}
+
+	/// Value of a field that can only have one, which is absent, or repeated with the same
+	/// value.
+	pub fn get(&self, name: &str) -> Result<Option<&'a str>, &'static str> {
+		let mut values = self.all(name);
+		let first = values.next();
+		match first {
+			Some(first) if values.any(|v| v != first) => Err("conflicting values of a header"),
+			_ => Ok(first),
+		}
+	}
+
+	/// Content-Length, which may also be repeated as a comma separated list, as long as all
+	/// the values agree.
+	pub fn content_length(&self) -> Result<Option<usize>, &'static str> {
+		let mut length = None;
+		for value in self.all("content-length").flat_map(|v| v.split(',')) {
+			let value = value.trim_matches([' ', '\t']);
+			if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+				return Err("invalid content-length");
+			}
+			let value = value.parse::<usize>().map_err(|_| "invalid content-length")?;
+			if length.is_some_and(|l| l != value) {
+				return Err("conflicting content-length");
+			}
+			length = Some(value);
+		}
+		Ok(length)
+	}
+}
+
+/// A media type with its parameters, as in Content-Type. Type, subtype and parameter names are
+/// lowercase, and parameter values are unquoted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaType {
+	pub essence: String,
+	pub params: Vec<(String, String)>,
+}
+
+impl MediaType {
+	/// Whether `self` is of this (accepted) type, and has all of its parameters. A `profile` is
+	/// a space separated list of URIs, of which one has to match.
+	pub fn matches(&self, accepted: &MediaType) -> bool {
+		self.essence == accepted.essence
+			&& accepted.params.iter().all(|(name, value)| {
+				self.params.iter().any(|(n, v)| {
+					n == name
+						&& match name.as_str() {
+							"profile" => v.split_ascii_whitespace().any(|p| p == value),
+							"charset" => v.eq_ignore_ascii_case(value),
+							_ => v == value,
+						}
+				})
+			})
+	}
+}
+
+impl FromStr for MediaType {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (essence, mut rest) = s.split_once(';').unwrap_or((s, ""));
+		let essence = essence.trim_matches([' ', '\t']).to_ascii_lowercase();
+		match essence.split_once('/') {
+			Some((t, st)) if is_token(t) && is_token(st) => {}
+			_ => return Err("invalid media type"),
+		}
+
+		let mut params = Vec::new();
+		loop {
+			rest = rest.trim_start_matches([' ', '\t', ';']);
+			if rest.is_empty() {
+				break;
+			}
+			let (name, after) = rest.split_once('=').ok_or("media type parameter without value")?;
+			if !is_token(name) {
+				return Err("invalid media type parameter");
+			}
+			let (value, after) = match after.strip_prefix('"') {
+				Some(quoted) => unquote(quoted)?,
+				None => {
+					let (value, rest) = after.split_once(';').unwrap_or((after, ""));
+					let value = value.trim_end_matches([' ', '\t']);
+					if !is_token(value) {
+						return Err("invalid media type parameter");
+					}
+					(value.to_string(), rest)
+				}
+			};
+			rest = after;
+			params.push((name.to_ascii_lowercase(), value));
+		}
+		Ok(MediaType { essence, params })
+	}
+}
+
+/// Read a quoted string up to its closing quote, and return it and what comes after it.
+fn unquote(s: &str) -> Result<(String, &str), &'static str> {
+	let mut value = String::new();
+	let mut chars = s.char_indices();
+	while let Some((i, c)) = chars.next() {
+		match c {
+			'"' => return Ok((value, &s[i + 1..])),
+			'\\' => value.push(chars.next().ok_or("unterminated quoted string")?.1),
+			c => value.push(c),
+		}
+	}
+	Err("unterminated quoted string")
+}
+
+fn is_token(s: &str) -> bool {
+	!s.is_empty()
+		&& s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
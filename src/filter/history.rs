@@ -0,0 +1,73 @@
+use std::{
+	collections::VecDeque,
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::reputation::Subject;
+
+/// Verdicts kept per actor and instance.
+const KEEP: usize = 20;
+/// Actors and instances without a verdict for this long are forgotten.
+const FORGET_SECS: u64 = 24 * 60 * 60;
+
+/// Latest verdicts on notes per actor and instance, for moderators looking into them.
+#[derive(Debug, Clone)]
+pub struct History {
+	verdicts: Arc<DashMap<(Subject, String), VecDeque<Verdict>>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Verdict {
+	/// unix time in seconds
+	pub at: u64,
+	/// `accepted`, or the kind of rejection.
+	pub verdict: String,
+	pub actor: String,
+}
+
+impl History {
+	#[allow(clippy::new_without_default)] // spawns a cleanup task
+	pub fn new() -> Self {
+		let history = History { verdicts: Arc::new(DashMap::new()) };
+
+		let verdicts = history.verdicts.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(3600));
+			loop {
+				interval.tick().await;
+				let now = now();
+				verdicts.retain(|_, v| v.back().is_some_and(|v| now - v.at < FORGET_SECS));
+			}
+		});
+
+		history
+	}
+
+	/// Record a verdict on a note from `actor` on `host`.
+	pub fn record(&self, actor: &str, host: &str, verdict: &str) {
+		let verdict = Verdict { at: now(), verdict: verdict.to_string(), actor: actor.to_string() };
+		for key in [(Subject::Actor, actor.to_string()), (Subject::Instance, host.to_string())] {
+			let mut verdicts = self.verdicts.entry(key).or_default();
+			if verdicts.len() == KEEP {
+				verdicts.pop_front();
+			}
+			verdicts.push_back(verdict.clone());
+		}
+	}
+
+	/// Latest verdicts on the actor or instance, oldest first.
+	pub fn get(&self, kind: Subject, subject: &str) -> Vec<Verdict> {
+		self.verdicts
+			.get(&(kind, subject.to_string()))
+			.map(|v| v.iter().cloned().collect())
+			.unwrap_or_default()
+	}
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
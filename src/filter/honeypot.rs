@@ -0,0 +1,86 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::*;
+
+const DEFAULT_BLOCK_SECS: u64 = 24 * 60 * 60;
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+
+/// `[honeypot]` in the config file: decoy paths no real server or client asks for, like
+/// `/wp-login.php`. Whoever does is a scanner, and is blocked for a while.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HoneypotConfig {
+	/// Request paths, matched without the query string. A trailing `*` matches any path
+	/// starting with the rest.
+	pub paths: Vec<String>,
+	/// Seconds to block a client for once it asked for one of them.
+	#[serde(default = "default_block_secs")]
+	pub block_secs: u64,
+}
+
+fn default_block_secs() -> u64 {
+	DEFAULT_BLOCK_SECS
+}
+
+impl HoneypotConfig {
+	pub fn validate(&self) -> Result<(), String> {
+		if self.paths.is_empty() {
+			return Err("honeypot needs at least one path".to_string());
+		}
+		if let Some(path) = self.paths.iter().find(|p| !p.starts_with('/')) {
+			return Err(format!("honeypot path {} must start with /", path));
+		}
+		if self.block_secs == 0 {
+			return Err("honeypot block_secs must be at least 1".to_string());
+		}
+		Ok(())
+	}
+}
+
+/// Clients blocked for asking for a decoy path, until their block runs out.
+#[derive(Debug, Clone)]
+pub struct Honeypot {
+	config: Arc<HoneypotConfig>,
+	blocked: Arc<DashMap<IpAddr, Instant>>,
+}
+
+impl Honeypot {
+	pub fn new(config: HoneypotConfig) -> Self {
+		let honeypot = Honeypot { config: Arc::new(config), blocked: Arc::new(DashMap::new()) };
+
+		let blocked = honeypot.blocked.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				blocked.retain(|_, until| *until > Instant::now());
+			}
+		});
+
+		honeypot
+	}
+
+	/// Whether `path` is a decoy.
+	pub fn is_trap(&self, path: &str) -> bool {
+		let path = path.split('?').next().unwrap_or_default();
+		self.config.paths.iter().any(|trap| match trap.strip_suffix('*') {
+			Some(prefix) => path.starts_with(prefix),
+			None => path == trap,
+		})
+	}
+
+	/// Block a client that asked for a decoy path.
+	pub fn trip(&self, client: IpAddr, path: &str) {
+		let until = Instant::now() + Duration::from_secs(self.config.block_secs);
+		if self.blocked.insert(client, until).is_none() {
+			info!("Blocking {} for {}s for asking for {}", client, self.config.block_secs, path);
+		}
+	}
+
+	pub fn is_blocked(&self, client: IpAddr) -> bool {
+		self.blocked.get(&client).is_some_and(|until| *until > Instant::now())
+	}
+}
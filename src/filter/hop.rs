@@ -0,0 +1,68 @@
+/// Header fields that only mean something to the connection they came in on (RFC 7230, section
+/// 6.1), along with the non-standard Proxy-Connection.
+const FIELDS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "te", "upgrade"];
+
+/// Fields the request can't be read without, kept even if Connection names them.
+const FRAMING: [&str; 3] = ["content-length", "host", "transfer-encoding"];
+
+/// Whether the request asks to switch the connection over to WebSocket, like Misskey's and
+/// Mastodon's streaming API. Connection and Upgrade must reach the AP server for that.
+pub fn is_upgrade(header: &[u8]) -> bool {
+	let mut connection = false;
+	let mut websocket = false;
+	for line in header.split_inclusive(|&b| b == b'\n').skip(1) {
+		let Some((name, value)) = field(line) else {
+			continue;
+		};
+		let mut options = value.split(',').map(str::trim);
+		if name.eq_ignore_ascii_case("connection") {
+			connection |= options.any(|o| o.eq_ignore_ascii_case("upgrade"));
+		} else if name.eq_ignore_ascii_case("upgrade") {
+			websocket |= options.any(|o| o.eq_ignore_ascii_case("websocket"));
+		}
+	}
+	header.starts_with(b"GET ") && connection && websocket
+}
+
+/// Remove hop-by-hop fields from a request header, and those Connection names as such, whatever
+/// their case or spacing, along with any folded continuation lines. Connection and Upgrade are
+/// kept for an `upgrade`.
+pub fn strip(header: &mut Vec<u8>, upgrade: bool) {
+	let mut named: Vec<String> = Vec::new();
+	for (i, line) in header.split_inclusive(|&b| b == b'\n').enumerate() {
+		let Some((name, value)) = field(line) else {
+			continue;
+		};
+		if i > 0 && name.eq_ignore_ascii_case("connection") {
+			let options = value.split(',').map(|o| o.trim().to_ascii_lowercase());
+			named.extend(options.filter(|o| !o.is_empty() && !FRAMING.contains(&o.as_str())));
+		}
+	}
+
+	let mut kept = Vec::with_capacity(header.len());
+	let mut stripping = false;
+	for (i, line) in header.split_inclusive(|&b| b == b'\n').enumerate() {
+		if stripping && (line.starts_with(b" ") || line.starts_with(b"\t")) {
+			continue;
+		}
+		let name = field(line).map(|(name, _)| name).unwrap_or_default();
+		let switching = upgrade
+			&& (name.eq_ignore_ascii_case("connection") || name.eq_ignore_ascii_case("upgrade"));
+		stripping = i > 0
+			&& !name.is_empty()
+			&& !switching
+			&& (FIELDS.iter().any(|f| f.eq_ignore_ascii_case(name))
+				|| named.iter().any(|n| n.eq_ignore_ascii_case(name)));
+		if !stripping {
+			kept.extend_from_slice(line);
+		}
+	}
+	*header = kept;
+}
+
+/// Name and value of a header line.
+fn field(line: &[u8]) -> Option<(&str, &str)> {
+	let line = std::str::from_utf8(line).ok()?;
+	let (name, value) = line.split_once(':')?;
+	Some((name.trim(), value.trim()))
+}
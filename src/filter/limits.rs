@@ -0,0 +1,106 @@
+/// Limits on the shape of JSON bodies, checked before they're parsed, so payloads crafted to
+/// keep the parser busy are turned away cheaply.
+#[derive(Debug, Clone, Copy)]
+pub struct JsonLimits {
+	/// Arrays and objects inside one another.
+	pub max_depth: usize,
+	/// Object keys in the whole body.
+	pub max_keys: usize,
+	/// Bytes of a single string, as written, escapes and all.
+	pub max_string: usize,
+}
+
+impl JsonLimits {
+	/// Which limit `body` is over, if any. It's only scanned, not validated: malformed JSON is
+	/// left for the parser to reject.
+	pub fn check(&self, body: &[u8]) -> Result<(), &'static str> {
+		let mut depth = 0usize;
+		let mut keys = 0usize;
+		// where the string being scanned started, and whether the last byte escaped this one
+		let mut string = None;
+		let mut escaped = false;
+		for (i, &b) in body.iter().enumerate() {
+			if let Some(start) = string {
+				if escaped {
+					escaped = false;
+				} else if b == b'\\' {
+					escaped = true;
+				} else if b == b'"' {
+					if i - start > self.max_string {
+						return Err("string too long");
+					}
+					string = None;
+				}
+				continue;
+			}
+			match b {
+				b'"' => string = Some(i + 1),
+				b'{' | b'[' => {
+					depth += 1;
+					if depth > self.max_depth {
+						return Err("nested too deep");
+					}
+				}
+				b'}' | b']' => depth = depth.saturating_sub(1),
+				b':' => {
+					keys += 1;
+					if keys > self.max_keys {
+						return Err("too many keys");
+					}
+				}
+				_ => {}
+			}
+		}
+		match string {
+			Some(start) if body.len() - start > self.max_string => Err("string too long"),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const LIMITS: JsonLimits = JsonLimits { max_depth: 3, max_keys: 4, max_string: 8 };
+
+	#[test]
+	fn allows_bodies_within_limits() {
+		assert_eq!(LIMITS.check(br#"{"a":[{"b":1}],"c":"12345678","d":null}"#), Ok(()));
+		assert_eq!(LIMITS.check(b""), Ok(()));
+	}
+
+	#[test]
+	fn limits_depth() {
+		assert_eq!(LIMITS.check(b"[[[1]]]"), Ok(()));
+		assert_eq!(LIMITS.check(b"[[[[1]]]]"), Err("nested too deep"));
+		assert_eq!(LIMITS.check(br#"{"a":{"b":{"c":{}}}}"#), Err("nested too deep"));
+		// siblings aren't nested
+		assert_eq!(LIMITS.check(b"[[[1]],[[2]],[[3]]]"), Ok(()));
+	}
+
+	#[test]
+	fn limits_keys() {
+		assert_eq!(LIMITS.check(br#"{"a":1,"b":2,"c":3,"d":4}"#), Ok(()));
+		assert_eq!(LIMITS.check(br#"{"a":1,"b":2,"c":{"d":4,"e":5}}"#), Err("too many keys"));
+	}
+
+	#[test]
+	fn limits_strings() {
+		assert_eq!(LIMITS.check(br#"["123456789"]"#), Err("string too long"));
+		assert_eq!(LIMITS.check(br#"{"123456789":1}"#), Err("string too long"));
+		// escapes count as written
+		assert_eq!(LIMITS.check(br#"["\"\"\"\""]"#), Ok(()));
+		assert_eq!(LIMITS.check(br#"["\"\"\"\"\""]"#), Err("string too long"));
+		// cut off in the middle of one
+		assert_eq!(LIMITS.check(br#"["123456789"#), Err("string too long"));
+		assert_eq!(LIMITS.check(br#"["1234"#), Ok(()));
+	}
+
+	#[test]
+	fn ignores_structure_inside_strings() {
+		assert_eq!(LIMITS.check(br#"["[[[[{:::::}"]"#), Err("string too long"));
+		let limits = JsonLimits { max_string: 100, ..LIMITS };
+		assert_eq!(limits.check(br#"["[[[[{a:b:c:d:e:}", "\"[[[["]"#), Ok(()));
+	}
+}
@@ -0,0 +1,25 @@
+use sonic_rs::Value;
+use url::Url;
+
+use crate::attachments::attachment_urls;
+
+/// The first attachment of a remote note that points at the receiving server, at `local`, like
+/// Misskey's `/proxy/` and `/files/` URLs, directly or through a media proxy's `url` parameter.
+/// Remote notes have no business attaching our files: it makes our server fetch and serve
+/// whatever the proxy is told to, for every reader, on the spammer's behalf.
+pub fn local_attachment<'a>(ap_json: &'a Value, local: &str) -> Option<&'a str> {
+	attachment_urls(ap_json).into_iter().find(|url| {
+		let Ok(url) = url.parse::<Url>() else {
+			return false;
+		};
+		let proxied = url
+			.query_pairs()
+			.filter(|(name, _)| name == "url")
+			.any(|(_, target)| target.parse::<Url>().ok().is_some_and(|t| is_host(&t, local)));
+		is_host(&url, local) || proxied
+	})
+}
+
+fn is_host(url: &Url, host: &str) -> bool {
+	url.host_str().is_some_and(|h| h.eq_ignore_ascii_case(host.trim_end_matches('.')))
+}
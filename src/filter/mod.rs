@@ -1,27 +1,389 @@
-use std::time::Duration;
+use std::{
+	fmt,
+	net::{IpAddr, SocketAddr},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc, RwLock,
+	},
+	time::{Duration, Instant},
+};
 
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sonic_rs::{JsonContainerTrait, JsonValueTrait, Value};
 use thiserror::Error;
 use tokio::{io, net::TcpStream, time::timeout};
 use tracing::*;
 use url::Url;
 
-use crate::query::Query;
+use self::{
+	asn::{AsnConfig, Asns},
+	canonical::CanonicalBody,
+	classifier::{Classifier, ClassifierConfig},
+	decisions::{Decision, DecisionCache},
+	dedup::SeenDeliveries,
+	digest::DigestConfig,
+	domain_block::{DomainBlockConfig, DomainBlocker},
+	followers::{Followers, FollowersConfig},
+	headers::{Headers, MediaType},
+	history::{History, Verdict},
+	honeypot::{Honeypot, HoneypotConfig},
+	limits::JsonLimits,
+	moves::Moves,
+	panic::{Panic, PanicConfig},
+	pow::{Pow, PowConfig},
+	recipients::Recipients,
+	rejections::Rejections,
+	replies::ReplyTracker,
+	rules::{
+		canary::{Canary, CanaryConfig},
+		pack::{PackLists, Packs},
+		Action, Facts, Need, RuleConfig, RuleError, RuleSet,
+	},
+	score::Score,
+	shadow::{Shadow, ShadowConfig},
+	signature::Signed,
+	strict::Strict,
+	suspend::{SuspendConfig, Suspender},
+	throttle::{Throttle, ThrottleWindow},
+	user_agent::{UserAgentConfig, UserAgents},
+	velocity::VelocityTracker,
+	webfinger::{WebFinger, WebFingerConfig},
+};
+use crate::{
+	attachments::AttachmentList,
+	audit::{Audit, Record, Trail},
+	cache::CacheConfig,
+	domains::DomainList,
+	events::{Event, Events, EventsConfig},
+	flag::Reporter,
+	quarantine::Quarantine,
+	query::{Backend, InstanceStats, QueryError, User},
+	reputation::{self, Reputation, Subject},
+	share::Share,
+	statsd::{Statsd, StatsdConfig},
+	telemetry::{Telemetry, TelemetryConfig},
+	upstream::{Picked, Routes, Upstream},
+};
+use fingerprint::Fingerprints;
+use forwarded::Cidr;
 
-pub struct FilterBuilder {}
+pub mod asn;
+mod audience;
+pub mod canonical;
+pub mod classifier;
+mod decisions;
+mod dedup;
+pub mod digest;
+pub mod domain_block;
+mod emoji;
+pub mod fingerprint;
+pub mod followers;
+pub mod forwarded;
+mod gibberish;
+pub mod headers;
+pub mod history;
+pub mod honeypot;
+mod hop;
+pub mod limits;
+mod media_proxy;
+mod moves;
+mod origin;
+pub mod panic;
+mod persist;
+pub mod pow;
+mod published;
+pub mod recipients;
+pub mod rejections;
+mod relay;
+mod remote;
+mod replies;
+pub mod request_id;
+pub mod responses;
+pub mod rules;
+pub mod score;
+pub mod shadow;
+pub mod signature;
+pub mod strict;
+pub mod suspend;
+mod tags;
+mod target;
+pub mod text;
+pub mod throttle;
+pub mod user_agent;
+mod velocity;
+pub mod webfinger;
 
+/// Which deliveries the filter sits in front of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Direction {
+	/// Deliveries from the fediverse to the AP server's shared inbox.
+	Inbound,
+	/// Deliveries from the AP server to remote inboxes, to catch compromised local accounts.
+	Outbound,
+}
+
+/// Whether verdicts are enforced at the edge or left to the AP server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Enforcement {
+	/// Reject, quarantine and throttle as the rules say.
+	Enforce,
+	/// Forward everything, adding X-Musubi-Score and X-Musubi-Signals headers.
+	Annotate,
+}
+
+pub struct FilterBuilder {
+	origin_exceptions: Vec<String>,
+	relays: Vec<String>,
+	content_types: Option<Vec<MediaType>>,
+	trusted_proxies: Option<Vec<Cidr>>,
+	json_limits: JsonLimits,
+	canonical_body: CanonicalBody,
+	max_audience: usize,
+	reply_flood_window: Duration,
+	reply_flood_max: usize,
+	decision_ttl: Duration,
+	duplicate_ttl: Duration,
+	caches: CacheConfig,
+	surge_factor: u32,
+	max_hashtags: usize,
+	max_notes_per_day: u32,
+	max_published_age: Duration,
+	max_published_ahead: Duration,
+	spam_score_threshold: u32,
+	score_action: Action,
+	first_dm_action: Option<Action>,
+	move_action: Option<Action>,
+	rules: Option<RuleSet>,
+	canary: Option<CanaryConfig>,
+	shadow: Option<ShadowConfig>,
+	packs: Packs,
+	quarantine_size: usize,
+	enforcement: Enforcement,
+	check_recipients: bool,
+	inspect_updates: bool,
+	direction: Direction,
+	reputation: Option<Reputation>,
+	fingerprints: Option<Fingerprints>,
+	share: Option<Share>,
+	blocklist: Option<DomainList>,
+	allowlist: Option<DomainList>,
+	tarpit: Option<DomainList>,
+	attachments: Option<AttachmentList>,
+	panic: Option<PanicConfig>,
+	suspend: Option<SuspendConfig>,
+	domain_block: Option<DomainBlockConfig>,
+	reporter: Option<Reporter>,
+	classifier: Option<ClassifierConfig>,
+	honeypot: Option<HoneypotConfig>,
+	user_agents: Option<UserAgentConfig>,
+	asn: Option<AsnConfig>,
+	webfinger: Option<WebFingerConfig>,
+	followers: Option<FollowersConfig>,
+	pow: Option<PowConfig>,
+	events: Option<EventsConfig>,
+	audit: Option<Audit>,
+	digest: Option<DigestConfig>,
+	statsd: Option<StatsdConfig>,
+	telemetry: Option<TelemetryConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+	origin_exceptions: Arc<[String]>,
+	relays: Arc<[String]>,
+	content_types: Arc<[MediaType]>,
+	trusted_proxies: Arc<[Cidr]>,
+	json_limits: JsonLimits,
+	canonical_body: CanonicalBody,
+	replies: ReplyTracker,
+	velocity: VelocityTracker,
+	decisions: DecisionCache,
+	seen: SeenDeliveries,
+	history: History,
+	rejections: Rejections,
+	max_published_age: Duration,
+	max_published_ahead: Duration,
+	score_action: Action,
+	first_dm_action: Option<Action>,
+	move_action: Option<Action>,
+	inspect_updates: bool,
+	tuning: Arc<RwLock<Tuning>>,
+	throttle: Throttle,
+	quarantine: Quarantine,
+	strict: Strict,
+	moves: Moves,
+	/// Pipeline B, compared with what the filter decides.
+	shadow: Option<Arc<Shadow>>,
+	enforcement: Enforcement,
+	recipients: Option<Recipients>,
+	direction: Direction,
+	reputation: Reputation,
+	fingerprints: Fingerprints,
+	share: Option<Share>,
+	blocklist: Option<DomainList>,
+	allowlist: Option<DomainList>,
+	tarpit: Option<DomainList>,
+	attachments: Option<AttachmentList>,
+	panic: Option<Panic>,
+	suspend: Option<Suspender>,
+	domain_block: Option<DomainBlocker>,
+	reporter: Option<Reporter>,
+	classifier: Option<Classifier>,
+	honeypot: Option<Honeypot>,
+	user_agents: Option<UserAgents>,
+	asns: Option<Asns>,
+	webfinger: Option<WebFinger>,
+	followers: Option<Followers>,
+	pow: Option<Pow>,
+	events: Option<Events>,
+	audit: Option<Audit>,
+	digest: Option<digest::Digest>,
+	statsd: Option<Statsd>,
+	telemetry: Option<Telemetry>,
+}
+
+/// Limits the spam signals are scored against. Adjustable at runtime.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Thresholds {
+	pub max_audience: usize,
+	pub reply_flood_max: usize,
+	pub max_hashtags: usize,
+	pub max_notes_per_day: u32,
+	pub spam_score_threshold: u32,
+}
+
+/// Thresholds to change, leaving out the ones to keep.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThresholdsPatch {
+	pub max_audience: Option<usize>,
+	pub reply_flood_max: Option<usize>,
+	pub max_hashtags: Option<usize>,
+	pub max_notes_per_day: Option<u32>,
+	pub spam_score_threshold: Option<u32>,
+}
+
+impl ThresholdsPatch {
+	pub fn apply(&self, thresholds: &mut Thresholds) {
+		if let Some(max_audience) = self.max_audience {
+			thresholds.max_audience = max_audience;
+		}
+		if let Some(reply_flood_max) = self.reply_flood_max {
+			thresholds.reply_flood_max = reply_flood_max;
+		}
+		if let Some(max_hashtags) = self.max_hashtags {
+			thresholds.max_hashtags = max_hashtags;
+		}
+		if let Some(max_notes_per_day) = self.max_notes_per_day {
+			thresholds.max_notes_per_day = max_notes_per_day;
+		}
+		if let Some(spam_score_threshold) = self.spam_score_threshold {
+			thresholds.spam_score_threshold = spam_score_threshold;
+		}
+	}
+}
+
+/// What the filter knows about an actor or instance, for moderators looking into how its notes
+/// are judged.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+	pub reputation: f64,
+	/// The actor as the AP server's DB has it, if it does.
+	pub user: Option<User>,
+	/// The instance as the AP server's DB has it, if it does.
+	pub instance: Option<InstanceStats>,
+	/// Distinct local notes the actor replied to within the reply flood window.
+	pub recent_replies: usize,
+	/// Throttle windows the actor is counted in.
+	pub throttles: Vec<ThrottleWindow>,
+	/// Notes admitted from the instance within the last hour, and usually per hour.
+	pub rate: Option<(u32, f64)>,
+	/// Latest verdicts on its notes, oldest first.
+	pub verdicts: Vec<Verdict>,
+}
+
+/// Everything that can be changed at runtime, replaced as a whole so an activity is always
+/// judged by one consistent version.
 #[derive(Debug, Clone)]
-pub struct Filter {}
+struct Tuning {
+	rules: Arc<RuleSet>,
+	/// A candidate ruleset being tried out.
+	canary: Option<Arc<Canary>>,
+	thresholds: Thresholds,
+	packs: Arc<PackLists>,
+}
 
 const FILTER_CRITERION_TIMEOUT_MS: u64 = 100;
 const HEADER_TIMEOUT_MS: u64 = 500;
 const BODY_TIMEOUT_MS: u64 = 1000;
-const SKETCHY_INSTANCE_THRESHOLD: i32 = 5;
+const LOW_REPUTATION_FOLLOWERS: i32 = 5;
+const DEFAULT_MAX_AUDIENCE: usize = 10;
+const DEFAULT_REPLY_FLOOD_WINDOW_SECS: u64 = 600;
+const DEFAULT_REPLY_FLOOD_MAX: usize = 10;
+const DEFAULT_DECISION_TTL_SECS: u64 = 300;
+const DEFAULT_DUPLICATE_TTL_SECS: u64 = 300;
+const DEFAULT_JSON_LIMITS: JsonLimits =
+	JsonLimits { max_depth: 64, max_keys: 10000, max_string: 1024 * 1024 };
+const DEFAULT_SURGE_FACTOR: u32 = 10;
+const DEFAULT_MAX_HASHTAGS: usize = 5;
+/// Emoji, and share of the text they make up, at which a note is an emoji flood.
+const EMOJI_FLOOD_MIN: usize = 5;
+const EMOJI_FLOOD_PERCENT: u32 = 50;
+const DEFAULT_MAX_NOTES_PER_DAY: u32 = 1000;
+const DEFAULT_MAX_PUBLISHED_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_MAX_PUBLISHED_AHEAD_SECS: u64 = 60 * 60;
+/// Accounts younger than this have their notes per day checked.
+const NEW_ACCOUNT_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+const DEFAULT_QUARANTINE_SIZE: usize = 1000;
+/// Content-Types accepted for deliveries, unless configured otherwise.
+pub const DEFAULT_CONTENT_TYPES: [&str; 2] = ["application/activity+json", "application/ld+json"];
+/// Name the spam score stage goes by in tags, logs and quarantine.
+const SCORE_STAGE: &str = "score";
+/// Name the check on `Move`s goes by in tags, logs and quarantine.
+const MOVE_STAGE: &str = "move";
+/// Object types inspected as notes. Polls carry text in their options as well as their content.
+const NOTE_TYPES: [&str; 2] = ["Note", "Question"];
+/// Name domains pinned to strict mode go by in tags, logs and quarantine.
+const STRICT_STAGE: &str = "strict";
+/// Name the check on DMs from strangers goes by in tags, logs and quarantine.
+const FIRST_DM_STAGE: &str = "first-dm";
 
 pub struct Admit {
 	pub incoming_stream: TcpStream,
+	pub upstream: Picked,
 	pub pending_header: Vec<u8>,
 	pub pending_body: Vec<u8>,
+	/// Where to remember the delivery as forwarded, and by what, once the AP server took it.
+	seen: Option<(SeenDeliveries, String)>,
+}
+
+impl Admit {
+	/// Whether this is a delivery to an inbox, read in full.
+	pub fn is_delivery(&self) -> bool {
+		is_delivery(&self.pending_header)
+	}
+
+	/// Whether repeats of the delivery are to be dropped once the AP server took it, which
+	/// [`Admit::answered`] is to be told about.
+	pub fn remembers(&self) -> bool {
+		self.seen.is_some()
+	}
+
+	/// Remember the delivery as forwarded if the AP server took it, by the status line it
+	/// answered with. Deliveries it failed on or never got stay unknown, so they can be retried.
+	pub fn answered(&mut self, status_line: &[u8]) {
+		let taken = status_line.starts_with(b"HTTP/1.") && status_line.get(9) == Some(&b'2');
+		if let (true, Some((seen, key))) = (taken, self.seen.take()) {
+			seen.insert(key);
+		}
+	}
+}
+
+pub struct Rejected {
+	pub incoming_stream: TcpStream,
+	pub reason: RejectReason,
 }
 
 #[derive(Error, Debug)]
@@ -39,29 +401,1024 @@ pub enum RejectReason {
 	#[error("Bad request: {0}")]
 	BadRequest(&'static str),
 	#[error("Invalid ActivityStream ({0}):\n{1}")]
-	InvalidRequest(&'static str, String),
+	InvalidRequest(&'static str, Payload),
 	#[error("Spam detected:\n{1}")]
-	Spam(String, String),
+	Spam(String, Payload),
+	#[error("Quarantined as #{0} by rule \"{2}\" (from {1})")]
+	Quarantined(u64, String, String),
+	#[error("Throttled by rule \"{1}\" (from {0})")]
+	Throttled(String, String),
+	#[error("Blocked domain {0}")]
+	Blocked(String),
+	#[error("Unexpected Host header: {0}")]
+	UnexpectedHost(String),
+	#[error("Tarpitted confirmed spammer {0}")]
+	Tarpitted(String),
+	#[error("Repeated delivery of {0}")]
+	Duplicate(String),
+	#[error("JSON too complex to parse: {0}")]
+	TooComplex(&'static str),
+	#[error("AP server at {0} is down")]
+	Unavailable(String),
+	#[error("AP server at {0} took too long to take the request")]
+	UpstreamTimeout(String),
+	#[error("Asked for honeypot path {1} (from {0})")]
+	Honeypot(IpAddr, String),
+	#[error("Blocked {0} for having asked for a honeypot path")]
+	HoneypotBlocked(IpAddr),
+	#[error("Denied User-Agent {0}")]
+	UserAgent(String),
+	#[error("Blocked network AS{1} (from {0})")]
+	Asn(String, u32),
+	#[error("Actor {0} unknown to its own instance")]
+	Unacknowledged(String),
+	#[error("Delivery to missing local user {0}")]
+	NoRecipient(String),
+	#[error("Challenged first delivery from {0}")]
+	Challenged(String, String),
+}
+
+/// Whether bodies of rejected activities are replaced by a hash and a short excerpt in logs.
+pub static REDACT_PAYLOADS: AtomicBool = AtomicBool::new(false);
+
+const REDACTED_EXCERPT_LEN: usize = 64;
+
+/// Raw body of a rejected activity, as carried by [`RejectReason`].
+#[derive(Debug)]
+pub struct Payload(pub String);
+
+impl Payload {
+	pub fn new(body: &[u8]) -> Self {
+		Payload(String::from_utf8_lossy(body).to_string())
+	}
+}
+
+impl fmt::Display for Payload {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if !REDACT_PAYLOADS.load(Ordering::Relaxed) {
+			return f.write_str(&self.0);
+		}
+		let excerpt: String = self.0.chars().take(REDACTED_EXCERPT_LEN).collect();
+		write!(
+			f,
+			"[redacted sha256:{:x}, {} bytes] {}{}",
+			Sha256::digest(self.0.as_bytes()),
+			self.0.len(),
+			excerpt,
+			if excerpt.len() < self.0.len() { "…" } else { "" }
+		)
+	}
+}
+
+impl RejectReason {
+	/// Short, body-less description of the reason. Identical reasons share the same kind.
+	pub fn kind(&self) -> &'static str {
+		match self {
+			RejectReason::Timeout(_) => "timeout",
+			RejectReason::IO(_) => "io error",
+			RejectReason::Query(_) => "query error",
+			RejectReason::ConnectionTerminated => "connection terminated",
+			RejectReason::MalformedHeader(what) => what,
+			RejectReason::BadRequest(what) => what,
+			RejectReason::InvalidRequest(what, _) => what,
+			RejectReason::Spam(..) => "spam",
+			RejectReason::Quarantined(..) => "quarantined",
+			RejectReason::Throttled(..) => "throttled",
+			RejectReason::Blocked(_) => "blocked",
+			RejectReason::UnexpectedHost(_) => "unexpected host",
+			RejectReason::Tarpitted(_) => "tarpitted",
+			RejectReason::Duplicate(_) => "duplicate",
+			RejectReason::TooComplex(what) => what,
+			RejectReason::Unavailable(_) => "unavailable",
+			RejectReason::UpstreamTimeout(_) => "upstream timeout",
+			RejectReason::Honeypot(..) => "honeypot",
+			RejectReason::HoneypotBlocked(_) => "honeypot blocked",
+			RejectReason::UserAgent(_) => "user agent",
+			RejectReason::Asn(..) => "blocked network",
+			RejectReason::Unacknowledged(_) => "unacknowledged actor",
+			RejectReason::NoRecipient(_) => "no recipient",
+			RejectReason::Challenged(..) => "challenged",
+		}
+	}
+
+	/// What was done with the request instead of forwarding it.
+	pub fn action(&self) -> &'static str {
+		match self {
+			RejectReason::Quarantined(..) => "quarantine",
+			RejectReason::Throttled(..) => "throttle",
+			RejectReason::Tarpitted(_) => "tarpit",
+			_ => "reject",
+		}
+	}
+
+	/// Name to configure the response to this kind of rejection by.
+	pub fn category(&self) -> &'static str {
+		match self {
+			RejectReason::Timeout(_) => "timeout",
+			RejectReason::IO(_) => "io",
+			RejectReason::Query(_) => "query",
+			RejectReason::ConnectionTerminated => "terminated",
+			RejectReason::MalformedHeader(_) | RejectReason::BadRequest(_) => "malformed",
+			RejectReason::InvalidRequest(..) => "invalid",
+			RejectReason::Spam(..) => "spam",
+			RejectReason::Quarantined(..) => "quarantined",
+			RejectReason::Throttled(..) => "throttled",
+			RejectReason::Blocked(_) => "blocked",
+			RejectReason::UnexpectedHost(_) => "unexpected-host",
+			RejectReason::Tarpitted(_) => "tarpitted",
+			RejectReason::Duplicate(_) => "duplicate",
+			RejectReason::TooComplex(_) => "too-complex",
+			RejectReason::Unavailable(_) => "unavailable",
+			RejectReason::UpstreamTimeout(_) => "upstream-timeout",
+			RejectReason::Honeypot(..) | RejectReason::HoneypotBlocked(_) => "honeypot",
+			RejectReason::UserAgent(_) => "user-agent",
+			RejectReason::Asn(..) => "asn",
+			RejectReason::Unacknowledged(_) => "webfinger",
+			RejectReason::NoRecipient(_) => "no-recipient",
+			RejectReason::Challenged(..) => "challenged",
+		}
+	}
+
+	/// Built-in response to send to the client before closing the connection, if any.
+	pub fn response(&self, direction: Direction) -> Option<&'static [u8]> {
+		match self {
+			// our own AP server would retry a dropped delivery forever
+			RejectReason::Spam(..) | RejectReason::Blocked(_)
+				if direction == Direction::Outbound =>
+			{
+				Some(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+			}
+			// pretend everything is fine so the sender doesn't retry
+			RejectReason::Quarantined(..) | RejectReason::Duplicate(_) => {
+				Some(b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+			}
+			RejectReason::UnexpectedHost(_) => {
+				Some(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+			}
+			// as the AP server would, so senders stop delivering there
+			RejectReason::NoRecipient(_) => {
+				Some(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+			}
+			RejectReason::Throttled(..) => Some(
+				b"HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+			),
+			RejectReason::UpstreamTimeout(_) => Some(
+				b"HTTP/1.1 504 Gateway Timeout\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+			),
+			// so the sender backs off and retries later instead of counting a failure
+			RejectReason::Unavailable(_) => Some(
+				b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nRetry-After: 60\r\n\
+				Connection: close\r\n\r\n",
+			),
+			_ => None,
+		}
+	}
+
+	/// Host the rejected activity came from, if known.
+	pub fn origin(&self) -> Option<String> {
+		match self {
+			RejectReason::Spam(actor, _)
+			| RejectReason::Quarantined(_, actor, _)
+			| RejectReason::Throttled(actor, _) => {
+				actor.parse::<Url>().ok().and_then(|a| a.host_str().map(|h| h.to_string()))
+			}
+			RejectReason::Blocked(host)
+			| RejectReason::Asn(host, _)
+			| RejectReason::Challenged(host, _) => Some(host.clone()),
+			RejectReason::Tarpitted(uri)
+			| RejectReason::Duplicate(uri)
+			| RejectReason::Unacknowledged(uri) => {
+				uri.parse::<Url>().ok().and_then(|a| a.host_str().map(|h| h.to_string()))
+			}
+			_ => None,
+		}
+	}
+
+	/// Short reason, actor and raw body of the rejected activity, if we got far enough to read
+	/// them.
+	pub fn payload(&self) -> Option<(&'static str, Option<&str>, &str)> {
+		match self {
+			RejectReason::InvalidRequest(_, body) => Some((self.kind(), None, &body.0)),
+			RejectReason::Spam(actor, body) => Some((self.kind(), Some(actor), &body.0)),
+			_ => None,
+		}
+	}
 }
 
 impl Filter {
 	pub fn builder() -> FilterBuilder {
-		FilterBuilder {}
+		FilterBuilder {
+			origin_exceptions: Vec::new(),
+			relays: Vec::new(),
+			content_types: None,
+			trusted_proxies: None,
+			json_limits: DEFAULT_JSON_LIMITS,
+			canonical_body: CanonicalBody::Off,
+			max_audience: DEFAULT_MAX_AUDIENCE,
+			reply_flood_window: Duration::from_secs(DEFAULT_REPLY_FLOOD_WINDOW_SECS),
+			reply_flood_max: DEFAULT_REPLY_FLOOD_MAX,
+			decision_ttl: Duration::from_secs(DEFAULT_DECISION_TTL_SECS),
+			duplicate_ttl: Duration::from_secs(DEFAULT_DUPLICATE_TTL_SECS),
+			caches: CacheConfig::default(),
+			surge_factor: DEFAULT_SURGE_FACTOR,
+			max_hashtags: DEFAULT_MAX_HASHTAGS,
+			max_notes_per_day: DEFAULT_MAX_NOTES_PER_DAY,
+			max_published_age: Duration::from_secs(DEFAULT_MAX_PUBLISHED_AGE_SECS),
+			max_published_ahead: Duration::from_secs(DEFAULT_MAX_PUBLISHED_AHEAD_SECS),
+			spam_score_threshold: score::STRONG,
+			score_action: Action::Reject,
+			first_dm_action: None,
+			move_action: None,
+			rules: None,
+			canary: None,
+			shadow: None,
+			packs: Packs::default(),
+			quarantine_size: DEFAULT_QUARANTINE_SIZE,
+			enforcement: Enforcement::Enforce,
+			check_recipients: false,
+			inspect_updates: false,
+			direction: Direction::Inbound,
+			reputation: None,
+			fingerprints: None,
+			share: None,
+			blocklist: None,
+			allowlist: None,
+			tarpit: None,
+			attachments: None,
+			panic: None,
+			suspend: None,
+			domain_block: None,
+			reporter: None,
+			classifier: None,
+			honeypot: None,
+			user_agents: None,
+			asn: None,
+			webfinger: None,
+			followers: None,
+			pow: None,
+			events: None,
+			audit: None,
+			digest: None,
+			statsd: None,
+			telemetry: None,
+		}
+	}
+
+	/// Rules as configured, in evaluation order.
+	pub fn rule_configs(&self) -> Vec<RuleConfig> {
+		#[allow(clippy::unwrap_used)]
+		self.tuning.read().unwrap().rules.configs().to_vec()
+	}
+
+	pub fn thresholds(&self) -> Thresholds {
+		#[allow(clippy::unwrap_used)]
+		self.tuning.read().unwrap().thresholds
+	}
+
+	pub fn reputation(&self) -> &Reputation {
+		&self.reputation
+	}
+
+	pub fn quarantine(&self) -> &Quarantine {
+		&self.quarantine
+	}
+
+	pub fn strict(&self) -> &Strict {
+		&self.strict
+	}
+
+	pub fn rejections(&self) -> &Rejections {
+		&self.rejections
+	}
+
+	/// Change rules and thresholds at runtime.
+	///
+	/// `edit` works on copies, which replace the live ones only if it succeeds and the edited
+	/// rules compile. Nothing changes otherwise.
+	pub fn retune(
+		&self, edit: impl FnOnce(&mut Vec<RuleConfig>, &mut Thresholds) -> Result<(), RuleError>,
+	) -> Result<(), RuleError> {
+		#[allow(clippy::unwrap_used)]
+		let mut tuning = self.tuning.write().unwrap();
+		let mut rules = tuning.rules.configs().to_vec();
+		let mut thresholds = tuning.thresholds;
+		edit(&mut rules, &mut thresholds)?;
+		let (packs, canary) = (tuning.packs.clone(), tuning.canary.clone());
+		let rules = Arc::new(RuleSet::compile(&rules)?);
+		*tuning = Tuning { rules, canary, thresholds, packs };
+		// verdicts under the old rules no longer hold
+		self.decisions.clear();
+		Ok(())
+	}
+
+	/// Replace the rules, the candidate ruleset, and the keywords and fingerprints of rule packs,
+	/// as read again from the config file. Nothing changes if the rules don't compile.
+	pub fn reload(
+		&self, rules: Vec<RuleConfig>, canary: Option<Canary>, packs: PackLists,
+	) -> Result<(), RuleError> {
+		let rules = Arc::new(RuleSet::compile(&rules)?);
+		#[allow(clippy::unwrap_used)]
+		let mut tuning = self.tuning.write().unwrap();
+		let thresholds = tuning.thresholds;
+		let canary = canary.map(Arc::new);
+		*tuning = Tuning { rules, canary, thresholds, packs: Arc::new(packs) };
+		self.decisions.clear();
+		Ok(())
+	}
+
+	/// Report on an actor URI or instance host, with its stats looked up through `query`.
+	pub async fn report(
+		&self, kind: Subject, subject: &str, query: &dyn Backend,
+	) -> Result<Report, QueryError> {
+		let mut report = Report {
+			reputation: self.reputation.get(kind, subject),
+			user: None,
+			instance: None,
+			recent_replies: 0,
+			throttles: Vec::new(),
+			rate: None,
+			verdicts: self.history.get(kind, subject),
+		};
+		match kind {
+			Subject::Actor => {
+				report.user = query.get_user(subject).await?;
+				report.recent_replies = self.replies.recent(subject);
+				report.throttles = self.throttle.windows(subject);
+			}
+			Subject::Instance => {
+				report.instance = query.get_instance_stats(subject).await?;
+				report.rate = self.velocity.rate(subject);
+			}
+		}
+		Ok(report)
 	}
 }
 
-// TODO:
 impl FilterBuilder {
-	pub fn build(&self) -> Filter {
-		Filter {}
+	/// Hosts which may serve activities for actors on other hosts, and vice versa.
+	pub fn origin_exceptions(mut self, hosts: Vec<String>) -> Self {
+		self.origin_exceptions = hosts;
+		self
+	}
+
+	/// Actor URIs of trusted relays. Their own activities skip spam detection, and activities
+	/// they forward wrapped in an `Announce` are judged by the original author instead.
+	pub fn relays(mut self, relays: Vec<String>) -> Self {
+		self.relays = relays;
+		self
+	}
+
+	/// Content-Types a delivery may have, instead of [`DEFAULT_CONTENT_TYPES`].
+	pub fn content_types(mut self, types: Vec<MediaType>) -> Self {
+		self.content_types = Some(types);
+		self
+	}
+
+	/// Peers allowed to tell the AP server who the client is, with X-Forwarded-For and the
+	/// like, instead of [`forwarded::DEFAULT_TRUSTED_PROXIES`].
+	pub fn trusted_proxies(mut self, proxies: Vec<Cidr>) -> Self {
+		self.trusted_proxies = Some(proxies);
+		self
+	}
+
+	/// Limits on the shape of delivered JSON, past which it isn't even parsed.
+	pub fn json_limits(mut self, limits: JsonLimits) -> Self {
+		self.json_limits = limits;
+		self
+	}
+
+	/// Whether to turn away delivered JSON the AP server might read differently, or forward it
+	/// as parsed here.
+	pub fn canonical_body(mut self, canonical_body: CanonicalBody) -> Self {
+		self.canonical_body = canonical_body;
+		self
+	}
+
+	/// Max number of recipients a low reputation actor may address directly in one note.
+	pub fn max_audience(mut self, max_audience: usize) -> Self {
+		self.max_audience = max_audience;
+		self
+	}
+
+	/// Max number of distinct local notes an actor without followers may reply to within
+	/// `window`.
+	pub fn reply_flood(mut self, window: Duration, max: usize) -> Self {
+		self.reply_flood_window = window;
+		self.reply_flood_max = max;
+		self
+	}
+
+	/// How long to remember verdicts on notes, so identical deliveries are judged once. Zero
+	/// judges every delivery.
+	pub fn decision_ttl(mut self, ttl: Duration) -> Self {
+		self.decision_ttl = ttl;
+		self
+	}
+
+	/// How long to drop exact repeats of a forwarded delivery for. Zero forwards every one.
+	pub fn duplicate_ttl(mut self, ttl: Duration) -> Self {
+		self.duplicate_ttl = ttl;
+		self
+	}
+
+	/// Memory budgets of the verdict, dedup and move caches.
+	pub fn caches(mut self, caches: CacheConfig) -> Self {
+		self.caches = caches;
+		self
+	}
+
+	/// How many times its usual rate of notes an instance must deliver within an hour to count
+	/// as surging.
+	pub fn surge_factor(mut self, factor: u32) -> Self {
+		self.surge_factor = factor;
+		self
+	}
+
+	/// Max number of hashtags a note from an actor nobody follows may carry.
+	pub fn max_hashtags(mut self, max_hashtags: usize) -> Self {
+		self.max_hashtags = max_hashtags;
+		self
+	}
+
+	/// Max number of notes per day a new account with a handful of followers may have posted.
+	pub fn max_notes_per_day(mut self, max_notes_per_day: u32) -> Self {
+		self.max_notes_per_day = max_notes_per_day;
+		self
+	}
+
+	/// How long ago, or how far ahead, a note may claim to be published. Zero doesn't check.
+	pub fn published_skew(mut self, max_age: Duration, max_ahead: Duration) -> Self {
+		self.max_published_age = max_age;
+		self.max_published_ahead = max_ahead;
+		self
+	}
+
+	/// Total signal weight at which an activity is considered spam.
+	pub fn spam_score_threshold(mut self, threshold: u32) -> Self {
+		self.spam_score_threshold = threshold;
+		self
+	}
+
+	/// What to do with activities crossing the spam score threshold.
+	pub fn score_action(mut self, action: Action) -> Self {
+		self.score_action = action;
+		self
+	}
+
+	/// What to do with DMs from actors without positive reputation that none of the local
+	/// recipients follow, if anything.
+	pub fn first_dm_action(mut self, action: Option<Action>) -> Self {
+		self.first_dm_action = action;
+		self
+	}
+
+	/// What to do with `Move`s of accounts that look made to collect followers, if anything.
+	pub fn move_action(mut self, action: Option<Action>) -> Self {
+		self.move_action = action;
+		self
+	}
+
+	/// How many quarantined activities to keep around.
+	pub fn quarantine_size(mut self, size: usize) -> Self {
+		self.quarantine_size = size;
+		self
+	}
+
+	pub fn enforcement(mut self, enforcement: Enforcement) -> Self {
+		self.enforcement = enforcement;
+		self
+	}
+
+	/// Reject deliveries to personal inboxes of local users that don't exist, or are deleted or
+	/// suspended, before reading them.
+	pub fn check_recipients(mut self, check: bool) -> Self {
+		self.check_recipients = check;
+		self
+	}
+
+	/// Inspect edits of notes too, like new ones, as spam gets edited into harmless notes.
+	pub fn inspect_updates(mut self, inspect: bool) -> Self {
+		self.inspect_updates = inspect;
+		self
+	}
+
+	pub fn direction(mut self, direction: Direction) -> Self {
+		self.direction = direction;
+		self
+	}
+
+	/// Where actor and instance reputation is kept. In-memory only if not set.
+	pub fn reputation(mut self, reputation: Reputation) -> Self {
+		self.reputation = Some(reputation);
+		self
+	}
+
+	/// Fingerprints of known spam content, shared with whatever else feeds them.
+	pub fn fingerprints(mut self, fingerprints: Fingerprints) -> Self {
+		self.fingerprints = Some(fingerprints);
+		self
+	}
+
+	/// Publish spam fingerprints and instance reputation to other deployments.
+	pub fn share(mut self, share: Share) -> Self {
+		self.share = Some(share);
+		self
+	}
+
+	/// Domains to reject everything from.
+	pub fn blocklist(mut self, blocklist: DomainList) -> Self {
+		self.blocklist = Some(blocklist);
+		self
+	}
+
+	/// Attachments of known spam campaigns, by URL or hosting domain.
+	pub fn attachment_blocklist(mut self, attachments: AttachmentList) -> Self {
+		self.attachments = Some(attachments);
+		self
+	}
+
+	/// Switch to stricter rules while spam rejections surge.
+	pub fn panic(mut self, config: PanicConfig) -> Self {
+		self.panic = Some(config);
+		self
+	}
+
+	/// Suspend or report actors through the local Misskey's admin API after repeated spam.
+	pub fn suspend(mut self, config: SuspendConfig) -> Self {
+		self.suspend = Some(config);
+		self
+	}
+
+	/// Block instances through the local Mastodon's admin API after a lot of spam.
+	pub fn domain_block(mut self, config: DomainBlockConfig) -> Self {
+		self.domain_block = Some(config);
+		self
+	}
+
+	/// Report spam to the AP server's moderators with `Flag` activities.
+	pub fn reporter(mut self, reporter: Reporter) -> Self {
+		self.reporter = Some(reporter);
+		self
+	}
+
+	/// Ask an external classifier to score notes that get this far.
+	pub fn classifier(mut self, config: ClassifierConfig) -> Self {
+		self.classifier = Some(config);
+		self
+	}
+
+	/// Block clients that ask for decoy paths, like `/wp-login.php`, for a while.
+	pub fn honeypot(mut self, config: HoneypotConfig) -> Self {
+		self.honeypot = Some(config);
+		self
+	}
+
+	/// Reject deliveries from denied User-Agents, and be wary of those without one.
+	pub fn user_agents(mut self, config: UserAgentConfig) -> Self {
+		self.user_agents = Some(config);
+		self
+	}
+
+	/// Reject or be wary of deliveries from actors hosted in some networks.
+	pub fn asn(mut self, config: AsnConfig) -> Self {
+		self.asn = Some(config);
+		self
+	}
+
+	/// Ask instances whether actors the AP server doesn't know exist there.
+	pub fn webfinger(mut self, config: WebFingerConfig) -> Self {
+		self.webfinger = Some(config);
+		self
+	}
+
+	/// Ask instances how many followers actors with only a handful here have there.
+	pub fn followers(mut self, config: FollowersConfig) -> Self {
+		self.followers = Some(config);
+		self
+	}
+
+	/// Challenge first deliveries from unknown instances to a proof of work.
+	pub fn pow(mut self, config: PowConfig) -> Self {
+		self.pow = Some(config);
+		self
+	}
+
+	/// Publish every decision to NATS.
+	pub fn events(mut self, config: EventsConfig) -> Self {
+		self.events = Some(config);
+		self
+	}
+
+	/// Write every decision to an audit log.
+	pub fn audit(mut self, audit: Audit) -> Self {
+		self.audit = Some(audit);
+		self
+	}
+
+	/// Email a digest of what was filtered every day or week.
+	pub fn digest(mut self, config: DigestConfig) -> Self {
+		self.digest = Some(config);
+		self
+	}
+
+	/// Push metrics to a StatsD or DogStatsD agent.
+	pub fn statsd(mut self, config: StatsdConfig) -> Self {
+		self.statsd = Some(config);
+		self
+	}
+
+	/// Upload salted hashes of confirmed spam to a community endpoint.
+	pub fn telemetry(mut self, config: TelemetryConfig) -> Self {
+		self.telemetry = Some(config);
+		self
+	}
+
+	/// Domains to let everything through from without looking for spam.
+	pub fn allowlist(mut self, allowlist: DomainList) -> Self {
+		self.allowlist = Some(allowlist);
+		self
+	}
+
+	/// Tarpit deliveries from these domains, and from actors with rock bottom reputation.
+	/// Nothing is tarpitted if not set.
+	pub fn tarpit(mut self, tarpit: DomainList) -> Self {
+		self.tarpit = Some(tarpit);
+		self
+	}
+
+	/// Rules to apply instead of the default ruleset.
+	pub fn rules(mut self, rules: RuleSet) -> Self {
+		self.rules = Some(rules);
+		self
+	}
+
+	/// A candidate ruleset to judge a share of actors with, and compare with the active rules on
+	/// the rest. Its rules must compile.
+	pub fn canary(mut self, canary: CanaryConfig) -> Self {
+		self.canary = Some(canary);
+		self
+	}
+
+	/// Pipeline B, judging every note like the filter does without acting on it, and compared
+	/// with it. Its rules must compile.
+	pub fn shadow(mut self, shadow: ShadowConfig) -> Self {
+		self.shadow = Some(shadow);
+		self
+	}
+
+	/// Rule packs, whose rules follow the others. Their rules must compile.
+	pub fn packs(mut self, packs: Packs) -> Self {
+		self.packs = packs;
+		self
+	}
+
+	pub fn build(self) -> Filter {
+		let mut rules = self.rules.unwrap_or_else(|| match self.direction {
+			Direction::Inbound => RuleSet::default_rules(),
+			// the default rules judge remote senders, and every sender is local here
+			Direction::Outbound => RuleSet::empty(),
+		});
+		if !self.packs.rules.is_empty() {
+			let merged = [rules.configs(), &self.packs.rules[..]].concat();
+			#[allow(clippy::unwrap_used)] // both compiled on their own
+			let merged = RuleSet::compile(&merged).unwrap();
+			rules = merged;
+		}
+		#[allow(clippy::unwrap_used)] // validated at startup
+		let canary = self.canary.map(|c| Arc::new(Canary::compile(&c, &self.packs.rules).unwrap()));
+		#[allow(clippy::unwrap_used)] // validated at startup
+		let shadow = self.shadow.map(|c| Arc::new(Shadow::compile(c, &self.packs.rules).unwrap()));
+		let filter = Filter {
+			origin_exceptions: self.origin_exceptions.into(),
+			relays: self.relays.into(),
+			content_types: self
+				.content_types
+				.unwrap_or_else(|| {
+					#[allow(clippy::unwrap_used)] // known to be valid
+					DEFAULT_CONTENT_TYPES.iter().map(|t| t.parse().unwrap()).collect()
+				})
+				.into(),
+			trusted_proxies: self
+				.trusted_proxies
+				.unwrap_or_else(|| {
+					#[allow(clippy::unwrap_used)] // known to be valid
+					forwarded::DEFAULT_TRUSTED_PROXIES.iter().map(|p| p.parse().unwrap()).collect()
+				})
+				.into(),
+			json_limits: self.json_limits,
+			canonical_body: self.canonical_body,
+			replies: ReplyTracker::new(self.reply_flood_window),
+			velocity: VelocityTracker::new(self.surge_factor),
+			decisions: DecisionCache::new(self.decision_ttl, self.caches.decisions_mb),
+			seen: SeenDeliveries::new(self.duplicate_ttl, self.caches.dedup_mb),
+			history: History::new(),
+			rejections: Rejections::default(),
+			max_published_age: self.max_published_age,
+			max_published_ahead: self.max_published_ahead,
+			score_action: self.score_action,
+			first_dm_action: self.first_dm_action,
+			move_action: self.move_action,
+			inspect_updates: self.inspect_updates,
+			tuning: Arc::new(RwLock::new(Tuning {
+				rules: Arc::new(rules),
+				canary,
+				thresholds: Thresholds {
+					max_audience: self.max_audience,
+					reply_flood_max: self.reply_flood_max,
+					max_hashtags: self.max_hashtags,
+					max_notes_per_day: self.max_notes_per_day,
+					spam_score_threshold: self.spam_score_threshold,
+				},
+				packs: Arc::new(self.packs.lists),
+			})),
+			throttle: Throttle::new(),
+			quarantine: Quarantine::new(self.quarantine_size),
+			strict: Strict::new(),
+			moves: Moves::new(self.caches.moves_mb),
+			shadow,
+			enforcement: self.enforcement,
+			recipients: self.check_recipients.then(Recipients::new),
+			direction: self.direction,
+			reputation: self.reputation.unwrap_or_else(|| {
+				Reputation::new(Duration::from_secs(reputation::DEFAULT_HALF_LIFE_HOURS * 3600))
+			}),
+			fingerprints: self.fingerprints.unwrap_or_else(Fingerprints::new),
+			share: self.share,
+			blocklist: self.blocklist,
+			allowlist: self.allowlist,
+			tarpit: self.tarpit,
+			attachments: self.attachments,
+			panic: self.panic.map(Panic::new),
+			suspend: self.suspend.map(Suspender::new),
+			domain_block: self.domain_block.map(DomainBlocker::new),
+			reporter: self.reporter,
+			classifier: self.classifier.map(Classifier::new),
+			honeypot: self.honeypot.map(Honeypot::new),
+			user_agents: self.user_agents.map(UserAgents::new),
+			asns: self.asn.map(Asns::new),
+			webfinger: self.webfinger.map(WebFinger::new),
+			followers: self.followers.map(Followers::new),
+			pow: self.pow.map(Pow::new),
+			events: self.events.map(Events::new),
+			audit: self.audit,
+			digest: self.digest.map(digest::Digest::new),
+			statsd: self.statsd.map(Statsd::new),
+			telemetry: self.telemetry.map(Telemetry::new),
+		};
+		if let Some(panic) = &filter.panic {
+			tokio::spawn(panic.clone().watch(filter.clone()));
+		}
+		if let Some(digest) = &filter.digest {
+			tokio::spawn(digest.clone().watch(filter.clone()));
+		}
+		filter
+	}
+}
+
+/// DB stats of the actor and its instance, looked up at most once per activity and only when
+/// needed.
+struct Stats<'a> {
+	query: &'a dyn Backend,
+	actor: &'a str,
+	host: &'a str,
+	direction: Direction,
+	followers: Option<&'a Followers>,
+	user: Option<Option<User>>,
+	instance: Option<Option<InstanceStats>>,
+}
+
+impl<'a> Stats<'a> {
+	fn new(
+		query: &'a dyn Backend, actor: &'a str, host: &'a str, direction: Direction,
+		followers: Option<&'a Followers>,
+	) -> Self {
+		Stats { query, actor, host, direction, followers, user: None, instance: None }
+	}
+
+	async fn user(&mut self) -> Result<Option<&User>, QueryError> {
+		if self.user.is_none() {
+			self.user = Some(match self.direction {
+				Direction::Inbound => self.query.get_user(self.actor).await?,
+				// local users have no URI in the DB, but their actor URL ends with their id
+				Direction::Outbound => match self.actor.trim_end_matches('/').rsplit('/').next() {
+					Some(id) => self.query.get_local_user(id).await?,
+					None => None,
+				},
+			});
+			// the AP server's count of a remote actor's followers may be long out of date
+			if let (Some(Some(user)), Some(followers)) = (&mut self.user, self.followers) {
+				if low_reputation(Some(user)) {
+					if let Some(count) = followers.count(self.actor, self.host).await {
+						debug!("{} has {} followers on {}", self.actor, count, self.host);
+						user.followers = user.followers.max(count);
+					}
+				}
+			}
+		}
+		Ok(self.user.as_ref().and_then(|u| u.as_ref()))
+	}
+
+	async fn instance(&mut self) -> Result<Option<&InstanceStats>, QueryError> {
+		if self.instance.is_none() {
+			self.instance = Some(self.query.get_instance_stats(self.host).await?);
+		}
+		Ok(self.instance.as_ref().and_then(|i| i.as_ref()))
+	}
+
+	fn fetched_user(&self) -> Option<Option<&User>> {
+		self.user.as_ref().map(|u| u.as_ref())
+	}
+
+	fn fetched_instance(&self) -> Option<Option<&InstanceStats>> {
+		self.instance.as_ref().map(|i| i.as_ref())
+	}
+
+	/// Judge `base` with `judge`, looking up the stats it asks for until it has what it needs.
+	async fn settle<T>(
+		&mut self, base: &Facts<'_>, mut judge: impl FnMut(&Facts) -> Result<T, Need>,
+	) -> Result<T, QueryError> {
+		loop {
+			let facts =
+				Facts { actor: self.fetched_user(), instance: self.fetched_instance(), ..*base };
+			match judge(&facts) {
+				Ok(judged) => return Ok(judged),
+				Err(Need::Actor) => {
+					self.user().await?;
+				}
+				Err(Need::Instance) => {
+					self.instance().await?;
+				}
+			}
+		}
+	}
+}
+
+/// Unknown actors or actors with only a handful of followers.
+fn low_reputation(user: Option<&User>) -> bool {
+	match user {
+		Some(user) => user.followers < LOW_REPUTATION_FOLLOWERS,
+		None => true,
+	}
+}
+
+/// Unknown actors or actors nobody follows.
+fn no_followers(user: Option<&User>) -> bool {
+	match user {
+		Some(user) => user.followers == 0,
+		None => true,
+	}
+}
+
+/// Notes per day posted by an account younger than [`NEW_ACCOUNT_AGE`], counting it at least an
+/// hour old so the first few notes don't look like a flood.
+fn notes_per_day(user: &User) -> Option<u32> {
+	if user.age >= NEW_ACCOUNT_AGE {
+		return None;
+	}
+	let days = user.age.max(Duration::from_secs(60 * 60)).as_secs_f64() / (24.0 * 60.0 * 60.0);
+	Some((f64::from(user.notes.max(0)) / days) as u32)
+}
+
+/// Whether the request is an activity delivered to an inbox.
+fn is_delivery(header: &[u8]) -> bool {
+	header.starts_with(b"POST ") && targets_inbox(header)
+}
+
+/// Whether the request line targets an inbox, in origin or absolute form.
+fn targets_inbox(header: &[u8]) -> bool {
+	let line = header.split(|&b| b == b'\r' || b == b'\n').next().unwrap_or_default();
+	let Some(target) = line.split(|&b| b == b' ').nth(1) else {
+		return false;
+	};
+	let path = target.split(|&b| b == b'?').next().unwrap_or_default();
+	path.ends_with(b"/inbox")
+}
+
+/// Target of the request line, like `/users/alice?page=1`, if it's UTF-8.
+fn request_target(header: &[u8]) -> Option<&str> {
+	let line = header.split(|&b| b == b'\r' || b == b'\n').next()?;
+	std::str::from_utf8(line.split(|&b| b == b' ').nth(1)?).ok()
+}
+
+/// Value of the Host header, if there's exactly one.
+fn request_host(header: &[u8]) -> Option<&str> {
+	Headers::parse(header).ok()?.get("host").ok()?
+}
+
+/// Whether the URI points at the server with host `local`.
+fn is_local(uri: &str, local: Option<&str>) -> bool {
+	let Some(local) = local else {
+		return false;
+	};
+	uri.parse::<Url>().ok().is_some_and(|u| u.host_str() == Some(local))
+}
+
+/// URIs in a property that may be a single link or object, or an array of those.
+fn uris(value: &Value) -> Vec<&str> {
+	fn uri(v: &Value) -> Option<&str> {
+		v.as_str().or_else(|| v.get("id").and_then(|i| i.as_str()))
+	}
+	match value.as_array() {
+		Some(values) => values.iter().filter_map(uri).collect(),
+		None => uri(value).into_iter().collect(),
 	}
 }
 
 impl Filter {
 	pub async fn handler(
-		&self, incoming_stream: TcpStream, query: Query,
-	) -> Result<Admit, RejectReason> {
-		trace!("New connection from: {:?}", incoming_stream.peer_addr());
+		&self, incoming_stream: TcpStream, routes: &Routes,
+	) -> Result<Admit, Rejected> {
+		let peer = incoming_stream.peer_addr().ok();
+		self.relayed_handler(incoming_stream, peer, routes).await
+	}
+
+	/// [`Filter::handler`] for a connection relayed on behalf of `peer`, such as one TLS was
+	/// taken off of.
+	pub async fn relayed_handler(
+		&self, incoming_stream: TcpStream, peer: Option<SocketAddr>, routes: &Routes,
+	) -> Result<Admit, Rejected> {
+		let mut seen_key = None;
+		let started = Instant::now();
+		let mut request_id = request_id::generate();
+		Span::current().record("request_id", request_id.as_str());
+		let mut trail = Trail::default();
+		let inspected = self
+			.inspect(&incoming_stream, peer, routes, &mut seen_key, &mut request_id, &mut trail)
+			.await;
+		if let Some(statsd) = &self.statsd {
+			statsd.inspected(started.elapsed());
+			match &inspected {
+				Ok(_) => statsd.admitted(),
+				Err(reason) => statsd.rejected(reason.category()),
+			}
+		}
+		if let Some(audit) = &self.audit {
+			// requests for pages and media are let through without a decision to speak of
+			let decided = match &inspected {
+				Ok((header, ..)) => is_delivery(header).then_some(("accepted", "forward")),
+				Err(reason) => Some((reason.kind(), reason.action())),
+			};
+			if let Some((verdict, action)) = decided {
+				audit.record(&Record {
+					request_id: &request_id,
+					verdict,
+					action,
+					trail: &trail,
+					latency_us: started.elapsed().as_micros().try_into().unwrap_or(u64::MAX),
+				});
+			}
+		}
+		match inspected {
+			Ok((pending_header, pending_body, upstream)) => {
+				// only once forwarded, so rejected deliveries can be retried
+				let seen = seen_key.map(|key| (self.seen.clone(), key));
+				let upstream = routes.pick(&upstream);
+				Ok(Admit { incoming_stream, upstream, pending_header, pending_body, seen })
+			}
+			Err(reason) => {
+				let origin = reason.origin();
+				if let Some(origin) = &origin {
+					self.rejections.record(origin.clone(), reason.kind());
+				}
+				if let Some(digest) = &self.digest {
+					digest.rejected(reason.kind(), origin.as_deref());
+				}
+				// verdicts on notes are published as they're made, with more to say
+				let note_verdict = matches!(
+					reason,
+					RejectReason::Spam(..)
+						| RejectReason::Quarantined(..)
+						| RejectReason::Throttled(..)
+				);
+				if let (Some(events), false) = (&self.events, note_verdict) {
+					events.publish(&Event {
+						verdict: reason.kind(),
+						actor: None,
+						host: origin.as_deref(),
+						note: None,
+						score: None,
+						signals: Vec::new(),
+						request_id: &request_id,
+					});
+				}
+				Err(Rejected { incoming_stream, reason })
+			}
+		}
+	}
+
+	/// Read as much of the request as needed to judge it, and return what was read so far and
+	/// where to forward it. Deliveries to remember as forwarded if they are get a `seen_key`.
+	async fn inspect(
+		&self, incoming_stream: &TcpStream, peer: Option<SocketAddr>, routes: &Routes,
+		seen_key: &mut Option<String>, request_id: &mut String, trail: &mut Trail,
+	) -> Result<(Vec<u8>, Vec<u8>, Upstream), RejectReason> {
+		trace!("New connection from: {:?}", peer);
+		let trusted = match peer {
+			Some(SocketAddr::V4(peer)) => {
+				self.trusted_proxies.iter().any(|p| p.contains(*peer.ip()))
+			}
+			_ => false,
+		};
 
 		const HEADER_FILTER_LEN: usize = 17;
 
@@ -103,11 +1460,18 @@ impl Filter {
 			return Err(RejectReason::ConnectionTerminated);
 		}
 
-		// currently we only care about POST /inbox
-		if header[0..HEADER_FILTER_LEN] != *b"POST /inbox HTTP/" {
-			return Ok(Admit { incoming_stream, pending_header: header, pending_body: body });
+		// targets like `/users/../inbox` would get past the check for deliveries below, and be
+		// taken for one by the AP server
+		let request_line = header.contains(&b'\n');
+		if request_line {
+			target::check(&header, self.direction).map_err(RejectReason::MalformedHeader)?;
 		}
 
+		// currently we only care about deliveries, to the shared inbox or an actor's, checked
+		// again once the request line is complete. Everything else is let through as well, once
+		// its header is complete and cleaned up below
+		let delivery = header.starts_with(b"POST ") && (!request_line || targets_inbox(&header));
+
 		// we should be able to get rest of the header in 500ms
 		timeout(Duration::from_millis(HEADER_TIMEOUT_MS), async {
 			let mut header_done = false;
@@ -145,44 +1509,152 @@ impl Filter {
 			Ok(())
 		})
 		.await??;
+		if !request_line {
+			target::check(&header, self.direction).map_err(RejectReason::MalformedHeader)?;
+		}
+		// https:// deliveries of an AP server using us as its proxy come as tunnels we can't look
+		// into, so they can only be let through
+		let tunnel = target::is_tunnel(&header, self.direction);
+		if tunnel {
+			target::tunnelled(&header);
+		}
 
-		// get host, content-length & content-type
-		let mut content_length = None;
-		let mut content_type = None;
-		for line in header.split(|&x| x == b'\n') {
-			if crate::HOST.get().is_some() && content_length.is_some() && content_type.is_some() {
-				break;
+		// so nginx, spam-musubi and the AP server all log the same ID
+		if trusted {
+			let headers = Headers::parse(&header).ok();
+			if let Some(id) = headers.as_ref().and_then(request_id::forwarded) {
+				*request_id = id.to_string();
+				Span::current().record("request_id", id);
 			}
-			// might not be "safe" without reverse proxy in front
-			if crate::HOST.get().is_none()
-				&& (line.starts_with(b"Host: ") || line.starts_with(b"host: "))
-			{
-				if let Ok(h) = std::str::from_utf8(&line[6..line.len() - 1]) {
-					crate::HOST.set(h.to_string()).ok();
+		}
+		let upgrade = !delivery && (hop::is_upgrade(&header) || tunnel);
+		if header.ends_with(b"\r\n\r\n") {
+			// the AP server must only ever see our own verdicts, whatever the request
+			strip_headers(&mut header, b"x-musubi-");
+			strip_headers(&mut header, b"x-request-id:");
+			append_header(&mut header, request_id::HEADER, request_id);
+			// replayed as they are, the client's keep-alive wishes leave the AP server waiting
+			// for requests that never come. Only one is read per connection, unless it's
+			// switched over to WebSocket or a tunnel and relayed as is from then on
+			hop::strip(&mut header, upgrade);
+			if !upgrade {
+				append_header(&mut header, "Connection", "close");
+			}
+		}
+
+		if let Some(honeypot) = &self.honeypot {
+			// never the proxy itself, which would block everyone behind it
+			let client = match trusted {
+				true => Headers::parse(&header).ok().and_then(|h| forwarded::client(&h)),
+				false => peer.map(|peer| peer.ip()),
+			};
+			if let Some(client) = client {
+				if honeypot.is_blocked(client) {
+					return Err(RejectReason::HoneypotBlocked(client));
+				}
+				let path = request_target(&header).unwrap_or_default();
+				if honeypot.is_trap(path) {
+					honeypot.trip(client, path);
+					return Err(RejectReason::Honeypot(client, path.to_string()));
 				}
 			}
-			if content_length.is_none()
-				&& (line.starts_with(b"Content-Length: ") || line.starts_with(b"content-length: "))
-			{
-				content_length = std::str::from_utf8(&line[16..line.len() - 1])
-					.ok()
-					.and_then(|x| x.parse::<usize>().ok());
+		}
+
+		// clients could pass for anyone to the AP server otherwise
+		if !trusted {
+			forwarded::strip(&mut header);
+		}
+
+		// outbound, Host is the remote server
+		let upstream = match self.direction {
+			Direction::Inbound => {
+				// scanners and host header confusion
+				let host = request_host(&header);
+				if !routes.accepts(host) {
+					return Err(RejectReason::UnexpectedHost(host.unwrap_or_default().to_string()));
+				}
+				routes.route(host)
 			}
-			if content_type.is_none()
-				&& (line.starts_with(b"Content-Type: ") || line.starts_with(b"content-type: "))
-			{
-				content_type = std::str::from_utf8(&line[14..line.len() - 1]).ok();
+			Direction::Outbound => routes.route(None),
+		};
+		// before reading the body and judging it, for nothing
+		if !routes.is_up(upstream) {
+			return Err(RejectReason::Unavailable(upstream.backends.to_string()));
+		}
+		// including streaming connections, which have no body to wait for and stay open for as
+		// long as they like
+		if !delivery || !targets_inbox(&header) {
+			return Ok((header, body, upstream.clone()));
+		}
+		let query = upstream.query.as_ref();
+
+		// get host, content-length & content-type
+		let headers = Headers::parse(&header).map_err(RejectReason::MalformedHeader)?;
+		// might not be "safe" without reverse proxy in front
+		if crate::HOST.get().is_none()
+			&& self.direction == Direction::Inbound
+			&& upstream.host.is_none()
+		{
+			if let Ok(Some(h)) = headers.get("host") {
+				crate::HOST.set(h.to_string()).ok();
 			}
 		}
-		let content_length =
-			content_length.ok_or(RejectReason::MalformedHeader("content-length not found"))?;
-		let content_type =
-			content_type.ok_or(RejectReason::MalformedHeader("content-type not found"))?;
+		// the body is read by Content-Length, while the AP server would go by Transfer-Encoding,
+		// and see another request in the rest (RFC 9112 section 6.3)
+		if headers.all("transfer-encoding").next().is_some() {
+			return Err(RejectReason::MalformedHeader("transfer-encoding not supported"));
+		}
+		let content_length = headers
+			.content_length()
+			.map_err(RejectReason::MalformedHeader)?
+			.ok_or(RejectReason::MalformedHeader("content-length not found"))?;
+		let content_type = headers
+			.get("content-type")
+			.map_err(RejectReason::MalformedHeader)?
+			.ok_or(RejectReason::MalformedHeader("content-type not found"))?
+			.parse::<MediaType>()
+			.map_err(RejectReason::MalformedHeader)?;
 
-		if !content_type.starts_with("application/activity+json")
-			&& !content_type.starts_with("application/ld+json")
+		if !self.content_types.iter().any(|accepted| content_type.matches(accepted)) {
+			return Err(RejectReason::BadRequest("content-type not accepted"));
+		}
+
+		// spam scripts give themselves away before their body is read. Outbound, every sender
+		// is our own AP server
+		let user_agent = headers.all("user-agent").next().map(|ua| ua.to_string());
+		let key_id = headers.all("signature").next().and_then(origin::key_id).map(str::to_string);
+		let pow_solution = match self.pow {
+			Some(_) => headers.all(pow::HEADER).next().map(|s| s.to_string()),
+			None => None,
+		};
+		let user_agent_verdict = match (&self.user_agents, self.direction) {
+			(Some(user_agents), Direction::Inbound) => user_agents.check(user_agent.as_deref()),
+			_ => user_agent::Verdict::Fine,
+		};
+		if user_agent_verdict == user_agent::Verdict::Denied
+			&& self.enforcement != Enforcement::Annotate
 		{
-			return Err(RejectReason::BadRequest("content-type not application/activity+json"));
+			return Err(RejectReason::UserAgent(user_agent.unwrap_or_default()));
+		}
+
+		// enumeration attacks and deliveries to dead mailboxes, which the AP server would only
+		// turn away after reading them
+		if let (Some(recipients), Direction::Inbound) = (&self.recipients, self.direction) {
+			if let Some(id) = request_target(&header).and_then(recipients::inbox_owner) {
+				if !recipients.active(upstream.backends.primary(), query, id).await? {
+					return Err(RejectReason::NoRecipient(id.to_string()));
+				}
+			}
+		}
+
+		// clients waiting for a go-ahead before the body get it from us, since the AP server
+		// won't see the request until the body is in
+		if expects_continue(&header, &headers) {
+			strip_headers(&mut header, b"expect:");
+			if body.is_empty() {
+				let go_ahead = write_all(incoming_stream, b"HTTP/1.1 100 Continue\r\n\r\n");
+				timeout(Duration::from_millis(HEADER_TIMEOUT_MS), go_ahead).await??;
+			}
 		}
 
 		// read body
@@ -219,78 +1691,743 @@ impl Filter {
 		// we assume reverse proxy always uses HTTP/1.0 or HTTP/1.1 to forward back
 		// so no need to handle encoded requests
 
+		self.json_limits.check(&body).map_err(RejectReason::TooComplex)?;
+
 		let ap_json = sonic_rs::from_slice::<Value>(&body).map_err(|_| {
-			RejectReason::InvalidRequest(
-				"malformed JSON",
-				String::from_utf8_lossy(&body).to_string(),
-			)
+			RejectReason::InvalidRequest("malformed JSON", Payload::new(&body))
 		})?;
+		if self.canonical_body != CanonicalBody::Off {
+			if let Some(key) = canonical::duplicate_key(&ap_json) {
+				debug!("Delivery gives key {:?} twice", key);
+				return Err(RejectReason::InvalidRequest("duplicate key", Payload::new(&body)));
+			}
+			if self.canonical_body == CanonicalBody::Rewrite {
+				body = canonical::rewrite(&mut header, &ap_json).map_err(|_| {
+					RejectReason::InvalidRequest("malformed JSON", Payload::new(&body))
+				})?;
+			}
+		}
+
+		// replay floods and senders stuck retrying
+		if let Some(id) = ap_json.get("id").and_then(|i| i.as_str()) {
+			if self.enforcement != Enforcement::Annotate {
+				let host = request_host(&header);
+				let key = SeenDeliveries::key(upstream.backends.primary(), &header, host, &body);
+				if self.seen.contains(&key) {
+					return Err(RejectReason::Duplicate(id.to_string()));
+				}
+				*seen_key = Some(key);
+			}
+		}
+
+		// forged deliveries are signed with a key of the forger's, not the actor's instance
+		let mut forged_key = false;
+		if let Some(key_id) = &key_id {
+			let (exceptions, relays) = (&self.origin_exceptions, &self.relays);
+			if let Err(what) = origin::check_key_id(&ap_json, key_id, exceptions, relays) {
+				if self.enforcement != Enforcement::Annotate {
+					return Err(RejectReason::InvalidRequest(what, Payload::new(&body)));
+				}
+				forged_key = true;
+			}
+		}
+
+		// relays forward others' activities, which are judged by their original author
+		let relayed = ap_json
+			.get("actor")
+			.and_then(|a| a.as_str())
+			.is_some_and(|a| self.relays.iter().any(|r| r == a));
+		let ap_json = if relayed {
+			let Some(inner) = relay::unwrap(&ap_json) else {
+				self.annotate(&mut header, &Marks::default());
+				return Ok((header, body, upstream.clone()));
+			};
+			inner
+		} else {
+			ap_json
+		};
 
 		// spam detection part
 
 		// spam doesn't seem to be sending out raw malformed requests
 		// fingers crossed
 
-		// check if this is a new note
-		if ap_json
-			.get("type")
-			.and_then(|t| t.as_str())
-			.and_then(|t| if t == "Create" || t == "create" { Some(()) } else { None })
-			.is_none() || ap_json
-			.get("object")
-			.and_then(|o| o.get("type"))
-			.and_then(|t| t.as_str())
-			.and_then(|t| if t == "Note" || t == "note" { Some(()) } else { None })
-			.is_none()
+		// blocked domains get nothing through, whatever the activity
+		let actor_host = ap_json
+			.get("actor")
+			.and_then(|a| a.as_str())
+			.and_then(|a| a.parse::<Url>().ok())
+			.and_then(|a| a.host_str().map(|h| h.to_string()));
+		let blocked = match (&self.blocklist, &actor_host) {
+			(Some(blocklist), Some(host)) => blocklist.contains(host),
+			_ => false,
+		} || actor_host.as_deref().is_some_and(|host| upstream.tenant.blocks(host));
+		if blocked && self.enforcement != Enforcement::Annotate {
+			return Err(RejectReason::Blocked(actor_host.unwrap_or_default()));
+		}
+		if let (Some(tarpit), Some(actor), Some(host)) =
+			(&self.tarpit, ap_json.get("actor").and_then(|a| a.as_str()), &actor_host)
+		{
+			let confirmed = tarpit.contains(host) || self.reputation.confirmed_spammer(actor);
+			if confirmed
+				&& self.enforcement != Enforcement::Annotate
+				&& self.direction == Direction::Inbound
+			{
+				return Err(RejectReason::Tarpitted(actor.to_string()));
+			}
+		}
+		// moderators pinned the domain to stricter treatment for a while, over the allowlist
+		let mut pinned = Marks::default();
+		let strict = actor_host.as_deref().and_then(|host| self.strict.action(host));
+		if let (Some(action), Some(actor)) = (strict, ap_json.get("actor").and_then(|a| a.as_str()))
 		{
-			return Ok(Admit { incoming_stream, pending_header: header, pending_body: body });
+			trail.matched(STRICT_STAGE, &pinned.score);
+			self.stage_act(action, STRICT_STAGE, actor, &header, &body, &mut pinned)?;
+		}
+		let allowed = match (&self.allowlist, &actor_host) {
+			(Some(allowlist), Some(host)) => allowlist.contains(host),
+			_ => false,
+		};
+		if allowed && !blocked && strict.is_none() {
+			self.annotate(&mut header, &Marks::default());
+			return Ok((header, body, upstream.clone()));
+		}
+		#[allow(clippy::unwrap_used)]
+		let mut tuning = self.tuning.read().unwrap().clone();
+		upstream.tenant.thresholds.apply(&mut tuning.thresholds);
+
+		// only look at new posts, and edits if asked to
+		let activity_type = ap_json.get("type").and_then(|t| t.as_str()).unwrap_or_default();
+		let updated = self.inspect_updates && activity_type.eq_ignore_ascii_case("Update");
+		if !activity_type.eq_ignore_ascii_case("Create") && !updated {
+			// accounts made to collect followers, then hand them on to a spam account
+			let mut moved = false;
+			if let (Some(action), Direction::Inbound) = (self.move_action, self.direction) {
+				let origin = ap_json.get("object").and_then(|o| uris(o).first().copied());
+				let target = ap_json.get("target").and_then(|t| uris(t).first().copied());
+				// only accounts can move themselves, others are just claiming they did
+				let actor = ap_json.get("actor").and_then(|a| uris(a).first().copied());
+				let origin = origin.filter(|&origin| Some(origin) == actor);
+				if let (true, Some(origin), Some(target)) =
+					(activity_type.eq_ignore_ascii_case("Move"), origin, target)
+				{
+					let user = query.get_user(origin).await?;
+					if let Some(suspicion) = self.moves.record(origin, target, user.as_ref()) {
+						debug!("{} moved to {}, {}", origin, target, suspicion.as_str());
+						moved = true;
+						trail.matched(MOVE_STAGE, &pinned.score);
+						self.stage_act(action, MOVE_STAGE, origin, &header, &body, &mut pinned)?;
+					}
+				}
+			}
+			if strict.is_some() || moved {
+				self.annotate(&mut header, &pinned);
+			}
+			return Ok((header, body, upstream.clone()));
 		}
 
 		let actor = ap_json
 			.get("actor")
 			.and_then(|a| a.as_str())
 			.and_then(|a| a.parse::<Url>().ok())
-			.ok_or(RejectReason::InvalidRequest(
-				"invalid actor",
-				String::from_utf8_lossy(&body).to_string(),
-			))?;
-		let host = actor.host_str().ok_or(RejectReason::InvalidRequest(
-			"invalid actor (no host)",
-			String::from_utf8_lossy(&body).to_string(),
-		))?;
-
-		// only check if this note generates notifications
-		if let Some(ccs) =
-			ap_json.get("object").and_then(|o| o.get("cc")).and_then(|cc| cc.as_array())
+			.ok_or(RejectReason::InvalidRequest("invalid actor", Payload::new(&body)))?;
+		let host = actor
+			.host_str()
+			.ok_or(RejectReason::InvalidRequest("invalid actor (no host)", Payload::new(&body)))?;
+		trail.actor = Some(actor.to_string());
+		trail.host = Some(host.to_string());
+		if self.direction == Direction::Outbound {
+			// only our own AP server delivers through here
+			crate::HOST.get_or_init(|| host.to_string());
+		}
+		let local = upstream.host.as_deref().or_else(|| crate::HOST.get().map(|h| h.as_str()));
+
+		let mut marks = pinned;
+		if blocked {
+			marks.score.add("blocklist", score::STRONG);
+		}
+		match user_agent_verdict {
+			user_agent::Verdict::Denied => marks.score.add("user-agent", score::STRONG),
+			user_agent::Verdict::Missing => {
+				let weight = self.user_agents.as_ref().map_or(0, |u| u.missing_weight);
+				if weight > 0 {
+					debug!("{} sent no User-Agent", actor);
+					marks.score.add("user-agent", weight);
+				}
+			}
+			user_agent::Verdict::Fine => {}
+		}
+
+		// spam instances cluster on a few bulletproof hosters
+		if let (Some(asns), Direction::Inbound) = (&self.asns, self.direction) {
+			match asns.check(host).await {
+				asn::Verdict::Blocked(asn) => {
+					if self.enforcement != Enforcement::Annotate {
+						return Err(RejectReason::Asn(host.to_string(), asn));
+					}
+					marks.score.add("asn", score::STRONG);
+				}
+				asn::Verdict::Suspect(asn) => {
+					debug!("{} is hosted in AS{}", actor, asn);
+					marks.score.add("asn", asns.weight());
+				}
+				asn::Verdict::Fine => {}
+			}
+		}
+
+		// first contact from an instance nobody here has heard of has to be worked for
+		if let (Some(pow), Direction::Inbound, Enforcement::Enforce) =
+			(&self.pow, self.direction, self.enforcement)
 		{
-			if ccs
-				.iter()
-				.filter_map(|cc| cc.as_str().and_then(|cc| cc.parse::<Url>().ok()))
-				.any(|cc| cc.host_str() == crate::HOST.get().map(|s| s.as_str()))
-			{
-				let instance_stats =
-					query.get_instance_stats(host).await?.ok_or(RejectReason::Spam(
-						actor.to_string(),
-						String::from_utf8_lossy(&body).to_string(),
-					))?;
-				if instance_stats.followers < SKETCHY_INSTANCE_THRESHOLD
-					&& instance_stats.following < SKETCHY_INSTANCE_THRESHOLD
+			if let Some(solution) = &pow_solution {
+				strip_headers(&mut header, b"musubi-pow:");
+				if pow.solved(host, solution) {
+					debug!("{} solved a proof-of-work challenge", host);
+					pow.pass(host);
+				}
+			}
+			if !pow.passed(host) {
+				if query.get_instance_stats(host).await?.is_none() {
+					return Err(RejectReason::Challenged(host.to_string(), pow.challenge(host)));
+				}
+				pow.pass(host);
+			}
+		}
+
+		if let Err(what) = origin::check_origin(&ap_json, host, &self.origin_exceptions) {
+			if self.enforcement != Enforcement::Annotate {
+				return Err(RejectReason::InvalidRequest(what, Payload::new(&body)));
+			}
+			marks.score.add("origin", score::STRONG);
+		}
+		if forged_key {
+			marks.score.add("key-id", score::STRONG);
+		}
+
+		// check if this is a new note, or a poll
+		let object_type =
+			ap_json.get("object").and_then(|o| o.get("type")).and_then(|t| t.as_str());
+		if !object_type.is_some_and(|t| NOTE_TYPES.iter().any(|n| t.eq_ignore_ascii_case(n))) {
+			self.annotate(&mut header, &marks);
+			return Ok((header, body, upstream.clone()));
+		}
+
+		// the note reports point moderators to
+		let note = ap_json.get("object").and_then(|o| uris(o).first().copied());
+		trail.note = note.map(str::to_string);
+
+		// the same note delivered to many inboxes, or retried, is scored once. Throttles, rules
+		// and reputation still count every delivery
+		let cache_key = DecisionCache::key(upstream.backends.primary(), actor.as_str(), &body);
+		let cached = match self.decisions.get(&cache_key) {
+			Some(Decision::Accept(scored)) => Some(scored),
+			Some(Decision::Reject) => {
+				self.verdict("spam", actor.as_str(), host, note, None, request_id);
+				return Err(RejectReason::Spam(actor.to_string(), Payload::new(&body)));
+			}
+			None => None,
+		};
+
+		let followers = self.followers.as_ref().filter(|_| self.direction == Direction::Inbound);
+		let mut stats = Stats::new(query, actor.as_str(), host, self.direction, followers);
+
+		let audience = audience::audience_size(&ap_json);
+		let mut recent_replies = 0;
+		if let Some(in_reply_to) = ap_json
+			.get("object")
+			.and_then(|o| o.get("inReplyTo"))
+			.and_then(|r| r.as_str())
+			.filter(|r| is_local(r, local) == (self.direction == Direction::Inbound))
+		{
+			recent_replies = self.replies.record(actor.as_str(), in_reply_to);
+		}
+		let hashtags = tags::hashtag_count(&ap_json);
+		let mentions = tags::mention_count(&ap_json);
+		let emoji = emoji::emoji(&ap_json);
+		// edits aren't new notes, so they don't add to the rate
+		let (instance_rate, instance_surge) =
+			if updated { self.velocity.surging(host) } else { self.velocity.check(host) };
+		let fingerprint = fingerprint::content_fingerprint(&ap_json);
+
+		// signals found the first time round, before any rule acted on them
+		let fresh = match cached {
+			Some(scored) => {
+				marks = scored;
+				None
+			}
+			None => {
+				// actors made up by the sender, which their own instance has never heard of
+				if let (Some(webfinger), Direction::Inbound) = (&self.webfinger, self.direction) {
+					if stats.user().await?.is_none()
+						&& webfinger.check(actor.as_str(), host).await
+							== webfinger::Verdict::Unacknowledged
+					{
+						debug!("{} is unknown to {}", actor, host);
+						if self.enforcement != Enforcement::Annotate {
+							return Err(RejectReason::Unacknowledged(actor.to_string()));
+						}
+						marks.score.add("webfinger", score::STRONG);
+					}
+				}
+
+				// mention blasts from nobodies
+				if audience > tuning.thresholds.max_audience && low_reputation(stats.user().await?)
+				{
+					debug!("{} addressed {} recipients directly", actor, audience);
+					marks.score.add("audience", score::STRONG);
+				}
+
+				// bots replying to every trending post
+				let flood = recent_replies > tuning.thresholds.reply_flood_max;
+				if flood && no_followers(stats.user().await?) {
+					debug!("{} replied to {} notes", actor, recent_replies);
+					marks.score.add("reply-flood", score::STRONG);
+				}
+
+				// hashtag-stuffed promos
+				if hashtags > tuning.thresholds.max_hashtags && no_followers(stats.user().await?) {
+					debug!("{} used {} hashtags", actor, hashtags);
+					marks.score.add("hashtags", score::STRONG);
+				}
+
+				// rows of emoji thrown at strangers
+				let flood = emoji.count >= EMOJI_FLOOD_MIN && emoji.percent >= EMOJI_FLOOD_PERCENT;
+				if flood && mentions > 0 && no_followers(stats.user().await?) {
+					let (count, percent) = (emoji.count, emoji.percent);
+					debug!("{} sent {} emoji ({}%) mentioning others", actor, count, percent);
+					marks.score.add("emoji-flood", score::WEAK);
+				}
+
+				// aaaaaa… and templates stamped over and over by throwaway accounts
+				let unproven = self.reputation.get(Subject::Actor, actor.as_str()) <= 0.0;
+				if unproven
+					&& gibberish::is_gibberish(&ap_json)
+					&& low_reputation(stats.user().await?)
 				{
-					let user_stats =
-						query.get_user(actor.as_str()).await?.ok_or(RejectReason::Spam(
-							actor.to_string(),
-							String::from_utf8_lossy(&body).to_string(),
-						))?;
-					if user_stats.followers == 0 && user_stats.following == 0 {
-						return Err(RejectReason::Spam(
-							actor.to_string(),
-							String::from_utf8_lossy(&body).to_string(),
-						));
+					debug!("{} sent gibberish", actor);
+					marks.score.add("gibberish", score::WEAK);
+				}
+
+				// replayed spam, and bot software with no idea what time it is
+				if let Some(age) = published::age(&ap_json) {
+					let max_age = self.max_published_age.as_secs() as i64;
+					let max_ahead = self.max_published_ahead.as_secs() as i64;
+					if (max_age > 0 && age > max_age) || (max_ahead > 0 && -age > max_ahead) {
+						debug!("{} sent a note published {}s ago", actor, age);
+						marks.score.add("published", score::STRONG);
 					}
 				}
+
+				// brand-new accounts that already posted thousands of notes
+				let user = stats.user().await?;
+				let notes_per_day = user.and_then(notes_per_day);
+				let prolific =
+					notes_per_day.is_some_and(|n| n > tuning.thresholds.max_notes_per_day);
+				if prolific && low_reputation(user) {
+					let notes = notes_per_day.unwrap_or(0);
+					debug!("{} posted {} notes per day since joining", actor, notes);
+					marks.score.add("notes-rate", score::STRONG);
+				}
+
+				// campaigns reusing the same hosted image or file, however the text is reworded
+				if self.attachments.as_ref().is_some_and(|a| a.matches(&ap_json)) {
+					debug!("{} attached a blocklisted file", actor);
+					marks.score.add("attachment", score::STRONG);
+				}
+
+				// remote notes getting our own media proxy or drive to fetch and serve files
+				if let (Some(local), Direction::Inbound) = (local, self.direction) {
+					if let Some(url) = media_proxy::local_attachment(&ap_json, local) {
+						debug!("{} attached {} from this server", actor, url);
+						marks.score.add("media-proxy", score::WEAK);
+					}
+				}
+
+				// instances suddenly delivering far more than usual, e.g. overrun by spam bots
+				if instance_surge {
+					debug!("{} delivered {} notes in the last hour", host, instance_rate);
+					marks.score.add("velocity", score::WEAK);
+				}
+
+				// copies of content already judged spam, here, by a peer or by a rule pack
+				let known = |f: &str| {
+					self.fingerprints.contains(f) || tuning.packs.fingerprints.contains(f)
+				};
+				if fingerprint.as_deref().is_some_and(known) {
+					debug!("{} sent known spam content", actor);
+					marks.score.add("fingerprint", score::STRONG);
+				}
+
+				// phrases of campaigns that rule packs were written against
+				let note_text = text::note_text(&ap_json);
+				if let Some(keyword) = note_text.as_deref().and_then(|t| tuning.packs.keyword_in(t))
+				{
+					debug!("{} used the keyword \"{}\"", actor, keyword);
+					marks.score.add("keyword", score::STRONG);
+				}
+
+				// whatever the external classifier makes of it
+				if let Some(classifier) = &self.classifier {
+					if let Some(score) = classifier.score(&body).await.filter(|s| *s > 0) {
+						debug!("{} was scored {} by the classifier", actor, score);
+						marks.score.add("classifier", score);
+					}
+				}
+
+				Some(marks.clone())
+			}
+		};
+
+		// known spammers get less leeway. Known-good senders get no more, or a busy instance's
+		// reputation alone would outweigh strong signals from a fresh account on it
+		let actor_reputation = self.reputation.get(Subject::Actor, actor.as_str()).round() as i64;
+		let instance_reputation = self.reputation.get(Subject::Instance, host).round() as i64;
+		let penalty = (actor_reputation + instance_reputation).min(0);
+		let threshold = i64::from(tuning.thresholds.spam_score_threshold);
+		let threshold = (threshold + penalty).max(1);
+
+		// whether this note generates notifications
+		let audience_local = ap_json
+			.get("object")
+			.and_then(|o| o.get("cc"))
+			.is_some_and(|cc| uris(cc).into_iter().any(|uri| is_local(uri, local)));
+
+		let object_type = object_type.unwrap_or_default();
+		// stats are filled in as rules need them
+		let base = Facts {
+			activity_type,
+			object_type,
+			actor: None,
+			instance: None,
+			mentions,
+			hashtags,
+			emojis: emoji.count,
+			emoji_percent: emoji.percent,
+			audience_size: audience,
+			audience_local,
+			visibility: audience::visibility(&ap_json).as_str(),
+			recent_replies,
+			instance_rate,
+			instance_surge,
+			score: marks.score.total(),
+			actor_reputation,
+			instance_reputation,
+		};
+
+		// pipeline B judges the note too, for comparison only, once like the signals
+		if let (Some(shadow), Some(_)) = (&self.shadow, &fresh) {
+			let user = stats.user().await?;
+			let notes_per_day = user.and_then(notes_per_day);
+			let thresholds = shadow.thresholds(&tuning.thresholds);
+			let mut shadow_score = marks.score.without(shadow::THRESHOLD_SIGNALS);
+			if audience > thresholds.max_audience && low_reputation(user) {
+				shadow_score.add("audience", score::STRONG);
+			}
+			if recent_replies > thresholds.reply_flood_max && no_followers(user) {
+				shadow_score.add("reply-flood", score::STRONG);
+			}
+			if hashtags > thresholds.max_hashtags && no_followers(user) {
+				shadow_score.add("hashtags", score::STRONG);
+			}
+			let prolific = notes_per_day.is_some_and(|n| n > thresholds.max_notes_per_day);
+			if prolific && low_reputation(user) {
+				shadow_score.add("notes-rate", score::STRONG);
+			}
+			let shadow_threshold = i64::from(thresholds.spam_score_threshold);
+			let shadow_threshold = (shadow_threshold + penalty).max(1);
+			let shadow_rules = shadow.rules.as_ref().unwrap_or(&tuning.rules);
+			let shadow_action = shadow.score_action.unwrap_or(self.score_action);
+			let (a, b) = stats
+				.settle(&base, |facts| {
+					let crossed = i64::from(marks.score.total()) >= threshold;
+					let a = shadow::decide(crossed, self.score_action, &tuning.rules, facts)?;
+					let facts = Facts { score: shadow_score.total(), ..*facts };
+					let crossed = i64::from(shadow_score.total()) >= shadow_threshold;
+					Ok((a, shadow::decide(crossed, shadow_action, shadow_rules, &facts)?))
+				})
+				.await?;
+			shadow.compare(note.unwrap_or(actor.as_str()), &a, &b, self.statsd.as_ref());
+		}
+
+		// spam verdicts from here on are recorded along with the note
+		let judged = |result: Result<(), RejectReason>, marks: &Marks| {
+			result.inspect_err(|e| {
+				let (actor, fingerprint) = (actor.as_str(), fingerprint.as_deref());
+				// our own users are left to the moderators, however much they send
+				let counted = self.suspend.is_some() || self.domain_block.is_some();
+				let signed = (counted && !is_local(actor, local))
+					.then(|| Signed::new(&header, &body, upstream.query.clone()));
+				let score = &marks.score;
+				let key = cache_key.as_str();
+				self.record_spam(e, actor, host, note, score, fingerprint, key, request_id, signed)
+			})
+		};
+
+		if i64::from(marks.score.total()) >= threshold {
+			debug!("{} scored {} ({})", actor, marks.score.total(), marks.score);
+			trail.matched(SCORE_STAGE, &marks.score);
+			let (action, actor) = (self.score_action, actor.as_str());
+			let acted = self.stage_act(action, SCORE_STAGE, actor, &header, &body, &mut marks);
+			judged(acted, &marks)?;
+		}
+
+		// spam waves DM people to stay off public timelines, where moderators would see them
+		if let (Some(action), Direction::Inbound) = (self.first_dm_action, self.direction) {
+			let stranger = actor_reputation <= 0 && audience::is_direct(&ap_json);
+			let recipients =
+				if stranger { audience::direct_recipients(&ap_json) } else { <_>::default() };
+			// local actor URLs end with the user's id
+			let ids: Vec<&str> = recipients
+				.into_iter()
+				.filter(|uri| is_local(uri, local))
+				.filter_map(|uri| uri.trim_end_matches('/').rsplit('/').next())
+				.collect();
+			if !ids.is_empty() && query.followed_by(actor.as_str(), &ids).await? == Some(false) {
+				debug!("{} sent a DM to {} people who don't follow them", actor, ids.len());
+				trail.matched(FIRST_DM_STAGE, &marks.score);
+				let actor = actor.as_str();
+				let acted =
+					self.stage_act(action, FIRST_DM_STAGE, actor, &header, &body, &mut marks);
+				judged(acted, &marks)?;
+			}
+		}
+
+		// the candidate ruleset judges its share of actors, and shadows the active one on the rest
+		let mut judging: &RuleSet = &tuning.rules;
+		if let Some(canary) = &tuning.canary {
+			let picked = canary.picks(actor.as_str());
+			if picked {
+				judging = &canary.rules;
+			}
+			let (active, candidate) = stats
+				.settle(&base, |facts| {
+					Ok((tuning.rules.decision(facts)?, canary.rules.decision(facts)?))
+				})
+				.await?;
+			if active.map(|r| r.action) != candidate.map(|r| r.action) {
+				let verdict = |rule: Option<&rules::Rule>| match rule {
+					Some(rule) => format!("{:?} by rule \"{}\"", rule.action, rule.name),
+					None => "let through".to_string(),
+				};
+				info!(
+					"Canary diverges on {} ({}): active rules {}, candidate {}",
+					note.unwrap_or(actor.as_str()),
+					if picked { "judged by candidate" } else { "shadow" },
+					verdict(active),
+					verdict(candidate),
+				);
 			}
 		}
 
-		Ok(Admit { incoming_stream, pending_header: header, pending_body: body })
+		let mut next_rule = 0;
+		loop {
+			let facts = Facts { score: marks.score.total(), ..base };
+			let matched =
+				stats.settle(&facts, |facts| judging.next_match(next_rule, facts)).await?;
+			let Some((i, rule)) = matched else {
+				break;
+			};
+			debug!("{} matched rule \"{}\"", actor, rule.name);
+			trail.matched(&rule.name, &marks.score);
+			next_rule = i + 1;
+			let (actor, limit, window) = (actor.as_str(), rule.limit, rule.window);
+			let acted =
+				self.act(rule.action, &rule.name, limit, window, actor, &header, &body, &mut marks);
+			judged(acted, &marks)?;
+		}
+
+		self.annotate(&mut header, &marks);
+		trail.scored(&marks.score);
+		self.verdict("accepted", actor.as_str(), host, note, Some(&marks.score), request_id);
+		if let Some(fresh) = fresh {
+			self.decisions.insert(cache_key, Decision::Accept(fresh));
+		}
+		self.reputation.accepted(actor.as_str(), host);
+		if !updated {
+			self.velocity.record(host);
+		}
+
+		Ok((header, body, upstream.clone()))
+	}
+
+	/// Record a verdict on a note, for moderators and the event stream.
+	fn verdict(
+		&self, verdict: &str, actor: &str, host: &str, note: Option<&str>, score: Option<&Score>,
+		request_id: &str,
+	) {
+		self.history.record(actor, host, verdict);
+		if verdict == "accepted" {
+			if let Some(digest) = &self.digest {
+				digest.accepted();
+			}
+			if let Some(statsd) = &self.statsd {
+				statsd.accepted();
+			}
+		}
+		if let Some(events) = &self.events {
+			events.publish(&Event {
+				verdict,
+				actor: Some(actor),
+				host: Some(host),
+				note,
+				score: score.map(|s| s.total()),
+				signals: score.map(|s| s.signals()).unwrap_or_default(),
+				request_id,
+			});
+		}
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	fn record_spam(
+		&self, reason: &RejectReason, actor: &str, host: &str, note: Option<&str>, score: &Score,
+		fingerprint: Option<&str>, cache_key: &str, request_id: &str, signed: Option<Signed>,
+	) {
+		self.verdict(reason.kind(), actor, host, note, Some(score), request_id);
+		if !matches!(reason, RejectReason::Spam(..) | RejectReason::Quarantined(..)) {
+			return;
+		}
+		if matches!(reason, RejectReason::Spam(..)) {
+			self.decisions.insert(cache_key.to_string(), Decision::Reject);
+			if let (Some(suspend), Some(signed)) = (&self.suspend, &signed) {
+				suspend.rejected(actor, signed.clone());
+			}
+			if let (Some(domain_block), Some(signed)) = (&self.domain_block, signed) {
+				domain_block.rejected(host, signed);
+			}
+			if let (Some(telemetry), Some(fingerprint)) = (&self.telemetry, fingerprint) {
+				telemetry.spam(fingerprint, host);
+			}
+		}
+		if let Some(panic) = &self.panic {
+			panic.rejected();
+		}
+		if let Some(reporter) = &self.reporter {
+			let why = match reason {
+				RejectReason::Quarantined(id, _, rule) => {
+					format!("Held for review as #{} by spam-musubi rule \"{}\"", id, rule)
+				}
+				_ => "Rejected as spam by spam-musubi".to_string(),
+			};
+			reporter.report(actor, note, &why);
+		}
+		self.reputation.spam(actor, host);
+		if let Some(fingerprint) = fingerprint {
+			self.fingerprints.insert(fingerprint);
+		}
+		if let Some(share) = &self.share {
+			if let Some(fingerprint) = fingerprint {
+				share.fingerprint(fingerprint);
+			}
+			share.instance_reputation(host, reputation::SPAM);
+		}
+	}
+
+	/// Pass tags and, when annotating, the spam score on to the AP server as headers.
+	fn annotate(&self, header: &mut Vec<u8>, marks: &Marks) {
+		if !marks.tags.is_empty() {
+			append_header(header, "X-Musubi-Tags", &marks.tags.join(", "));
+		}
+		if self.enforcement == Enforcement::Annotate {
+			append_header(header, "X-Musubi-Score", &marks.score.total().to_string());
+			append_header(header, "X-Musubi-Signals", &marks.score.signals().join(", "));
+		}
 	}
+
+	/// Carry out the action of a pipeline stage, throttling like a rule without a limit of its
+	/// own.
+	fn stage_act(
+		&self, action: Action, stage: &str, actor: &str, header: &[u8], body: &[u8],
+		marks: &mut Marks,
+	) -> Result<(), RejectReason> {
+		let window = Duration::from_secs(rules::DEFAULT_THROTTLE_WINDOW_SECS);
+		self.act(action, stage, rules::DEFAULT_THROTTLE_LIMIT, window, actor, header, body, marks)
+	}
+
+	/// Carry out the action of a matched rule or stage. Returns an error if the activity must
+	/// not be forwarded.
+	#[allow(clippy::too_many_arguments)]
+	fn act(
+		&self, action: Action, name: &str, limit: u32, window: Duration, actor: &str,
+		header: &[u8], body: &[u8], marks: &mut Marks,
+	) -> Result<(), RejectReason> {
+		if self.enforcement == Enforcement::Annotate
+			&& matches!(action, Action::Reject | Action::Quarantine | Action::Throttle)
+		{
+			// leave the verdict to the AP server
+			if name != SCORE_STAGE {
+				marks.score.add(format!("rule:{}", name), score::STRONG);
+			}
+			return Ok(());
+		}
+
+		match action {
+			Action::Reject => Err(RejectReason::Spam(actor.to_string(), Payload::new(body))),
+			Action::Quarantine => {
+				let id = self.quarantine.hold(actor, name, header.to_vec(), body.to_vec());
+				Err(RejectReason::Quarantined(id, actor.to_string(), name.to_string()))
+			}
+			Action::Tag => {
+				marks.tags.push(name.to_string());
+				Ok(())
+			}
+			Action::Throttle => {
+				if self.throttle.allow(name, actor, limit, window) {
+					Ok(())
+				} else {
+					Err(RejectReason::Throttled(actor.to_string(), name.to_string()))
+				}
+			}
+			Action::Log => {
+				info!("Rule \"{}\" matched activity from {}", name, actor);
+				Ok(())
+			}
+		}
+	}
+}
+
+/// What the filter found out about an activity it lets through.
+#[derive(Debug, Default, Clone)]
+struct Marks {
+	score: Score,
+	tags: Vec<String>,
+}
+
+/// Whether the client waits for `100 Continue` before sending the body. Only HTTP/1.1 has it.
+fn expects_continue(header: &[u8], headers: &Headers) -> bool {
+	let http11 = header.split(|&b| b == b'\r').next().is_some_and(|l| l.ends_with(b" HTTP/1.1"));
+	http11 && headers.all("expect").any(|e| e.eq_ignore_ascii_case("100-continue"))
+}
+
+/// Write all of `buf` to a stream shared with readers.
+async fn write_all(stream: &TcpStream, mut buf: &[u8]) -> io::Result<()> {
+	while !buf.is_empty() {
+		stream.writable().await?;
+		match stream.try_write(buf) {
+			Ok(n) => buf = &buf[n..],
+			Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(())
+}
+
+/// Remove header lines whose name starts with `prefix` (lowercase), in any case.
+fn strip_headers(header: &mut Vec<u8>, prefix: &[u8]) {
+	let mut kept = Vec::with_capacity(header.len());
+	for (i, line) in header.split_inclusive(|&b| b == b'\n').enumerate() {
+		if i > 0 && line.len() >= prefix.len() && line[..prefix.len()].eq_ignore_ascii_case(prefix)
+		{
+			continue;
+		}
+		kept.extend_from_slice(line);
+	}
+	*header = kept;
+}
+
+/// Add a header line to a complete request header.
+fn append_header(header: &mut Vec<u8>, name: &str, value: &str) {
+	// don't let rule names smuggle in extra header lines
+	let value: String = value.chars().filter(|c| !c.is_control()).collect();
+	let at = header.len() - 2;
+	header.splice(at..at, format!("{}: {}\r\n", name, value).into_bytes());
 }
@@ -0,0 +1,138 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+
+use crate::{
+	cache::{self, CacheStats},
+	query::User,
+};
+
+/// How long moves are remembered, to find chains and accounts many move to.
+const WINDOW_SECS: u64 = 30 * 24 * 60 * 60;
+const CLEANUP_INTERVAL_SECS: u64 = 60 * 60;
+/// Accounts younger than this moving away haven't been around long enough to have moved for
+/// real.
+const MIN_AGE_SECS: u64 = 7 * 24 * 60 * 60;
+/// Distinct accounts moving to the same one at which it's a funnel.
+const MAX_ORIGINS: usize = 3;
+/// Rough size of an account moved to, with the few moving to it.
+const ENTRY_BYTES: usize = 512;
+/// Accounts moved to that are remembered without a budget.
+const MAX_TARGETS: usize = 10000;
+
+/// Why a `Move` looks like follower harvesting rather than someone changing servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suspicion {
+	/// The account moving away was only just created.
+	Fresh,
+	/// The account moving away was itself moved to recently, passing followers down a chain.
+	Chain,
+	/// Several accounts moved to the same one recently, funnelling their followers into it.
+	Funnel,
+}
+
+impl Suspicion {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Suspicion::Fresh => "fresh",
+			Suspicion::Chain => "chain",
+			Suspicion::Funnel => "funnel",
+		}
+	}
+}
+
+/// Remembers recent `Move`s, by the account moved to, to tell account migrations from accounts
+/// made to collect followers and hand them on to spam accounts.
+#[derive(Debug, Clone)]
+pub struct Moves {
+	/// Accounts moved from, and when, by the account moved to.
+	targets: Arc<DashMap<String, VecDeque<(Instant, String)>>>,
+	max_targets: usize,
+	stats: Arc<CacheStats>,
+}
+
+impl Moves {
+	/// Remember moves in at most `budget_mb` MiB.
+	pub fn new(budget_mb: Option<u64>) -> Self {
+		// a month of moves adds up, so there's a cap without a budget too
+		let max_targets = match budget_mb {
+			Some(_) => cache::max_entries(budget_mb, ENTRY_BYTES),
+			None => MAX_TARGETS,
+		};
+		let moves = Moves {
+			targets: Arc::new(DashMap::new()),
+			max_targets,
+			stats: CacheStats::register("moves", ENTRY_BYTES),
+		};
+
+		let targets = moves.targets.clone();
+		let stats = moves.stats.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				targets.retain(|_, origins| {
+					origins.retain(|(at, _)| !expired(*at));
+					!origins.is_empty()
+				});
+				stats.set_entries(targets.len());
+			}
+		});
+
+		moves
+	}
+
+	/// Record `origin` moving to `target`, and judge it. `user` is what the AP server knows of
+	/// `origin`, if anything.
+	pub fn record(&self, origin: &str, target: &str, user: Option<&User>) -> Option<Suspicion> {
+		if !self.targets.contains_key(target) && self.targets.len() >= self.max_targets {
+			let latest = |origins: &VecDeque<(Instant, String)>| origins.back().map(|(at, _)| *at);
+			cache::make_room(&self.targets, self.max_targets, &self.stats, latest);
+		}
+		let origins = {
+			let mut origins = self.targets.entry(target.to_string()).or_default();
+			origins.retain(|(at, o)| !expired(*at) && o != origin);
+			origins.push_back((Instant::now(), origin.to_string()));
+			origins.len()
+		};
+		self.stats.set_entries(self.targets.len());
+		let moved_to = self
+			.targets
+			.get(origin)
+			.is_some_and(|origins| origins.iter().any(|(at, _)| !expired(*at)));
+
+		if user.is_some_and(|u| u.age < Duration::from_secs(MIN_AGE_SECS)) {
+			Some(Suspicion::Fresh)
+		} else if moved_to {
+			Some(Suspicion::Chain)
+		} else if origins >= MAX_ORIGINS {
+			Some(Suspicion::Funnel)
+		} else {
+			None
+		}
+	}
+}
+
+fn expired(at: Instant) -> bool {
+	at.elapsed() >= Duration::from_secs(WINDOW_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test(start_paused = true)]
+	async fn forgets_the_oldest_targets_past_the_budget() {
+		let moves = Moves { max_targets: 10, ..Moves::new(None) };
+		for i in 0..=10 {
+			let (origin, target) =
+				(format!("https://a.example/{}", i), format!("https://b.example/{}", i));
+			moves.record(&origin, &target, None);
+			tokio::time::advance(Duration::from_secs(1)).await;
+		}
+		assert_eq!(moves.targets.len(), 10);
+		assert!(!moves.targets.contains_key("https://b.example/0"));
+		assert!(moves.targets.contains_key("https://b.example/10"));
+	}
+}
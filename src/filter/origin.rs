@@ -0,0 +1,69 @@
+use sonic_rs::{JsonValueTrait, Value};
+use url::Url;
+
+use super::uris;
+
+/// Check that the activity `id` and `object.attributedTo` live on the same host as the actor.
+///
+/// Forged-origin spam tends to claim an actor on one instance while the activity itself comes
+/// from another. Hosts in `exceptions` may mismatch freely.
+pub fn check_origin(
+	ap_json: &Value, actor_host: &str, exceptions: &[String],
+) -> Result<(), &'static str> {
+	let same_origin = |uri: &str| {
+		let Some(host) = uri.parse::<Url>().ok().and_then(|u| u.host_str().map(|h| h.to_owned()))
+		else {
+			return false;
+		};
+		host == actor_host || exceptions.iter().any(|e| *e == host || e == actor_host)
+	};
+
+	if let Some(id) = ap_json.get("id").and_then(|i| i.as_str()) {
+		if !same_origin(id) {
+			return Err("activity id host doesn't match actor");
+		}
+	}
+
+	let attributed_to = ap_json.get("object").and_then(|o| o.get("attributedTo"));
+	if attributed_to.is_some_and(|a| uris(a).into_iter().any(|a| !same_origin(a))) {
+		return Err("attributedTo host doesn't match actor");
+	}
+
+	Ok(())
+}
+
+/// The `keyId` of an HTTP `Signature` header, the key the request claims to be signed with.
+pub fn key_id(signature: &str) -> Option<&str> {
+	signature.split(',').find_map(|param| {
+		let (name, value) = param.trim().split_once('=')?;
+		name.trim().eq_ignore_ascii_case("keyId").then(|| value.trim().trim_matches('"'))
+	})
+}
+
+/// Check that the key signing a delivery lives on the same host as the actor, without fetching
+/// it. Deliveries forged for another instance's actor have to be signed with a key of the
+/// forger's.
+///
+/// Keys of `relays` may sign for anyone, and hosts in `exceptions` may mismatch freely. The
+/// `creator` of an LD signature counts for nothing, since it isn't verified: anyone can claim
+/// one.
+pub fn check_key_id(
+	ap_json: &Value, key_id: &str, exceptions: &[String], relays: &[String],
+) -> Result<(), &'static str> {
+	if relays.iter().any(|relay| key_id.starts_with(relay.as_str())) {
+		return Ok(());
+	}
+	let host_of = |uri: &str| uri.parse::<Url>().ok().and_then(|u| u.host_str().map(str::to_owned));
+	let actor = ap_json.get("actor").and_then(|a| uris(a).first().copied());
+	let Some(actor_host) = actor.and_then(host_of) else {
+		return Ok(());
+	};
+	let Some(signer_host) = host_of(key_id) else {
+		return Err("keyId is not a URL");
+	};
+	let excepted = exceptions.iter().any(|e| *e == signer_host || *e == actor_host);
+	if signer_host != actor_host && !excepted {
+		return Err("keyId host doesn't match actor");
+	}
+	Ok(())
+}
@@ -0,0 +1,179 @@
+use std::{
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::*;
+use url::Url;
+
+use super::{
+	rules::{RuleConfig, RuleSet},
+	velocity::VelocityTracker,
+	Filter, Thresholds, ThresholdsPatch,
+};
+use crate::http;
+
+const DEFAULT_FACTOR: u32 = 5;
+const DEFAULT_CALM_MINUTES: u64 = 30;
+/// Key rejections are counted under, as if they all came from one instance.
+const REJECTIONS: &str = "";
+
+/// `[panic]` in the config file: stricter rules to switch to while spam rejections surge, as
+/// during a wave, until things calm down again.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PanicConfig {
+	/// How many times the usual rate of spam rejections within an hour sets off panic mode.
+	#[serde(default = "default_factor")]
+	pub factor: u32,
+	/// Minutes without a surge before the usual rules are back.
+	#[serde(default = "default_calm_minutes")]
+	pub calm_minutes: u64,
+	/// Ruleset to use while panicking, instead of the current one.
+	pub rules: Option<Vec<RuleConfig>>,
+	/// Thresholds to change while panicking.
+	pub thresholds: Option<ThresholdsPatch>,
+	/// HTTP(S) URL to POST a JSON notice to when panic mode starts and ends.
+	pub webhook: Option<String>,
+}
+
+fn default_factor() -> u32 {
+	DEFAULT_FACTOR
+}
+
+fn default_calm_minutes() -> u64 {
+	DEFAULT_CALM_MINUTES
+}
+
+impl PanicConfig {
+	/// Check the ruleset and webhook before they're needed, in the middle of a wave.
+	pub fn validate(&self) -> Result<(), String> {
+		if let Some(rules) = &self.rules {
+			RuleSet::compile(rules).map_err(|e| format!("invalid panic rules: {}", e))?;
+		}
+		if let Some(webhook) = &self.webhook {
+			let url = Url::parse(webhook).map_err(|e| format!("invalid panic webhook: {}", e))?;
+			if !http::supports(&url) {
+				return Err("panic webhook must be an http:// or https:// URL".to_string());
+			}
+		}
+		Ok(())
+	}
+
+	/// Port the webhook is sent to, if any.
+	pub fn webhook_port(&self) -> Option<u16> {
+		Url::parse(self.webhook.as_deref()?).ok()?.port_or_known_default()
+	}
+}
+
+/// Spam rejections counted against their usual rate, to tell when a wave starts and ends.
+#[derive(Debug, Clone)]
+pub struct Panic {
+	config: Arc<PanicConfig>,
+	rejections: VelocityTracker,
+	/// Rules and thresholds to go back to, while panicking.
+	saved: Arc<Mutex<Option<Saved>>>,
+}
+
+type Saved = (Vec<RuleConfig>, Thresholds);
+
+impl Panic {
+	pub fn new(config: PanicConfig) -> Self {
+		let rejections = VelocityTracker::new(config.factor);
+		Panic { config: Arc::new(config), rejections, saved: Arc::new(Mutex::new(None)) }
+	}
+
+	/// Count a spam rejection.
+	pub fn rejected(&self) {
+		self.rejections.record(REJECTIONS);
+	}
+
+	/// Keep an eye on the rejection rate of `filter`, switching its rules as waves come and go.
+	pub async fn watch(self, filter: Filter) {
+		let calm = Duration::from_secs(self.config.calm_minutes * 60);
+		let mut calm_since = None;
+		let mut interval = tokio::time::interval(Duration::from_secs(60));
+		loop {
+			interval.tick().await;
+			let (last_hour, surge) = self.rejections.surging(REJECTIONS);
+			#[allow(clippy::unwrap_used)]
+			let panicking = self.saved.lock().unwrap().is_some();
+			if surge {
+				calm_since = None;
+				if !panicking {
+					self.start(&filter, last_hour);
+				}
+			} else if panicking && calm_since.get_or_insert_with(Instant::now).elapsed() >= calm {
+				calm_since = None;
+				self.end(&filter);
+			}
+		}
+	}
+
+	fn start(&self, filter: &Filter, last_hour: u32) {
+		let result = filter.retune(|rules, thresholds| {
+			#[allow(clippy::unwrap_used)]
+			self.saved.lock().unwrap().replace((rules.clone(), *thresholds));
+			if let Some(panic_rules) = &self.config.rules {
+				rules.clone_from(panic_rules);
+			}
+			if let Some(patch) = &self.config.thresholds {
+				patch.apply(thresholds);
+			}
+			Ok(())
+		});
+		match result {
+			Ok(()) => {
+				let message = format!(
+					"Panic mode on: {} spam rejections in the last hour, over {} times the usual",
+					last_hour, self.config.factor
+				);
+				warn!("{}", message);
+				self.notify("panic-started", message);
+			}
+			Err(e) => {
+				#[allow(clippy::unwrap_used)]
+				self.saved.lock().unwrap().take();
+				error!("Could not switch to panic rules: {}", e);
+			}
+		}
+	}
+
+	fn end(&self, filter: &Filter) {
+		#[allow(clippy::unwrap_used)]
+		let Some((saved_rules, saved_thresholds)) = self.saved.lock().unwrap().take() else {
+			return;
+		};
+		let result = filter.retune(|rules, thresholds| {
+			*rules = saved_rules;
+			*thresholds = saved_thresholds;
+			Ok(())
+		});
+		match result {
+			Ok(()) => {
+				let message = format!(
+					"Panic mode off: no surge of spam rejections for {} minutes",
+					self.config.calm_minutes
+				);
+				warn!("{}", message);
+				self.notify("panic-ended", message);
+			}
+			Err(e) => error!("Could not switch back from panic rules: {}", e),
+		}
+	}
+
+	fn notify(&self, event: &'static str, message: String) {
+		let Some(url) = self.config.webhook.as_deref().and_then(|w| Url::parse(w).ok()) else {
+			return;
+		};
+		tokio::spawn(async move {
+			// "text" is what Slack-style webhooks display
+			let body = sonic_rs::json!({ "event": event, "text": message }).to_string();
+			if let Err(e) = http::post_json(&url, &[], &body).await {
+				warn!("Could not send panic notice to {}: {}", url, e);
+			}
+		});
+	}
+}
@@ -0,0 +1,142 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::time::Instant;
+use tracing::*;
+
+use super::Filter;
+use crate::db::StateDb;
+
+/// Seconds between saves of the short-lived filter state.
+pub const SAVE_INTERVAL_SECS: u64 = 60;
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Unix time in seconds of an instant in the past.
+fn unix(at: Instant) -> i64 {
+	now().saturating_sub(at.elapsed().as_secs()) as i64
+}
+
+/// The instant of a unix time saved by an earlier run, if this run's clock reaches back to it.
+fn instant(unix: i64) -> Option<Instant> {
+	let ago = now().saturating_sub(u64::try_from(unix).ok()?);
+	Instant::now().checked_sub(Duration::from_secs(ago))
+}
+
+impl Filter {
+	/// Throttle windows, delivery dedup, recent replies and spam fingerprints, as saved by a
+	/// previous run. Returns how many entries were put back.
+	pub async fn restore_state(&self, db: &StateDb) -> Result<usize, sqlx::Error> {
+		let mut restored = 0;
+
+		let rows = sqlx::query_as::<_, (String, String, i64, i64, i64)>(
+			"SELECT rule, actor, start, length, count FROM throttle",
+		)
+		.fetch_all(db.pool())
+		.await?;
+		for (rule, actor, start, length, count) in rows {
+			if let Some(start) = instant(start) {
+				let length = Duration::from_secs(length as u64);
+				self.throttle.restore(rule, actor, start, length, count as u32);
+				restored += 1;
+			}
+		}
+
+		let rows = sqlx::query_as::<_, (String, i64)>("SELECT key, at FROM seen_deliveries")
+			.fetch_all(db.pool())
+			.await?;
+		for (key, at) in rows {
+			if let Some(at) = instant(at) {
+				self.seen.restore(key, at);
+				restored += 1;
+			}
+		}
+
+		let rows = sqlx::query_as::<_, (String, String, i64)>(
+			"SELECT actor, target, at FROM replies ORDER BY at",
+		)
+		.fetch_all(db.pool())
+		.await?;
+		for (actor, target, at) in rows {
+			if let Some(at) = instant(at) {
+				self.replies.restore(actor, target, at);
+				restored += 1;
+			}
+		}
+
+		let rows = sqlx::query_as::<_, (String, i64)>("SELECT fingerprint, at FROM fingerprints")
+			.fetch_all(db.pool())
+			.await?;
+		for (fingerprint, at) in rows {
+			if let Some(at) = instant(at) {
+				self.fingerprints.restore(fingerprint, at);
+				restored += 1;
+			}
+		}
+
+		Ok(restored)
+	}
+
+	/// Replace what the state DB holds of the short-lived filter state with the current one.
+	pub async fn save_state(&self, db: &StateDb) -> Result<(), sqlx::Error> {
+		let mut tx = db.pool().begin().await?;
+		for table in ["throttle", "seen_deliveries", "replies", "fingerprints"] {
+			sqlx::query(&format!("DELETE FROM {}", table)).execute(&mut *tx).await?;
+		}
+		for (rule, actor, start, length, count) in self.throttle.entries() {
+			sqlx::query(
+				"INSERT INTO throttle (rule, actor, start, length, count) VALUES (?, ?, ?, ?, ?)",
+			)
+			.bind(rule)
+			.bind(actor)
+			.bind(unix(start))
+			.bind(length.as_secs() as i64)
+			.bind(count as i64)
+			.execute(&mut *tx)
+			.await?;
+		}
+		for (key, at) in self.seen.entries() {
+			sqlx::query("INSERT INTO seen_deliveries (key, at) VALUES (?, ?)")
+				.bind(key)
+				.bind(unix(at))
+				.execute(&mut *tx)
+				.await?;
+		}
+		for (actor, target, at) in self.replies.entries() {
+			sqlx::query("INSERT INTO replies (actor, target, at) VALUES (?, ?, ?)")
+				.bind(actor)
+				.bind(target)
+				.bind(unix(at))
+				.execute(&mut *tx)
+				.await?;
+		}
+		for (fingerprint, at) in self.fingerprints.entries() {
+			sqlx::query("INSERT INTO fingerprints (fingerprint, at) VALUES (?, ?)")
+				.bind(fingerprint)
+				.bind(unix(at))
+				.execute(&mut *tx)
+				.await?;
+		}
+		tx.commit().await?;
+		debug!("Saved filter state");
+		Ok(())
+	}
+
+	/// Save the short-lived filter state every [`SAVE_INTERVAL_SECS`], for as long as the
+	/// process runs.
+	pub fn save_state_periodically(&self, db: StateDb) {
+		let filter = self.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(SAVE_INTERVAL_SECS));
+			// the first tick is immediate, and there's nothing new to save yet
+			interval.tick().await;
+			loop {
+				interval.tick().await;
+				if let Err(e) = filter.save_state(&db).await {
+					warn!("Could not save filter state: {}", e);
+				}
+			}
+		});
+	}
+}
@@ -0,0 +1,251 @@
+use std::{
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+use tracing::*;
+
+/// Header retries carry their solution in, as `<challenge>:<nonce>`.
+pub const HEADER: &str = "Musubi-PoW";
+const DEFAULT_DIFFICULTY: u8 = 20;
+const DEFAULT_REMEMBER_HOURS: u64 = 7 * 24;
+/// How long a challenge can be solved for.
+const CHALLENGE_SECS: u64 = 10 * 60;
+const MAX_NONCE_LEN: usize = 64;
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+
+/// `[pow]` in the config file, experimental: answer first deliveries from instances the AP server
+/// doesn't know with a proof-of-work challenge. Servers running a companion module solve it and
+/// retry, while bulk senders don't bother.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PowConfig {
+	/// Leading zero bits the SHA-256 of a solution must have. Each one doubles the work.
+	#[serde(default = "default_difficulty")]
+	pub difficulty: u8,
+	/// Hours an instance that solved a challenge, or turned out to be known, isn't challenged.
+	#[serde(default = "default_remember_hours")]
+	pub remember_hours: u64,
+}
+
+fn default_difficulty() -> u8 {
+	DEFAULT_DIFFICULTY
+}
+
+fn default_remember_hours() -> u64 {
+	DEFAULT_REMEMBER_HOURS
+}
+
+impl PowConfig {
+	pub fn validate(&self) -> Result<(), String> {
+		if !(1..=32).contains(&self.difficulty) {
+			return Err("pow difficulty must be between 1 and 32".to_string());
+		}
+		if self.remember_hours == 0 {
+			return Err("pow remember_hours must be at least 1".to_string());
+		}
+		Ok(())
+	}
+}
+
+/// Hands out challenges and checks solutions. Challenges are signed rather than stored, with a
+/// key made up at startup, so they don't survive restarts.
+#[derive(Debug, Clone)]
+pub struct Pow {
+	config: Arc<PowConfig>,
+	key: Arc<[u8; 32]>,
+	/// Instances let through without a challenge, and until when.
+	passed: Arc<DashMap<String, Instant>>,
+}
+
+impl Pow {
+	pub fn new(config: PowConfig) -> Self {
+		let mut key = [0; 32];
+		if let Err(e) = getrandom::getrandom(&mut key) {
+			warn!("Could not make up a key for proof-of-work challenges: {}", e);
+		}
+		let pow =
+			Pow { config: Arc::new(config), key: Arc::new(key), passed: Arc::new(DashMap::new()) };
+
+		let passed = pow.passed.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				passed.retain(|_, until| *until > Instant::now());
+			}
+		});
+
+		pow
+	}
+
+	/// Whether deliveries from `host` go through without a challenge for now.
+	pub fn passed(&self, host: &str) -> bool {
+		self.passed.get(host).is_some_and(|until| *until > Instant::now())
+	}
+
+	/// Let deliveries from `host` through without a challenge for a while.
+	pub fn pass(&self, host: &str) {
+		let until = Instant::now() + Duration::from_secs(self.config.remember_hours * 3600);
+		self.passed.insert(host.to_string(), until);
+	}
+
+	/// A challenge for `host`, as the WWW-Authenticate header value.
+	pub fn challenge(&self, host: &str) -> String {
+		let expiry = unix_time() + CHALLENGE_SECS;
+		let token = format!("{}.{}", expiry, self.sign(host, expiry));
+		format!("{} challenge=\"{}\", difficulty={}", HEADER, token, self.config.difficulty)
+	}
+
+	/// Whether `solution`, from the header, solves a challenge given to `host` that's still good.
+	pub fn solved(&self, host: &str, solution: &str) -> bool {
+		let Some((token, nonce)) = solution.rsplit_once(':') else {
+			return false;
+		};
+		let Some((expiry, signature)) = token.split_once('.') else {
+			return false;
+		};
+		let Ok(expiry) = expiry.parse::<u64>() else {
+			return false;
+		};
+		if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN || expiry < unix_time() {
+			return false;
+		}
+		// constant time doesn't matter much for a challenge anyone can ask for, but it's free
+		let expected = self.sign(host, expiry);
+		let signed = expected.len() == signature.len()
+			&& expected.bytes().zip(signature.bytes()).fold(0, |d, (a, b)| d | (a ^ b)) == 0;
+		let zeros = leading_zeros(&Sha256::digest(solution.as_bytes()));
+		signed && zeros >= u32::from(self.config.difficulty)
+	}
+
+	fn sign(&self, host: &str, expiry: u64) -> String {
+		#[allow(clippy::unwrap_used)] // HMAC takes keys of any length
+		let mut mac = Hmac::<Sha256>::new_from_slice(&self.key[..]).unwrap();
+		mac.update(format!("{}\n{}", host, expiry).as_bytes());
+		hex::encode(mac.finalize().into_bytes())
+	}
+}
+
+/// 401 answering a delivery with a challenge.
+pub fn response(challenge: &str) -> Vec<u8> {
+	format!(
+		"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: {}\r\nContent-Length: 0\r\n\
+		Connection: close\r\n\r\n",
+		challenge
+	)
+	.into_bytes()
+}
+
+fn leading_zeros(hash: &[u8]) -> u32 {
+	let mut zeros = 0;
+	for byte in hash {
+		if *byte != 0 {
+			return zeros + byte.leading_zeros();
+		}
+		zeros += 8;
+	}
+	zeros
+}
+
+fn unix_time() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pow(difficulty: u8) -> Pow {
+		Pow::new(PowConfig { difficulty, remember_hours: 1 })
+	}
+
+	/// The challenge token in a WWW-Authenticate header value.
+	fn token(challenge: &str) -> &str {
+		let start = challenge.find("challenge=\"").map_or(0, |i| i + 11);
+		let len = challenge[start..].find('"').unwrap_or_default();
+		&challenge[start..start + len]
+	}
+
+	/// A nonce solving `token` at `difficulty`.
+	fn solve(token: &str, difficulty: u8) -> String {
+		(0u64..)
+			.map(|nonce| format!("{}:{}", token, nonce))
+			.find(|solution| {
+				leading_zeros(&Sha256::digest(solution.as_bytes())) >= u32::from(difficulty)
+			})
+			.unwrap_or_default()
+	}
+
+	#[test]
+	fn counts_leading_zero_bits() {
+		assert_eq!(leading_zeros(&[0x80, 0]), 0);
+		assert_eq!(leading_zeros(&[0x01, 0xff]), 7);
+		assert_eq!(leading_zeros(&[0, 0x20]), 10);
+		assert_eq!(leading_zeros(&[0, 0]), 16);
+		assert_eq!(leading_zeros(&[]), 0);
+	}
+
+	#[tokio::test]
+	async fn accepts_solutions_to_its_own_challenges() {
+		let pow = pow(8);
+		let challenge = pow.challenge("spam.example");
+		assert!(challenge.ends_with("difficulty=8"), "{}", challenge);
+		let solution = solve(token(&challenge), 8);
+		assert!(pow.solved("spam.example", &solution));
+		// the challenge was given to another instance
+		assert!(!pow.solved("other.example", &solution));
+		// or by another process
+		assert!(!self::pow(8).solved("spam.example", &solution));
+	}
+
+	#[tokio::test]
+	async fn rejects_bad_solutions() {
+		let pow = pow(8);
+		let token = token(&pow.challenge("spam.example")).to_string();
+		let nonce = (0u64..)
+			.find(|nonce| {
+				let solution = format!("{}:{}", token, nonce);
+				leading_zeros(&Sha256::digest(solution.as_bytes())) < 8
+			})
+			.unwrap_or_default();
+		assert!(!pow.solved("spam.example", &format!("{}:{}", token, nonce)));
+		assert!(!pow.solved("spam.example", &format!("{}:", token)));
+		assert!(!pow.solved("spam.example", &format!("{}:{}", token, "0".repeat(65))));
+		assert!(!pow.solved("spam.example", &token));
+		assert!(!pow.solved("spam.example", ""));
+
+		// a forged signature, or an expiry moved later
+		let (expiry, signature) = token.split_once('.').unwrap_or_default();
+		let forged = format!("{}.{}", expiry, "0".repeat(signature.len()));
+		assert!(!pow.solved("spam.example", &solve(&forged, 8)));
+		let later = format!("{}.{}", expiry.parse::<u64>().unwrap_or_default() + 1, signature);
+		assert!(!pow.solved("spam.example", &solve(&later, 8)));
+		// one that ran out
+		let expired = format!("1.{}", pow.sign("spam.example", 1));
+		assert!(!pow.solved("spam.example", &solve(&expired, 8)));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn remembers_passed_instances_for_a_while() {
+		let pow = pow(8);
+		assert!(!pow.passed("spam.example"));
+		pow.pass("spam.example");
+		assert!(pow.passed("spam.example"));
+		tokio::time::advance(Duration::from_secs(3600)).await;
+		assert!(!pow.passed("spam.example"));
+	}
+
+	#[test]
+	fn validates_config() {
+		assert!(PowConfig { difficulty: 20, remember_hours: 1 }.validate().is_ok());
+		assert!(PowConfig { difficulty: 0, remember_hours: 1 }.validate().is_err());
+		assert!(PowConfig { difficulty: 33, remember_hours: 1 }.validate().is_err());
+		assert!(PowConfig { difficulty: 20, remember_hours: 0 }.validate().is_err());
+	}
+}
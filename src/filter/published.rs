@@ -0,0 +1,24 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sonic_rs::{JsonValueTrait, Value};
+
+use crate::http;
+
+/// Seconds since the note was `published`, or since the activity was if the note doesn't say.
+/// For edits, since the note was `updated`, or the `Update` was published, as the note itself
+/// may be old. Negative if it claims to be from the future.
+pub fn age(ap_json: &Value) -> Option<i64> {
+	let object = ap_json.get("object");
+	let edited = ap_json
+		.get("type")
+		.and_then(|t| t.as_str())
+		.is_some_and(|t| t.eq_ignore_ascii_case("Update"));
+	let field = if edited { "updated" } else { "published" };
+	let published = object
+		.and_then(|o| o.get(field))
+		.or_else(|| ap_json.get("published"))
+		.and_then(|p| p.as_str())
+		.and_then(http::parse_timestamp)?;
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+	Some(now as i64 - published as i64)
+}
@@ -0,0 +1,74 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+
+use crate::{
+	query::{Backend, QueryError},
+	upstream::Address,
+};
+
+/// How long a local user found active is remembered.
+const ACTIVE_SECS: u64 = 10 * 60;
+/// How long a missing local user is remembered, in case it was just being created.
+const MISSING_SECS: u64 = 60;
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+
+/// Local users deliveries were addressed to, and whether they can take them, so enumeration
+/// attacks and deliveries to dead mailboxes don't each cost a DB query.
+#[derive(Debug, Clone, Default)]
+pub struct Recipients {
+	known: Arc<DashMap<String, (Instant, bool)>>,
+}
+
+impl Recipients {
+	pub fn new() -> Self {
+		let recipients = Recipients::default();
+
+		let known = recipients.known.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				known.retain(|_, (checked, active)| !expired(*checked, *active));
+			}
+		});
+
+		recipients
+	}
+
+	/// Whether the user `id` of the AP server at `upstream` exists and isn't deleted or
+	/// suspended.
+	pub async fn active(
+		&self, upstream: &Address, query: &dyn Backend, id: &str,
+	) -> Result<bool, QueryError> {
+		let key = format!("{}\n{}", upstream, id);
+		if let Some(known) = self.known.get(&key) {
+			let (checked, active) = *known;
+			if !expired(checked, active) {
+				return Ok(active);
+			}
+		}
+		let active = query.local_user_active(id).await?;
+		self.known.insert(key, (Instant::now(), active));
+		Ok(active)
+	}
+}
+
+/// Id of the local user whose personal inbox `target` is, as in `/users/<id>/inbox`. Shared
+/// inboxes have none.
+pub fn inbox_owner(target: &str) -> Option<&str> {
+	// absolute form, as in `POST https://host/users/<id>/inbox`
+	let path = match target.split_once("://") {
+		Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+		None => target,
+	};
+	let path = path.split('?').next().unwrap_or_default();
+	let id = path.strip_prefix("/users/")?.strip_suffix("/inbox")?;
+	(!id.is_empty() && !id.contains('/')).then_some(id)
+}
+
+fn expired(checked: Instant, active: bool) -> bool {
+	let ttl = if active { ACTIVE_SECS } else { MISSING_SECS };
+	checked.elapsed() >= Duration::from_secs(ttl)
+}
@@ -0,0 +1,95 @@
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex, MutexGuard},
+	time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Rejections are counted over this window and the one before it.
+const WINDOW: Duration = Duration::from_secs(3600);
+/// Origins counted per window, so a wave from random domains can't grow the map without bound.
+const MAX_KEYS: usize = 100_000;
+
+/// Recent rejections by origin and kind, for finding out who is sending the most spam.
+#[derive(Debug, Clone)]
+pub struct Rejections(Arc<Mutex<Windows>>);
+
+#[derive(Debug)]
+struct Windows {
+	started: Instant,
+	current: HashMap<(String, &'static str), u64>,
+	previous: HashMap<(String, &'static str), u64>,
+}
+
+/// How often an origin got a kind of rejection, over the last one to two hours.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejectionCount {
+	pub origin: String,
+	pub kind: String,
+	pub count: u64,
+}
+
+impl Default for Rejections {
+	fn default() -> Self {
+		Rejections(Arc::new(Mutex::new(Windows {
+			started: Instant::now(),
+			current: HashMap::new(),
+			previous: HashMap::new(),
+		})))
+	}
+}
+
+impl Rejections {
+	pub fn record(&self, origin: String, kind: &'static str) {
+		let mut windows = self.windows();
+		let len = windows.current.len();
+		match windows.current.get_mut(&(origin.clone(), kind)) {
+			Some(count) => *count += 1,
+			None if len < MAX_KEYS => {
+				windows.current.insert((origin, kind), 1);
+			}
+			None => {}
+		}
+	}
+
+	/// Total of rejections counted.
+	pub fn total(&self) -> u64 {
+		let windows = self.windows();
+		windows.current.values().chain(windows.previous.values()).sum()
+	}
+
+	/// The `limit` origins and kinds rejected the most, most first.
+	pub fn top(&self, limit: usize) -> Vec<RejectionCount> {
+		let windows = self.windows();
+		let mut counts = HashMap::<_, u64>::new();
+		for (key, count) in windows.current.iter().chain(windows.previous.iter()) {
+			*counts.entry(key).or_default() += count;
+		}
+		let mut counts: Vec<_> = counts
+			.into_iter()
+			.map(|((origin, kind), count)| RejectionCount {
+				origin: origin.clone(),
+				kind: kind.to_string(),
+				count,
+			})
+			.collect();
+		counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.origin.cmp(&b.origin)));
+		counts.truncate(limit);
+		counts
+	}
+
+	/// The windows, moved along if the current one is over.
+	fn windows(&self) -> MutexGuard<'_, Windows> {
+		// counts are still good after a panic elsewhere
+		let mut windows = self.0.lock().unwrap_or_else(|e| e.into_inner());
+		let elapsed = windows.started.elapsed();
+		if elapsed >= WINDOW {
+			let current = std::mem::take(&mut windows.current);
+			// a whole window without rejections leaves nothing to carry over
+			windows.previous = if elapsed < WINDOW * 2 { current } else { HashMap::new() };
+			windows.started = Instant::now();
+		}
+		windows
+	}
+}
@@ -0,0 +1,18 @@
+use sonic_rs::{json, JsonValueTrait, Value};
+
+/// The activity a relay forwarded, when it wraps it whole in an `Announce`, or `None` for the
+/// relay's own activities and announcements of bare URIs.
+///
+/// A wrapped object that isn't an activity, like a `Note`, is judged as if its author had
+/// created it.
+pub fn unwrap(ap_json: &Value) -> Option<Value> {
+	if ap_json.get("type").and_then(|t| t.as_str()) != Some("Announce") {
+		return None;
+	}
+	let inner = ap_json.get("object").filter(|o| o.is_object())?;
+	if inner.get("actor").is_some() {
+		return Some(inner.clone());
+	}
+	let author = inner.get("attributedTo").and_then(|a| a.as_str())?;
+	Some(json!({ "type": "Create", "actor": author, "object": inner.clone() }))
+}
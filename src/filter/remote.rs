@@ -0,0 +1,120 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use sonic_rs::Value;
+use tokio::time::{timeout, Instant};
+use url::Url;
+
+use crate::http::{self, HttpError};
+
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Check the settings lookups on other instances share, `what` naming the config section.
+pub fn validate(
+	what: &str, via: Option<&str>, timeout_ms: u64, max_per_minute: u32,
+) -> Result<(), String> {
+	if let Some(via) = via {
+		let url = Url::parse(via).map_err(|e| format!("invalid {} via: {}", what, e))?;
+		if !http::supports(&url) {
+			return Err(format!("{} via must be an http:// or https:// URL", what));
+		}
+	}
+	if timeout_ms == 0 {
+		return Err(format!("{} timeout_ms must be at least 1", what));
+	}
+	if max_per_minute == 0 {
+		return Err(format!("{} max_per_minute must be at least 1", what));
+	}
+	Ok(())
+}
+
+/// Port lookups connect to: the proxy's, or HTTPS on the instances.
+pub fn connect_port(via: Option<&str>) -> Option<u16> {
+	match via {
+		Some(via) => Url::parse(via).ok()?.port_or_known_default(),
+		None => Some(443),
+	}
+}
+
+/// Asks other instances about their actors, directly or through an HTTP proxy, no more than so
+/// many times per instance per minute.
+#[derive(Debug, Clone)]
+pub struct Remote {
+	via: Option<Url>,
+	timeout: Duration,
+	max_per_minute: u32,
+	/// Lookups per instance in the current minute, and when it started.
+	lookups: Arc<DashMap<String, (Instant, u32)>>,
+}
+
+impl Remote {
+	/// Takes settings already checked by [`validate`].
+	pub fn new(via: Option<&str>, timeout_ms: u64, max_per_minute: u32) -> Self {
+		let remote = Remote {
+			via: via.and_then(|via| Url::parse(via).ok()),
+			timeout: Duration::from_millis(timeout_ms),
+			max_per_minute,
+			lookups: Arc::new(DashMap::new()),
+		};
+
+		let lookups = remote.lookups.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				lookups.retain(|_, (since, _)| since.elapsed() < MINUTE);
+			}
+		});
+
+		remote
+	}
+
+	/// Count a lookup on `host`, unless it had its share this minute.
+	pub fn allow(&self, host: &str) -> bool {
+		let mut lookups = self.lookups.entry(host.to_string()).or_insert((Instant::now(), 0));
+		let (since, count) = &mut *lookups;
+		if since.elapsed() >= MINUTE {
+			*since = Instant::now();
+			*count = 0;
+		}
+		if *count >= self.max_per_minute {
+			return false;
+		}
+		*count += 1;
+		true
+	}
+
+	/// Run a lookup, giving up on it after the configured timeout.
+	pub async fn limit<T>(
+		&self, lookup: impl Future<Output = Result<T, HttpError>>,
+	) -> Result<T, HttpError> {
+		match timeout(self.timeout, lookup).await {
+			Ok(lookup) => lookup,
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	/// Fetch a JSON document.
+	pub async fn fetch(&self, url: &Url, accept: &str) -> Result<Value, HttpError> {
+		let response = http::get_via(self.via.as_ref(), url, &[("Accept", accept)]).await?;
+		sonic_rs::from_str(&response).map_err(|_| HttpError::MalformedResponse)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test(start_paused = true)]
+	async fn limits_lookups_per_instance_and_minute() {
+		let remote = Remote::new(None, 1000, 2);
+		assert!(remote.allow("remote.example"));
+		assert!(remote.allow("remote.example"));
+		assert!(!remote.allow("remote.example"));
+		assert!(remote.allow("other.example"));
+
+		tokio::time::advance(MINUTE).await;
+		assert!(remote.allow("remote.example"));
+	}
+}
@@ -0,0 +1,104 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+
+/// Remembers which local notes each actor replied to recently.
+#[derive(Debug, Clone)]
+pub struct ReplyTracker {
+	window: Duration,
+	replies: Arc<DashMap<String, VecDeque<(Instant, String)>>>,
+}
+
+impl ReplyTracker {
+	pub fn new(window: Duration) -> Self {
+		let tracker = ReplyTracker { window, replies: Arc::new(DashMap::new()) };
+
+		// forget actors that went quiet
+		let replies = tracker.replies.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(window);
+			loop {
+				interval.tick().await;
+				replies.retain(|_, targets| {
+					targets.back().is_some_and(|(at, _)| at.elapsed() < window)
+				});
+			}
+		});
+
+		tracker
+	}
+
+	/// Record a reply and return how many distinct notes the actor replied to within the window.
+	pub fn record(&self, actor: &str, target: &str) -> usize {
+		let mut targets = self.replies.entry(actor.to_string()).or_default();
+		while targets.front().is_some_and(|(at, _)| at.elapsed() >= self.window) {
+			targets.pop_front();
+		}
+		if !targets.iter().any(|(_, t)| t == target) {
+			targets.push_back((Instant::now(), target.to_string()));
+		}
+		targets.len()
+	}
+
+	/// How many distinct notes the actor replied to within the window.
+	pub fn recent(&self, actor: &str) -> usize {
+		self.replies.get(actor).map_or(0, |targets| {
+			targets.iter().filter(|(at, _)| at.elapsed() < self.window).count()
+		})
+	}
+
+	/// Every recent reply as (actor, target, when), oldest first per actor.
+	pub fn entries(&self) -> Vec<(String, String, Instant)> {
+		self.replies
+			.iter()
+			.flat_map(|entry| {
+				let actor = entry.key();
+				let targets = entry.value().iter().filter(|(at, _)| at.elapsed() < self.window);
+				targets.map(|(at, target)| (actor.clone(), target.clone(), *at)).collect::<Vec<_>>()
+			})
+			.collect()
+	}
+
+	/// Put back a reply from [`ReplyTracker::entries`], given in the same order.
+	pub fn restore(&self, actor: String, target: String, at: Instant) {
+		if at.elapsed() < self.window {
+			self.replies.entry(actor).or_default().push_back((at, target));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const WINDOW: Duration = Duration::from_secs(60);
+
+	#[tokio::test(start_paused = true)]
+	async fn counts_distinct_notes_within_the_window() {
+		let tracker = ReplyTracker::new(WINDOW);
+		assert_eq!(tracker.record("alice", "https://local.example/notes/1"), 1);
+		assert_eq!(tracker.record("alice", "https://local.example/notes/2"), 2);
+		// replying twice to the same note
+		assert_eq!(tracker.record("alice", "https://local.example/notes/1"), 2);
+		assert_eq!(tracker.record("bob", "https://local.example/notes/1"), 1);
+		assert_eq!(tracker.recent("alice"), 2);
+		assert_eq!(tracker.recent("carol"), 0);
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn forgets_replies_past_the_window() {
+		let tracker = ReplyTracker::new(WINDOW);
+		tracker.record("alice", "https://local.example/notes/1");
+		tokio::time::advance(WINDOW / 2).await;
+		tracker.record("alice", "https://local.example/notes/2");
+
+		tokio::time::advance(WINDOW / 2).await;
+		assert_eq!(tracker.recent("alice"), 1);
+		assert_eq!(tracker.record("alice", "https://local.example/notes/3"), 2);
+
+		tokio::time::advance(WINDOW).await;
+		assert_eq!(tracker.recent("alice"), 0);
+		assert!(tracker.entries().is_empty());
+	}
+}
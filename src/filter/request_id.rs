@@ -0,0 +1,34 @@
+use std::fmt::Write;
+
+use super::headers::Headers;
+
+/// Header carrying the ID, from a trusted proxy and on to the AP server.
+pub const HEADER: &str = "X-Request-Id";
+/// Longest ID taken from a trusted proxy.
+const MAX_LEN: usize = 128;
+
+/// A random UUID, version 4.
+pub fn generate() -> String {
+	let mut bytes = [0u8; 16];
+	// the OS running out of randomness only makes IDs less unique
+	getrandom::getrandom(&mut bytes).ok();
+	bytes[6] = (bytes[6] & 0x0F) | 0x40;
+	bytes[8] = (bytes[8] & 0x3F) | 0x80;
+	let mut id = String::with_capacity(36);
+	for (i, b) in bytes.iter().enumerate() {
+		if matches!(i, 4 | 6 | 8 | 10) {
+			id.push('-');
+		}
+		write!(id, "{:02x}", b).ok();
+	}
+	id
+}
+
+/// The ID a trusted proxy like nginx gave the request, so all logs share one, if it's sane.
+pub fn forwarded<'a>(headers: &Headers<'a>) -> Option<&'a str> {
+	let id = headers.all(HEADER).next()?;
+	let sane = !id.is_empty()
+		&& id.len() <= MAX_LEN
+		&& id.bytes().all(|b| b.is_ascii_graphic() && b != b'"' && b != b'\\');
+	sane.then_some(id)
+}
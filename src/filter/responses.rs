@@ -0,0 +1,132 @@
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{pow, Direction, RejectReason};
+
+/// Reason categories responses can be configured for, see [`RejectReason::category`].
+const CATEGORIES: &[&str] = &[
+	"timeout",
+	"io",
+	"query",
+	"terminated",
+	"malformed",
+	"invalid",
+	"spam",
+	"quarantined",
+	"throttled",
+	"blocked",
+	"tarpitted",
+	"unexpected-host",
+	"duplicate",
+	"too-complex",
+	"unavailable",
+	"upstream-timeout",
+	"honeypot",
+	"user-agent",
+	"asn",
+	"webfinger",
+	"no-recipient",
+];
+
+#[derive(Error, Debug)]
+pub enum ResponseError {
+	#[error("unknown reject reason `{0}`, expected one of: {}", CATEGORIES.join(", "))]
+	UnknownReason(String),
+	#[error("invalid status {0} for `{1}`")]
+	Status(u16, String),
+	#[error("invalid response `{0}` for `{1}`, expected a status or \"close\"")]
+	Action(String, String),
+}
+
+/// A response as written in the config file: a status code, a status code with a
+/// `Retry-After` in seconds, or `"close"` to hang up without a response.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ResponseConfig {
+	Status(u16),
+	Close(String),
+	Full { status: u16, retry_after: Option<u64> },
+}
+
+/// How to answer each kind of rejection before closing the connection.
+///
+/// Whatever isn't configured keeps the built-in behavior.
+#[derive(Debug, Clone)]
+pub struct Responses {
+	direction: Direction,
+	configured: Arc<HashMap<&'static str, Option<Vec<u8>>>>,
+}
+
+impl Responses {
+	pub fn new(
+		direction: Direction, configs: &HashMap<String, ResponseConfig>,
+	) -> Result<Self, ResponseError> {
+		let mut configured = HashMap::new();
+		for (reason, config) in configs {
+			let Some(category) = CATEGORIES.iter().find(|c| **c == reason) else {
+				return Err(ResponseError::UnknownReason(reason.clone()));
+			};
+			let (status, retry_after) = match config {
+				ResponseConfig::Close(close) if close == "close" => {
+					configured.insert(*category, None);
+					continue;
+				}
+				ResponseConfig::Close(other) => {
+					return Err(ResponseError::Action(other.clone(), reason.clone()))
+				}
+				ResponseConfig::Status(status) => (*status, None),
+				ResponseConfig::Full { status, retry_after } => (*status, *retry_after),
+			};
+			if !(100..=599).contains(&status) {
+				return Err(ResponseError::Status(status, reason.clone()));
+			}
+			configured.insert(*category, Some(response(status, retry_after)));
+		}
+		Ok(Responses { direction, configured: Arc::new(configured) })
+	}
+
+	/// Response to send for a rejection, if any.
+	pub fn get<'a>(&'a self, reason: &RejectReason) -> Option<Cow<'a, [u8]>> {
+		// the challenge is the whole point of the answer
+		if let RejectReason::Challenged(_, challenge) = reason {
+			return Some(Cow::Owned(pow::response(challenge)));
+		}
+		match self.configured.get(reason.category()) {
+			Some(response) => response.as_deref().map(Cow::Borrowed),
+			None => reason.response(self.direction).map(Cow::Borrowed),
+		}
+	}
+}
+
+fn response(status: u16, retry_after: Option<u64>) -> Vec<u8> {
+	let mut response = format!("HTTP/1.1 {} {}\r\nContent-Length: 0\r\n", status, phrase(status));
+	if let Some(retry_after) = retry_after {
+		response.push_str(&format!("Retry-After: {}\r\n", retry_after));
+	}
+	response.push_str("Connection: close\r\n\r\n");
+	response.into_bytes()
+}
+
+fn phrase(status: u16) -> &'static str {
+	match status {
+		200 => "OK",
+		202 => "Accepted",
+		204 => "No Content",
+		400 => "Bad Request",
+		401 => "Unauthorized",
+		403 => "Forbidden",
+		404 => "Not Found",
+		410 => "Gone",
+		413 => "Payload Too Large",
+		421 => "Misdirected Request",
+		422 => "Unprocessable Content",
+		429 => "Too Many Requests",
+		500 => "Internal Server Error",
+		502 => "Bad Gateway",
+		503 => "Service Unavailable",
+		504 => "Gateway Timeout",
+		_ => "",
+	}
+}
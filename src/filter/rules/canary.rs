@@ -0,0 +1,55 @@
+use std::{
+	collections::hash_map::DefaultHasher,
+	hash::{Hash, Hasher},
+};
+
+use serde::Deserialize;
+
+use super::{RuleConfig, RuleError, RuleSet};
+
+/// `[canary]` in the config file: a candidate ruleset to try out next to the active one before
+/// it replaces it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryConfig {
+	/// Replaces `[[rules]]` for the actors the candidate judges. Rule pack rules follow them as
+	/// usual.
+	pub rules: Vec<RuleConfig>,
+	/// Share of actors, in percent, whose activities the candidate judges instead of the active
+	/// rules. With 0, it only runs in shadow.
+	#[serde(default)]
+	pub percent: u8,
+}
+
+impl CanaryConfig {
+	pub fn validate(&self) -> Result<(), String> {
+		if self.percent > 100 {
+			return Err(format!("canary percent {} is over 100", self.percent));
+		}
+		RuleSet::compile(&self.rules).map(|_| ()).map_err(|e| e.to_string())
+	}
+}
+
+/// A candidate ruleset judging a fixed share of actors, and compared with the active rules on
+/// the rest.
+#[derive(Debug)]
+pub struct Canary {
+	pub rules: RuleSet,
+	percent: u8,
+}
+
+impl Canary {
+	/// Compile the candidate, followed by the rule packs' rules like the active ones.
+	pub fn compile(config: &CanaryConfig, packs: &[RuleConfig]) -> Result<Self, RuleError> {
+		let rules = RuleSet::compile(&[&config.rules[..], packs].concat())?;
+		Ok(Canary { rules, percent: config.percent })
+	}
+
+	/// Whether the candidate judges `actor`. The same actors always are, so their verdicts
+	/// don't flip between deliveries.
+	pub fn picks(&self, actor: &str) -> bool {
+		let mut hasher = DefaultHasher::new();
+		actor.hash(&mut hasher);
+		hasher.finish() % 100 < u64::from(self.percent)
+	}
+}
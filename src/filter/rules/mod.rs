@@ -0,0 +1,553 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::query::{InstanceStats, User};
+
+pub mod canary;
+pub mod pack;
+mod parse;
+
+pub const DEFAULT_THROTTLE_LIMIT: u32 = 1;
+pub const DEFAULT_THROTTLE_WINDOW_SECS: u64 = 60;
+
+/// What the filter used to hard-code: notes notifying local users from unknown or tiny
+/// instances, sent by actors nobody follows and who follow nobody.
+const DEFAULT_RULES: &[(&str, &str)] = &[(
+	"nobody from a sketchy instance",
+	"activity.type == 'Create' && (object.type == 'Note' || object.type == 'Question')
+		&& audience.local
+		&& (!instance.known
+			|| (instance.followers < 5 && instance.following < 5
+				&& (!actor.known || (actor.followers == 0 && actor.following == 0))))",
+)];
+
+#[derive(Error, Debug)]
+pub enum RuleError {
+	#[error("unexpected {0} at offset {1}")]
+	Unexpected(String, usize),
+	#[error("unknown field `{0}`")]
+	UnknownField(String),
+	#[error("cannot {0}")]
+	Type(String),
+	#[error("rule \"{0}\": {1}")]
+	InRule(String, Box<RuleError>),
+	#[error("no rule named \"{0}\"")]
+	NotFound(String),
+}
+
+/// What to do with an activity matching a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+	/// Drop the delivery.
+	Reject,
+	/// Hold the activity for review and tell the sender it was accepted.
+	Quarantine,
+	/// Forward the activity, annotated with the rule name in a header.
+	Tag,
+	/// Forward up to `limit` matching activities per actor every `window` seconds, reject the rest.
+	Throttle,
+	/// Forward the activity, logging the match.
+	Log,
+}
+
+/// A rule as written in the config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	pub when: String,
+	pub action: Action,
+	/// For `throttle`: matching activities let through per window.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub limit: Option<u32>,
+	/// For `throttle`: window length in seconds.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub window: Option<u64>,
+}
+
+impl RuleConfig {
+	/// Name of the rule at index `i`: its own, or its position counting from 1.
+	fn display_name(&self, i: usize) -> String {
+		self.name.clone().unwrap_or_else(|| format!("#{}", i + 1))
+	}
+}
+
+/// Index of the rule called `name` among `configs`.
+pub fn position(configs: &[RuleConfig], name: &str) -> Result<usize, RuleError> {
+	configs
+		.iter()
+		.enumerate()
+		.position(|(i, rule)| rule.display_name(i) == name)
+		.ok_or_else(|| RuleError::NotFound(name.to_string()))
+}
+
+#[derive(Debug)]
+pub struct Rule {
+	pub name: String,
+	pub action: Action,
+	pub limit: u32,
+	pub window: Duration,
+	when: Expr,
+}
+
+/// Compiled rules, evaluated in order.
+///
+/// `tag`, `log` and `throttle` (while under its limit) let evaluation continue with the next
+/// rule, the first `reject` or `quarantine` match decides.
+#[derive(Debug)]
+pub struct RuleSet {
+	rules: Vec<Rule>,
+	configs: Vec<RuleConfig>,
+}
+
+/// Everything a rule condition can look at.
+///
+/// Actor and instance stats come from the DB and are only looked up once a rule actually needs
+/// them: `None` means not fetched yet, `Some(None)` means the DB doesn't know them.
+pub struct Facts<'a> {
+	pub activity_type: &'a str,
+	pub object_type: &'a str,
+	pub actor: Option<Option<&'a User>>,
+	pub instance: Option<Option<&'a InstanceStats>>,
+	pub mentions: usize,
+	pub hashtags: usize,
+	pub emojis: usize,
+	/// Share of the text that's emoji, in percent.
+	pub emoji_percent: u32,
+	pub audience_size: usize,
+	pub audience_local: bool,
+	/// `public`, `unlisted`, `followers` or `direct`.
+	pub visibility: &'a str,
+	pub recent_replies: usize,
+	/// Notes admitted from the instance within the last hour.
+	pub instance_rate: u32,
+	pub instance_surge: bool,
+	pub score: u32,
+	pub actor_reputation: i64,
+	pub instance_reputation: i64,
+}
+
+/// Stats the rules need before they can be decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Need {
+	Actor,
+	Instance,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+	Bool,
+	Int,
+	Str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+	Eq,
+	Ne,
+	Lt,
+	Le,
+	Gt,
+	Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+	ActivityType,
+	ObjectType,
+	ActorKnown,
+	ActorFollowers,
+	ActorFollowing,
+	ActorNotes,
+	ActorReputation,
+	InstanceKnown,
+	InstanceFollowers,
+	InstanceFollowing,
+	InstanceNotes,
+	InstanceReputation,
+	InstanceRate,
+	InstanceSurge,
+	ContentMentions,
+	ContentHashtags,
+	ContentEmojis,
+	ContentEmojiPercent,
+	AudienceSize,
+	AudienceLocal,
+	AudienceVisibility,
+	RepliesRecent,
+	Score,
+}
+
+#[derive(Debug)]
+enum Lit {
+	Bool(bool),
+	Int(i64),
+	Str(String),
+}
+
+#[derive(Debug)]
+enum Expr {
+	Lit(Lit),
+	Field(Field),
+	Not(Box<Expr>),
+	And(Box<Expr>, Box<Expr>),
+	Or(Box<Expr>, Box<Expr>),
+	Cmp(CmpOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Val<'a> {
+	Bool(bool),
+	Int(i64),
+	Str(&'a str),
+}
+
+impl RuleSet {
+	pub fn compile(configs: &[RuleConfig]) -> Result<Self, RuleError> {
+		let rules = configs
+			.iter()
+			.enumerate()
+			.map(|(i, rule)| {
+				let name = rule.display_name(i);
+				match parse::parse(&rule.when) {
+					Ok(when) => Ok(Rule {
+						name,
+						action: rule.action,
+						limit: rule.limit.unwrap_or(DEFAULT_THROTTLE_LIMIT),
+						window: Duration::from_secs(
+							rule.window.unwrap_or(DEFAULT_THROTTLE_WINDOW_SECS),
+						),
+						when,
+					}),
+					Err(e) => Err(RuleError::InRule(name, Box::new(e))),
+				}
+			})
+			.collect::<Result<_, _>>()?;
+		Ok(RuleSet { rules, configs: configs.to_vec() })
+	}
+
+	pub fn default_rules() -> Self {
+		let rules: Vec<_> = DEFAULT_RULES
+			.iter()
+			.map(|(name, when)| RuleConfig {
+				name: Some(name.to_string()),
+				when: when.to_string(),
+				action: Action::Reject,
+				limit: None,
+				window: None,
+			})
+			.collect();
+		#[allow(clippy::unwrap_used)]
+		RuleSet::compile(&rules).unwrap()
+	}
+
+	pub fn empty() -> Self {
+		RuleSet { rules: Vec::new(), configs: Vec::new() }
+	}
+
+	/// What the rules were compiled from.
+	pub fn configs(&self) -> &[RuleConfig] {
+		&self.configs
+	}
+
+	/// First rule at or after index `from` matching the facts, with its index, or which stats are
+	/// missing to decide that.
+	pub fn next_match(&self, from: usize, facts: &Facts) -> Result<Option<(usize, &Rule)>, Need> {
+		for (i, rule) in self.rules.iter().enumerate().skip(from) {
+			if rule.when.eval(facts)? == Val::Bool(true) {
+				return Ok(Some((i, rule)));
+			}
+		}
+		Ok(None)
+	}
+
+	/// The first `reject` or `quarantine` rule matching the facts, which decides the verdict,
+	/// leaving out throttles since they depend on what came before.
+	pub fn decision(&self, facts: &Facts) -> Result<Option<&Rule>, Need> {
+		let mut from = 0;
+		while let Some((i, rule)) = self.next_match(from, facts)? {
+			if matches!(rule.action, Action::Reject | Action::Quarantine) {
+				return Ok(Some(rule));
+			}
+			from = i + 1;
+		}
+		Ok(None)
+	}
+}
+
+impl Field {
+	fn from_name(name: &str) -> Option<Self> {
+		Some(match name {
+			"activity.type" => Field::ActivityType,
+			"object.type" => Field::ObjectType,
+			"actor.known" => Field::ActorKnown,
+			"actor.followers" => Field::ActorFollowers,
+			"actor.following" => Field::ActorFollowing,
+			"actor.notes" => Field::ActorNotes,
+			"actor.reputation" => Field::ActorReputation,
+			"instance.known" => Field::InstanceKnown,
+			"instance.followers" => Field::InstanceFollowers,
+			"instance.following" => Field::InstanceFollowing,
+			"instance.notes" => Field::InstanceNotes,
+			"instance.reputation" => Field::InstanceReputation,
+			"instance.rate" => Field::InstanceRate,
+			"instance.surge" => Field::InstanceSurge,
+			"content.mentions" => Field::ContentMentions,
+			"content.hashtags" => Field::ContentHashtags,
+			"content.emojis" => Field::ContentEmojis,
+			"content.emoji_percent" => Field::ContentEmojiPercent,
+			"audience.size" => Field::AudienceSize,
+			"audience.local" => Field::AudienceLocal,
+			"audience.visibility" => Field::AudienceVisibility,
+			"replies.recent" => Field::RepliesRecent,
+			"score" => Field::Score,
+			_ => return None,
+		})
+	}
+
+	fn ty(self) -> Type {
+		match self {
+			Field::ActivityType | Field::ObjectType | Field::AudienceVisibility => Type::Str,
+			Field::ActorKnown
+			| Field::InstanceKnown
+			| Field::InstanceSurge
+			| Field::AudienceLocal => Type::Bool,
+			_ => Type::Int,
+		}
+	}
+
+	fn get<'a>(self, facts: &Facts<'a>) -> Result<Val<'a>, Need> {
+		let actor = || facts.actor.ok_or(Need::Actor);
+		let instance = || facts.instance.ok_or(Need::Instance);
+		Ok(match self {
+			Field::ActivityType => Val::Str(facts.activity_type),
+			Field::ObjectType => Val::Str(facts.object_type),
+			Field::ActorKnown => Val::Bool(actor()?.is_some()),
+			Field::ActorFollowers => Val::Int(actor()?.map_or(0, |u| u.followers).into()),
+			Field::ActorFollowing => Val::Int(actor()?.map_or(0, |u| u.following).into()),
+			Field::ActorNotes => Val::Int(actor()?.map_or(0, |u| u.notes).into()),
+			Field::ActorReputation => Val::Int(facts.actor_reputation),
+			Field::InstanceKnown => Val::Bool(instance()?.is_some()),
+			Field::InstanceFollowers => Val::Int(instance()?.map_or(0, |i| i.followers).into()),
+			Field::InstanceFollowing => Val::Int(instance()?.map_or(0, |i| i.following).into()),
+			Field::InstanceNotes => Val::Int(instance()?.map_or(0, |i| i.notes).into()),
+			Field::InstanceReputation => Val::Int(facts.instance_reputation),
+			Field::InstanceRate => Val::Int(facts.instance_rate.into()),
+			Field::InstanceSurge => Val::Bool(facts.instance_surge),
+			Field::ContentMentions => Val::Int(facts.mentions as i64),
+			Field::ContentHashtags => Val::Int(facts.hashtags as i64),
+			Field::ContentEmojis => Val::Int(facts.emojis as i64),
+			Field::ContentEmojiPercent => Val::Int(facts.emoji_percent.into()),
+			Field::AudienceSize => Val::Int(facts.audience_size as i64),
+			Field::AudienceLocal => Val::Bool(facts.audience_local),
+			Field::AudienceVisibility => Val::Str(facts.visibility),
+			Field::RepliesRecent => Val::Int(facts.recent_replies as i64),
+			Field::Score => Val::Int(facts.score.into()),
+		})
+	}
+}
+
+impl Expr {
+	fn ty(&self) -> Type {
+		match self {
+			Expr::Lit(Lit::Bool(_)) => Type::Bool,
+			Expr::Lit(Lit::Int(_)) => Type::Int,
+			Expr::Lit(Lit::Str(_)) => Type::Str,
+			Expr::Field(field) => field.ty(),
+			_ => Type::Bool,
+		}
+	}
+
+	/// Evaluate with short-circuiting, so stats behind an unsatisfied condition are never
+	/// needed.
+	fn eval<'a>(&'a self, facts: &Facts<'a>) -> Result<Val<'a>, Need> {
+		Ok(match self {
+			Expr::Lit(Lit::Bool(b)) => Val::Bool(*b),
+			Expr::Lit(Lit::Int(n)) => Val::Int(*n),
+			Expr::Lit(Lit::Str(s)) => Val::Str(s),
+			Expr::Field(field) => field.get(facts)?,
+			Expr::Not(expr) => Val::Bool(expr.eval(facts)? != Val::Bool(true)),
+			Expr::And(lhs, rhs) => {
+				Val::Bool(lhs.eval(facts)? == Val::Bool(true) && rhs.eval(facts)? == Val::Bool(true))
+			}
+			Expr::Or(lhs, rhs) => {
+				Val::Bool(lhs.eval(facts)? == Val::Bool(true) || rhs.eval(facts)? == Val::Bool(true))
+			}
+			Expr::Cmp(op, lhs, rhs) => {
+				let (lhs, rhs) = (lhs.eval(facts)?, rhs.eval(facts)?);
+				if let (Val::Str(l), Val::Str(r)) = (&lhs, &rhs) {
+					// activity types are case-insensitive in the wild
+					return Ok(Val::Bool(l.eq_ignore_ascii_case(r) == (*op == CmpOp::Eq)));
+				}
+				Val::Bool(match op {
+					CmpOp::Eq => lhs == rhs,
+					CmpOp::Ne => lhs != rhs,
+					CmpOp::Lt => lhs < rhs,
+					CmpOp::Le => lhs <= rhs,
+					CmpOp::Gt => lhs > rhs,
+					CmpOp::Ge => lhs >= rhs,
+				})
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+	use std::time::Duration;
+
+	use super::*;
+
+	const NOBODY: User = User { followers: 0, following: 0, notes: 3, age: Duration::ZERO };
+
+	fn facts() -> Facts<'static> {
+		Facts {
+			activity_type: "Create",
+			object_type: "Note",
+			actor: Some(None),
+			instance: Some(None),
+			mentions: 1,
+			hashtags: 0,
+			emojis: 0,
+			emoji_percent: 0,
+			audience_size: 1,
+			audience_local: true,
+			visibility: "public",
+			recent_replies: 0,
+			instance_rate: 0,
+			instance_surge: false,
+			score: 0,
+			actor_reputation: 0,
+			instance_reputation: 0,
+		}
+	}
+
+	fn eval(src: &str, facts: &Facts) -> Result<bool, Need> {
+		let expr = parse::parse(src).unwrap();
+		Ok(expr.eval(facts)? == Val::Bool(true))
+	}
+
+	#[test]
+	fn parses_valid_rules() {
+		for src in [
+			"true",
+			"content.mentions > 3",
+			"content.mentions>=3&&!audience.local",
+			"(actor.followers == 0) || score >= 100",
+			"object.type == \"Question\" && audience.visibility != 'direct'",
+			DEFAULT_RULES[0].1,
+		] {
+			assert!(parse::parse(src).is_ok(), "{}", src);
+		}
+	}
+
+	#[test]
+	fn rejects_invalid_rules() {
+		for (src, error) in [
+			("", "unexpected end of rule at offset 0"),
+			("content.mentions > ", "unexpected end of rule at offset 19"),
+			("score = 1", "unexpected = at offset 6"),
+			("score > 1 &", "unexpected & at offset 10"),
+			("(score > 1", "unexpected end of rule at offset 10"),
+			("score > 1)", "unexpected RParen at offset 9"),
+			("object.type == 'Note", "unexpected end of rule at offset 20"),
+			("score # 1", "unexpected # at offset 6"),
+			("actor.age > 1", "unknown field `actor.age`"),
+			("99999999999999999999 > 1", "unexpected 99999999999999999999 at offset 0"),
+		] {
+			let e = parse::parse(src).map(|_| ()).unwrap_err();
+			assert_eq!(e.to_string(), error, "{}", src);
+		}
+	}
+
+	#[test]
+	fn not_binds_tighter_than_and_than_or() {
+		let facts = facts();
+		// || (&& …) rather than (|| …) &&
+		assert!(eval("true || false && false", &facts).unwrap());
+		assert!(eval("false && false || true", &facts).unwrap());
+		// (!true) || true rather than !(true || true)
+		assert!(eval("!true || true", &facts).unwrap());
+		// (!false) && false rather than !(false && false)
+		assert!(!eval("!false && false", &facts).unwrap());
+		assert!(eval("!(false && false)", &facts).unwrap());
+		assert!(eval("!!true", &facts).unwrap());
+		// comparisons bind tighter than all of them, or `!score` would be a type error
+		assert!(!eval("!score == 0", &facts).unwrap());
+		assert!(eval("content.mentions == 1 && score == 0", &facts).unwrap());
+	}
+
+	#[test]
+	fn checks_types() {
+		for (src, error) in [
+			("score", "cannot use a non-boolean expression as a condition"),
+			("'Note'", "cannot use a non-boolean expression as a condition"),
+			("score == 'Note'", "cannot compare Int with Str"),
+			("audience.local == 1", "cannot compare Bool with Int"),
+			("object.type < 'Note'", "cannot order Str values"),
+			("audience.local >= true", "cannot order Bool values"),
+			("score && true", "cannot apply `&&` to Int"),
+			("true || object.type", "cannot apply `||` to Str"),
+			("!score", "cannot apply `!` to Int"),
+		] {
+			let e = parse::parse(src).map(|_| ()).unwrap_err();
+			assert_eq!(e.to_string(), error, "{}", src);
+		}
+	}
+
+	#[test]
+	fn compares_strings_case_insensitively() {
+		let facts = Facts { activity_type: "create", object_type: "QUESTION", ..facts() };
+		assert!(eval("activity.type == 'Create'", &facts).unwrap());
+		assert!(eval("object.type == 'question'", &facts).unwrap());
+		assert!(!eval("object.type != 'Question'", &facts).unwrap());
+		assert!(eval("object.type != 'Note'", &facts).unwrap());
+		assert!(!eval("object.type == 'Questions'", &facts).unwrap());
+	}
+
+	#[test]
+	fn asks_for_stats_once() {
+		let rules = RuleSet::compile(&[RuleConfig {
+			name: None,
+			when: "!instance.known || (actor.followers == 0 && actor.following == 0 \
+				&& actor.notes < 10 && instance.followers < 5)"
+				.into(),
+			action: Action::Reject,
+			limit: None,
+			window: None,
+		}])
+		.unwrap();
+		let stats = InstanceStats { followers: 1, following: 1, notes: 10 };
+
+		let mut facts = Facts { actor: None, instance: None, ..facts() };
+		let mut needed = Vec::new();
+		let decision = loop {
+			match rules.decision(&facts) {
+				Ok(rule) => break rule.map(|r| r.name.clone()),
+				Err(need) => {
+					needed.push(need);
+					match need {
+						Need::Actor => facts.actor = Some(Some(&NOBODY)),
+						Need::Instance => facts.instance = Some(Some(&stats)),
+					}
+				}
+			}
+		};
+		assert_eq!(decision.as_deref(), Some("#1"));
+		assert_eq!(needed, [Need::Instance, Need::Actor]);
+	}
+
+	#[test]
+	fn skips_stats_behind_unsatisfied_conditions() {
+		let facts = Facts { actor: None, instance: None, audience_local: false, ..facts() };
+		assert!(!eval("audience.local && actor.followers == 0", &facts).unwrap());
+		assert!(eval("!audience.local || !instance.known", &facts).unwrap());
+		assert_eq!(eval("actor.known", &facts), Err(Need::Actor));
+	}
+}
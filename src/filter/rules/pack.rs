@@ -0,0 +1,128 @@
+use std::{
+	collections::HashSet,
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::RuleConfig;
+
+/// The only pack format understood so far, in `[pack] format`.
+pub const FORMAT: u32 = 1;
+/// Extension of pack files. Anything else in the directory is ignored.
+const EXTENSION: &str = "toml";
+
+#[derive(Error, Debug)]
+pub enum PackError {
+	#[error("Could not read {0}: {1}")]
+	Read(PathBuf, io::Error),
+	#[error("Invalid rule pack {0}: {1}")]
+	Parse(PathBuf, toml::de::Error),
+	#[error("{0} is in pack format {1}, only {FORMAT} is supported")]
+	Format(PathBuf, u32),
+	#[error("{0} and {1} are both named \"{2}\"")]
+	Duplicate(PathBuf, PathBuf, String),
+}
+
+/// A rule pack file: countermeasures against a campaign, shared as one file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulePack {
+	pub pack: PackInfo,
+	#[serde(default)]
+	pub rules: Vec<RuleConfig>,
+	/// Phrases whose presence in a note's text is a strong `keyword` signal, matched
+	/// case-insensitively.
+	#[serde(default)]
+	pub keywords: Vec<String>,
+	/// Content fingerprints of the campaign's notes, as exchanged with `--share-db`.
+	#[serde(default)]
+	pub fingerprints: Vec<String>,
+}
+
+/// `[pack]`: what the pack is, for whoever finds it in their directory.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackInfo {
+	pub format: u32,
+	/// Prefixed to the names of the pack's rules.
+	pub name: String,
+	/// The pack's own version, for its maintainers.
+	pub version: Option<String>,
+	pub description: Option<String>,
+	pub homepage: Option<String>,
+}
+
+/// Keywords and fingerprints of every loaded pack together.
+#[derive(Debug, Default)]
+pub struct PackLists {
+	/// Lowercased.
+	pub keywords: Vec<String>,
+	pub fingerprints: HashSet<String>,
+}
+
+impl PackLists {
+	/// First keyword in `text`, if any.
+	pub fn keyword_in(&self, text: &str) -> Option<&str> {
+		if self.keywords.is_empty() {
+			return None;
+		}
+		let text = text.to_lowercase();
+		self.keywords.iter().find(|k| text.contains(k.as_str())).map(|k| k.as_str())
+	}
+}
+
+/// Every pack in a directory, merged.
+#[derive(Debug, Default)]
+pub struct Packs {
+	/// Rules of all packs in file name order, to evaluate after the configured ones.
+	pub rules: Vec<RuleConfig>,
+	pub lists: PackLists,
+}
+
+impl RulePack {
+	pub fn load(path: &Path) -> Result<Self, PackError> {
+		let src = fs::read_to_string(path).map_err(|e| PackError::Read(path.to_path_buf(), e))?;
+		let pack: RulePack =
+			toml::from_str(&src).map_err(|e| PackError::Parse(path.to_path_buf(), e))?;
+		if pack.pack.format != FORMAT {
+			return Err(PackError::Format(path.to_path_buf(), pack.pack.format));
+		}
+		Ok(pack)
+	}
+}
+
+impl Packs {
+	/// Load the `*.toml` packs in `dir`, in file name order so the merged rules are too.
+	pub fn load_dir(dir: &Path) -> Result<Self, PackError> {
+		let read = |e| PackError::Read(dir.to_path_buf(), e);
+		let mut paths = Vec::new();
+		for entry in fs::read_dir(dir).map_err(read)? {
+			let path = entry.map_err(read)?.path();
+			if path.is_file() && path.extension().is_some_and(|e| e == EXTENSION) {
+				paths.push(path);
+			}
+		}
+		paths.sort();
+
+		let mut packs = Packs::default();
+		let mut names: Vec<(String, PathBuf)> = Vec::new();
+		for path in paths {
+			let pack = RulePack::load(&path)?;
+			if let Some((_, other)) = names.iter().find(|(name, _)| *name == pack.pack.name) {
+				return Err(PackError::Duplicate(other.clone(), path, pack.pack.name));
+			}
+			for (i, rule) in pack.rules.into_iter().enumerate() {
+				let name = format!("{}/{}", pack.pack.name, rule.display_name(i));
+				packs.rules.push(RuleConfig { name: Some(name), ..rule });
+			}
+			packs.lists.keywords.extend(pack.keywords.iter().map(|k| k.to_lowercase()));
+			packs.lists.fingerprints.extend(pack.fingerprints);
+			names.push((pack.pack.name, path));
+		}
+		packs.lists.keywords.retain(|k| !k.trim().is_empty());
+		Ok(packs)
+	}
+}
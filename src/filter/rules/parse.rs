@@ -0,0 +1,214 @@
+use super::{CmpOp, Expr, Field, Lit, RuleError, Type};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Ident(String),
+	Int(i64),
+	Str(String),
+	And,
+	Or,
+	Not,
+	Cmp(CmpOp),
+	LParen,
+	RParen,
+}
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, RuleError> {
+	let mut tokens = Vec::new();
+	let mut chars = src.char_indices().peekable();
+	while let Some(&(at, c)) = chars.peek() {
+		let token = match c {
+			c if c.is_whitespace() => {
+				chars.next();
+				continue;
+			}
+			'(' => {
+				chars.next();
+				Token::LParen
+			}
+			')' => {
+				chars.next();
+				Token::RParen
+			}
+			'&' | '|' | '=' => {
+				chars.next();
+				if chars.next_if(|&(_, n)| n == c).is_none() {
+					return Err(RuleError::Unexpected(c.to_string(), at));
+				}
+				match c {
+					'&' => Token::And,
+					'|' => Token::Or,
+					_ => Token::Cmp(CmpOp::Eq),
+				}
+			}
+			'!' | '<' | '>' => {
+				chars.next();
+				let eq = chars.next_if(|&(_, n)| n == '=').is_some();
+				match (c, eq) {
+					('!', false) => Token::Not,
+					('!', true) => Token::Cmp(CmpOp::Ne),
+					('<', false) => Token::Cmp(CmpOp::Lt),
+					('<', true) => Token::Cmp(CmpOp::Le),
+					('>', false) => Token::Cmp(CmpOp::Gt),
+					_ => Token::Cmp(CmpOp::Ge),
+				}
+			}
+			'\'' | '"' => {
+				chars.next();
+				let mut s = String::new();
+				loop {
+					match chars.next() {
+						Some((_, q)) if q == c => break,
+						Some((_, ch)) => s.push(ch),
+						None => return Err(RuleError::Unexpected("end of rule".into(), src.len())),
+					}
+				}
+				Token::Str(s)
+			}
+			c if c.is_ascii_digit() => {
+				let mut n = String::new();
+				while let Some((_, d)) = chars.next_if(|(_, d)| d.is_ascii_digit()) {
+					n.push(d);
+				}
+				Token::Int(n.parse().map_err(|_| RuleError::Unexpected(n, at))?)
+			}
+			c if c.is_ascii_alphabetic() || c == '_' => {
+				let mut ident = String::new();
+				while let Some((_, ch)) =
+					chars.next_if(|(_, ch)| ch.is_ascii_alphanumeric() || *ch == '_' || *ch == '.')
+				{
+					ident.push(ch);
+				}
+				Token::Ident(ident)
+			}
+			c => return Err(RuleError::Unexpected(c.to_string(), at)),
+		};
+		tokens.push((at, token));
+	}
+	Ok(tokens)
+}
+
+/// Recursive descent parser for rule conditions.
+///
+/// ```text
+/// or      := and ("||" and)*
+/// and     := not ("&&" not)*
+/// not     := "!" not | cmp
+/// cmp     := primary (("==" | "!=" | "<" | "<=" | ">" | ">=") primary)?
+/// primary := int | string | "true" | "false" | field | "(" or ")"
+/// ```
+struct Parser {
+	tokens: Vec<(usize, Token)>,
+	pos: usize,
+	len: usize,
+}
+
+pub fn parse(src: &str) -> Result<Expr, RuleError> {
+	let mut parser = Parser { tokens: tokenize(src)?, pos: 0, len: src.len() };
+	let expr = parser.or()?;
+	if let Some((at, token)) = parser.tokens.get(parser.pos) {
+		return Err(RuleError::Unexpected(format!("{:?}", token), *at));
+	}
+	if expr.ty() != Type::Bool {
+		return Err(RuleError::Type("use a non-boolean expression as a condition".into()));
+	}
+	Ok(expr)
+}
+
+impl Parser {
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.pos).map(|(_, t)| t)
+	}
+
+	fn next(&mut self) -> Result<Token, RuleError> {
+		let token = self
+			.tokens
+			.get(self.pos)
+			.map(|(_, t)| t.clone())
+			.ok_or(RuleError::Unexpected("end of rule".into(), self.len))?;
+		self.pos += 1;
+		Ok(token)
+	}
+
+	fn unexpected(&self) -> RuleError {
+		match self.tokens.get(self.pos.saturating_sub(1)) {
+			Some((at, token)) => RuleError::Unexpected(format!("{:?}", token), *at),
+			None => RuleError::Unexpected("end of rule".into(), self.len),
+		}
+	}
+
+	fn or(&mut self) -> Result<Expr, RuleError> {
+		let mut lhs = self.and()?;
+		while self.peek() == Some(&Token::Or) {
+			self.pos += 1;
+			let rhs = self.and()?;
+			lhs = Expr::Or(Box::new(bool_operand(lhs, "||")?), Box::new(bool_operand(rhs, "||")?));
+		}
+		Ok(lhs)
+	}
+
+	fn and(&mut self) -> Result<Expr, RuleError> {
+		let mut lhs = self.not()?;
+		while self.peek() == Some(&Token::And) {
+			self.pos += 1;
+			let rhs = self.not()?;
+			lhs =
+				Expr::And(Box::new(bool_operand(lhs, "&&")?), Box::new(bool_operand(rhs, "&&")?));
+		}
+		Ok(lhs)
+	}
+
+	fn not(&mut self) -> Result<Expr, RuleError> {
+		if self.peek() == Some(&Token::Not) {
+			self.pos += 1;
+			let expr = self.not()?;
+			return Ok(Expr::Not(Box::new(bool_operand(expr, "!")?)));
+		}
+		self.cmp()
+	}
+
+	fn cmp(&mut self) -> Result<Expr, RuleError> {
+		let lhs = self.primary()?;
+		let Some(Token::Cmp(op)) = self.peek().cloned() else {
+			return Ok(lhs);
+		};
+		self.pos += 1;
+		let rhs = self.primary()?;
+
+		let (lt, rt) = (lhs.ty(), rhs.ty());
+		if lt != rt {
+			return Err(RuleError::Type(format!("compare {:?} with {:?}", lt, rt)));
+		}
+		if lt != Type::Int && !matches!(op, CmpOp::Eq | CmpOp::Ne) {
+			return Err(RuleError::Type(format!("order {:?} values", lt)));
+		}
+		Ok(Expr::Cmp(op, Box::new(lhs), Box::new(rhs)))
+	}
+
+	fn primary(&mut self) -> Result<Expr, RuleError> {
+		match self.next()? {
+			Token::Int(n) => Ok(Expr::Lit(Lit::Int(n))),
+			Token::Str(s) => Ok(Expr::Lit(Lit::Str(s))),
+			Token::Ident(ident) => match ident.as_str() {
+				"true" => Ok(Expr::Lit(Lit::Bool(true))),
+				"false" => Ok(Expr::Lit(Lit::Bool(false))),
+				_ => Field::from_name(&ident).map(Expr::Field).ok_or(RuleError::UnknownField(ident)),
+			},
+			Token::LParen => {
+				let expr = self.or()?;
+				match self.next()? {
+					Token::RParen => Ok(expr),
+					_ => Err(self.unexpected()),
+				}
+			}
+			_ => Err(self.unexpected()),
+		}
+	}
+}
+
+fn bool_operand(expr: Expr, op: &str) -> Result<Expr, RuleError> {
+	match expr.ty() {
+		Type::Bool => Ok(expr),
+		ty => Err(RuleError::Type(format!("apply `{}` to {:?}", op, ty))),
+	}
+}
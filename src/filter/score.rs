@@ -0,0 +1,47 @@
+use std::fmt;
+
+/// Weight of a signal that is damning on its own.
+pub const STRONG: u32 = 100;
+/// Weight of a signal that only tips the balance along with others.
+pub const WEAK: u32 = 50;
+
+/// Spam signals raised by the heuristics for one activity.
+///
+/// Each heuristic adds a named, weighted signal instead of rejecting outright, and the activity
+/// is rejected once the total crosses the configured threshold.
+#[derive(Debug, Default, Clone)]
+pub struct Score {
+	signals: Vec<(String, u32)>,
+}
+
+impl Score {
+	pub fn add(&mut self, signal: impl Into<String>, weight: u32) {
+		self.signals.push((signal.into(), weight));
+	}
+
+	/// The same signals, leaving out those named `names`.
+	pub fn without(&self, names: &[&str]) -> Score {
+		let signals = self.signals.iter().filter(|(signal, _)| !names.contains(&signal.as_str()));
+		Score { signals: signals.cloned().collect() }
+	}
+
+	pub fn total(&self) -> u32 {
+		self.signals.iter().map(|(_, weight)| weight).sum()
+	}
+
+	pub fn signals(&self) -> Vec<&str> {
+		self.signals.iter().map(|(signal, _)| signal.as_str()).collect()
+	}
+}
+
+impl fmt::Display for Score {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for (i, (signal, weight)) in self.signals.iter().enumerate() {
+			if i > 0 {
+				f.write_str(", ")?;
+			}
+			write!(f, "{}={}", signal, weight)?;
+		}
+		Ok(())
+	}
+}
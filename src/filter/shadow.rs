@@ -0,0 +1,96 @@
+use serde::Deserialize;
+use tracing::*;
+
+use super::{
+	rules::{Action, Facts, Need, RuleConfig, RuleError, RuleSet},
+	Thresholds, ThresholdsPatch, SCORE_STAGE,
+};
+use crate::statsd::Statsd;
+
+/// Signals raised by comparing a measure with a threshold, raised again with pipeline B's.
+pub const THRESHOLD_SIGNALS: &[&str] = &["audience", "reply-flood", "hashtags", "notes-rate"];
+
+/// `[shadow]` in the config file: pipeline B, judging every note alongside the enforced
+/// pipeline A without acting on it. Whatever it leaves out is the same as in A.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShadowConfig {
+	/// Thresholds to change from A's, as they are at the time.
+	#[serde(default)]
+	pub thresholds: ThresholdsPatch,
+	/// Replaces A's rules. Rule pack rules follow them as usual.
+	pub rules: Option<Vec<RuleConfig>>,
+	/// What B does with notes scoring over its threshold.
+	pub score_action: Option<Action>,
+}
+
+impl ShadowConfig {
+	pub fn validate(&self) -> Result<(), String> {
+		match &self.rules {
+			Some(rules) => RuleSet::compile(rules).map(|_| ()).map_err(|e| e.to_string()),
+			None => Ok(()),
+		}
+	}
+}
+
+/// What a pipeline decides on a note: the `reject` or `quarantine` that stops it, and the
+/// stage or rule behind it. Nothing if it's let through.
+pub type Outcome = Option<(Action, String)>;
+
+/// Pipeline B, and how it would decide.
+#[derive(Debug)]
+pub struct Shadow {
+	thresholds: ThresholdsPatch,
+	/// `None` to judge by A's rules.
+	pub rules: Option<RuleSet>,
+	pub score_action: Option<Action>,
+}
+
+impl Shadow {
+	/// Compile B's rules, followed by the rule packs' rules like A's.
+	pub fn compile(config: ShadowConfig, packs: &[RuleConfig]) -> Result<Self, RuleError> {
+		let rules = match &config.rules {
+			Some(rules) => Some(RuleSet::compile(&[&rules[..], packs].concat())?),
+			None => None,
+		};
+		Ok(Shadow { thresholds: config.thresholds, rules, score_action: config.score_action })
+	}
+
+	/// B's thresholds, from A's current ones.
+	pub fn thresholds(&self, a: &Thresholds) -> Thresholds {
+		let mut thresholds = *a;
+		self.thresholds.apply(&mut thresholds);
+		thresholds
+	}
+
+	/// Log and count a note A and B decide differently on.
+	pub fn compare(&self, subject: &str, a: &Outcome, b: &Outcome, statsd: Option<&Statsd>) {
+		let diff = match (a, b) {
+			(None, None) => return,
+			(Some((a, _)), Some((b, _))) if a == b => return,
+			// B would catch what A lets through: more spam caught, or a false positive
+			(None, Some(_)) => "stricter",
+			(Some(_), None) => "looser",
+			(Some(_), Some(_)) => "different",
+		};
+		let describe = |outcome: &Outcome| match outcome {
+			Some((action, name)) => format!("{:?} by \"{}\"", action, name),
+			None => "let through".to_string(),
+		};
+		info!("Shadow pipeline is {} on {}: A {}, B {}", diff, subject, describe(a), describe(b));
+		if let Some(statsd) = statsd {
+			statsd.shadow_diverged(diff);
+		}
+	}
+}
+
+/// How a pipeline decides: by the score stage if the score crossed its threshold and its action
+/// stops the note, or else by the first deciding rule.
+pub fn decide(
+	crossed: bool, score_action: Action, rules: &RuleSet, facts: &Facts,
+) -> Result<Outcome, Need> {
+	if crossed && matches!(score_action, Action::Reject | Action::Quarantine) {
+		return Ok(Some((score_action, SCORE_STAGE.to_string())));
+	}
+	Ok(rules.decision(facts)?.map(|rule| (rule.action, rule.name.clone())))
+}
@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rsa::{
+	pkcs1::DecodeRsaPublicKey,
+	pkcs1v15::{Signature, VerifyingKey},
+	pkcs8::DecodePublicKey,
+	signature::Verifier,
+	RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use tracing::*;
+
+use super::headers::Headers;
+use crate::query::Backend;
+
+/// A delivery whose HTTP signature is only checked once something depends on who sent it, off
+/// the request path.
+#[derive(Clone)]
+pub struct Signed {
+	inner: Arc<Inner>,
+}
+
+struct Inner {
+	header: Vec<u8>,
+	body: Vec<u8>,
+	query: Arc<dyn Backend>,
+	signer: OnceCell<Option<String>>,
+}
+
+impl Signed {
+	pub fn new(header: &[u8], body: &[u8], query: Arc<dyn Backend>) -> Self {
+		let inner =
+			Inner { header: header.to_vec(), body: body.to_vec(), query, signer: OnceCell::new() };
+		Signed { inner: Arc::new(inner) }
+	}
+
+	/// The actor whose key signed the delivery, if the signature covers its target and body
+	/// and verifies against the key the AP server has on record. Keys it has never fetched are
+	/// not fetched here, so deliveries signed with them have no signer.
+	pub async fn signer(&self) -> Option<String> {
+		let inner = &self.inner;
+		let signer = inner.signer.get_or_init(|| async {
+			match verify(&inner.header, &inner.body, inner.query.as_ref()).await {
+				Ok(signer) => Some(signer),
+				Err(e) => {
+					debug!("Could not verify delivery signature: {}", e);
+					None
+				}
+			}
+		});
+		signer.await.clone()
+	}
+}
+
+async fn verify(header: &[u8], body: &[u8], query: &dyn Backend) -> Result<String, &'static str> {
+	let headers = Headers::parse(header)?;
+	let signature = headers.all("signature").next().ok_or("no signature")?;
+	let key_id = param(signature, "keyId").ok_or("no keyId")?;
+	if !matches!(param(signature, "algorithm"), None | Some("rsa-sha256") | Some("hs2019")) {
+		return Err("unsupported algorithm");
+	}
+	let signed: Vec<&str> = param(signature, "headers").unwrap_or("date").split(' ').collect();
+	// a signature over neither could be replayed on any request
+	if !signed.contains(&"(request-target)") || !signed.contains(&"digest") {
+		return Err("request target or digest not signed");
+	}
+	let expected = BASE64.encode(Sha256::digest(body));
+	let digest = headers.get("digest")?.ok_or("no digest")?;
+	let digest_matches = digest.split(',').any(|d| {
+		let (algorithm, value) = d.trim().split_once('=').unwrap_or_default();
+		algorithm.eq_ignore_ascii_case("SHA-256") && value == expected
+	});
+	if !digest_matches {
+		return Err("digest doesn't match the body");
+	}
+
+	let mut string = Vec::with_capacity(signed.len());
+	for name in signed {
+		let name = name.to_ascii_lowercase();
+		let value = match name.as_str() {
+			"(request-target)" => {
+				let line = header.split(|&b| b == b'\r' || b == b'\n').next().unwrap_or_default();
+				let line = std::str::from_utf8(line).map_err(|_| "request line isn't UTF-8")?;
+				let mut parts = line.split(' ');
+				let method = parts.next().unwrap_or_default().to_ascii_lowercase();
+				format!("{} {}", method, parts.next().ok_or("no request target")?)
+			}
+			_ => {
+				let values: Vec<&str> = headers.all(&name).collect();
+				if values.is_empty() {
+					return Err("signed header missing");
+				}
+				values.join(", ")
+			}
+		};
+		string.push(format!("{}: {}", name, value));
+	}
+
+	let key = match query.public_key(key_id).await {
+		Ok(Some(key)) => key,
+		Ok(None) => return Err("unknown key"),
+		Err(_) => return Err("could not look up key"),
+	};
+	let public = RsaPublicKey::from_public_key_pem(&key.pem)
+		.or_else(|_| RsaPublicKey::from_pkcs1_pem(&key.pem))
+		.map_err(|_| "malformed key")?;
+	let bytes = param(signature, "signature").ok_or("no signature value")?;
+	let bytes = BASE64.decode(bytes).map_err(|_| "signature isn't base64")?;
+	let signature = Signature::try_from(bytes.as_slice()).map_err(|_| "malformed signature")?;
+	VerifyingKey::<Sha256>::new(public)
+		.verify(string.join("\n").as_bytes(), &signature)
+		.map_err(|_| "signature doesn't verify")?;
+	Ok(key.owner)
+}
+
+/// A parameter of an HTTP `Signature` header, without its quotes.
+fn param<'a>(signature: &'a str, name: &str) -> Option<&'a str> {
+	signature.split(',').find_map(|param| {
+		let (n, value) = param.trim().split_once('=')?;
+		n.trim().eq_ignore_ascii_case(name).then(|| value.trim().trim_matches('"'))
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use rsa::{
+		pkcs1v15::SigningKey,
+		pkcs8::{EncodePublicKey, LineEnding},
+		rand_core::OsRng,
+		signature::{SignatureEncoding, Signer},
+		RsaPrivateKey,
+	};
+
+	use super::*;
+	use crate::query::MemoryBackend;
+
+	const KEY_ID: &str = "https://remote.example/users/a#main-key";
+	const ACTOR: &str = "https://remote.example/users/a";
+	const BODY: &[u8] = br#"{"type":"Create"}"#;
+
+	fn delivery(key: &RsaPrivateKey) -> Vec<u8> {
+		let digest = format!("SHA-256={}", BASE64.encode(Sha256::digest(BODY)));
+		let string =
+			format!("(request-target): post /inbox\nhost: local.example\ndigest: {}", digest);
+		let signature = SigningKey::<Sha256>::new(key.clone()).sign(string.as_bytes());
+		let header = format!(
+			"POST /inbox HTTP/1.1\r\nHost: local.example\r\nDigest: {}\r\nSignature: keyId=\"{}\",\
+			algorithm=\"rsa-sha256\",headers=\"(request-target) host digest\",signature=\"{}\"\r\n\r\n",
+			digest,
+			KEY_ID,
+			BASE64.encode(signature.to_bytes())
+		);
+		header.into_bytes()
+	}
+
+	fn query(key: &RsaPrivateKey) -> Arc<dyn Backend> {
+		let pem = key.to_public_key().to_public_key_pem(LineEnding::LF).unwrap_or_default();
+		Arc::new(MemoryBackend::new().public_key(KEY_ID, ACTOR, &pem))
+	}
+
+	#[tokio::test]
+	async fn verifies_the_signer() -> Result<(), rsa::Error> {
+		let key = RsaPrivateKey::new(&mut OsRng, 1024)?;
+		let header = delivery(&key);
+
+		let signed = Signed::new(&header, BODY, query(&key));
+		assert_eq!(signed.signer().await.as_deref(), Some(ACTOR));
+
+		// the body isn't what was signed
+		let signed = Signed::new(&header, br#"{"type":"Delete"}"#, query(&key));
+		assert_eq!(signed.signer().await, None);
+
+		// nor is the key
+		let other = RsaPrivateKey::new(&mut OsRng, 1024)?;
+		let signed = Signed::new(&header, BODY, query(&other));
+		assert_eq!(signed.signer().await, None);
+
+		// a key the AP server never fetched
+		let signed = Signed::new(&header, BODY, Arc::new(MemoryBackend::new()));
+		assert_eq!(signed.signer().await, None);
+		Ok(())
+	}
+}
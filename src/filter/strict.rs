@@ -0,0 +1,97 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use super::rules::Action;
+use crate::domains::normalize;
+
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_HOURS: u64 = 24;
+
+/// Domains moderators pinned to stricter treatment for a while, every activity from them taking
+/// the pinned action, short of blocking them for good. Pins last until they run out or restart.
+#[derive(Debug, Clone)]
+pub struct Strict {
+	pins: Arc<DashMap<String, Pin>>,
+}
+
+/// A pinned domain, as reported to moderators.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StrictPin {
+	pub domain: String,
+	pub action: Action,
+	/// Seconds until the pin runs out.
+	pub remaining: u64,
+}
+
+#[derive(Debug)]
+struct Pin {
+	action: Action,
+	until: Instant,
+}
+
+impl Strict {
+	#[allow(clippy::new_without_default)] // spawns a cleanup task
+	pub fn new() -> Self {
+		let strict = Strict { pins: Arc::new(DashMap::new()) };
+
+		let pins = strict.pins.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				let now = Instant::now();
+				pins.retain(|_, pin| pin.until > now);
+			}
+		});
+
+		strict
+	}
+
+	/// Pin domains to `action` for `length`, replacing earlier pins, and return how many.
+	pub fn pin(&self, domains: &[String], action: Action, length: Duration) -> usize {
+		let until = Instant::now() + length;
+		let mut pinned = 0;
+		for domain in domains.iter().filter_map(|d| normalize(d)) {
+			self.pins.insert(domain, Pin { action, until });
+			pinned += 1;
+		}
+		pinned
+	}
+
+	/// Unpin domains and return how many were pinned.
+	pub fn unpin(&self, domains: &[String]) -> usize {
+		let domains = domains.iter().filter_map(|d| normalize(d));
+		domains.filter(|domain| self.pins.remove(domain).is_some()).count()
+	}
+
+	/// Action pinned for `host` or the nearest domain it is under, if any still holds.
+	pub fn action(&self, host: &str) -> Option<Action> {
+		let mut host = host.trim_end_matches('.');
+		loop {
+			if let Some(pin) = self.pins.get(host).filter(|pin| pin.until > Instant::now()) {
+				return Some(pin.action);
+			}
+			host = host.split_once('.')?.1;
+		}
+	}
+
+	/// Pins still in effect, by domain.
+	pub fn list(&self) -> Vec<StrictPin> {
+		let now = Instant::now();
+		let mut pins: Vec<_> = self
+			.pins
+			.iter()
+			.filter(|entry| entry.until > now)
+			.map(|entry| StrictPin {
+				domain: entry.key().clone(),
+				action: entry.action,
+				remaining: entry.until.duration_since(now).as_secs(),
+			})
+			.collect();
+		pins.sort_unstable_by(|a, b| a.domain.cmp(&b.domain));
+		pins
+	}
+}
@@ -0,0 +1,174 @@
+use std::{
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use sonic_rs::{JsonValueTrait, Value};
+use tracing::*;
+use url::Url;
+
+use super::signature::Signed;
+use crate::http::{self, HttpError};
+
+const DEFAULT_AFTER: u32 = 3;
+/// Actors without a spam rejection for this long start counting from zero again.
+const FORGET_SECS: u64 = 24 * 60 * 60;
+
+/// `[suspend]` in the config file: the local Misskey's admin API, to have actors that keep
+/// sending spam dealt with inside the AP server too.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SuspendConfig {
+	/// HTTP(S) base URL of the Misskey server, like `http://127.0.0.1:3000`.
+	pub api: String,
+	/// Access token of a moderator account, with the `write:admin:suspend-user` permission to
+	/// suspend, or `write:report-abuse` to report.
+	pub token: String,
+	/// Spam rejections of an actor before it's suspended or reported.
+	#[serde(default = "default_after")]
+	pub after: u32,
+	#[serde(default)]
+	pub action: SuspendAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuspendAction {
+	/// Suspend the account.
+	Suspend,
+	/// Report the account to the moderators.
+	#[default]
+	Report,
+}
+
+fn default_after() -> u32 {
+	DEFAULT_AFTER
+}
+
+impl SuspendConfig {
+	/// Check the API URL at startup rather than on the first spammer.
+	pub fn validate(&self) -> Result<(), String> {
+		let url = Url::parse(&self.api).map_err(|e| format!("invalid suspend api: {}", e))?;
+		if !http::supports(&url) {
+			return Err("suspend api must be an http:// or https:// URL".to_string());
+		}
+		if self.after == 0 {
+			return Err("suspend after must be at least 1".to_string());
+		}
+		Ok(())
+	}
+
+	/// Port the API is reached on.
+	pub fn api_port(&self) -> Option<u16> {
+		Url::parse(&self.api).ok()?.port_or_known_default()
+	}
+}
+
+/// Spam rejections counted per actor, to suspend or report the ones that keep at it.
+#[derive(Debug, Clone)]
+pub struct Suspender {
+	config: Arc<SuspendConfig>,
+	/// Spam rejections and the unix time of the latest, per actor.
+	counts: Arc<DashMap<String, (u32, u64)>>,
+}
+
+impl Suspender {
+	pub fn new(config: SuspendConfig) -> Self {
+		let suspender = Suspender { config: Arc::new(config), counts: Arc::new(DashMap::new()) };
+
+		let counts = suspender.counts.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(3600));
+			loop {
+				interval.tick().await;
+				let now = now();
+				counts.retain(|_, (_, at)| now - *at < FORGET_SECS);
+			}
+		});
+
+		suspender
+	}
+
+	/// Count a spam rejection of `actor`, and act on it once there are enough. Only deliveries
+	/// signed with the actor's own key count: anyone can name someone else's actor in a forged
+	/// one, to have them suspended.
+	pub fn rejected(&self, actor: &str, signed: Signed) {
+		let (config, counts) = (self.config.clone(), self.counts.clone());
+		let actor = actor.to_string();
+		tokio::spawn(async move {
+			if signed.signer().await.as_deref() != Some(actor.as_str()) {
+				debug!("Not counting spam from {} toward suspending it, not signed by it", actor);
+				return;
+			}
+			let count = {
+				let mut entry = counts.entry(actor.clone()).or_insert((0, 0));
+				entry.0 += 1;
+				entry.1 = now();
+				entry.0
+			};
+			// only once: later rejections race with the account going away
+			if count != config.after {
+				return;
+			}
+
+			let verb = match config.action {
+				SuspendAction::Suspend => "suspend",
+				SuspendAction::Report => "report",
+			};
+			match act(&config, &actor).await {
+				Ok(()) => info!("Asked Misskey to {} {} after {} rejections", verb, actor, count),
+				Err(e) => warn!("Could not {} {}: {}", verb, actor, e),
+			}
+		});
+	}
+}
+
+async fn act(config: &SuspendConfig, actor: &str) -> Result<(), HttpError> {
+	let user_id = resolve(config, actor).await?;
+	let body = match config.action {
+		SuspendAction::Suspend => {
+			sonic_rs::json!({ "i": config.token.as_str(), "userId": user_id.as_str() })
+		}
+		SuspendAction::Report => sonic_rs::json!({
+			"i": config.token.as_str(),
+			"userId": user_id.as_str(),
+			"comment": "Sent spam rejected by spam-musubi",
+		}),
+	};
+	let endpoint = match config.action {
+		SuspendAction::Suspend => "api/admin/suspend-user",
+		SuspendAction::Report => "api/users/report-abuse",
+	};
+	http::post_json(&api_url(config, endpoint)?, &[], &body.to_string()).await?;
+	Ok(())
+}
+
+/// Misskey's id of the user with AP id `actor`.
+async fn resolve(config: &SuspendConfig, actor: &str) -> Result<String, HttpError> {
+	let body = sonic_rs::json!({ "i": config.token.as_str(), "uri": actor }).to_string();
+	let response = http::post_json(&api_url(config, "api/ap/show")?, &[], &body).await?;
+	let response: Value =
+		sonic_rs::from_str(&response).map_err(|_| HttpError::MalformedResponse)?;
+	if response.get("type").and_then(|t| t.as_str()) != Some("User") {
+		return Err(HttpError::MalformedResponse);
+	}
+	response
+		.get("object")
+		.and_then(|o| o.get("id"))
+		.and_then(|id| id.as_str())
+		.map(str::to_string)
+		.ok_or(HttpError::MalformedResponse)
+}
+
+fn api_url(config: &SuspendConfig, endpoint: &str) -> Result<Url, HttpError> {
+	let base = format!("{}/", config.api.trim_end_matches('/'));
+	Url::parse(&base)
+		.and_then(|base| base.join(endpoint))
+		.map_err(|_| HttpError::NoHost(config.api.clone()))
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,25 @@
+use sonic_rs::{JsonContainerTrait, JsonValueTrait, Value};
+
+/// Number of `Hashtag` entries in `object.tag`.
+pub fn hashtag_count(ap_json: &Value) -> usize {
+	ap_json
+		.get("object")
+		.and_then(|o| o.get("tag"))
+		.and_then(|t| t.as_array())
+		.map(|tags| {
+			tags.iter().filter(|t| t.get("type").and_then(|t| t.as_str()) == Some("Hashtag")).count()
+		})
+		.unwrap_or(0)
+}
+
+/// Number of `Mention` entries in `object.tag`.
+pub fn mention_count(ap_json: &Value) -> usize {
+	ap_json
+		.get("object")
+		.and_then(|o| o.get("tag"))
+		.and_then(|t| t.as_array())
+		.map(|tags| {
+			tags.iter().filter(|t| t.get("type").and_then(|t| t.as_str()) == Some("Mention")).count()
+		})
+		.unwrap_or(0)
+}
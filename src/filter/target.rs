@@ -0,0 +1,88 @@
+use std::sync::Once;
+
+use tracing::*;
+
+use super::Direction;
+
+static TUNNEL_WARNING: Once = Once::new();
+
+/// Check the request line of `header`, which must be complete, for targets that could pass for
+/// another path to the filter than to the AP server: absolute form, except outbound where the
+/// AP server uses us as its proxy, dot segments, however escaped, and control bytes. Outbound,
+/// `CONNECT` tunnels to a `host:port` pass too.
+pub fn check(header: &[u8], direction: Direction) -> Result<(), &'static str> {
+	let line = header.split(|&b| b == b'\n').next().unwrap_or_default();
+	let line = line.strip_suffix(b"\r").unwrap_or(line);
+	if line.iter().any(|b| b.is_ascii_control()) {
+		return Err("control bytes in request line");
+	}
+	let Some(target) = line.split(|&b| b == b' ').nth(1) else {
+		return Err("no request target");
+	};
+	let path = match target {
+		b"*" => return Ok(()),
+		_ if is_tunnel(header, direction) => return Ok(()),
+		[b'/', ..] => target,
+		_ if direction == Direction::Outbound => {
+			let scheme = [&b"http://"[..], b"https://"].into_iter().find(|s| target.starts_with(s));
+			let Some(scheme) = scheme else {
+				return Err("request target is not absolute");
+			};
+			let rest = &target[scheme.len()..];
+			rest.iter().position(|&b| b == b'/').map_or(&b""[..], |i| &rest[i..])
+		}
+		_ => return Err("request target in absolute form"),
+	};
+	let path = path.split(|&b| b == b'?' || b == b'#').next().unwrap_or_default();
+	if has_dot_segment(path) {
+		return Err("dot segment in request target");
+	}
+	Ok(())
+}
+
+/// Whether `path` has a `.` or `..` segment, with dots and slashes escaped or not, and
+/// backslashes taken for slashes as some servers do.
+fn has_dot_segment(path: &[u8]) -> bool {
+	let mut decoded = Vec::with_capacity(path.len());
+	let mut i = 0;
+	while i < path.len() {
+		let escaped = path.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+		match (path[i], escaped.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+			(b'%', Some(byte @ (b'.' | b'/' | b'\\'))) => {
+				decoded.push(byte);
+				i += 3;
+			}
+			(byte, _) => {
+				decoded.push(byte);
+				i += 1;
+			}
+		}
+	}
+	decoded.split(|&b| b == b'/' || b == b'\\').any(|segment| segment == b"." || segment == b"..")
+}
+
+/// Whether `header` opens a `CONNECT` tunnel through us as the AP server's proxy, to a
+/// `host:port`.
+pub fn is_tunnel(header: &[u8], direction: Direction) -> bool {
+	let line = header.split(|&b| b == b'\r' || b == b'\n').next().unwrap_or_default();
+	let mut parts = line.split(|&b| b == b' ');
+	let (method, target) = (parts.next(), parts.next().unwrap_or_default());
+	direction == Direction::Outbound
+		&& method == Some(b"CONNECT")
+		&& !target.contains(&b'/')
+		&& target.contains(&b':')
+}
+
+/// Note a tunnel let through without looking into it, warning about it the first time.
+pub fn tunnelled(header: &[u8]) {
+	let line = header.split(|&b| b == b'\r' || b == b'\n').next().unwrap_or_default();
+	let line = String::from_utf8_lossy(line);
+	TUNNEL_WARNING.call_once(|| {
+		warn!(
+			"Let through {} without inspecting it: deliveries to https:// inboxes are tunnelled \
+			through the proxy, and only plain http:// ones can be checked",
+			line
+		)
+	});
+	debug!("Tunnelling {}", line);
+}
@@ -0,0 +1,268 @@
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use sonic_rs::{JsonContainerTrait, JsonValueTrait, Value};
+
+/// Tags that break text into separate lines or paragraphs.
+const BLOCK_TAGS: [&str; 13] =
+	["p", "br", "div", "li", "blockquote", "pre", "hr", "h1", "h2", "h3", "h4", "h5", "h6"];
+/// Tags whose content isn't text at all.
+const SKIPPED_TAGS: [&str; 3] = ["script", "style", "template"];
+/// Tags without content, which are never closed.
+const VOID_TAGS: [&str; 7] = ["br", "hr", "img", "input", "link", "meta", "wbr"];
+/// Longest named or numeric character reference, `;` included.
+const MAX_ENTITY_LEN: usize = 12;
+/// Named character references decoded, the common ones. Others are left as written.
+const ENTITIES: [(&str, char); 24] = [
+	("amp", '&'),
+	("lt", '<'),
+	("gt", '>'),
+	("quot", '"'),
+	("apos", '\''),
+	("nbsp", '\u{A0}'),
+	("shy", '\u{AD}'),
+	("zwsp", '\u{200B}'),
+	("lrm", '\u{200E}'),
+	("rlm", '\u{200F}'),
+	("hellip", '…'),
+	("mdash", '—'),
+	("ndash", '–'),
+	("laquo", '«'),
+	("raquo", '»'),
+	("lsquo", '‘'),
+	("rsquo", '’'),
+	("ldquo", '“'),
+	("rdquo", '”'),
+	("bull", '•'),
+	("middot", '·'),
+	("copy", '©'),
+	("reg", '®'),
+	("trade", '™'),
+];
+
+/// Set once by [`configure`], or the defaults on first use.
+static CONFIG: OnceCell<TextConfig> = OnceCell::new();
+
+/// `[text]` in the config file: how notes' HTML is read as text.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextConfig {
+	/// Classes of elements readers never see, left out along with their content.
+	#[serde(default = "default_hidden_classes")]
+	pub hidden_classes: Vec<String>,
+}
+
+fn default_hidden_classes() -> Vec<String> {
+	// Mastodon's "RE: <link>" fallback for quote posts
+	vec!["quote-inline".to_string()]
+}
+
+impl Default for TextConfig {
+	fn default() -> Self {
+		TextConfig { hidden_classes: default_hidden_classes() }
+	}
+}
+
+/// Read notes' HTML as `config` says from now on. Call it at startup, before any is read.
+pub fn configure(config: TextConfig) {
+	CONFIG.set(config).ok();
+}
+
+/// `object.content` as text, see [`html_text`], followed by the options of polls.
+pub fn note_text(ap_json: &Value) -> Option<String> {
+	let object = ap_json.get("object")?;
+	let content = object.get("content").and_then(|c| c.as_str());
+	let options = poll_options(object);
+	if content.is_none() && options.is_empty() {
+		return None;
+	}
+	let mut text = content.map(html_text).unwrap_or_default();
+	for option in options {
+		let option = plain_text(option);
+		if !text.is_empty() && !option.is_empty() {
+			text.push(' ');
+		}
+		text.push_str(&option);
+	}
+	Some(text)
+}
+
+/// Names of a `Question`'s options, single (`oneOf`) or multiple choice (`anyOf`).
+fn poll_options(object: &Value) -> Vec<&str> {
+	["oneOf", "anyOf"]
+		.into_iter()
+		.filter_map(|field| object.get(field).and_then(|o| o.as_array()))
+		.flat_map(|options| options.iter())
+		.filter_map(|option| option.get("name").and_then(|n| n.as_str()))
+		.collect()
+}
+
+/// Plain text like poll options as a reader sees it, whitespace collapsed and zero-width
+/// characters dropped.
+fn plain_text(text: &str) -> String {
+	let visible: String = text.chars().filter(|c| !is_invisible(*c)).collect();
+	visible.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// HTML as a reader sees it, whitespace collapsed: tags stripped, character references
+/// decoded, and zero-width characters dropped, so spam can't split words with them. Inline
+/// tags are dropped without a trace, so mentions like `@<span>name</span>` stay one word, and
+/// links Mastodon shortens with `invisible` spans come out whole. Scripts, styles and
+/// elements of the hidden classes are left out with their content.
+pub fn html_text(html: &str) -> String {
+	let hidden = &CONFIG.get_or_init(TextConfig::default).hidden_classes;
+	let mut text = String::with_capacity(html.len());
+	// element being left out, and how many of its kind are open inside it
+	let mut skipping: Option<(&str, usize)> = None;
+	let mut rest = html;
+	while let Some(c) = rest.chars().next() {
+		if let Some(comment) = rest.strip_prefix("<!--") {
+			rest = comment.split_once("-->").map_or("", |(_, after)| after);
+			continue;
+		}
+		let (c, len) = match c {
+			'<' => match Tag::parse(rest) {
+				Some(tag) => {
+					rest = &rest[tag.len..];
+					let void = tag.self_closing || VOID_TAGS.iter().any(|v| tag.is(v));
+					if let Some((name, depth)) = &mut skipping {
+						if tag.is(name) && !void {
+							*depth = if tag.closing { *depth - 1 } else { *depth + 1 };
+							if *depth == 0 {
+								skipping = None;
+							}
+						}
+						continue;
+					}
+					let skipped = SKIPPED_TAGS.iter().any(|s| tag.is(s))
+						|| tag.classes().any(|c| hidden.iter().any(|h| h == c));
+					if skipped && !tag.closing && !void {
+						skipping = Some((tag.name, 1));
+					} else if BLOCK_TAGS.iter().any(|b| tag.is(b)) {
+						text.push(' ');
+					}
+					continue;
+				}
+				None => ('<', 1),
+			},
+			'&' => entity(rest).unwrap_or(('&', 1)),
+			c => (c, c.len_utf8()),
+		};
+		rest = &rest[len..];
+		if skipping.is_none() && !is_invisible(c) {
+			text.push(c);
+		}
+	}
+	text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// An HTML tag, as far as text extraction cares.
+struct Tag<'a> {
+	name: &'a str,
+	closing: bool,
+	self_closing: bool,
+	class: &'a str,
+	/// Bytes up to and including its `>`.
+	len: usize,
+}
+
+impl<'a> Tag<'a> {
+	/// The tag `html` starts with, unless it starts with a `<` that isn't one, like in `a < b`.
+	fn parse(html: &'a str) -> Option<Self> {
+		let bytes = html.as_bytes();
+		let closing = bytes.get(1) == Some(&b'/');
+		let start = if closing { 2 } else { 1 };
+		if !bytes.get(start).is_some_and(u8::is_ascii_alphabetic) {
+			return None;
+		}
+		let name_len =
+			bytes[start..].iter().position(|b| !(b.is_ascii_alphanumeric() || *b == b'-'));
+		let mut i = start + name_len.unwrap_or(bytes.len() - start);
+		let name = &html[start..i];
+
+		let mut class = "";
+		let skip_space = |i: &mut usize| {
+			while bytes.get(*i).is_some_and(u8::is_ascii_whitespace) {
+				*i += 1;
+			}
+		};
+		loop {
+			while bytes.get(i).is_some_and(|b| b.is_ascii_whitespace() || *b == b'/') {
+				i += 1;
+			}
+			if *bytes.get(i)? == b'>' {
+				break;
+			}
+			let attribute_start = i;
+			while bytes.get(i).is_some_and(|b| !b.is_ascii_whitespace() && !b"=>/".contains(b)) {
+				i += 1;
+			}
+			let attribute = &html[attribute_start..i];
+			if attribute.is_empty() {
+				// a stray `=`
+				i += 1;
+				continue;
+			}
+			skip_space(&mut i);
+			if bytes.get(i) != Some(&b'=') {
+				continue;
+			}
+			i += 1;
+			skip_space(&mut i);
+			let value = match *bytes.get(i)? {
+				quote @ (b'"' | b'\'') => {
+					let end = i + 1 + bytes[i + 1..].iter().position(|&b| b == quote)?;
+					let value = &html[i + 1..end];
+					i = end + 1;
+					value
+				}
+				_ => {
+					let value_start = i;
+					while bytes.get(i).is_some_and(|b| !b.is_ascii_whitespace() && *b != b'>') {
+						i += 1;
+					}
+					&html[value_start..i]
+				}
+			};
+			if attribute.eq_ignore_ascii_case("class") {
+				class = value;
+			}
+		}
+		let self_closing = html[..i].ends_with('/');
+		Some(Tag { name, closing, self_closing, class, len: i + 1 })
+	}
+
+	fn is(&self, name: &str) -> bool {
+		self.name.eq_ignore_ascii_case(name)
+	}
+
+	fn classes(&self) -> impl Iterator<Item = &'a str> {
+		self.class.split_ascii_whitespace()
+	}
+}
+
+/// The character the character reference `html` starts with stands for, and its length.
+fn entity(html: &str) -> Option<(char, usize)> {
+	let end = html.bytes().take(MAX_ENTITY_LEN).position(|b| b == b';')?;
+	let name = &html[1..end];
+	let c = match name.strip_prefix('#') {
+		Some(number) => {
+			let code = match number.strip_prefix(['x', 'X']) {
+				Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+				None => number.parse().ok()?,
+			};
+			char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+		}
+		None => ENTITIES.iter().find(|(n, _)| *n == name)?.1,
+	};
+	Some((c, end + 1))
+}
+
+/// Characters that take up no space, which spam sprinkles into words to dodge keyword lists.
+/// Zero-width joiners are kept, as emoji and some scripts need them.
+fn is_invisible(c: char) -> bool {
+	matches!(
+		c,
+		'\u{AD}' | '\u{34F}' | '\u{180E}' | '\u{200B}' | '\u{200E}' | '\u{200F}'
+			| '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}'
+	)
+}
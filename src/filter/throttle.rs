@@ -0,0 +1,132 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+
+/// Fixed-window counters for `throttle` actions, per rule and actor.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+	windows: Arc<DashMap<(String, String), Window>>,
+}
+
+/// A throttle window an actor is in, as reported to moderators.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThrottleWindow {
+	pub rule: String,
+	/// Activities counted so far.
+	pub count: u32,
+	/// Seconds until the window ends.
+	pub remaining: u64,
+}
+
+#[derive(Debug)]
+struct Window {
+	start: Instant,
+	length: Duration,
+	count: u32,
+}
+
+impl Throttle {
+	#[allow(clippy::new_without_default)] // spawns a cleanup task
+	pub fn new() -> Self {
+		let throttle = Throttle { windows: Arc::new(DashMap::new()) };
+
+		let windows = throttle.windows.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				windows.retain(|_, w| w.start.elapsed() < w.length);
+			}
+		});
+
+		throttle
+	}
+
+	/// Count one activity and return whether it's still within `limit` per `window`.
+	pub fn allow(&self, rule: &str, actor: &str, limit: u32, window: Duration) -> bool {
+		let mut w = self
+			.windows
+			.entry((rule.to_string(), actor.to_string()))
+			.or_insert_with(|| Window { start: Instant::now(), length: window, count: 0 });
+		if w.start.elapsed() >= w.length {
+			*w = Window { start: Instant::now(), length: window, count: 0 };
+		}
+		w.count += 1;
+		w.count <= limit
+	}
+
+	/// Windows of every rule the actor is currently counted by.
+	pub fn windows(&self, actor: &str) -> Vec<ThrottleWindow> {
+		self.windows
+			.iter()
+			.filter(|entry| entry.key().1 == actor && entry.start.elapsed() < entry.length)
+			.map(|entry| ThrottleWindow {
+				rule: entry.key().0.clone(),
+				count: entry.count,
+				remaining: entry.length.saturating_sub(entry.start.elapsed()).as_secs(),
+			})
+			.collect()
+	}
+
+	/// Every open window as (rule, actor, start, length, count), to save across restarts.
+	pub fn entries(&self) -> Vec<(String, String, Instant, Duration, u32)> {
+		self.windows
+			.iter()
+			.filter(|entry| entry.start.elapsed() < entry.length)
+			.map(|entry| {
+				let (rule, actor) = entry.key().clone();
+				(rule, actor, entry.start, entry.length, entry.count)
+			})
+			.collect()
+	}
+
+	/// Put back a window from [`Throttle::entries`].
+	pub fn restore(
+		&self, rule: String, actor: String, start: Instant, length: Duration, count: u32,
+	) {
+		self.windows.insert((rule, actor), Window { start, length, count });
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MINUTE: Duration = Duration::from_secs(60);
+
+	#[tokio::test(start_paused = true)]
+	async fn allows_up_to_the_limit_per_window() {
+		let throttle = Throttle::new();
+		assert!(throttle.allow("rule", "alice", 2, MINUTE));
+		assert!(throttle.allow("rule", "alice", 2, MINUTE));
+		assert!(!throttle.allow("rule", "alice", 2, MINUTE));
+
+		tokio::time::advance(MINUTE - Duration::from_secs(1)).await;
+		assert!(!throttle.allow("rule", "alice", 2, MINUTE));
+
+		// a fresh window once the first one ends
+		tokio::time::advance(Duration::from_secs(1)).await;
+		assert!(throttle.allow("rule", "alice", 2, MINUTE));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn counts_per_rule_and_actor() {
+		let throttle = Throttle::new();
+		assert!(throttle.allow("rule", "alice", 1, MINUTE));
+		assert!(!throttle.allow("rule", "alice", 1, MINUTE));
+		assert!(throttle.allow("rule", "bob", 1, MINUTE));
+		assert!(throttle.allow("other", "alice", 1, MINUTE));
+
+		let mut windows = throttle.windows("alice");
+		windows.sort_by(|a, b| a.rule.cmp(&b.rule));
+		let counts: Vec<_> = windows.iter().map(|w| (w.rule.as_str(), w.count)).collect();
+		assert_eq!(counts, [("other", 1), ("rule", 2)]);
+
+		tokio::time::advance(MINUTE).await;
+		assert!(throttle.windows("alice").is_empty());
+	}
+}
@@ -0,0 +1,96 @@
+use serde::Deserialize;
+
+use super::score;
+
+/// `[user_agents]` in the config file: User-Agents of inbox POSTs to turn away or be wary of,
+/// checked before the body is even read.
+///
+/// Patterns match the whole User-Agent in any case, with `*` standing for any run of
+/// characters, like `python-requests/*`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserAgentConfig {
+	/// Spam scripts and the like, rejected outright.
+	#[serde(default)]
+	pub deny: Vec<String>,
+	/// Exceptions to `deny`, like a known AP server whose User-Agent a broad pattern catches.
+	#[serde(default)]
+	pub allow: Vec<String>,
+	/// Weight of the `user-agent` signal raised by deliveries without a User-Agent. AP servers
+	/// all send one.
+	#[serde(default = "default_missing_weight")]
+	pub missing_weight: u32,
+}
+
+fn default_missing_weight() -> u32 {
+	score::WEAK
+}
+
+impl UserAgentConfig {
+	pub fn validate(&self) -> Result<(), String> {
+		if let Some(pattern) = self.deny.iter().chain(&self.allow).find(|p| p.trim().is_empty()) {
+			return Err(format!("user agent pattern \"{}\" matches nothing", pattern));
+		}
+		Ok(())
+	}
+}
+
+/// What a delivery's User-Agent says about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+	Fine,
+	Missing,
+	Denied,
+}
+
+/// Checks User-Agents against the configured patterns.
+#[derive(Debug, Clone)]
+pub struct UserAgents {
+	deny: Vec<String>,
+	allow: Vec<String>,
+	pub missing_weight: u32,
+}
+
+impl UserAgents {
+	pub fn new(config: UserAgentConfig) -> Self {
+		let lowercase = |patterns: Vec<String>| patterns.iter().map(|p| p.to_lowercase()).collect();
+		UserAgents {
+			deny: lowercase(config.deny),
+			allow: lowercase(config.allow),
+			missing_weight: config.missing_weight,
+		}
+	}
+
+	pub fn check(&self, user_agent: Option<&str>) -> Verdict {
+		let Some(user_agent) = user_agent.filter(|ua| !ua.is_empty()) else {
+			return Verdict::Missing;
+		};
+		let user_agent = user_agent.to_lowercase();
+		let any = |patterns: &[String]| patterns.iter().any(|p| matches(p, &user_agent));
+		if any(&self.deny) && !any(&self.allow) {
+			return Verdict::Denied;
+		}
+		Verdict::Fine
+	}
+}
+
+/// Whether `pattern` matches all of `s`, `*` matching any run of characters.
+fn matches(pattern: &str, s: &str) -> bool {
+	let mut parts = pattern.split('*');
+	let first = parts.next().unwrap_or_default();
+	let Some(mut rest) = s.strip_prefix(first) else {
+		return false;
+	};
+	let mut parts: Vec<&str> = parts.collect();
+	// without a `*`, the pattern is the whole string
+	let Some(last) = parts.pop() else {
+		return rest.is_empty();
+	};
+	for part in parts {
+		match rest.find(part) {
+			Some(i) => rest = &rest[i + part.len()..],
+			None => return false,
+		}
+	}
+	rest.ends_with(last)
+}
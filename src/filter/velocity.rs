@@ -0,0 +1,219 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tokio::time::Instant;
+
+/// How long the baseline remembers, as the half-life of its moving average.
+const BASELINE_HALF_LIFE_MINUTES: f64 = 24.0 * 60.0;
+/// How long an instance is watched before its baseline is trusted.
+const WARMUP_MINUTES: u64 = 6 * 60;
+/// Rates below this are never a surge, however quiet the instance usually is.
+const MIN_SURGE_PER_HOUR: u32 = 60;
+/// Instances not heard from for this long are forgotten.
+const FORGET_MINUTES: u64 = 7 * 24 * 60;
+
+/// Notes admitted per origin instance, over the last hour and on average, to spot instances
+/// suddenly delivering far more than usual, like one whose signups were overrun by spam bots.
+#[derive(Debug, Clone)]
+pub struct VelocityTracker {
+	start: Instant,
+	factor: u32,
+	instances: Arc<DashMap<String, Rate>>,
+}
+
+#[derive(Debug)]
+struct Rate {
+	first_minute: u64,
+	minute: u64,
+	/// Notes in the current minute.
+	current: u32,
+	/// Notes per earlier minute within the last hour.
+	recent: VecDeque<(u64, u32)>,
+	/// Moving average of notes per minute, up to an hour ago, so a surge doesn't raise its own
+	/// bar.
+	baseline: f64,
+	/// First minute not yet in the baseline.
+	folded: u64,
+}
+
+impl VelocityTracker {
+	/// Track instances, and call it a surge when the last hour saw `factor` times the usual.
+	pub fn new(factor: u32) -> Self {
+		let tracker =
+			VelocityTracker { start: Instant::now(), factor, instances: Arc::new(DashMap::new()) };
+
+		let instances = tracker.instances.clone();
+		let start = tracker.start;
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(3600));
+			loop {
+				interval.tick().await;
+				let now = start.elapsed().as_secs() / 60;
+				instances.retain(|_, rate| now - rate.minute < FORGET_MINUTES);
+			}
+		});
+
+		tracker
+	}
+
+	fn now(&self) -> u64 {
+		self.start.elapsed().as_secs() / 60
+	}
+
+	/// Notes admitted from the instance within the last hour, counting one more about to be,
+	/// and whether that's a surge over its baseline.
+	pub fn check(&self, host: &str) -> (u32, bool) {
+		self.judge(host, 1)
+	}
+
+	/// Notes admitted from the instance within the last hour, and whether that's a surge.
+	pub fn surging(&self, host: &str) -> (u32, bool) {
+		self.judge(host, 0)
+	}
+
+	fn judge(&self, host: &str, pending: u32) -> (u32, bool) {
+		let now = self.now();
+		let Some(mut rate) = self.instances.get_mut(host) else {
+			return (pending, false);
+		};
+		rate.advance(now);
+		let last_hour = rate.last_hour() + pending;
+		let surge = now - rate.first_minute >= WARMUP_MINUTES
+			&& last_hour >= MIN_SURGE_PER_HOUR
+			&& f64::from(last_hour) > rate.usual_per_hour() * f64::from(self.factor);
+		(last_hour, surge)
+	}
+
+	/// Notes admitted from the instance within the last hour, and usually per hour.
+	pub fn rate(&self, host: &str) -> Option<(u32, f64)> {
+		let now = self.now();
+		let mut rate = self.instances.get_mut(host)?;
+		rate.advance(now);
+		Some((rate.last_hour(), rate.usual_per_hour()))
+	}
+
+	/// Count a note admitted from the instance.
+	pub fn record(&self, host: &str) {
+		let now = self.now();
+		let mut rate = self.instances.entry(host.to_string()).or_insert_with(|| Rate {
+			first_minute: now,
+			minute: now,
+			current: 0,
+			recent: VecDeque::new(),
+			baseline: 0.0,
+			folded: now,
+		});
+		rate.advance(now);
+		rate.current += 1;
+	}
+}
+
+/// How much of the baseline is kept from one minute to the next.
+fn decay() -> f64 {
+	0.5f64.powf(1.0 / BASELINE_HALF_LIFE_MINUTES)
+}
+
+impl Rate {
+	/// Move on to minute `now`, folding the minutes that left the last hour into the baseline.
+	fn advance(&mut self, now: u64) {
+		if now <= self.minute {
+			return;
+		}
+		self.recent.push_back((self.minute, self.current));
+		self.minute = now;
+		self.current = 0;
+
+		let decay = decay();
+		while let Some(&(minute, count)) = self.recent.front() {
+			if now - minute < 60 {
+				break;
+			}
+			self.recent.pop_front();
+			// quiet minutes in between
+			self.baseline *= decay.powf((minute - self.folded) as f64);
+			self.baseline = self.baseline * decay + f64::from(count) * (1.0 - decay);
+			self.folded = minute + 1;
+		}
+		let hour_ago = now.saturating_sub(59);
+		if hour_ago > self.folded {
+			self.baseline *= decay.powf((hour_ago - self.folded) as f64);
+			self.folded = hour_ago;
+		}
+	}
+
+	fn usual_per_hour(&self) -> f64 {
+		// the average starts from 0, so make up for the minutes before the instance was seen
+		let watched = (self.folded - self.first_minute) as f64;
+		self.baseline / (1.0 - decay().powf(watched)).max(f64::EPSILON) * 60.0
+	}
+
+	fn last_hour(&self) -> u32 {
+		self.current + self.recent.iter().map(|(_, count)| count).sum::<u32>()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MINUTE: Duration = Duration::from_secs(60);
+	const FACTOR: u32 = 5;
+
+	/// A tracker that saw `host` deliver a note every 10 minutes for `hours`.
+	async fn steady(host: &str, hours: u64) -> VelocityTracker {
+		let tracker = VelocityTracker::new(FACTOR);
+		for _ in 0..hours * 6 {
+			tracker.record(host);
+			tokio::time::advance(10 * MINUTE).await;
+		}
+		tracker
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn counts_the_last_hour() {
+		let tracker = VelocityTracker::new(FACTOR);
+		assert_eq!(tracker.check("big.example"), (1, false));
+		assert_eq!(tracker.surging("big.example"), (0, false));
+		for _ in 0..3 {
+			tracker.record("big.example");
+		}
+		tokio::time::advance(30 * MINUTE).await;
+		tracker.record("big.example");
+		assert_eq!(tracker.surging("big.example"), (4, false));
+		assert_eq!(tracker.check("big.example"), (5, false));
+
+		tokio::time::advance(31 * MINUTE).await;
+		assert_eq!(tracker.surging("big.example"), (1, false));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn spots_a_surge_over_the_baseline() {
+		let tracker = steady("big.example", 8).await;
+		let (_, usual) = tracker.rate("big.example").unwrap_or_default();
+		assert!((5.0..7.0).contains(&usual), "usually {} an hour", usual);
+
+		for _ in 0..100 {
+			tracker.record("big.example");
+		}
+		assert_eq!(tracker.surging("big.example"), (105, true));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn needs_a_warmed_up_baseline() {
+		let tracker = steady("big.example", 2).await;
+		for _ in 0..100 {
+			tracker.record("big.example");
+		}
+		assert_eq!(tracker.surging("big.example"), (105, false));
+	}
+
+	#[tokio::test(start_paused = true)]
+	async fn never_calls_a_low_rate_a_surge() {
+		let tracker = steady("big.example", 8).await;
+		for _ in 0..MIN_SURGE_PER_HOUR - 10 {
+			tracker.record("big.example");
+		}
+		let (rate, surge) = tracker.surging("big.example");
+		assert!(rate < MIN_SURGE_PER_HOUR && !surge);
+	}
+}
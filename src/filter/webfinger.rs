@@ -0,0 +1,149 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use serde::Deserialize;
+use sonic_rs::{JsonContainerTrait, JsonValueTrait};
+use tokio::time::Instant;
+use tracing::*;
+use url::Url;
+
+use super::remote::{self, Remote};
+use crate::http::HttpError;
+
+const DEFAULT_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_MAX_PER_MINUTE: u32 = 30;
+/// How long an actor its instance acknowledged is remembered.
+const ACKNOWLEDGED_SECS: u64 = 24 * 60 * 60;
+/// How long an actor its instance denied is remembered, in case it was just being created.
+const UNACKNOWLEDGED_SECS: u64 = 60 * 60;
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+const JRD_JSON: &str = "application/jrd+json";
+
+/// `[webfinger]` in the config file: ask the instances of actors the AP server doesn't know yet
+/// whether they exist there at all.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebFingerConfig {
+	/// HTTP proxy to send lookups through, with the instance in the Host header, like nginx
+	/// with `proxy_pass https://$host`. Instances are asked over HTTPS directly without one.
+	#[serde(default)]
+	pub via: Option<String>,
+	/// Milliseconds to wait for an answer before letting the note through without one.
+	#[serde(default = "default_timeout_ms")]
+	pub timeout_ms: u64,
+	/// Lookups per instance per minute. Past that, actors from it go unchecked for the minute.
+	#[serde(default = "default_max_per_minute")]
+	pub max_per_minute: u32,
+}
+
+fn default_timeout_ms() -> u64 {
+	DEFAULT_TIMEOUT_MS
+}
+
+fn default_max_per_minute() -> u32 {
+	DEFAULT_MAX_PER_MINUTE
+}
+
+impl WebFingerConfig {
+	/// Check the settings at startup rather than on the first note.
+	pub fn validate(&self) -> Result<(), String> {
+		remote::validate("webfinger", self.via.as_deref(), self.timeout_ms, self.max_per_minute)
+	}
+
+	/// Port connections go out to: the proxy's, or HTTPS on the instances.
+	pub fn connect_port(&self) -> Option<u16> {
+		remote::connect_port(self.via.as_deref())
+	}
+}
+
+/// What an actor's own instance says about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+	Acknowledged,
+	/// The instance answered, and doesn't know the actor.
+	Unacknowledged,
+	/// No answer, or too many lookups on the instance already.
+	Unknown,
+}
+
+/// Looks actors up on their instances with WebFinger. Fails open: an instance that's down, slow,
+/// or already asked too often leaves the actor unchecked.
+#[derive(Debug, Clone)]
+pub struct WebFinger {
+	/// Actors looked up, whether they were acknowledged, and when.
+	actors: Arc<DashMap<String, (Instant, bool)>>,
+	remote: Remote,
+}
+
+impl WebFinger {
+	pub fn new(config: WebFingerConfig) -> Self {
+		let webfinger = WebFinger {
+			actors: Arc::new(DashMap::new()),
+			remote: Remote::new(config.via.as_deref(), config.timeout_ms, config.max_per_minute),
+		};
+
+		let actors = webfinger.actors.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				actors.retain(|_, (checked, acknowledged)| !expired(*checked, *acknowledged));
+			}
+		});
+
+		webfinger
+	}
+
+	/// Whether the instance of `actor`, at `host`, acknowledges it.
+	pub async fn check(&self, actor: &str, host: &str) -> Verdict {
+		if let Some(cached) = self.actors.get(actor) {
+			let (checked, acknowledged) = *cached;
+			if !expired(checked, acknowledged) {
+				return if acknowledged { Verdict::Acknowledged } else { Verdict::Unacknowledged };
+			}
+		}
+		if !self.remote.allow(host) {
+			debug!("Too many WebFinger lookups on {}, not checking {}", host, actor);
+			return Verdict::Unknown;
+		}
+
+		let lookup = self.remote.limit(self.lookup(actor, host)).await;
+		let acknowledged = match lookup {
+			Ok(acknowledged) => acknowledged,
+			// instances answer 404 for accounts they don't have
+			Err(HttpError::Status(404 | 410, _)) => false,
+			Err(e) => {
+				debug!("Could not look up {} with WebFinger: {}", actor, e);
+				return Verdict::Unknown;
+			}
+		};
+		self.actors.insert(actor.to_string(), (Instant::now(), acknowledged));
+		if acknowledged {
+			Verdict::Acknowledged
+		} else {
+			Verdict::Unacknowledged
+		}
+	}
+
+	/// Ask `host` about the actor by its URI, which Mastodon, Misskey and Pleroma all take as the
+	/// resource, and see if the answer points back at it.
+	async fn lookup(&self, actor: &str, host: &str) -> Result<bool, HttpError> {
+		let mut url = Url::parse(&format!("https://{}/.well-known/webfinger", host))
+			.map_err(|_| HttpError::NoHost(host.to_string()))?;
+		url.query_pairs_mut().append_pair("resource", actor);
+		let jrd = self.remote.fetch(&url, JRD_JSON).await?;
+
+		let aliases = jrd.get("aliases").and_then(|a| a.as_array());
+		let links = jrd.get("links").and_then(|l| l.as_array());
+		let aliased = aliases.is_some_and(|a| a.iter().any(|a| a.as_str() == Some(actor)));
+		let linked = links.is_some_and(|l| {
+			l.iter().any(|link| link.get("href").and_then(|h| h.as_str()) == Some(actor))
+		});
+		Ok(aliased || linked)
+	}
+}
+
+fn expired(checked: Instant, acknowledged: bool) -> bool {
+	let ttl = if acknowledged { ACKNOWLEDGED_SECS } else { UNACKNOWLEDGED_SECS };
+	checked.elapsed() >= Duration::from_secs(ttl)
+}
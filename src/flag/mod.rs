@@ -0,0 +1,197 @@
+use std::{
+	fs, io,
+	path::PathBuf,
+	sync::Arc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rsa::{
+	pkcs1::DecodeRsaPrivateKey,
+	pkcs1v15::SigningKey,
+	pkcs8::{DecodePrivateKey, EncodePublicKey, LineEnding},
+	signature::{SignatureEncoding, Signer},
+	RsaPrivateKey,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sonic_rs::Value;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tracing::*;
+use url::Url;
+
+use crate::http::{self, HttpError};
+
+/// How many reports can be waiting for delivery before we start dropping them.
+const QUEUE_LEN: usize = 256;
+const ACTIVITY_JSON: &str = "application/activity+json";
+
+#[derive(Error, Debug)]
+pub enum FlagError {
+	#[error("Could not read private key: {0}")]
+	Read(#[from] io::Error),
+	#[error("Invalid private key, expected PKCS#8 or PKCS#1 PEM")]
+	Key,
+	#[error("Invalid URL: {0}")]
+	Url(String),
+	#[error(transparent)]
+	Http(#[from] HttpError),
+}
+
+/// `[flag]` in the config file: a system actor to report spam to the AP server's moderators as,
+/// with AP `Flag` activities.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlagConfig {
+	/// AP id of the system actor, where its actor document (see `flag-actor`) is served.
+	pub actor: String,
+	/// Id of the actor's public key. Defaults to `<actor>#main-key`.
+	pub key_id: Option<String>,
+	/// The actor's RSA private key, in PEM.
+	pub private_key: PathBuf,
+	/// HTTP(S) URL of the AP server's shared inbox, like `http://127.0.0.1:3000/inbox`.
+	pub inbox: String,
+}
+
+impl FlagConfig {
+	fn key_id(&self) -> String {
+		self.key_id.clone().unwrap_or_else(|| format!("{}#main-key", self.actor))
+	}
+
+	/// Port the inbox is reached on.
+	pub fn inbox_port(&self) -> Option<u16> {
+		Url::parse(&self.inbox).ok()?.port_or_known_default()
+	}
+}
+
+/// The system actor's key, read before the sandbox shuts files away.
+#[derive(Clone)]
+pub struct FlagKey {
+	config: Arc<FlagConfig>,
+	key: Arc<RsaPrivateKey>,
+	signing_key: Arc<SigningKey<Sha256>>,
+}
+
+impl FlagKey {
+	pub fn load(config: FlagConfig) -> Result<Self, FlagError> {
+		for url in [&config.actor, &config.inbox] {
+			Url::parse(url).map_err(|_| FlagError::Url(url.clone()))?;
+		}
+		if !Url::parse(&config.inbox).is_ok_and(|inbox| http::supports(&inbox)) {
+			return Err(FlagError::Url(config.inbox));
+		}
+		let pem = fs::read_to_string(&config.private_key)?;
+		let key = RsaPrivateKey::from_pkcs8_pem(&pem)
+			.or_else(|_| RsaPrivateKey::from_pkcs1_pem(&pem))
+			.map_err(|_| FlagError::Key)?;
+		let signing_key = Arc::new(SigningKey::new(key.clone()));
+		Ok(FlagKey { config: Arc::new(config), key: Arc::new(key), signing_key })
+	}
+
+	/// Actor document of the system actor, to be served at its id so the AP server can check
+	/// the signatures of its reports.
+	pub fn actor_document(&self) -> Value {
+		let public_key_pem =
+			self.key.to_public_key().to_public_key_pem(LineEnding::LF).unwrap_or_default();
+		let actor = self.config.actor.as_str();
+		sonic_rs::json!({
+			"@context": ["https://www.w3.org/ns/activitystreams", "https://w3id.org/security/v1"],
+			"id": actor,
+			"type": "Application",
+			"preferredUsername": "spam-musubi",
+			"name": "spam-musubi",
+			"inbox": format!("{}/inbox", actor).as_str(),
+			"publicKey": {
+				"id": self.config.key_id().as_str(),
+				"owner": actor,
+				"publicKeyPem": public_key_pem.as_str(),
+			},
+		})
+	}
+
+	/// Headers to POST `body` to `url` with, signed with the actor's key.
+	fn sign(&self, url: &Url, body: &str) -> Vec<(&'static str, String)> {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+		let date = http::http_date(now.as_secs());
+		let digest = format!("SHA-256={}", BASE64.encode(Sha256::digest(body.as_bytes())));
+		let signed = format!(
+			"(request-target): post {}\nhost: {}\ndate: {}\ndigest: {}",
+			http::target(url),
+			http::host_header(url).unwrap_or_default(),
+			date,
+			digest
+		);
+		let signature = self.signing_key.sign(signed.as_bytes());
+		let signature = format!(
+			"keyId=\"{}\",algorithm=\"rsa-sha256\",headers=\"(request-target) host date digest\",\
+			signature=\"{}\"",
+			self.config.key_id(),
+			BASE64.encode(signature.to_bytes())
+		);
+		vec![("Date", date), ("Digest", digest), ("Signature", signature)]
+	}
+}
+
+/// Spam to report, queued for delivery.
+struct Flag {
+	actor: String,
+	object: Option<String>,
+	reason: String,
+}
+
+/// Reports spam as `Flag` activities to the AP server's inbox, so it shows up among the
+/// moderators' reports.
+#[derive(Debug, Clone)]
+pub struct Reporter {
+	tx: mpsc::Sender<Flag>,
+}
+
+impl Reporter {
+	pub fn new(key: FlagKey) -> Self {
+		let (tx, mut rx) = mpsc::channel::<Flag>(QUEUE_LEN);
+		tokio::spawn(async move {
+			while let Some(flag) = rx.recv().await {
+				if let Err(e) = deliver(&key, &flag).await {
+					warn!("Could not report {}: {}", flag.actor, e);
+				}
+			}
+		});
+		Reporter { tx }
+	}
+
+	/// Queue a report of `actor`, and of the note it sent if known. Never blocks; drops the
+	/// report if deliveries are lagging behind.
+	pub fn report(&self, actor: &str, object: Option<&str>, reason: &str) {
+		let flag = Flag {
+			actor: actor.to_string(),
+			object: object.map(|o| o.to_string()),
+			reason: reason.to_string(),
+		};
+		if self.tx.try_send(flag).is_err() {
+			debug!("Report queue full, dropping report of {}", actor);
+		}
+	}
+}
+
+async fn deliver(key: &FlagKey, flag: &Flag) -> Result<(), FlagError> {
+	let mut objects = vec![flag.actor.as_str()];
+	objects.extend(flag.object.as_deref());
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	let id = format!("{}/flags/{:x}", key.config.actor, now.as_nanos());
+	let body = sonic_rs::json!({
+		"@context": "https://www.w3.org/ns/activitystreams",
+		"id": id.as_str(),
+		"type": "Flag",
+		"actor": key.config.actor.as_str(),
+		"object": objects,
+		"content": flag.reason.as_str(),
+	})
+	.to_string();
+
+	let url = Url::parse(&key.config.inbox).map_err(|_| FlagError::Url(key.config.inbox.clone()))?;
+	let headers = key.sign(&url, &body);
+	let headers: Vec<_> = headers.iter().map(|(name, value)| (*name, value.as_str())).collect();
+	http::post(&url, ACTIVITY_JSON, &headers, &body).await?;
+	Ok(())
+}
@@ -0,0 +1,312 @@
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::{
+	io::{self, AsyncReadExt, AsyncWriteExt},
+	net::TcpStream,
+	time::timeout,
+};
+use url::Url;
+
+use crate::tls::{self, Stream};
+
+const TIMEOUT_SECS: u64 = 10;
+/// Longest response read, in bytes.
+const MAX_RESPONSE_LEN: u64 = 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum HttpError {
+	#[error("Only http:// and https:// URLs are supported, not {0}")]
+	Scheme(String),
+	#[error("URL without host: {0}")]
+	NoHost(String),
+	#[error("IO error: {0}")]
+	IO(#[from] io::Error),
+	#[error("Timed out")]
+	Timeout(#[from] tokio::time::error::Elapsed),
+	#[error("Malformed response")]
+	MalformedResponse,
+	#[error("Status {0}: {1}")]
+	Status(u16, String),
+}
+
+/// Whether URLs of `url`'s scheme can be requested: http://, and https:// with the server's
+/// certificate checked against the Mozilla root store bundled with the binary.
+pub fn supports(url: &Url) -> bool {
+	matches!(url.scheme(), "http" | "https")
+}
+
+/// Send a JSON body to an HTTP endpoint, like a webhook or the local AP server's API, and return
+/// the response body if the status is 2xx.
+pub async fn post_json(
+	url: &Url, headers: &[(&str, &str)], body: &str,
+) -> Result<String, HttpError> {
+	post(url, "application/json", headers, body).await
+}
+
+/// Like [`post_json`], for bodies of any type.
+pub async fn post(
+	url: &Url, content_type: &str, headers: &[(&str, &str)], body: &str,
+) -> Result<String, HttpError> {
+	request(url, Some((content_type, body)), headers).await
+}
+
+/// GET from an HTTP endpoint, and return the response body if the status is 2xx.
+pub async fn get(url: &Url, headers: &[(&str, &str)]) -> Result<String, HttpError> {
+	request(url, None, headers).await
+}
+
+/// Like [`get`], through `proxy` if given: an HTTP proxy that makes the actual connections,
+/// like nginx with `proxy_pass https://$host`. The proxy gets the URL's host in the Host header.
+pub async fn get_via(
+	proxy: Option<&Url>, url: &Url, headers: &[(&str, &str)],
+) -> Result<String, HttpError> {
+	let Some(proxy) = proxy else {
+		return get(url, headers).await;
+	};
+	if !supports(proxy) {
+		return Err(HttpError::Scheme(proxy.scheme().to_string()));
+	}
+	send(proxy, url, None, headers).await
+}
+
+async fn request(
+	url: &Url, body: Option<(&str, &str)>, headers: &[(&str, &str)],
+) -> Result<String, HttpError> {
+	if !supports(url) {
+		return Err(HttpError::Scheme(url.scheme().to_string()));
+	}
+	send(url, url, body, headers).await
+}
+
+/// Request `url` from the server at `server`, which is the same unless it's a proxy.
+async fn send(
+	server: &Url, url: &Url, body: Option<(&str, &str)>, headers: &[(&str, &str)],
+) -> Result<String, HttpError> {
+	let host = server.host_str().ok_or_else(|| HttpError::NoHost(server.to_string()))?;
+	let port = server.port_or_known_default().unwrap_or(80);
+	let https = server.scheme() == "https";
+
+	// HTTP/1.0, so the response comes whole rather than chunked
+	let mut request = format!(
+		"{} {} HTTP/1.0\r\nHost: {}\r\n",
+		if body.is_some() { "POST" } else { "GET" },
+		target(url),
+		host_header(url).unwrap_or_default()
+	);
+	if let Some((content_type, body)) = body {
+		request.push_str(&format!(
+			"Content-Type: {}\r\nContent-Length: {}\r\n",
+			content_type,
+			body.len()
+		));
+	}
+	for (name, value) in headers {
+		request.push_str(&format!("{}: {}\r\n", name, value));
+	}
+	request.push_str("\r\n");
+	if let Some((_, body)) = body {
+		request.push_str(body);
+	}
+
+	let response = timeout(Duration::from_secs(TIMEOUT_SECS), async {
+		let stream = TcpStream::connect((host, port)).await?;
+		let mut stream = match https {
+			true => tls::connect(host, stream).await?,
+			false => Stream::Plain(stream),
+		};
+		stream.write_all(request.as_bytes()).await?;
+		stream.flush().await?;
+		let mut response = Vec::new();
+		match stream.take(MAX_RESPONSE_LEN).read_to_end(&mut response).await {
+			// servers that close without saying goodbye over TLS, which is common enough
+			Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && !response.is_empty() => {}
+			result => {
+				result?;
+			}
+		}
+		Ok::<_, io::Error>(response)
+	})
+	.await??;
+
+	let response = String::from_utf8_lossy(&response);
+	let (head, body) = response.split_once("\r\n\r\n").ok_or(HttpError::MalformedResponse)?;
+	let status = head
+		.split(' ')
+		.nth(1)
+		.and_then(|s| s.parse::<u16>().ok())
+		.ok_or(HttpError::MalformedResponse)?;
+	if !(200..300).contains(&status) {
+		return Err(HttpError::Status(status, body.chars().take(200).collect()));
+	}
+	Ok(body.to_string())
+}
+
+/// Request target the URL is requested with: its path and query.
+pub fn target(url: &Url) -> String {
+	match url.query() {
+		Some(query) => format!("{}?{}", url.path(), query),
+		None => url.path().to_string(),
+	}
+}
+
+/// Value of the Host header requests to the URL are sent with.
+pub fn host_header(url: &Url) -> Option<String> {
+	let host = url.host_str()?;
+	Some(url.port().map_or(host.to_string(), |p| format!("{}:{}", host, p)))
+}
+
+/// IMF-fixdate of a unix time, as in the Date header.
+pub fn http_date(secs: u64) -> String {
+	const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+	const MONTHS: [&str; 12] =
+		["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+	let (days, secs) = (secs / 86400, secs % 86400);
+	let (year, month, day) = civil_from_days(days as i64);
+	format!(
+		"{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+		DAYS[(days % 7) as usize],
+		day,
+		MONTHS[month as usize - 1],
+		year,
+		secs / 3600,
+		secs % 3600 / 60,
+		secs % 60
+	)
+}
+
+/// Unix time of an RFC 3339 timestamp, like `2024-03-01T12:34:56.789Z` as APIs give them, or
+/// `2024-03-01T21:34:56+09:00`. Fractions of a second are dropped, and dates that don't exist,
+/// like February 30th, aren't read.
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+	let (date, time) = timestamp.split_once(['T', 't'])?;
+	let mut date = date.splitn(3, '-').map(|n| n.parse::<i64>().ok());
+	let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+	let (time, offset) = match time.strip_suffix(['Z', 'z']) {
+		Some(time) => (time, 0),
+		None => {
+			let at = time.rfind(['+', '-'])?;
+			let (hours, minutes) = time[at + 1..].split_once(':')?;
+			let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+			if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+				return None;
+			}
+			let offset = hours * 3600 + minutes * 60;
+			(&time[..at], if time[at..].starts_with('-') { -offset } else { offset })
+		}
+	};
+	let time = time.split('.').next()?;
+	let mut time = time.splitn(3, ':').map(|n| n.parse::<i64>().ok());
+	let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+	// leap seconds as 60
+	if !(0..24).contains(&hours) || !(0..60).contains(&minutes) || !(0..=60).contains(&seconds) {
+		return None;
+	}
+	if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+		return None;
+	}
+	let days = days_from_civil(year, month, day);
+	if civil_from_days(days) != (year, month as u32, day as u32) {
+		return None;
+	}
+	u64::try_from(days * 86400 + hours * 3600 + minutes * 60 + seconds - offset).ok()
+}
+
+/// Year, month and day of a day since the unix epoch, after Howard Hinnant's algorithms.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+	(yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Day since the unix epoch of a date, the inverse of [`civil_from_days`].
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let yoe = year.rem_euclid(400);
+	let mp = (month + 9) % 12;
+	let doy = (153 * mp + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn formats_http_dates() {
+		assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+		assert_eq!(http_date(951782400), "Tue, 29 Feb 2000 00:00:00 GMT");
+		assert_eq!(http_date(1709210096), "Thu, 29 Feb 2024 12:34:56 GMT");
+		assert_eq!(http_date(946684799), "Fri, 31 Dec 1999 23:59:59 GMT");
+		assert_eq!(http_date(946684800), "Sat, 01 Jan 2000 00:00:00 GMT");
+		assert_eq!(http_date(4107542400), "Mon, 01 Mar 2100 00:00:00 GMT");
+		assert_eq!(http_date(2147483648), "Tue, 19 Jan 2038 03:14:08 GMT");
+	}
+
+	#[test]
+	fn finds_dates_of_days() {
+		assert_eq!(civil_from_days(0), (1970, 1, 1));
+		assert_eq!(civil_from_days(-1), (1969, 12, 31));
+		assert_eq!(civil_from_days(10956), (1999, 12, 31));
+		assert_eq!(civil_from_days(10957), (2000, 1, 1));
+		assert_eq!(civil_from_days(11016), (2000, 2, 29));
+		assert_eq!(civil_from_days(19782), (2024, 2, 29));
+		// 2100 is no leap year
+		assert_eq!(civil_from_days(47540), (2100, 2, 28));
+		assert_eq!(civil_from_days(47541), (2100, 3, 1));
+		for days in (-800_000..800_000).step_by(97) {
+			let (year, month, day) = civil_from_days(days);
+			assert_eq!(days_from_civil(year, i64::from(month), i64::from(day)), days);
+		}
+	}
+
+	#[test]
+	fn parses_timestamps() {
+		assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+		assert_eq!(parse_timestamp("2000-02-29T00:00:00Z"), Some(951782400));
+		assert_eq!(parse_timestamp("2024-02-29T12:34:56.789Z"), Some(1709210096));
+		assert_eq!(parse_timestamp("2024-02-29t12:34:56z"), Some(1709210096));
+		assert_eq!(parse_timestamp("2024-02-29T21:34:56+09:00"), Some(1709210096));
+		assert_eq!(parse_timestamp("2024-02-29T08:04:56-04:30"), Some(1709210096));
+		assert_eq!(parse_timestamp("1999-12-31T23:59:59Z"), Some(946684799));
+		assert_eq!(parse_timestamp("2000-01-01T09:00:00+09:00"), Some(946684800));
+		assert_eq!(parse_timestamp("2016-12-31T23:59:60Z"), Some(1483228800));
+	}
+
+	#[test]
+	fn refuses_bad_timestamps() {
+		for bad in [
+			"",
+			"2024-02-29",
+			"2024-02-29 12:34:56Z",
+			"2024-02-29T12:34:56",
+			"2024-02-29T12:34Z",
+			"2024-02-29T12:34:56+0900",
+			"24-02-29T12:34:56Z",
+			"2024-2-x9T12:34:56Z",
+			"2023-02-29T00:00:00Z",
+			"2100-02-29T00:00:00Z",
+			"2024-02-30T00:00:00Z",
+			"2024-04-31T00:00:00Z",
+			"2024-00-10T00:00:00Z",
+			"2024-13-01T00:00:00Z",
+			"2024-01-00T00:00:00Z",
+			"2024-01-01T24:00:00Z",
+			"2024-01-01T00:60:00Z",
+			"2024-01-01T00:00:61Z",
+			"2024-01-01T00:00:00+24:00",
+			"1969-12-31T23:59:59Z",
+			"1970-01-01T08:59:59+09:00",
+		] {
+			assert_eq!(parse_timestamp(bad), None, "{}", bad);
+		}
+	}
+}
@@ -0,0 +1,40 @@
+#![warn(clippy::unwrap_used)]
+
+use once_cell::sync::OnceCell;
+
+#[cfg(all(feature = "console", not(tokio_unstable)))]
+compile_error!("the console feature needs RUSTFLAGS=\"--cfg tokio_unstable\"");
+
+pub mod accept;
+pub mod admin;
+pub mod attachments;
+pub mod audit;
+pub mod bench;
+pub mod cache;
+pub mod config;
+pub mod db;
+pub mod domains;
+pub mod dump;
+pub mod events;
+pub mod filter;
+pub mod flag;
+pub mod http;
+pub mod logging;
+pub mod quarantine;
+pub mod query;
+pub mod reputation;
+pub mod sandbox;
+pub mod share;
+pub mod smtp;
+pub mod startup;
+pub mod statsd;
+pub mod subscriptions;
+pub mod tarpit;
+pub mod telemetry;
+pub mod tls;
+pub mod upstream;
+#[cfg(feature = "io-uring")]
+pub mod uring;
+
+/// Host of the protected AP server, when there is only one.
+pub static HOST: OnceCell<String> = OnceCell::new();
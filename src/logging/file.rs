@@ -0,0 +1,165 @@
+use std::{
+	fs::{self, File, OpenOptions},
+	io::{self, Write},
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex, MutexGuard},
+	thread::{self, JoinHandle},
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use miniz_oxide::deflate::compress_to_vec;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Log file and when to rotate it.
+#[derive(Debug, Clone)]
+pub struct LogFile {
+	pub path: PathBuf,
+	/// Rotate once the file is this large, in bytes.
+	pub max_size: u64,
+	/// Rotate once the file is this old, or never if zero.
+	pub max_age: Duration,
+	/// How many rotated files to keep, as `<path>.1.gz` (the newest) to `<path>.<keep>.gz`.
+	pub keep: usize,
+}
+
+/// Appends log lines to a file, rotating and compressing it by itself so a long-running install
+/// neither fills the disk nor needs logrotate.
+#[derive(Debug, Clone)]
+pub struct FileSink(Arc<Mutex<Rotating>>);
+
+#[derive(Debug)]
+struct Rotating {
+	config: LogFile,
+	file: File,
+	written: u64,
+	opened: SystemTime,
+	/// Compression of the last rotated file, which must finish before the next rotation.
+	compressing: Option<JoinHandle<()>>,
+}
+
+impl FileSink {
+	pub fn open(config: LogFile) -> io::Result<Self> {
+		if let Some(dir) = config.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+			fs::create_dir_all(dir)?;
+		}
+		let file = append(&config.path)?;
+		let metadata = file.metadata()?;
+		// carry the age of the file over restarts, where the filesystem knows it
+		let opened = metadata.created().unwrap_or_else(|_| SystemTime::now());
+		let rotating =
+			Rotating { config, file, written: metadata.len(), opened, compressing: None };
+		Ok(FileSink(Arc::new(Mutex::new(rotating))))
+	}
+}
+
+impl<'a> MakeWriter<'a> for FileSink {
+	type Writer = FileLine<'a>;
+
+	fn make_writer(&'a self) -> Self::Writer {
+		// a panic while logging leaves the file usable
+		FileLine(self.0.lock().unwrap_or_else(|e| e.into_inner()))
+	}
+}
+
+/// The file, held for a single event so lines from different threads don't interleave.
+pub struct FileLine<'a>(MutexGuard<'a, Rotating>);
+
+impl Write for FileLine<'_> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let rotating = &mut *self.0;
+		if rotating.is_due() {
+			// keep logging to the old file rather than losing lines
+			if let Err(e) = rotating.rotate() {
+				eprintln!("Could not rotate {}: {}", rotating.config.path.display(), e);
+				rotating.opened = SystemTime::now();
+			}
+		}
+		let written = rotating.file.write(buf)?;
+		rotating.written += written as u64;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.0.file.flush()
+	}
+}
+
+impl Rotating {
+	fn is_due(&self) -> bool {
+		let age = SystemTime::now().duration_since(self.opened).unwrap_or_default();
+		self.written >= self.config.max_size
+			|| (!self.config.max_age.is_zero() && age >= self.config.max_age)
+	}
+
+	/// Shift the rotated files up by one, dropping the oldest, and move the current file to
+	/// `.1` to be compressed in the background.
+	fn rotate(&mut self) -> io::Result<()> {
+		if let Some(compressing) = self.compressing.take() {
+			let _ = compressing.join();
+		}
+		let path = &self.config.path;
+		if self.config.keep == 0 {
+			fs::remove_file(path)?;
+		} else {
+			for n in (1..self.config.keep).rev() {
+				match fs::rename(rotated(path, n, true), rotated(path, n + 1, true)) {
+					Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+					_ => {}
+				}
+			}
+			let plain = rotated(path, 1, false);
+			fs::rename(path, &plain)?;
+			self.compressing = Some(thread::spawn(move || {
+				if let Err(e) = gzip(&plain) {
+					eprintln!("Could not compress {}: {}", plain.display(), e);
+				}
+			}));
+		}
+		self.file = append(path)?;
+		self.written = 0;
+		self.opened = SystemTime::now();
+		Ok(())
+	}
+}
+
+fn append(path: &Path) -> io::Result<File> {
+	OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `<path>.<n>`, with `.gz` if `compressed`.
+fn rotated(path: &Path, n: usize, compressed: bool) -> PathBuf {
+	let mut rotated = path.as_os_str().to_owned();
+	rotated.push(format!(".{}{}", n, if compressed { ".gz" } else { "" }));
+	PathBuf::from(rotated)
+}
+
+/// Replace the file with a gzipped `<path>.gz`.
+fn gzip(path: &Path) -> io::Result<()> {
+	let data = fs::read(path)?;
+	let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as u32);
+	// magic, deflate, no flags, mtime, no extra flags, unix
+	let mut gz = vec![0x1f, 0x8b, 8, 0];
+	gz.extend_from_slice(&mtime.to_le_bytes());
+	gz.extend_from_slice(&[0, 3]);
+	gz.extend_from_slice(&compress_to_vec(&data, 6));
+	gz.extend_from_slice(&crc32(&data).to_le_bytes());
+	gz.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+	let mut compressed = path.as_os_str().to_owned();
+	compressed.push(".gz");
+	fs::write(compressed, gz)?;
+	fs::remove_file(path)
+}
+
+/// CRC-32 as in gzip trailers.
+fn crc32(data: &[u8]) -> u32 {
+	let mut table = [0u32; 256];
+	for (n, entry) in table.iter_mut().enumerate() {
+		let mut c = n as u32;
+		for _ in 0..8 {
+			c = if c & 1 == 1 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+		}
+		*entry = c;
+	}
+	!data.iter().fold(!0, |crc, &b| table[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8))
+}
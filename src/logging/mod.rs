@@ -0,0 +1,77 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tracing::*;
+
+use crate::filter::RejectReason;
+
+pub mod file;
+pub mod target;
+
+/// Sampler for "Rejected" log lines, so a spam wave doesn't flood the journal.
+///
+/// Only the first and then every `sample_rate`th rejection with the same reason and origin gets
+/// logged. Whatever got suppressed is reported as a single summary line per interval.
+#[derive(Debug, Clone)]
+pub struct RejectLog {
+	sample_rate: u64,
+	counters: Arc<DashMap<(&'static str, Option<String>), Counter>>,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+	seen: u64,
+	suppressed: u64,
+}
+
+impl RejectLog {
+	pub fn init(sample_rate: u64, summary_interval: Duration) -> Self {
+		let log = RejectLog { sample_rate: sample_rate.max(1), counters: Arc::new(DashMap::new()) };
+
+		if log.sample_rate > 1 {
+			let counters = log.counters.clone();
+			tokio::spawn(async move {
+				let mut interval = tokio::time::interval(summary_interval);
+				loop {
+					interval.tick().await;
+					counters.retain(|(kind, origin), counter| {
+						if counter.suppressed > 0 {
+							match origin {
+								Some(origin) => info!(
+									"Suppressed {} similar rejections from {} ({})",
+									counter.suppressed, origin, kind
+								),
+								None => info!(
+									"Suppressed {} similar rejections ({})",
+									counter.suppressed, kind
+								),
+							}
+						}
+						// forget quiet keys so the map doesn't keep every origin ever seen
+						let active = counter.seen > 0;
+						*counter = Counter::default();
+						active
+					});
+				}
+			});
+		}
+
+		log
+	}
+
+	/// Whether this rejection should be logged, or only counted towards the next summary.
+	pub fn should_log(&self, reason: &RejectReason) -> bool {
+		if self.sample_rate == 1 {
+			return true;
+		}
+
+		let mut counter = self.counters.entry((reason.kind(), reason.origin())).or_default();
+		counter.seen += 1;
+		if counter.seen % self.sample_rate == 1 {
+			true
+		} else {
+			counter.suppressed += 1;
+			false
+		}
+	}
+}
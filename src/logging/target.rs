@@ -0,0 +1,209 @@
+use std::{
+	ffi::CStr,
+	io::{self, Write},
+	os::unix::net::UnixDatagram,
+	path::Path,
+	process,
+	sync::Arc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use tracing::{Level, Metadata, Subscriber};
+use tracing_subscriber::{
+	fmt::{self, MakeWriter},
+	layer::SubscriberExt,
+	registry::LookupSpan,
+	util::SubscriberInitExt,
+	EnvFilter, Layer,
+};
+
+use super::file::{FileSink, LogFile};
+use crate::http::civil_from_days;
+
+const APP_NAME: &str = "spam-musubi";
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// LOG_DAEMON
+const FACILITY: u8 = 3;
+
+/// Where log lines go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogTarget {
+	/// Plain lines on stdout.
+	#[default]
+	Stdout,
+	/// RFC 5424 messages to the local syslog daemon at /dev/log.
+	Syslog,
+	/// Native journald entries, with the priority and identifier as fields.
+	Journald,
+	/// Lines appended to a file, which is rotated and compressed by spam-musubi itself.
+	File,
+}
+
+/// Install the global subscriber, filtered by `RUST_LOG`, writing to `target`. `file` is only
+/// used, and required, for [`LogTarget::File`].
+///
+/// Built with the `console` feature, it also serves tokio-console on 127.0.0.1:6669.
+pub fn init(target: LogTarget, file: Option<LogFile>) -> io::Result<()> {
+	let layer = layer(target, file)?.with_filter(EnvFilter::from_default_env());
+	let registry = tracing_subscriber::registry().with(layer);
+	#[cfg(feature = "console")]
+	let registry = registry.with(console_subscriber::spawn());
+	registry.init();
+	Ok(())
+}
+
+/// Formats and writes lines for `target`.
+fn layer<S>(
+	target: LogTarget, file: Option<LogFile>,
+) -> io::Result<Box<dyn Layer<S> + Send + Sync>>
+where
+	S: Subscriber + for<'a> LookupSpan<'a>,
+{
+	let (path, format) = match target {
+		LogTarget::Stdout => return Ok(fmt::layer().boxed()),
+		LogTarget::File => {
+			let file = file.ok_or_else(|| {
+				io::Error::new(io::ErrorKind::InvalidInput, "no log file given")
+			})?;
+			return Ok(fmt::layer().with_ansi(false).with_writer(FileSink::open(file)?).boxed());
+		}
+		LogTarget::Syslog => (SYSLOG_SOCKET, Format::Syslog { hostname: hostname() }),
+		LogTarget::Journald => (JOURNALD_SOCKET, Format::Journald),
+	};
+	if !Path::new(path).exists() {
+		return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path)));
+	}
+	let sink = Sink { socket: Arc::new(UnixDatagram::unbound()?), path, format: Arc::new(format) };
+	// logging must never hold up the proxy; lines are dropped while the daemon is backed up
+	sink.socket.set_nonblocking(true)?;
+	// time and level are carried by the message itself
+	let layer = fmt::layer().with_ansi(false).without_time().with_level(false).with_writer(sink);
+	Ok(layer.boxed())
+}
+
+#[derive(Debug)]
+enum Format {
+	Syslog { hostname: String },
+	Journald,
+}
+
+/// Sends every formatted event as one datagram to the daemon's socket.
+#[derive(Debug, Clone)]
+struct Sink {
+	socket: Arc<UnixDatagram>,
+	path: &'static str,
+	format: Arc<Format>,
+}
+
+impl<'a> MakeWriter<'a> for Sink {
+	type Writer = Line<'a>;
+
+	fn make_writer(&'a self) -> Self::Writer {
+		Line { sink: self, severity: severity(&Level::INFO), buf: Vec::new() }
+	}
+
+	fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+		Line { sink: self, severity: severity(meta.level()), buf: Vec::new() }
+	}
+}
+
+/// syslog(3) severity of a tracing level.
+fn severity(level: &Level) -> u8 {
+	match *level {
+		Level::ERROR => 3,
+		Level::WARN => 4,
+		Level::INFO => 6,
+		_ => 7,
+	}
+}
+
+/// A single event, collected and sent once it is fully formatted.
+struct Line<'a> {
+	sink: &'a Sink,
+	severity: u8,
+	buf: Vec<u8>,
+}
+
+impl Write for Line<'_> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.buf.extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Drop for Line<'_> {
+	fn drop(&mut self) {
+		let message = String::from_utf8_lossy(&self.buf);
+		let message = message.trim_end();
+		if message.is_empty() {
+			return;
+		}
+		let datagram = match &*self.sink.format {
+			Format::Syslog { hostname } => syslog_message(hostname, self.severity, message),
+			Format::Journald => journald_entry(self.severity, message),
+		};
+		// nowhere left to report a failure to
+		let _ = self.sink.socket.send_to(&datagram, self.sink.path);
+	}
+}
+
+/// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`, without MSGID or
+/// structured data.
+fn syslog_message(hostname: &str, severity: u8, message: &str) -> Vec<u8> {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	let (days, secs) = (now.as_secs() / 86400, now.as_secs() % 86400);
+	let (year, month, day) = civil_from_days(days as i64);
+	format!(
+		"<{}>1 {:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z {} {} {} - - {}",
+		FACILITY * 8 + severity,
+		year,
+		month,
+		day,
+		secs / 3600,
+		secs % 3600 / 60,
+		secs % 60,
+		now.subsec_millis(),
+		hostname,
+		APP_NAME,
+		process::id(),
+		message
+	)
+	.into_bytes()
+}
+
+/// Entry in journald's native protocol: `KEY=value` lines, or for values spanning lines, the key,
+/// the value's length as 64-bit little endian, and the value.
+fn journald_entry(severity: u8, message: &str) -> Vec<u8> {
+	let mut entry = format!(
+		"PRIORITY={}\nSYSLOG_IDENTIFIER={}\nSYSLOG_PID={}\n",
+		severity,
+		APP_NAME,
+		process::id()
+	)
+	.into_bytes();
+	if message.contains('\n') {
+		entry.extend_from_slice(b"MESSAGE\n");
+		entry.extend_from_slice(&(message.len() as u64).to_le_bytes());
+		entry.extend_from_slice(message.as_bytes());
+		entry.push(b'\n');
+	} else {
+		entry.extend_from_slice(format!("MESSAGE={}\n", message).as_bytes());
+	}
+	entry
+}
+
+/// Hostname for syslog messages, or the nil value `-` when it can't be told.
+fn hostname() -> String {
+	let mut buf = [0u8; 256];
+	let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+	match CStr::from_bytes_until_nul(&buf) {
+		Ok(name) if ret == 0 && !name.is_empty() => name.to_string_lossy().into_owned(),
+		_ => "-".to_string(),
+	}
+}
@@ -0,0 +1,82 @@
+use std::{
+	collections::VecDeque,
+	sync::{Arc, Mutex},
+	time::SystemTime,
+};
+
+/// Activities held back by a `quarantine` action instead of being forwarded.
+///
+/// The sender is told the delivery was accepted, so it doesn't retry. Only the most recent
+/// `capacity` activities are kept in memory; older ones are dropped.
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+	capacity: usize,
+	inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+	next_id: u64,
+	held: VecDeque<Held>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Held {
+	pub id: u64,
+	pub received: SystemTime,
+	pub actor: String,
+	pub rule: String,
+	pub header: Vec<u8>,
+	pub body: Vec<u8>,
+}
+
+impl Quarantine {
+	pub fn new(capacity: usize) -> Self {
+		Quarantine { capacity, inner: Arc::new(Mutex::new(Inner::default())) }
+	}
+
+	/// Hold an activity and return its quarantine id.
+	pub fn hold(&self, actor: &str, rule: &str, header: Vec<u8>, body: Vec<u8>) -> u64 {
+		self.push(SystemTime::now(), actor.to_string(), rule.to_string(), header, body)
+	}
+
+	/// Every activity held, oldest first.
+	pub fn held(&self) -> Vec<Held> {
+		#[allow(clippy::unwrap_used)]
+		self.inner.lock().unwrap().held.iter().cloned().collect()
+	}
+
+	/// The activity held under `id`, if it is still kept.
+	pub fn get(&self, id: u64) -> Option<Held> {
+		#[allow(clippy::unwrap_used)]
+		self.inner.lock().unwrap().held.iter().find(|held| held.id == id).cloned()
+	}
+
+	/// Stop holding the activity under `id`, e.g. once it's been released. Whether it was held.
+	pub fn remove(&self, id: u64) -> bool {
+		#[allow(clippy::unwrap_used)]
+		let mut inner = self.inner.lock().unwrap();
+		let len = inner.held.len();
+		inner.held.retain(|held| held.id != id);
+		inner.held.len() < len
+	}
+
+	/// Hold an activity from [`Quarantine::held`] again, under a new id.
+	pub fn restore(&self, held: Held) -> u64 {
+		self.push(held.received, held.actor, held.rule, held.header, held.body)
+	}
+
+	fn push(
+		&self, received: SystemTime, actor: String, rule: String, header: Vec<u8>, body: Vec<u8>,
+	) -> u64 {
+		#[allow(clippy::unwrap_used)]
+		let mut inner = self.inner.lock().unwrap();
+		inner.next_id += 1;
+		let id = inner.next_id;
+		while inner.held.len() >= self.capacity.max(1) {
+			inner.held.pop_front();
+		}
+		inner.held.push_back(Held { id, received, actor, rule, header, body });
+		id
+	}
+}
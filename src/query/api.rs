@@ -0,0 +1,309 @@
+use std::{
+	sync::Arc,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use sonic_rs::{JsonContainerTrait, JsonValueMutTrait, JsonValueTrait, Value};
+use url::Url;
+
+use super::{Backend, InstanceStats, PublicKey, QueryError, QueryInitError, QueryOpMode, User};
+use crate::{
+	cache::{self, CacheStats},
+	http::{self, HttpError},
+};
+
+/// How long answers are reused, since asking the API takes a lot longer than asking the DB.
+const CACHE_TTL: Duration = Duration::from_secs(60);
+/// Cached answers past which expired ones are dropped, without a memory budget.
+const CACHE_LEN: usize = 10000;
+/// Rough size of a cached answer with the actor URI or host it's for.
+const ENTRY_BYTES: usize = 160;
+
+/// Backend asking the AP server's HTTP API instead of its DB, for installs whose DB can't be
+/// reached, like on managed hosting.
+///
+/// Misskey answers everything through its API. Mastodon has no API to look up accounts by
+/// URI, so only actors at `/users/<name>` or `/@<name>` are found, and instance stats need a
+/// token with the `admin:read` scope.
+#[derive(Clone)]
+pub struct ApiBackend {
+	mode: QueryOpMode,
+	base: Url,
+	token: Option<String>,
+	users: Arc<Cache<User>>,
+	instances: Arc<Cache<InstanceStats>>,
+	/// Answers kept per cache.
+	cache_len: usize,
+	stats: Arc<CacheStats>,
+}
+
+impl ApiBackend {
+	/// `base` is the AP server's HTTP(S) URL, like `http://127.0.0.1:3000`.
+	pub fn new(
+		mode: QueryOpMode, base: &str, token: Option<String>,
+	) -> Result<Self, QueryInitError> {
+		let base = Url::parse(&format!("{}/", base.trim_end_matches('/')))
+			.map_err(|e| QueryInitError::Api(format!("invalid API URL {}: {}", base, e)))?;
+		if !http::supports(&base) {
+			return Err(QueryInitError::Api(format!("API URL must be http(s)://: {}", base)));
+		}
+		Ok(ApiBackend {
+			mode,
+			base,
+			token,
+			users: Arc::default(),
+			instances: Arc::default(),
+			cache_len: CACHE_LEN,
+			stats: CacheStats::register("lookups", ENTRY_BYTES),
+		})
+	}
+
+	/// Keep cached answers within `mb` MiB, split between actors and instances, instead of a
+	/// fixed number of them.
+	pub fn cache_budget(mut self, mb: Option<u64>) -> Self {
+		if mb.is_some() {
+			self.cache_len = (cache::max_entries(mb, ENTRY_BYTES) / 2).max(1);
+		}
+		self
+	}
+
+	fn cached<T: Copy>(&self, map: &Cache<T>, key: &str) -> Option<Option<T>> {
+		let found = map.get(key).filter(|e| e.0.elapsed() < CACHE_TTL).map(|e| e.1);
+		self.stats.count(found)
+	}
+
+	fn cache<T>(&self, map: &Cache<T>, key: &str, value: Option<T>) {
+		if map.len() >= self.cache_len {
+			map.retain(|_, e| e.0.elapsed() < CACHE_TTL);
+			cache::make_room(map, self.cache_len, &self.stats, |e| e.0);
+		}
+		map.insert(key.to_string(), (Instant::now(), value));
+		self.stats.set_entries(self.users.len() + self.instances.len());
+	}
+
+	fn url(&self, endpoint: &str) -> Result<Url, QueryError> {
+		self.base.join(endpoint).map_err(|_| QueryError::Api(HttpError::NoHost(endpoint.into())))
+	}
+
+	/// POST to a Misskey endpoint, with the token if there is one. `None` if there's nothing
+	/// to answer with.
+	async fn misskey(&self, endpoint: &str, mut body: Value) -> Result<Option<Value>, QueryError> {
+		if let (Some(token), Some(body)) = (&self.token, body.as_object_mut()) {
+			body.insert(&"i", token.as_str());
+		}
+		let response = http::post_json(&self.url(endpoint)?, &[], &body.to_string()).await;
+		match response {
+			Ok(response) if response.is_empty() => Ok(None),
+			Ok(response) => Ok(Some(parse(&response)?).filter(|v| !v.is_null())),
+			// Misskey answers 400 for users and notes it can't find
+			Err(HttpError::Status(400 | 404, _)) => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	/// Call a Mastodon endpoint, with the token if there is one. `None` if not found.
+	async fn mastodon(
+		&self, endpoint: &str, body: Option<Value>,
+	) -> Result<Option<Value>, QueryError> {
+		let url = self.url(endpoint)?;
+		let authorization = self.token.as_ref().map(|t| format!("Bearer {}", t));
+		let mut headers = vec![("X-Forwarded-Proto", "https")];
+		headers.extend(authorization.as_deref().map(|a| ("Authorization", a)));
+		let response = match body {
+			Some(body) => http::post_json(&url, &headers, &body.to_string()).await,
+			None => http::get(&url, &headers).await,
+		};
+		match response {
+			Ok(response) => Ok(Some(parse(&response)?)),
+			Err(HttpError::Status(404, _)) => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	async fn fetch_user(&self, uri: &str) -> Result<Option<User>, QueryError> {
+		match self.mode {
+			QueryOpMode::Misskey => {
+				let found = self.misskey("api/ap/show", sonic_rs::json!({ "uri": uri })).await?;
+				Ok(found
+					.filter(|f| f.get("type").and_then(|t| t.as_str()) == Some("User"))
+					.and_then(|f| f.get("object").and_then(misskey_user)))
+			}
+			QueryOpMode::Mastodon => {
+				let Some(acct) = acct(uri) else {
+					return Ok(None);
+				};
+				let endpoint = format!("api/v1/accounts/lookup?acct={}", acct);
+				Ok(self.mastodon(&endpoint, None).await?.as_ref().and_then(mastodon_user))
+			}
+		}
+	}
+
+	async fn fetch_instance(&self, host: &str) -> Result<Option<InstanceStats>, QueryError> {
+		match self.mode {
+			QueryOpMode::Misskey => {
+				let body = sonic_rs::json!({ "host": host });
+				let found = self.misskey("api/federation/show-instance", body).await?;
+				Ok(found.and_then(|f| {
+					Some(InstanceStats {
+						followers: count(&f, "followersCount")?,
+						following: count(&f, "followingCount")?,
+						notes: count(&f, "notesCount")?,
+					})
+				}))
+			}
+			QueryOpMode::Mastodon => {
+				// totals over all time, whatever the range
+				let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+				let (year, month, day) = http::civil_from_days((now.as_secs() / 86400) as i64);
+				let today = format!("{:04}-{:02}-{:02}", year, month, day);
+				let domain = sonic_rs::json!({ "domain": host });
+				let body = sonic_rs::json!({
+					"keys": ["instance_accounts", "instance_followers", "instance_follows",
+						"instance_statuses"],
+					"start_at": today.as_str(),
+					"end_at": today.as_str(),
+					"instance_accounts": domain.clone(),
+					"instance_followers": domain.clone(),
+					"instance_follows": domain.clone(),
+					"instance_statuses": domain,
+				});
+				let Some(measures) = self.mastodon("api/v1/admin/measures", Some(body)).await?
+				else {
+					return Ok(None);
+				};
+				let total = |key: &str| -> Option<i32> {
+					let measure = measures
+						.as_array()?
+						.iter()
+						.find(|m| m.get("key").and_then(|k| k.as_str()) == Some(key))?;
+					measure.get("total")?.as_str()?.parse().ok()
+				};
+				// no accounts known from there means the instance isn't known either
+				if total("instance_accounts").unwrap_or_default() == 0 {
+					return Ok(None);
+				}
+				Ok(Some(InstanceStats {
+					followers: total("instance_followers").unwrap_or_default(),
+					following: total("instance_follows").unwrap_or_default(),
+					notes: total("instance_statuses").unwrap_or_default(),
+				}))
+			}
+		}
+	}
+}
+
+#[async_trait]
+impl Backend for ApiBackend {
+	async fn get_user(&self, uri: &str) -> Result<Option<User>, QueryError> {
+		if let Some(user) = self.cached(&self.users, uri) {
+			return Ok(user);
+		}
+		let user = self.fetch_user(uri).await?;
+		self.cache(&self.users, uri, user);
+		Ok(user)
+	}
+
+	async fn get_local_user(&self, id: &str) -> Result<Option<User>, QueryError> {
+		match self.mode {
+			QueryOpMode::Misskey => {
+				let body = sonic_rs::json!({ "userId": id });
+				let found = self.misskey("api/users/show", body).await?;
+				// remote users have a host
+				let local = found.filter(|f| f.get("host").filter(|h| !h.is_null()).is_none());
+				Ok(local.as_ref().and_then(misskey_user))
+			}
+			// the last part of a local actor's URL is the username
+			QueryOpMode::Mastodon => {
+				let endpoint = format!("api/v1/accounts/lookup?acct={}", id);
+				Ok(self.mastodon(&endpoint, None).await?.as_ref().and_then(mastodon_user))
+			}
+		}
+	}
+
+	async fn local_user_active(&self, id: &str) -> Result<bool, QueryError> {
+		let flag = |user: &Value, key: &str| user.get(key).and_then(|f| f.as_bool()) == Some(true);
+		match self.mode {
+			QueryOpMode::Misskey => {
+				let body = sonic_rs::json!({ "userId": id });
+				let found = self.misskey("api/users/show", body).await?;
+				let local = found.filter(|f| f.get("host").filter(|h| !h.is_null()).is_none());
+				Ok(local.is_some_and(|u| !flag(&u, "isSuspended") && !flag(&u, "isDeleted")))
+			}
+			QueryOpMode::Mastodon => {
+				let endpoint = format!("api/v1/accounts/lookup?acct={}", id);
+				let found = self.mastodon(&endpoint, None).await?;
+				Ok(found.is_some_and(|a| !flag(&a, "suspended")))
+			}
+		}
+	}
+
+	async fn get_instance_stats(&self, host: &str) -> Result<Option<InstanceStats>, QueryError> {
+		if let Some(stats) = self.cached(&self.instances, host) {
+			return Ok(stats);
+		}
+		let stats = self.fetch_instance(host).await?;
+		self.cache(&self.instances, host, stats);
+		Ok(stats)
+	}
+
+	// neither API tells who follows whom but to the followers themselves
+	async fn followed_by(&self, _uri: &str, _ids: &[&str]) -> Result<Option<bool>, QueryError> {
+		Ok(None)
+	}
+
+	async fn public_key(&self, _key_id: &str) -> Result<Option<PublicKey>, QueryError> {
+		Ok(None)
+	}
+}
+
+type Cache<T> = DashMap<String, (Instant, Option<T>)>;
+
+fn parse(response: &str) -> Result<Value, QueryError> {
+	sonic_rs::from_str(response).map_err(|_| QueryError::Api(HttpError::MalformedResponse))
+}
+
+fn count(value: &Value, key: &str) -> Option<i32> {
+	value.get(key)?.as_i64().and_then(|n| i32::try_from(n).ok())
+}
+
+/// Time since `createdAt`, or since `created_at`.
+fn age(value: &Value, key: &str) -> Duration {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+	let created = value.get(key).and_then(|c| c.as_str()).and_then(http::parse_timestamp);
+	Duration::from_secs(created.map_or(0, |c| now.saturating_sub(c)))
+}
+
+fn misskey_user(user: &Value) -> Option<User> {
+	Some(User {
+		followers: count(user, "followersCount")?,
+		following: count(user, "followingCount")?,
+		notes: count(user, "notesCount")?,
+		age: age(user, "createdAt"),
+	})
+}
+
+fn mastodon_user(account: &Value) -> Option<User> {
+	Some(User {
+		followers: count(account, "followers_count")?,
+		following: count(account, "following_count")?,
+		notes: count(account, "statuses_count")?,
+		age: age(account, "created_at"),
+	})
+}
+
+/// `name@host` of an actor at `https://host/users/name` or `https://host/@name`, the URLs
+/// Mastodon and most others use.
+fn acct(uri: &str) -> Option<String> {
+	let url = Url::parse(uri).ok()?;
+	let host = url.host_str()?;
+	let mut segments = url.path_segments()?;
+	let name = match (segments.next(), segments.next(), segments.next()) {
+		(Some("users"), Some(name), None) => name,
+		(Some(at), None, None) => at.strip_prefix('@')?,
+		_ => return None,
+	};
+	let valid = !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_');
+	valid.then(|| format!("{}@{}", name, host))
+}
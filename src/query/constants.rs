@@ -3,15 +3,32 @@ use super::QueryOpMode;
 #[derive(Debug, Clone)]
 pub struct PreparedQueries {
 	pub get_user: &'static str,
+	pub get_local_user: &'static str,
+	pub local_user_active: &'static str,
 	pub get_instance_stats: &'static str,
+	pub followed_by: &'static str,
+	pub public_key: &'static str,
 }
 
 pub fn get_prepared_queries(mode: QueryOpMode) -> PreparedQueries {
 	match mode {
 		QueryOpMode::Misskey => PreparedQueries {
-			get_user: r#"SELECT t."followersCount", t."followingCount", t."notesCount" FROM public."user" t WHERE uri = $1 LIMIT 1"#,
+			get_user: r#"SELECT t."followersCount", t."followingCount", t."notesCount", EXTRACT(EPOCH FROM now() - t."createdAt")::bigint FROM public."user" t WHERE uri = $1 LIMIT 1"#,
+			get_local_user: r#"SELECT t."followersCount", t."followingCount", t."notesCount", EXTRACT(EPOCH FROM now() - t."createdAt")::bigint FROM public."user" t WHERE id = $1 AND host IS NULL LIMIT 1"#,
+			local_user_active: r#"SELECT 1 FROM public."user" t WHERE id = $1 AND host IS NULL AND NOT t."isSuspended" AND NOT t."isDeleted" LIMIT 1"#,
 			get_instance_stats: r#"SELECT "followersCount", "followingCount", "notesCount" FROM instance WHERE host = $1 LIMIT 1"#,
+			followed_by: r#"SELECT 1 FROM following f JOIN public."user" t ON t.id = f."followeeId" WHERE t.uri = $1 AND f."followerId" = ANY($2) AND f."followerHost" IS NULL LIMIT 1"#,
+			public_key: r#"SELECT t.uri, k."keyPem" FROM user_publickey k JOIN public."user" t ON t.id = k."userId" WHERE k."keyId" = $1 AND t.host IS NOT NULL LIMIT 1"#,
+		},
+		// usernames are what Mastodon inboxes are addressed by, and counters are bigint there
+		QueryOpMode::Mastodon => PreparedQueries {
+			get_user: r#"SELECT COALESCE(s.followers_count, 0)::int, COALESCE(s.following_count, 0)::int, COALESCE(s.statuses_count, 0)::int, EXTRACT(EPOCH FROM now() - a.created_at)::bigint FROM accounts a LEFT JOIN account_stats s ON s.account_id = a.id WHERE a.uri = $1 LIMIT 1"#,
+			get_local_user: r#"SELECT COALESCE(s.followers_count, 0)::int, COALESCE(s.following_count, 0)::int, COALESCE(s.statuses_count, 0)::int, EXTRACT(EPOCH FROM now() - a.created_at)::bigint FROM accounts a LEFT JOIN account_stats s ON s.account_id = a.id WHERE lower(a.username) = lower($1) AND a.domain IS NULL LIMIT 1"#,
+			local_user_active: r#"SELECT 1 FROM accounts a WHERE lower(a.username) = lower($1) AND a.domain IS NULL AND a.suspended_at IS NULL LIMIT 1"#,
+			get_instance_stats: r#"SELECT (SELECT count(*) FROM follows f JOIN accounts fa ON fa.id = f.account_id JOIN accounts ta ON ta.id = f.target_account_id WHERE fa.domain = $1 AND ta.domain IS NULL)::int, (SELECT count(*) FROM follows f JOIN accounts fa ON fa.id = f.account_id JOIN accounts ta ON ta.id = f.target_account_id WHERE fa.domain IS NULL AND ta.domain = $1)::int, COALESCE(SUM(s.statuses_count), 0)::int FROM accounts a LEFT JOIN account_stats s ON s.account_id = a.id WHERE a.domain = $1 HAVING count(*) > 0"#,
+			followed_by: r#"SELECT 1 FROM follows f JOIN accounts a ON a.id = f.account_id JOIN accounts t ON t.id = f.target_account_id WHERE t.uri = $1 AND a.domain IS NULL AND lower(a.username) IN (SELECT lower(n) FROM unnest($2::text[]) n) LIMIT 1"#,
+			// keys are kept with their account, whose URI is the keyId without the fragment
+			public_key: r#"SELECT a.uri, a.public_key FROM accounts a WHERE a.uri = split_part($1, '#', 1) AND a.domain IS NOT NULL AND a.public_key <> '' LIMIT 1"#,
 		},
-		_ => unimplemented!(),
 	}
 }
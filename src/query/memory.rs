@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use dashmap::{DashMap, DashSet};
+
+use super::{Backend, InstanceStats, PublicKey, QueryError, User};
+
+/// Backend answering from memory instead of the AP server's DB, for tests and benchmarks.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+	users: Arc<DashMap<String, User>>,
+	local_users: Arc<DashMap<String, User>>,
+	instances: Arc<DashMap<String, InstanceStats>>,
+	/// Users of this server by id, and remote actors they follow by URI.
+	follows: Arc<DashSet<(String, String)>>,
+	public_keys: Arc<DashMap<String, PublicKey>>,
+}
+
+impl MemoryBackend {
+	pub fn new() -> Self {
+		MemoryBackend::default()
+	}
+
+	/// Know a remote user by actor URI.
+	pub fn user(self, uri: &str, user: User) -> Self {
+		self.users.insert(uri.to_string(), user);
+		self
+	}
+
+	/// Know a user of this server by id.
+	pub fn local_user(self, id: &str, user: User) -> Self {
+		self.local_users.insert(id.to_string(), user);
+		self
+	}
+
+	pub fn instance(self, host: &str, stats: InstanceStats) -> Self {
+		self.instances.insert(host.to_string(), stats);
+		self
+	}
+
+	/// Have the user of this server with `id` follow the remote actor at `uri`.
+	pub fn follow(self, id: &str, uri: &str) -> Self {
+		self.follows.insert((id.to_string(), uri.to_string()));
+		self
+	}
+
+	/// Know the PEM public key with `key_id`, of the remote actor at `owner`.
+	pub fn public_key(self, key_id: &str, owner: &str, pem: &str) -> Self {
+		let key = PublicKey { owner: owner.to_string(), pem: pem.to_string() };
+		self.public_keys.insert(key_id.to_string(), key);
+		self
+	}
+}
+
+#[async_trait]
+impl Backend for MemoryBackend {
+	async fn get_user(&self, uri: &str) -> Result<Option<User>, QueryError> {
+		Ok(self.users.get(uri).map(|u| *u))
+	}
+
+	async fn get_local_user(&self, id: &str) -> Result<Option<User>, QueryError> {
+		Ok(self.local_users.get(id).map(|u| *u))
+	}
+
+	async fn local_user_active(&self, id: &str) -> Result<bool, QueryError> {
+		Ok(self.local_users.contains_key(id))
+	}
+
+	async fn get_instance_stats(&self, host: &str) -> Result<Option<InstanceStats>, QueryError> {
+		Ok(self.instances.get(host).map(|i| *i))
+	}
+
+	async fn followed_by(&self, uri: &str, ids: &[&str]) -> Result<Option<bool>, QueryError> {
+		let followed =
+			ids.iter().any(|id| self.follows.contains(&(id.to_string(), uri.to_string())));
+		Ok(Some(followed))
+	}
+
+	async fn public_key(&self, key_id: &str) -> Result<Option<PublicKey>, QueryError> {
+		Ok(self.public_keys.get(key_id).map(|k| k.clone()))
+	}
+}
@@ -1,13 +1,25 @@
+use std::{
+	mem,
+	sync::{Arc, RwLock},
+	time::Duration,
+};
+
 use clap::ValueEnum;
 use deadpool_postgres::{
 	tokio_postgres::{error::Error as PgError, NoTls},
 	Config, CreatePoolError, Pool, PoolError, Runtime,
 };
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+mod api;
 pub mod constants;
+mod memory;
 
+pub use api::ApiBackend;
 use constants::PreparedQueries;
+pub use memory::MemoryBackend;
 
 #[derive(Error, Debug)]
 pub enum QueryInitError {
@@ -15,6 +27,8 @@ pub enum QueryInitError {
 	Config(#[from] CreatePoolError),
 	#[error("Connection failure: {0}")]
 	ConnectionError(#[from] PoolError),
+	#[error("API error: {0}")]
+	Api(String),
 }
 
 #[derive(Error, Debug)]
@@ -23,34 +37,70 @@ pub enum QueryError {
 	DbError(#[from] PgError),
 	#[error("Pool error: {0}")]
 	PoolError(#[from] PoolError),
+	#[error("API error: {0}")]
+	Api(#[from] crate::http::HttpError),
 }
 
 #[derive(Clone)]
 pub struct Query {
-	pool: Pool,
+	/// The pool and the config it was made with, replaced when the credentials change.
+	current: Arc<RwLock<(Config, Pool)>>,
 	prepared_queries: PreparedQueries,
 }
 
-#[derive(Debug, Clone, ValueEnum)]
+#[derive(Debug, Clone, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum QueryOpMode {
 	Misskey,
 	Mastodon,
 }
 
-#[derive(Debug)]
+/// Where the filter looks up what the AP server knows about actors and instances.
+#[async_trait]
+pub trait Backend: Send + Sync {
+	async fn get_user(&self, uri: &str) -> Result<Option<User>, QueryError>;
+
+	/// Look up a user of this server by id.
+	async fn get_local_user(&self, id: &str) -> Result<Option<User>, QueryError>;
+
+	/// Whether a user of this server, by the id in their inbox's path, exists and isn't deleted
+	/// or suspended.
+	async fn local_user_active(&self, id: &str) -> Result<bool, QueryError>;
+
+	async fn get_instance_stats(&self, host: &str) -> Result<Option<InstanceStats>, QueryError>;
+
+	/// Whether any of these users of this server, by the ids in their actor URLs, follows the
+	/// remote actor at `uri`. `None` if the backend can't tell.
+	async fn followed_by(&self, uri: &str, ids: &[&str]) -> Result<Option<bool>, QueryError>;
+
+	/// The remote key with this `keyId`, as fetched by the AP server when it last verified a
+	/// delivery signed with it. `None` if it's unknown, or the backend can't tell.
+	async fn public_key(&self, key_id: &str) -> Result<Option<PublicKey>, QueryError>;
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct User {
 	pub followers: i32,
 	pub following: i32,
 	pub notes: i32,
+	/// Time since the account was created, or for remote actors, since it was first seen.
+	pub age: Duration,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct InstanceStats {
 	pub followers: i32,
 	pub following: i32,
 	pub notes: i32,
 }
 
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+	/// URI of the actor the key belongs to.
+	pub owner: String,
+	pub pem: String,
+}
+
 impl Query {
 	pub async fn init(
 		host: &str, port: u16, user: &str, password: &str, db_name: &str,
@@ -67,24 +117,75 @@ impl Query {
 		// check if connection is successful
 		let _ = pool.get().await?;
 
-		Ok(Query { pool, prepared_queries: constants::get_prepared_queries(query_op_mode) })
+		Ok(Query {
+			current: Arc::new(RwLock::new((cfg, pool))),
+			prepared_queries: constants::get_prepared_queries(query_op_mode),
+		})
+	}
+
+	fn pool(&self) -> Pool {
+		#[allow(clippy::unwrap_used)]
+		self.current.read().unwrap().1.clone()
+	}
+
+	/// Switch to new credentials once a connection with them works, for ones that expire like
+	/// Vault's. Queries already running finish on the old connections, which are closed after.
+	/// Returns whether the credentials changed.
+	pub async fn rotate(&self, user: &str, password: &str) -> Result<bool, QueryInitError> {
+		#[allow(clippy::unwrap_used)]
+		let mut cfg = self.current.read().unwrap().0.clone();
+		if cfg.user.as_deref() == Some(user) && cfg.password.as_deref() == Some(password) {
+			return Ok(false);
+		}
+		cfg.user = Some(user.to_owned());
+		cfg.password = Some(password.to_owned());
+		let pool = cfg.create_pool(Some(Runtime::Tokio1), NoTls)?;
+		let _ = pool.get().await?;
+
+		#[allow(clippy::unwrap_used)]
+		let (_, old) = mem::replace(&mut *self.current.write().unwrap(), (cfg, pool));
+		old.close();
+		Ok(true)
 	}
+}
 
-	pub async fn get_user(&self, uri: &str) -> Result<Option<User>, QueryError> {
-		let client = self.pool.get().await?;
+#[async_trait]
+impl Backend for Query {
+	async fn get_user(&self, uri: &str) -> Result<Option<User>, QueryError> {
+		let client = self.pool().get().await?;
 		let row = client.query(self.prepared_queries.get_user, &[&uri]).await?;
 
 		Ok(row.first().map(|row| User {
 			followers: row.get(0),
 			following: row.get(1),
 			notes: row.get(2),
+			age: Duration::from_secs(row.get::<_, i64>(3).max(0) as u64),
+		}))
+	}
+
+	async fn get_local_user(&self, id: &str) -> Result<Option<User>, QueryError> {
+		let client = self.pool().get().await?;
+		let row = client.query(self.prepared_queries.get_local_user, &[&id]).await?;
+
+		Ok(row.first().map(|row| User {
+			followers: row.get(0),
+			following: row.get(1),
+			notes: row.get(2),
+			age: Duration::from_secs(row.get::<_, i64>(3).max(0) as u64),
 		}))
 	}
 
-	pub async fn get_instance_stats(
+	async fn local_user_active(&self, id: &str) -> Result<bool, QueryError> {
+		let client = self.pool().get().await?;
+		let row = client.query(self.prepared_queries.local_user_active, &[&id]).await?;
+
+		Ok(!row.is_empty())
+	}
+
+	async fn get_instance_stats(
 		&self, host: &str,
 	) -> Result<Option<InstanceStats>, QueryError> {
-		let client = self.pool.get().await?;
+		let client = self.pool().get().await?;
 		let row = client.query(self.prepared_queries.get_instance_stats, &[&host]).await?;
 
 		Ok(row.first().map(|row| InstanceStats {
@@ -93,4 +194,18 @@ impl Query {
 			notes: row.get(2),
 		}))
 	}
+
+	async fn followed_by(&self, uri: &str, ids: &[&str]) -> Result<Option<bool>, QueryError> {
+		let client = self.pool().get().await?;
+		let row = client.query(self.prepared_queries.followed_by, &[&uri, &ids]).await?;
+
+		Ok(Some(!row.is_empty()))
+	}
+
+	async fn public_key(&self, key_id: &str) -> Result<Option<PublicKey>, QueryError> {
+		let client = self.pool().get().await?;
+		let row = client.query(self.prepared_queries.public_key, &[&key_id]).await?;
+
+		Ok(row.first().map(|row| PublicKey { owner: row.get(0), pem: row.get(1) }))
+	}
 }
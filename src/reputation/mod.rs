@@ -0,0 +1,259 @@
+use std::{
+	sync::Arc,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::db::StateDb;
+
+/// Reputation gained per accepted delivery.
+const ACCEPTED: f64 = 1.0;
+/// Reputation lost per spam verdict.
+pub const SPAM: f64 = -10.0;
+const MAX: f64 = 100.0;
+const MIN: f64 = -100.0;
+/// Score at or below which an actor counts as a confirmed spammer.
+const CONFIRMED_SPAMMER: f64 = -90.0;
+/// Scores that decayed this close to 0 are forgotten.
+const NEGLIGIBLE: f64 = 0.01;
+const FLUSH_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_HALF_LIFE_HOURS: u64 = 72;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Subject {
+	Actor,
+	Instance,
+}
+
+/// Reputation of actors and instances, between -100 and 100.
+///
+/// Accepted deliveries slowly build reputation and spam verdicts quickly burn it. Scores decay
+/// towards 0 with the configured half-life, so both old sins and old merits are forgotten
+/// eventually. When a state DB is configured, scores survive restarts.
+#[derive(Debug, Clone)]
+pub struct Reputation {
+	half_life: Duration,
+	scores: Arc<DashMap<(Subject, String), Entry>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+	score: f64,
+	/// unix time in seconds
+	updated: u64,
+	dirty: bool,
+}
+
+impl Subject {
+	fn as_str(self) -> &'static str {
+		match self {
+			Subject::Actor => "actor",
+			Subject::Instance => "instance",
+		}
+	}
+
+	fn from_str(s: &str) -> Option<Self> {
+		match s {
+			"actor" => Some(Subject::Actor),
+			"instance" => Some(Subject::Instance),
+			_ => None,
+		}
+	}
+}
+
+impl Reputation {
+	/// In-memory only reputation.
+	pub fn new(half_life: Duration) -> Self {
+		Reputation { half_life, scores: Arc::new(DashMap::new()) }
+	}
+
+	pub async fn init(half_life: Duration, db: Option<StateDb>) -> Result<Self, sqlx::Error> {
+		let reputation = Reputation::new(half_life);
+
+		if let Some(db) = &db {
+			let rows = sqlx::query_as::<_, (String, String, f64, i64)>(
+				"SELECT kind, subject, score, updated FROM reputation",
+			)
+			.fetch_all(db.pool())
+			.await?;
+			for (kind, subject, score, updated) in rows {
+				if let Some(kind) = Subject::from_str(&kind) {
+					let entry = Entry { score, updated: updated as u64, dirty: false };
+					reputation.scores.insert((kind, subject), entry);
+				}
+			}
+			info!("Loaded {} reputation scores", reputation.scores.len());
+		}
+
+		let flushing = reputation.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(FLUSH_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				match &db {
+					Some(db) => {
+						if let Err(e) = flushing.flush(db).await {
+							warn!("Could not save reputation scores: {}", e);
+						}
+					}
+					None => {
+						flushing.prune();
+					}
+				}
+			}
+		});
+
+		Ok(reputation)
+	}
+
+	/// How long it takes a score to decay to half.
+	pub fn half_life(&self) -> Duration {
+		self.half_life
+	}
+
+	/// Current, decayed score.
+	pub fn get(&self, kind: Subject, subject: &str) -> f64 {
+		self.scores
+			.get(&(kind, subject.to_string()))
+			.map(|e| self.decayed(*e, now()))
+			.unwrap_or(0.0)
+	}
+
+	/// Whether the actor sent so much spam lately that there is no doubt left.
+	pub fn confirmed_spammer(&self, actor: &str) -> bool {
+		self.get(Subject::Actor, actor) <= CONFIRMED_SPAMMER
+	}
+
+	pub fn accepted(&self, actor: &str, host: &str) {
+		self.add(Subject::Actor, actor, ACCEPTED);
+		self.add(Subject::Instance, host, ACCEPTED);
+	}
+
+	pub fn spam(&self, actor: &str, host: &str) {
+		self.add(Subject::Actor, actor, SPAM);
+		self.add(Subject::Instance, host, SPAM);
+	}
+
+	/// Apply a delta reported by another deployment.
+	pub fn adjust(&self, kind: Subject, subject: &str, delta: f64) {
+		self.add(kind, subject, delta.clamp(SPAM, ACCEPTED));
+	}
+
+	/// Every score as last updated, with the unix time of the update.
+	pub fn entries(&self) -> Vec<(Subject, String, f64, u64)> {
+		self.scores
+			.iter()
+			.map(|e| (e.key().0, e.key().1.clone(), e.score, e.updated))
+			.collect()
+	}
+
+	/// Put back a score from [`Reputation::entries`], unless a newer one is known.
+	pub fn restore(&self, kind: Subject, subject: &str, score: f64, updated: u64) -> bool {
+		let mut entry = self
+			.scores
+			.entry((kind, subject.to_string()))
+			.or_insert(Entry { score: 0.0, updated: 0, dirty: false });
+		if entry.updated > updated {
+			return false;
+		}
+		*entry = Entry { score: score.clamp(MIN, MAX), updated, dirty: true };
+		true
+	}
+
+	fn add(&self, kind: Subject, subject: &str, delta: f64) {
+		let now = now();
+		let mut entry = self
+			.scores
+			.entry((kind, subject.to_string()))
+			.or_insert(Entry { score: 0.0, updated: now, dirty: true });
+		let score = (self.decayed(*entry, now) + delta).clamp(MIN, MAX);
+		*entry = Entry { score, updated: now, dirty: true };
+	}
+
+	fn decayed(&self, entry: Entry, now: u64) -> f64 {
+		let elapsed = now.saturating_sub(entry.updated) as f64;
+		entry.score * 0.5f64.powf(elapsed / self.half_life.as_secs_f64().max(1.0))
+	}
+
+	/// Forget scores that decayed to nothing, returning which.
+	fn prune(&self) -> Vec<(Subject, String)> {
+		let now = now();
+		let mut pruned = Vec::new();
+		self.scores.retain(|key, entry| {
+			let keep = self.decayed(*entry, now).abs() >= NEGLIGIBLE;
+			if !keep {
+				pruned.push(key.clone());
+			}
+			keep
+		});
+		pruned
+	}
+
+	pub async fn flush(&self, db: &StateDb) -> Result<(), sqlx::Error> {
+		let pruned = self.prune();
+		let dirty: Vec<_> =
+			self.scores.iter().filter(|e| e.dirty).map(|e| (e.key().clone(), *e)).collect();
+		if dirty.is_empty() && pruned.is_empty() {
+			return Ok(());
+		}
+
+		let mut tx = db.pool().begin().await?;
+		for ((kind, subject), entry) in &dirty {
+			sqlx::query(
+				"INSERT INTO reputation (kind, subject, score, updated) VALUES (?, ?, ?, ?)
+				ON CONFLICT (kind, subject) DO UPDATE SET score = excluded.score, updated = excluded.updated",
+			)
+			.bind(kind.as_str())
+			.bind(subject)
+			.bind(entry.score)
+			.bind(entry.updated as i64)
+			.execute(&mut *tx)
+			.await?;
+		}
+		for (kind, subject) in &pruned {
+			sqlx::query("DELETE FROM reputation WHERE kind = ? AND subject = ?")
+				.bind(kind.as_str())
+				.bind(subject)
+				.execute(&mut *tx)
+				.await?;
+		}
+		tx.commit().await?;
+
+		// only what was saved is clean, not what changed while saving
+		for (key, saved) in &dirty {
+			if let Some(mut entry) = self.scores.get_mut(key) {
+				if entry.updated == saved.updated && entry.score == saved.score {
+					entry.dirty = false;
+				}
+			}
+		}
+		debug!("Saved {} reputation scores, forgot {}", dirty.len(), pruned.len());
+		Ok(())
+	}
+}
+
+fn now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn forgets_decayed_scores() {
+		let reputation = Reputation::new(Duration::from_secs(3600));
+		reputation.spam("https://remote.example/users/a", "remote.example");
+		// a day of hourly halvings leaves nothing worth keeping
+		let day_ago = now() - 24 * 3600;
+		reputation.restore(Subject::Actor, "https://remote.example/users/b", SPAM, day_ago);
+
+		let pruned = reputation.prune();
+		assert_eq!(pruned, vec![(Subject::Actor, "https://remote.example/users/b".to_string())]);
+		assert_eq!(reputation.entries().len(), 2);
+	}
+}
@@ -0,0 +1,314 @@
+use std::{
+	fs::{self, File, OpenOptions},
+	io,
+	os::{
+		fd::{AsRawFd, FromRawFd},
+		unix::fs::OpenOptionsExt,
+	},
+	path::PathBuf,
+};
+
+use nix::unistd::{setgid, setgroups, setuid, Group, User};
+use thiserror::Error;
+use tracing::*;
+
+#[derive(Error, Debug)]
+pub enum SandboxError {
+	#[error("No such user: {0}")]
+	UnknownUser(String),
+	#[error("No such group: {0}")]
+	UnknownGroup(String),
+	#[error("Could not drop privileges: {0}")]
+	Privileges(#[from] nix::Error),
+	#[error("Could not sandbox: {0}")]
+	IO(#[from] io::Error),
+	#[error("Could not sandbox: {0} not supported by this kernel")]
+	Unsupported(&'static str),
+}
+
+/// Switch to `user`, and to `group` or else the user's primary group, for good.
+pub fn drop_privileges(user: &str, group: Option<&str>) -> Result<(), SandboxError> {
+	let u = User::from_name(user)?.ok_or_else(|| SandboxError::UnknownUser(user.to_string()))?;
+	let gid = match group {
+		Some(name) => {
+			Group::from_name(name)?.ok_or_else(|| SandboxError::UnknownGroup(name.to_string()))?.gid
+		}
+		None => u.gid,
+	};
+	setgroups(&[gid])?;
+	setgid(gid)?;
+	setuid(u.uid)?;
+	info!("Running as {} (uid {}, gid {})", user, u.uid, gid);
+	Ok(())
+}
+
+/// What the process may still touch once sandboxed. Everything else on the filesystem is off
+/// limits, and so are TCP ports not listed, on kernels that can restrict them.
+///
+/// Must be applied while the process has a single thread, i.e. before the async runtime starts,
+/// since Landlock only restricts the calling thread and the threads it spawns afterwards.
+#[derive(Debug, Default)]
+pub struct Sandbox {
+	/// Directories to read and write in, created if missing.
+	pub writable: Vec<PathBuf>,
+	/// Directories and files to read.
+	pub readable: Vec<PathBuf>,
+	/// TCP ports to connect to.
+	pub connect_ports: Vec<u16>,
+}
+
+/// Needed to resolve hostnames: resolver config and NSS modules.
+const SYSTEM_READABLE: [&str; 4] = ["/etc", "/usr", "/lib", "/lib64"];
+
+impl Sandbox {
+	pub fn apply(&self) -> Result<(), SandboxError> {
+		for dir in &self.writable {
+			fs::create_dir_all(dir)?;
+		}
+		// required to sandbox without privileges, and for good measure otherwise
+		if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+			return Err(io::Error::last_os_error().into());
+		}
+		self.landlock()?;
+		seccomp()?;
+		Ok(())
+	}
+
+	fn landlock(&self) -> Result<(), SandboxError> {
+		let abi = landlock::abi();
+		if abi < 1 {
+			return Err(SandboxError::Unsupported("Landlock"));
+		}
+		let fs_access = landlock::fs_access(abi);
+		// listeners are bound by now, so binding is denied altogether
+		let net_access = if abi >= 4 {
+			landlock::ACCESS_NET_BIND_TCP | landlock::ACCESS_NET_CONNECT_TCP
+		} else {
+			0
+		};
+		if net_access == 0 {
+			warn!("Kernel can't restrict ports with Landlock; only the filesystem is sandboxed");
+		}
+		let ruleset = landlock::Ruleset::create(fs_access, net_access)?;
+
+		let read = landlock::ACCESS_FS_READ_FILE | landlock::ACCESS_FS_READ_DIR;
+		let system = SYSTEM_READABLE.iter().map(PathBuf::from).filter(|p| p.exists());
+		for path in system.chain(self.readable.iter().cloned()) {
+			ruleset.allow_path(&path, read)?;
+		}
+		for path in &self.writable {
+			ruleset.allow_path(path, fs_access & !landlock::ACCESS_FS_EXECUTE)?;
+		}
+		if net_access != 0 {
+			for port in &self.connect_ports {
+				ruleset.allow_port(*port, landlock::ACCESS_NET_CONNECT_TCP)?;
+			}
+		}
+		ruleset.restrict_self()
+	}
+}
+
+/// Landlock syscalls and structs, for lack of them in libc.
+mod landlock {
+	use super::*;
+
+	const CREATE_RULESET_VERSION: u32 = 1;
+	const RULE_PATH_BENEATH: libc::c_int = 1;
+	const RULE_NET_PORT: libc::c_int = 2;
+
+	pub const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+	pub const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+	pub const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+	/// Rights that only make sense on a directory, which can't be granted on a file.
+	const ACCESS_FS_DIR_ONLY: u64 = !((1 << 0) | (1 << 1) | (1 << 2) | (1 << 14) | (1 << 15));
+	pub const ACCESS_NET_BIND_TCP: u64 = 1 << 0;
+	pub const ACCESS_NET_CONNECT_TCP: u64 = 1 << 1;
+
+	#[repr(C)]
+	struct RulesetAttr {
+		handled_access_fs: u64,
+		handled_access_net: u64,
+	}
+
+	#[repr(C, packed)]
+	struct PathBeneathAttr {
+		allowed_access: u64,
+		parent_fd: i32,
+	}
+
+	#[repr(C)]
+	struct NetPortAttr {
+		allowed_access: u64,
+		port: u64,
+	}
+
+	/// Landlock ABI version of the kernel, or 0 if not available.
+	pub fn abi() -> i32 {
+		let abi = unsafe {
+			libc::syscall(
+				libc::SYS_landlock_create_ruleset,
+				std::ptr::null::<RulesetAttr>(),
+				0,
+				CREATE_RULESET_VERSION,
+			)
+		};
+		abi.max(0) as i32
+	}
+
+	/// Every filesystem right the kernel knows about, so none is left unrestricted.
+	pub fn fs_access(abi: i32) -> u64 {
+		match abi {
+			1 => (1 << 13) - 1,
+			// with REFER
+			2 => (1 << 14) - 1,
+			// with TRUNCATE
+			3 | 4 => (1 << 15) - 1,
+			// with IOCTL_DEV
+			_ => (1 << 16) - 1,
+		}
+	}
+
+	pub struct Ruleset(File);
+
+	impl Ruleset {
+		pub fn create(fs_access: u64, net_access: u64) -> io::Result<Self> {
+			let attr = RulesetAttr { handled_access_fs: fs_access, handled_access_net: net_access };
+			let fd = unsafe {
+				libc::syscall(
+					libc::SYS_landlock_create_ruleset,
+					&attr as *const RulesetAttr,
+					std::mem::size_of::<RulesetAttr>(),
+					0,
+				)
+			};
+			if fd < 0 {
+				return Err(io::Error::last_os_error());
+			}
+			Ok(Ruleset(unsafe { File::from_raw_fd(fd as i32) }))
+		}
+
+		pub fn allow_path(&self, path: &PathBuf, mut access: u64) -> io::Result<()> {
+			let file = OpenOptions::new().read(true).custom_flags(libc::O_PATH).open(path)?;
+			if !file.metadata()?.is_dir() {
+				access &= !ACCESS_FS_DIR_ONLY;
+			}
+			let attr = PathBeneathAttr { allowed_access: access, parent_fd: file.as_raw_fd() };
+			self.add_rule(RULE_PATH_BENEATH, &attr as *const PathBeneathAttr as *const libc::c_void)
+		}
+
+		pub fn allow_port(&self, port: u16, access: u64) -> io::Result<()> {
+			let attr = NetPortAttr { allowed_access: access, port: port as u64 };
+			self.add_rule(RULE_NET_PORT, &attr as *const NetPortAttr as *const libc::c_void)
+		}
+
+		fn add_rule(&self, kind: libc::c_int, attr: *const libc::c_void) -> io::Result<()> {
+			let ret = unsafe {
+				libc::syscall(libc::SYS_landlock_add_rule, self.0.as_raw_fd(), kind, attr, 0)
+			};
+			if ret != 0 {
+				return Err(io::Error::last_os_error());
+			}
+			Ok(())
+		}
+
+		pub fn restrict_self(self) -> Result<(), SandboxError> {
+			let ret =
+				unsafe { libc::syscall(libc::SYS_landlock_restrict_self, self.0.as_raw_fd(), 0) };
+			if ret != 0 {
+				return Err(io::Error::last_os_error().into());
+			}
+			Ok(())
+		}
+	}
+}
+
+/// Syscalls spam-musubi never makes, which would help an attacker who got code execution.
+const DENIED_SYSCALLS: [libc::c_long; 30] = [
+	libc::SYS_execve,
+	libc::SYS_execveat,
+	libc::SYS_ptrace,
+	libc::SYS_process_vm_readv,
+	libc::SYS_process_vm_writev,
+	libc::SYS_mount,
+	libc::SYS_umount2,
+	libc::SYS_pivot_root,
+	libc::SYS_chroot,
+	libc::SYS_kexec_load,
+	libc::SYS_kexec_file_load,
+	libc::SYS_init_module,
+	libc::SYS_finit_module,
+	libc::SYS_delete_module,
+	libc::SYS_bpf,
+	libc::SYS_perf_event_open,
+	libc::SYS_userfaultfd,
+	libc::SYS_unshare,
+	libc::SYS_setns,
+	libc::SYS_keyctl,
+	libc::SYS_add_key,
+	libc::SYS_request_key,
+	libc::SYS_personality,
+	libc::SYS_setuid,
+	libc::SYS_setgid,
+	libc::SYS_setreuid,
+	libc::SYS_setregid,
+	libc::SYS_setresuid,
+	libc::SYS_setresgid,
+	libc::SYS_setgroups,
+];
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Make the denied syscalls fail with EPERM, and kill the process on syscalls of another ABI.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn seccomp() -> Result<(), SandboxError> {
+	fn stmt(code: u32, k: u32) -> libc::sock_filter {
+		libc::sock_filter { code: code as u16, jt: 0, jf: 0, k }
+	}
+	fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+		libc::sock_filter { code: code as u16, jt, jf, k }
+	}
+	// offsets in struct seccomp_data
+	const NR: u32 = 0;
+	const ARCH: u32 = 4;
+	const DENY: u32 = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+	let mut filter = vec![
+		stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, ARCH),
+		jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, AUDIT_ARCH, 1, 0),
+		stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+		stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, NR),
+	];
+	// x32 syscalls share the x86_64 arch, with a flag in the number
+	#[cfg(target_arch = "x86_64")]
+	filter.extend([
+		jump(libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K, 0x4000_0000, 0, 1),
+		stmt(libc::BPF_RET | libc::BPF_K, DENY),
+	]);
+	for nr in DENIED_SYSCALLS {
+		filter.push(jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, nr as u32, 0, 1));
+		filter.push(stmt(libc::BPF_RET | libc::BPF_K, DENY));
+	}
+	filter.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+
+	let program = libc::sock_fprog { len: filter.len() as u16, filter: filter.as_mut_ptr() };
+	let ret = unsafe {
+		libc::syscall(
+			libc::SYS_seccomp,
+			libc::SECCOMP_SET_MODE_FILTER,
+			0,
+			&program as *const libc::sock_fprog,
+		)
+	};
+	if ret != 0 {
+		return Err(io::Error::last_os_error().into());
+	}
+	Ok(())
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn seccomp() -> Result<(), SandboxError> {
+	Err(SandboxError::Unsupported("seccomp on this architecture"))
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use deadpool_postgres::{
+	tokio_postgres::{self, config::Host},
+	Config, Pool, Runtime, SslMode,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tracing::*;
+
+use crate::{
+	filter::fingerprint::Fingerprints,
+	query::QueryInitError,
+	reputation::{self, Reputation, Subject},
+	tls,
+};
+
+const POLL_INTERVAL_SECS: u64 = 30;
+const PRUNE_INTERVAL_SECS: u64 = 3600;
+const POLL_BATCH: i64 = 1000;
+const QUEUE_LEN: usize = 1024;
+
+const SCHEMA: &str = r#"CREATE TABLE IF NOT EXISTS musubi_shared (
+	id BIGSERIAL PRIMARY KEY,
+	origin TEXT NOT NULL,
+	kind TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	delta DOUBLE PRECISION NOT NULL,
+	signature TEXT NOT NULL,
+	created TIMESTAMPTZ NOT NULL DEFAULT now()
+)"#;
+
+/// Opt-in exchange of spam fingerprints and instance reputation deltas between deployments,
+/// through a Postgres database they all can reach.
+///
+/// Every entry is signed with HMAC-SHA256 using a key shared by the participating deployments,
+/// and entries with bad signatures are ignored.
+#[derive(Debug, Clone)]
+pub struct Share {
+	tx: mpsc::Sender<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+	kind: &'static str,
+	subject: String,
+	delta: f64,
+}
+
+impl Share {
+	pub async fn init(
+		url: &str, origin: String, key: Vec<u8>, reputation: Reputation, fingerprints: Fingerprints,
+	) -> Result<Self, QueryInitError> {
+		let mut cfg = Config { url: Some(url.to_owned()), ..Default::default() };
+		// peers are usually elsewhere, so require TLS unless the URL says otherwise
+		if !url.contains("sslmode=") {
+			cfg.ssl_mode = Some(if is_local(url) { SslMode::Disable } else { SslMode::Require });
+		}
+		let pool = cfg.create_pool(Some(Runtime::Tokio1), tls::postgres())?;
+		pool.get().await?.batch_execute(SCHEMA).await.map_err(|e| {
+			QueryInitError::ConnectionError(deadpool_postgres::PoolError::Backend(e))
+		})?;
+
+		let (tx, mut rx) = mpsc::channel::<Entry>(QUEUE_LEN);
+
+		let publishing = (pool.clone(), origin.clone(), key.clone());
+		tokio::spawn(async move {
+			let (pool, origin, key) = publishing;
+			while let Some(entry) = rx.recv().await {
+				if let Err(e) = publish(&pool, &origin, &key, &entry).await {
+					warn!("Could not share {} {}: {}", entry.kind, entry.subject, e);
+				}
+			}
+		});
+
+		// entries shared before this start were applied by the previous run, if at all, and
+		// reputation losses must not be applied twice
+		let mut last_id: i64 = pool
+			.get()
+			.await?
+			.query_one("SELECT COALESCE(max(id), 0) FROM musubi_shared", &[])
+			.await
+			.map_err(|e| QueryInitError::ConnectionError(deadpool_postgres::PoolError::Backend(e)))?
+			.get(0);
+
+		// by the time a reputation loss has decayed to half, it no longer needs sharing
+		let pruning = pool.clone();
+		let max_age = reputation.half_life().as_secs_f64();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(PRUNE_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				if let Err(e) = prune(&pruning, max_age).await {
+					warn!("Could not prune shared entries: {}", e);
+				}
+			}
+		});
+
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(Duration::from_secs(POLL_INTERVAL_SECS));
+			loop {
+				interval.tick().await;
+				match poll(&pool, &origin, &key, last_id, &reputation, &fingerprints).await {
+					Ok(id) => last_id = id,
+					Err(e) => warn!("Could not fetch shared entries: {}", e),
+				}
+			}
+		});
+
+		Ok(Share { tx })
+	}
+
+	pub fn fingerprint(&self, fingerprint: &str) {
+		self.send(Entry { kind: "fingerprint", subject: fingerprint.to_string(), delta: 0.0 });
+	}
+
+	pub fn instance_reputation(&self, host: &str, delta: f64) {
+		self.send(Entry { kind: "instance", subject: host.to_string(), delta });
+	}
+
+	fn send(&self, entry: Entry) {
+		if self.tx.try_send(entry).is_err() {
+			trace!("Share queue is full, dropping entry");
+		}
+	}
+}
+
+/// Whether every host in a Postgres URL is this machine.
+fn is_local(url: &str) -> bool {
+	let Ok(config) = url.parse::<tokio_postgres::Config>() else {
+		return false;
+	};
+	config.get_hosts().iter().all(|host| match host {
+		Host::Tcp(host) => tls::is_local(host),
+		Host::Unix(_) => true,
+	})
+}
+
+fn sign(key: &[u8], origin: &str, kind: &str, subject: &str, delta: f64) -> Hmac<Sha256> {
+	#[allow(clippy::unwrap_used)] // HMAC takes keys of any length
+	let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+	mac.update(format!("{}\n{}\n{}\n{}", origin, kind, subject, delta).as_bytes());
+	mac
+}
+
+async fn publish(
+	pool: &Pool, origin: &str, key: &[u8], entry: &Entry,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+	let signature = hex::encode(
+		sign(key, origin, entry.kind, &entry.subject, entry.delta).finalize().into_bytes(),
+	);
+	pool.get()
+		.await?
+		.execute(
+			"INSERT INTO musubi_shared (origin, kind, subject, delta, signature) VALUES ($1, $2, $3, $4, $5)",
+			&[&origin, &entry.kind, &entry.subject, &entry.delta, &signature],
+		)
+		.await?;
+	Ok(())
+}
+
+async fn prune(pool: &Pool, max_age: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+	let pruned = pool
+		.get()
+		.await?
+		.execute(
+			"DELETE FROM musubi_shared WHERE created < now() - make_interval(secs => $1)",
+			&[&max_age],
+		)
+		.await?;
+	debug!("Pruned {} shared entries", pruned);
+	Ok(())
+}
+
+/// Apply entries other deployments shared since `last_id`, and return the new last id.
+async fn poll(
+	pool: &Pool, origin: &str, key: &[u8], last_id: i64, reputation: &Reputation,
+	fingerprints: &Fingerprints,
+) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+	let rows = pool
+		.get()
+		.await?
+		.query(
+			"SELECT id, origin, kind, subject, delta, signature FROM musubi_shared
+			WHERE id > $1 ORDER BY id LIMIT $2",
+			&[&last_id, &POLL_BATCH],
+		)
+		.await?;
+
+	let mut last_id = last_id;
+	for row in rows {
+		last_id = row.get(0);
+		let (from, kind, subject, delta, signature): (String, String, String, f64, String) =
+			(row.get(1), row.get(2), row.get(3), row.get(4), row.get(5));
+		if from == origin {
+			continue;
+		}
+		let valid = hex::decode(&signature)
+			.ok()
+			.is_some_and(|s| sign(key, &from, &kind, &subject, delta).verify_slice(&s).is_ok());
+		if !valid {
+			debug!("Ignoring shared {} {} from {} with bad signature", kind, subject, from);
+			continue;
+		}
+		match kind.as_str() {
+			"fingerprint" => fingerprints.insert(&subject),
+			// only losses are shared, at most one spam verdict's worth each
+			"instance" if delta.is_finite() => {
+				reputation.adjust(Subject::Instance, &subject, delta.clamp(reputation::SPAM, 0.0))
+			}
+			_ => {}
+		}
+	}
+	Ok(last_id)
+}
@@ -0,0 +1,196 @@
+use std::{
+	process,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use percent_encoding::percent_decode_str;
+use thiserror::Error;
+use tokio::{
+	io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+	net::TcpStream,
+	time::timeout,
+};
+use url::Url;
+
+use crate::{
+	http::http_date,
+	tls::{self, Stream},
+};
+
+const TIMEOUT_SECS: u64 = 30;
+const DEFAULT_PORT: u16 = 25;
+const DEFAULT_SMTPS_PORT: u16 = 465;
+/// Longest reply line read, in bytes.
+const MAX_LINE_LEN: u64 = 4096;
+
+#[derive(Error, Debug)]
+pub enum SmtpError {
+	#[error("Only smtp:// and smtps:// URLs are supported, not {0}")]
+	Scheme(String),
+	#[error("URL without host: {0}")]
+	NoHost(String),
+	#[error("{0} doesn't offer STARTTLS")]
+	NoStartTls(String),
+	#[error("IO error: {0}")]
+	IO(#[from] io::Error),
+	#[error("Timed out")]
+	Timeout(#[from] tokio::time::error::Elapsed),
+	#[error("Server said: {0}")]
+	Rejected(String),
+}
+
+/// A plain text email.
+#[derive(Debug)]
+pub struct Message<'a> {
+	pub from: &'a str,
+	pub to: &'a [String],
+	pub subject: &'a str,
+	pub body: &'a str,
+}
+
+/// Check a server URL: `smtp://host:port`, or `smtps://host:port` for TLS from the start,
+/// optionally with `user:password@` to log in with.
+pub fn validate(server: &str) -> Result<Url, String> {
+	let url = Url::parse(server).map_err(|e| e.to_string())?;
+	if !matches!(url.scheme(), "smtp" | "smtps") {
+		return Err(SmtpError::Scheme(url.scheme().to_string()).to_string());
+	}
+	if url.host_str().is_none() {
+		return Err(SmtpError::NoHost(server.to_string()).to_string());
+	}
+	Ok(url)
+}
+
+/// Port an SMTP server URL is reached on.
+pub fn port(url: &Url) -> u16 {
+	match url.scheme() {
+		"smtps" => url.port().unwrap_or(DEFAULT_SMTPS_PORT),
+		_ => url.port().unwrap_or(DEFAULT_PORT),
+	}
+}
+
+/// Send a message through the SMTP server at `server`.
+///
+/// The connection is over TLS, from the start for smtps:// or after STARTTLS otherwise, unless
+/// the server is on this machine. Servers elsewhere that don't offer STARTTLS are refused.
+pub async fn send(server: &Url, message: &Message<'_>) -> Result<(), SmtpError> {
+	if !matches!(server.scheme(), "smtp" | "smtps") {
+		return Err(SmtpError::Scheme(server.scheme().to_string()));
+	}
+	let host = server.host_str().ok_or_else(|| SmtpError::NoHost(server.to_string()))?;
+	timeout(Duration::from_secs(TIMEOUT_SECS), async {
+		let domain = message.from.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+		let mut stream = open(server, host, &format!("EHLO {}", domain)).await?;
+		if !server.username().is_empty() {
+			let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+			let password = decode(server.password().unwrap_or_default());
+			let login = format!("\0{}\0{}", decode(server.username()), password);
+			let auth = format!("AUTH PLAIN {}", STANDARD.encode(login));
+			command(&mut stream, &auth, b'2').await?;
+		}
+		command(&mut stream, &format!("MAIL FROM:<{}>", message.from), b'2').await?;
+		for to in message.to {
+			command(&mut stream, &format!("RCPT TO:<{}>", to), b'2').await?;
+		}
+		command(&mut stream, "DATA", b'3').await?;
+		stream.get_mut().write_all(&data(message)).await?;
+		stream.get_mut().flush().await?;
+		reply(&mut stream, b'2').await?;
+		// the message is on its way whatever the answer
+		let _ = command(&mut stream, "QUIT", b'2').await;
+		Ok(())
+	})
+	.await?
+}
+
+/// Connect to `host` and say `ehlo`, going over to TLS first unless it's on this machine.
+async fn open(server: &Url, host: &str, ehlo: &str) -> Result<BufReader<Stream>, SmtpError> {
+	let stream = TcpStream::connect((host, port(server))).await?;
+	if server.scheme() == "smtps" {
+		let mut stream = BufReader::new(tls::connect(host, stream).await?);
+		reply(&mut stream, b'2').await?;
+		command(&mut stream, ehlo, b'2').await?;
+		return Ok(stream);
+	}
+
+	let mut plain = BufReader::new(stream);
+	reply(&mut plain, b'2').await?;
+	let extensions = command(&mut plain, ehlo, b'2').await?;
+	if tls::is_local(host) {
+		return Ok(BufReader::new(Stream::Plain(plain.into_inner())));
+	}
+	let starttls = |line: &String| {
+		line.get(4..).is_some_and(|keyword| keyword.trim_end().eq_ignore_ascii_case("STARTTLS"))
+	};
+	if !extensions.iter().any(starttls) {
+		return Err(SmtpError::NoStartTls(host.to_string()));
+	}
+	command(&mut plain, "STARTTLS", b'2').await?;
+	// anything the server sent before the handshake is dropped with the plain reader
+	let mut stream = BufReader::new(tls::connect(host, plain.into_inner()).await?);
+	command(&mut stream, ehlo, b'2').await?;
+	Ok(stream)
+}
+
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+	stream: &mut BufReader<S>, line: &str, expect: u8,
+) -> Result<Vec<String>, SmtpError> {
+	stream.get_mut().write_all(format!("{}\r\n", line).as_bytes()).await?;
+	stream.get_mut().flush().await?;
+	reply(stream, expect).await
+}
+
+/// Read a reply, which may span lines, and check its code starts with `expect`. Returns its
+/// lines, like the extensions listed after EHLO.
+async fn reply(
+	read: &mut BufReader<impl AsyncRead + Unpin>, expect: u8,
+) -> Result<Vec<String>, SmtpError> {
+	let mut lines = Vec::new();
+	loop {
+		let mut line = String::new();
+		if (&mut *read).take(MAX_LINE_LEN).read_line(&mut line).await? == 0 {
+			return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+		}
+		// "250-" is followed by more lines, "250 " is the last
+		let last = line.as_bytes().get(3) != Some(&b'-');
+		let code = line.as_bytes().first().copied();
+		lines.push(line);
+		if !last {
+			continue;
+		}
+		return match code {
+			Some(code) if code == expect => Ok(lines),
+			_ => Err(SmtpError::Rejected(lines.pop().unwrap_or_default().trim_end().to_string())),
+		};
+	}
+}
+
+/// The message as sent after DATA: headers, the body with lines starting with a dot escaped,
+/// and the terminating dot.
+fn data(message: &Message) -> Vec<u8> {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	let domain = message.from.rsplit_once('@').map_or("localhost", |(_, domain)| domain);
+	let mut data = format!(
+		"From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}.{}.{}@{}>\r\n\
+		MIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\n\
+		Content-Transfer-Encoding: 8bit\r\n\r\n",
+		message.from,
+		message.to.join(", "),
+		message.subject,
+		http_date(now.as_secs()).replace("GMT", "+0000"),
+		now.as_secs(),
+		now.subsec_nanos(),
+		process::id(),
+		domain
+	);
+	for line in message.body.lines() {
+		if line.starts_with('.') {
+			data.push('.');
+		}
+		data.push_str(line);
+		data.push_str("\r\n");
+	}
+	data.push_str(".\r\n");
+	data.into_bytes()
+}
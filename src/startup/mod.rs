@@ -0,0 +1,121 @@
+use std::{env, fmt::Display, fs, process, str::FromStr};
+
+/// What kind of problem kept spam-musubi from starting, for supervisors to tell apart by the
+/// exit status. Codes are from sysexits.h.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+	/// Bad flags, env vars or config file. Fix them before trying again.
+	Config,
+	/// A DB or other server couldn't be reached. Worth retrying.
+	Unavailable,
+	/// The port, a file, directory or socket couldn't be bound, created or opened.
+	CantCreate,
+	/// Switching users or sandboxing was denied.
+	NoPerm,
+}
+
+impl Problem {
+	pub fn exit_code(self) -> i32 {
+		match self {
+			Problem::Config => 78,
+			Problem::Unavailable => 69,
+			Problem::CantCreate => 73,
+			Problem::NoPerm => 77,
+		}
+	}
+}
+
+/// Problems found while starting up, collected to be reported all at once rather than one per
+/// attempt.
+#[derive(Debug, Default)]
+pub struct Problems {
+	found: Vec<(Problem, String)>,
+}
+
+impl Problems {
+	pub fn new() -> Self {
+		Problems::default()
+	}
+
+	pub fn add(&mut self, problem: Problem, message: impl Display) {
+		self.found.push((problem, message.to_string()));
+	}
+
+	/// The value of `result`, or `None` after noting its error as a problem with `what`.
+	pub fn check<T, E: Display>(
+		&mut self, problem: Problem, what: &str, result: Result<T, E>,
+	) -> Option<T> {
+		result.map_err(|e| self.add(problem, format!("{}: {}", what, e))).ok()
+	}
+
+	/// The value of a required env var, see [`Problems::optional_env`].
+	pub fn env(&mut self, name: &str) -> Option<String> {
+		match self.optional_env(name) {
+			Some(Some(value)) => Some(value),
+			Some(None) => {
+				self.add(Problem::Config, format!("{} is not set", name));
+				None
+			}
+			None => None,
+		}
+	}
+
+	/// The value of an env var, or else the contents of the file `<name>_FILE` points to, as
+	/// with docker and Kubernetes secrets. `None` once noted as a problem.
+	pub fn optional_env(&mut self, name: &str) -> Option<Option<String>> {
+		env_or_file(name).map_err(|e| self.add(Problem::Config, e)).ok()
+	}
+
+	/// The value of a required env var, parsed.
+	pub fn env_parse<T: FromStr>(&mut self, name: &str) -> Option<T>
+	where
+		T::Err: Display,
+	{
+		let value = self.env(name)?;
+		self.check(Problem::Config, &format!("{}={}", name, value), value.parse())
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.found.is_empty()
+	}
+
+	/// Print every problem found and exit, if there are any.
+	pub fn exit_if_any(self) {
+		if !self.is_empty() {
+			self.exit();
+		}
+	}
+
+	/// Print every problem found and exit, with the status of the first.
+	pub fn exit(self) -> ! {
+		eprintln!("spam-musubi could not start:");
+		for (_, message) in &self.found {
+			eprintln!("  - {}", message);
+		}
+		// EX_SOFTWARE, if nothing was found after all
+		process::exit(self.found.first().map_or(70, |(first, _)| first.exit_code()));
+	}
+}
+
+/// The value of an env var, or else the contents of the file `<name>_FILE` points to.
+pub fn env_or_file(name: &str) -> Result<Option<String>, String> {
+	if let Ok(value) = env::var(name) {
+		return Ok(Some(value));
+	}
+	let file = format!("{}_FILE", name);
+	let Ok(path) = env::var(&file) else {
+		return Ok(None);
+	};
+	match fs::read_to_string(&path) {
+		// secrets are often written with a trailing newline
+		Ok(value) => Ok(Some(value.trim_end_matches(['\r', '\n']).to_string())),
+		Err(e) => Err(format!("{}={}: {}", file, path, e)),
+	}
+}
+
+/// Report a single problem and exit, for ones nothing else can be checked past.
+pub fn fail(problem: Problem, message: impl Display) -> ! {
+	let mut problems = Problems::new();
+	problems.add(problem, message);
+	problems.exit()
+}
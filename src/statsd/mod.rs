@@ -0,0 +1,195 @@
+use std::{
+	io,
+	net::{SocketAddr, UdpSocket},
+	sync::Arc,
+	time::Duration,
+};
+
+use serde::Deserialize;
+use tracing::*;
+
+use crate::{accept, cache, upstream::health};
+
+#[cfg(all(feature = "console", tokio_unstable))]
+mod runtime;
+
+const DEFAULT_PREFIX: &str = "musubi";
+/// Seconds between reports on the caches, the AP servers and the runtime.
+const REPORT_INTERVAL_SECS: u64 = 10;
+
+/// `[statsd]` in the config file: a StatsD or DogStatsD agent to push metrics to, for setups
+/// with push-based metrics only.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StatsdConfig {
+	/// Where the agent listens for UDP, like `127.0.0.1:8125`.
+	pub address: String,
+	#[serde(default = "default_prefix")]
+	pub prefix: String,
+	/// Send the kind of rejection and `tags` as DogStatsD tags, instead of in the metric name.
+	#[serde(default)]
+	pub dogstatsd: bool,
+	/// Tags like `env:prod` added to every metric, with `dogstatsd` only.
+	#[serde(default)]
+	pub tags: Vec<String>,
+}
+
+fn default_prefix() -> String {
+	DEFAULT_PREFIX.to_string()
+}
+
+impl StatsdConfig {
+	/// Check the address and names at startup rather than on the first metric.
+	pub fn validate(&self) -> Result<(), String> {
+		self.address
+			.parse::<SocketAddr>()
+			.map_err(|e| format!("statsd address must be an IP and port: {}", e))?;
+		let name = |s: &str| {
+			!s.is_empty()
+				&& s.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+		};
+		if !name(&self.prefix) {
+			return Err("statsd prefix must be letters, digits, dots, _ and -".to_string());
+		}
+		let tag = |s: &String| name(&s.replace(':', "")) && !s.starts_with(':');
+		if !self.tags.iter().all(tag) {
+			return Err("statsd tags must be like env:prod".to_string());
+		}
+		if !self.tags.is_empty() && !self.dogstatsd {
+			return Err("statsd tags need dogstatsd = true".to_string());
+		}
+		Ok(())
+	}
+}
+
+/// Pushes metrics over UDP as they happen. Never slows down the filter: a metric that can't be
+/// sent right away is dropped.
+#[derive(Debug, Clone)]
+pub struct Statsd {
+	config: Arc<StatsdConfig>,
+	socket: Option<Arc<UdpSocket>>,
+}
+
+impl Statsd {
+	pub fn new(config: StatsdConfig) -> Self {
+		let socket = match connect(&config) {
+			Ok(socket) => {
+				info!("Pushing metrics to StatsD at {}", config.address);
+				Some(Arc::new(socket))
+			}
+			Err(e) => {
+				warn!("Could not push metrics to StatsD at {}: {}", config.address, e);
+				None
+			}
+		};
+		let statsd = Statsd { config: Arc::new(config), socket };
+		if statsd.socket.is_some() {
+			let reporting = statsd.clone();
+			tokio::spawn(async move {
+				let every = Duration::from_secs(REPORT_INTERVAL_SECS);
+				let mut interval = tokio::time::interval(every);
+				#[cfg(all(feature = "console", tokio_unstable))]
+				let mut totals = runtime::RuntimeTotals::default();
+				loop {
+					interval.tick().await;
+					reporting.caches();
+					reporting.upstreams();
+					reporting.accepts();
+					#[cfg(all(feature = "console", tokio_unstable))]
+					reporting.runtime(every, &mut totals);
+				}
+			});
+		}
+		statsd
+	}
+
+	/// A delivery let through to the AP server.
+	pub fn admitted(&self) {
+		self.send("admitted", "1|c", None);
+	}
+
+	/// A delivery rejected, with the category responses are configured by.
+	pub fn rejected(&self, category: &str) {
+		self.send("rejected", "1|c", Some(("kind", category)));
+	}
+
+	/// A note judged not to be spam.
+	pub fn accepted(&self) {
+		self.send("notes.accepted", "1|c", None);
+	}
+
+	/// A note the shadow pipeline would decide on differently, by how.
+	pub fn shadow_diverged(&self, diff: &str) {
+		self.send("shadow.diverged", "1|c", Some(("diff", diff)));
+	}
+
+	/// Time taken to judge a delivery, whatever came of it.
+	pub fn inspected(&self, took: Duration) {
+		self.send("inspect", &format!("{:.3}|ms", took.as_secs_f64() * 1000.0), None);
+	}
+
+	/// Size of every cache, and what it counted since the last report.
+	fn caches(&self) {
+		for report in cache::reports() {
+			let tag = Some(("cache", report.name));
+			self.send("cache.entries", &format!("{}|g", report.entries), tag);
+			self.send("cache.bytes", &format!("{}|g", report.bytes), tag);
+			self.send("cache.hits", &format!("{}|c", report.hits), tag);
+			self.send("cache.misses", &format!("{}|c", report.misses), tag);
+			self.send("cache.evictions", &format!("{}|c", report.evictions), tag);
+		}
+	}
+
+	/// 1 for each AP server passing its health checks, 0 for each one down, to alert on.
+	fn upstreams(&self) {
+		for (address, up) in health::reports() {
+			// no dots or colons in metric names
+			let tag = address.replace(['.', ':', '[', ']'], "_");
+			self.send("upstream.up", &format!("{}|g", up as u8), Some(("upstream", &tag)));
+		}
+	}
+
+	/// Connections that couldn't be accepted, like for running out of file descriptors.
+	fn accepts(&self) {
+		let failures = accept::take_failures();
+		if failures > 0 {
+			self.send("accept.failures", &format!("{}|c", failures), None);
+		}
+	}
+
+	fn send(&self, name: &str, value: &str, tag: Option<(&str, &str)>) {
+		let Some(socket) = &self.socket else {
+			return;
+		};
+		let config = &self.config;
+		let metric = match tag {
+			Some((key, tag)) if config.dogstatsd => {
+				let tags = std::iter::once(format!("{}:{}", key, tag))
+					.chain(config.tags.iter().cloned())
+					.collect::<Vec<_>>()
+					.join(",");
+				format!("{}.{}:{}|#{}", config.prefix, name, value, tags)
+			}
+			Some((_, tag)) => format!("{}.{}.{}:{}", config.prefix, name, tag, value),
+			None if config.dogstatsd && !config.tags.is_empty() => {
+				format!("{}.{}:{}|#{}", config.prefix, name, value, config.tags.join(","))
+			}
+			None => format!("{}.{}:{}", config.prefix, name, value),
+		};
+		if let Err(e) = socket.send(metric.as_bytes()) {
+			trace!("Could not send metric: {}", e);
+		}
+	}
+}
+
+fn connect(config: &StatsdConfig) -> io::Result<UdpSocket> {
+	let address: SocketAddr = config.address.parse().map_err(io::Error::other)?;
+	let local: SocketAddr = match address {
+		SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+		SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+	};
+	let socket = UdpSocket::bind(local)?;
+	socket.connect(address)?;
+	socket.set_nonblocking(true)?;
+	Ok(socket)
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+
+use super::Statsd;
+
+/// Share of an interval a worker must have been busy for, without finishing a single poll, to
+/// count as blocked.
+const BLOCKED_BUSY_PERCENT: u128 = 95;
+
+/// Per-worker totals at the last report, to send what changed since.
+#[derive(Debug, Default)]
+pub struct RuntimeTotals {
+	polls: Vec<u64>,
+	busy: Vec<Duration>,
+}
+
+impl Statsd {
+	/// Tokio's view of the runtime: whether latency comes from starved workers rather than a
+	/// slow upstream or DB.
+	pub(super) fn runtime(&self, interval: Duration, last: &mut RuntimeTotals) {
+		let metrics = Handle::current().metrics();
+		let workers = metrics.num_workers();
+		last.polls.resize(workers, 0);
+		last.busy.resize(workers, Duration::ZERO);
+
+		let (mut polls, mut blocked) = (0, 0);
+		let (mut busy, mut poll_time) = (Duration::ZERO, Duration::ZERO);
+		for worker in 0..workers {
+			let worker_polls = metrics.worker_poll_count(worker);
+			let worker_busy = metrics.worker_total_busy_duration(worker);
+			let new_polls = worker_polls.saturating_sub(last.polls[worker]);
+			let new_busy = worker_busy.saturating_sub(last.busy[worker]);
+			if new_polls == 0
+				&& new_busy.as_millis() * 100 >= interval.as_millis() * BLOCKED_BUSY_PERCENT
+			{
+				blocked += 1;
+			}
+			polls += new_polls;
+			busy += new_busy;
+			poll_time = poll_time.max(metrics.worker_mean_poll_time(worker));
+			last.polls[worker] = worker_polls;
+			last.busy[worker] = worker_busy;
+		}
+		let busy_percent = busy.as_millis() * 100 / (interval.as_millis() * workers as u128).max(1);
+
+		self.send("runtime.workers", &format!("{}|g", workers), None);
+		self.send("runtime.tasks", &format!("{}|g", metrics.active_tasks_count()), None);
+		self.send("runtime.queue", &format!("{}|g", metrics.injection_queue_depth()), None);
+		self.send("runtime.blocking", &format!("{}|g", metrics.num_blocking_threads()), None);
+		self.send("runtime.polls", &format!("{}|c", polls), None);
+		self.send("runtime.busy", &format!("{}|g", busy_percent), None);
+		self.send("runtime.blocked", &format!("{}|g", blocked), None);
+		let poll_time = poll_time.as_secs_f64() * 1000.0;
+		self.send("runtime.poll_time", &format!("{:.3}|g", poll_time), None);
+	}
+}
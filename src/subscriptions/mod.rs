@@ -0,0 +1,472 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::*;
+use url::Url;
+
+use crate::{
+	attachments::AttachmentList,
+	db::StateDb,
+	domains::{self, DomainList},
+	filter::fingerprint::Fingerprints,
+	http::{self, HttpError},
+};
+
+const DEFAULT_INTERVAL_SECS: u64 = 3600;
+const MIN_INTERVAL_SECS: u64 = 60;
+/// Starts the first line of a bundle, followed by the signature of the rest.
+const SIGNATURE_PREFIX: &str = "ed25519 ";
+/// Starts the first signed line, followed by a number the publisher raises with every bundle.
+const SERIAL_PREFIX: &str = "serial ";
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+	#[error("Could not fetch: {0}")]
+	Fetch(#[from] HttpError),
+	#[error("No signature line")]
+	Unsigned,
+	#[error("Not signed by any of the configured keys")]
+	BadSignature,
+	#[error("No serial line")]
+	NoSerial,
+	#[error("Serial {0} is older than the applied {1}")]
+	Stale(i64, i64),
+	#[error("Could not update the list: {0}")]
+	List(#[from] sqlx::Error),
+}
+
+/// `[[subscriptions]]` in the config file: a list maintained elsewhere, pulled periodically and
+/// applied only when signed by one of the publisher's keys.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionConfig {
+	/// HTTP(S) URL of the bundle.
+	pub url: String,
+	pub list: ListKind,
+	/// Base64 Ed25519 public keys of the publisher. A bundle signed by any of them is applied.
+	pub keys: Vec<String>,
+	/// Seconds between pulls.
+	#[serde(default = "default_interval_secs")]
+	pub interval_secs: u64,
+}
+
+/// Which list a subscription adds to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListKind {
+	Blocklist,
+	Tarpit,
+	/// Attachment URLs, domains hosting them, or SHA-256 hashes of either.
+	Attachments,
+	/// Content fingerprints of spam notes.
+	Fingerprints,
+}
+
+fn default_interval_secs() -> u64 {
+	DEFAULT_INTERVAL_SECS
+}
+
+impl SubscriptionConfig {
+	/// Check the URL and keys at startup rather than on the first pull.
+	pub fn validate(&self) -> Result<(), String> {
+		let url = Url::parse(&self.url).map_err(|e| format!("invalid url {}: {}", self.url, e))?;
+		if !http::supports(&url) {
+			return Err(format!("{} must be an http:// or https:// URL", self.url));
+		}
+		if self.keys.is_empty() {
+			return Err(format!("{} needs at least one key", self.url));
+		}
+		for key in &self.keys {
+			parse_key(key).ok_or_else(|| format!("{} is not an Ed25519 public key", key))?;
+		}
+		if self.interval_secs < MIN_INTERVAL_SECS {
+			return Err(format!("interval_secs must be at least {}", MIN_INTERVAL_SECS));
+		}
+		Ok(())
+	}
+
+	/// Port the bundle is fetched from.
+	pub fn url_port(&self) -> Option<u16> {
+		Url::parse(&self.url).ok()?.port_or_known_default()
+	}
+}
+
+/// A public key, unless it's not a point on the curve or one of small order, which would
+/// verify signatures of anything.
+fn parse_key(key: &str) -> Option<VerifyingKey> {
+	let key = STANDARD.decode(key.trim()).ok()?;
+	let key = VerifyingKey::from_bytes(key.as_slice().try_into().ok()?).ok()?;
+	(!key.is_weak()).then_some(key)
+}
+
+/// The lists subscriptions can add to.
+#[derive(Debug, Clone)]
+pub struct Lists {
+	pub blocklist: DomainList,
+	pub tarpit: DomainList,
+	pub attachments: AttachmentList,
+	pub fingerprints: Fingerprints,
+}
+
+impl Lists {
+	/// Add an entry to a list, and return whether it wasn't listed already.
+	async fn add(&self, kind: ListKind, entry: &str) -> Result<bool, sqlx::Error> {
+		let entry = [entry.to_string()];
+		Ok(match kind {
+			ListKind::Blocklist => self.blocklist.add(&entry).await? > 0,
+			ListKind::Tarpit => self.tarpit.add(&entry).await? > 0,
+			ListKind::Attachments => self.attachments.add(&entry).await? > 0,
+			ListKind::Fingerprints => {
+				let new = !self.fingerprints.contains(&entry[0]);
+				self.fingerprints.insert(&entry[0]);
+				new
+			}
+		})
+	}
+
+	async fn remove(&self, kind: ListKind, entry: &str) -> Result<(), sqlx::Error> {
+		let entry = [entry.to_string()];
+		match kind {
+			ListKind::Blocklist => {
+				self.blocklist.remove(&entry).await?;
+			}
+			ListKind::Tarpit => {
+				self.tarpit.remove(&entry).await?;
+			}
+			ListKind::Attachments => {
+				self.attachments.remove(&entry).await?;
+			}
+			ListKind::Fingerprints => {
+				self.fingerprints.remove(&entry[0]);
+			}
+		}
+		Ok(())
+	}
+}
+
+/// What a subscription applied: the serial of its last bundle, and the entries it listed, to
+/// unlist again once a bundle drops them.
+///
+/// When a state DB is configured, both survive restarts.
+struct Applied {
+	url: String,
+	serial: Option<i64>,
+	entries: HashSet<String>,
+	db: Option<StateDb>,
+}
+
+impl Applied {
+	async fn load(url: &str, db: Option<StateDb>) -> Result<Self, sqlx::Error> {
+		let mut applied =
+			Applied { url: url.to_string(), serial: None, entries: HashSet::new(), db };
+		if let Some(db) = &applied.db {
+			applied.serial = sqlx::query_as::<_, (i64,)>(
+				"SELECT serial FROM subscription_serials WHERE url = ?",
+			)
+			.bind(url)
+			.fetch_optional(db.pool())
+			.await?
+			.map(|(serial,)| serial);
+			let rows = sqlx::query_as::<_, (String,)>(
+				"SELECT entry FROM subscription_entries WHERE url = ?",
+			)
+			.bind(url)
+			.fetch_all(db.pool())
+			.await?;
+			applied.entries.extend(rows.into_iter().map(|(entry,)| entry));
+		}
+		Ok(applied)
+	}
+
+	async fn save(
+		&mut self, serial: Option<i64>, added: &[String], removed: &[String],
+	) -> Result<(), sqlx::Error> {
+		// the lists already changed, so remember that even if the DB can't
+		self.serial = serial.or(self.serial);
+		self.entries.extend(added.iter().cloned());
+		for entry in removed {
+			self.entries.remove(entry);
+		}
+
+		let Some(db) = &self.db else {
+			return Ok(());
+		};
+		let mut tx = db.pool().begin().await?;
+		if let Some(serial) = serial {
+			sqlx::query(
+				"INSERT INTO subscription_serials (url, serial) VALUES (?, ?)
+				ON CONFLICT (url) DO UPDATE SET serial = excluded.serial",
+			)
+			.bind(&self.url)
+			.bind(serial)
+			.execute(&mut *tx)
+			.await?;
+		}
+		for entry in added {
+			sqlx::query("INSERT OR IGNORE INTO subscription_entries (url, entry) VALUES (?, ?)")
+				.bind(&self.url)
+				.bind(entry)
+				.execute(&mut *tx)
+				.await?;
+		}
+		for entry in removed {
+			sqlx::query("DELETE FROM subscription_entries WHERE url = ? AND entry = ?")
+				.bind(&self.url)
+				.bind(entry)
+				.execute(&mut *tx)
+				.await?;
+		}
+		tx.commit().await
+	}
+}
+
+/// Pull every subscription in the background, for as long as the process runs.
+pub fn subscribe(subscriptions: &[SubscriptionConfig], lists: Lists, db: Option<StateDb>) {
+	for config in subscriptions {
+		let keys = config.keys.iter().filter_map(|k| parse_key(k)).collect::<Vec<_>>().into();
+		tokio::spawn(pull(Arc::new(config.clone()), keys, lists.clone(), db.clone()));
+	}
+}
+
+async fn pull(
+	config: Arc<SubscriptionConfig>, keys: Arc<[VerifyingKey]>, lists: Lists, db: Option<StateDb>,
+) {
+	let Ok(url) = Url::parse(&config.url) else {
+		return;
+	};
+	let mut applied = match Applied::load(&config.url, db).await {
+		Ok(applied) => applied,
+		Err(e) => {
+			warn!("Could not load what {} applied before: {}", url, e);
+			return;
+		}
+	};
+	let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+	loop {
+		interval.tick().await;
+		match apply(&url, config.list, keys.clone(), &lists, &mut applied).await {
+			Ok((added, removed)) => {
+				info!("Applied {}: {} entries added, {} removed", url, added, removed)
+			}
+			Err(e) => warn!("Could not apply {}: {}", url, e),
+		}
+	}
+}
+
+/// Fetch and verify a bundle, then bring the list in line with it. Returns how many entries were
+/// added and removed.
+async fn apply(
+	url: &Url, kind: ListKind, keys: Arc<[VerifyingKey]>, lists: &Lists, applied: &mut Applied,
+) -> Result<(usize, usize), BundleError> {
+	let bundle = http::get(url, &[]).await?;
+	let signed = verify(bundle, &keys)?;
+	// an old bundle replayed from the URL would undo what the publisher fixed since
+	let (serial, listed) = serial(&signed)?;
+	if let Some(last) = applied.serial.filter(|&last| serial < last) {
+		return Err(BundleError::Stale(serial, last));
+	}
+	let entries: HashSet<String> = match kind {
+		// domain lists may also be Mastodon's domain block export
+		ListKind::Blocklist | ListKind::Tarpit => {
+			domains::parse_import(listed).into_iter().collect()
+		}
+		ListKind::Attachments | ListKind::Fingerprints => listed
+			.lines()
+			.map(str::trim)
+			.filter(|l| !l.is_empty() && !l.starts_with('#'))
+			.map(str::to_string)
+			.collect(),
+	};
+
+	let (mut added, mut removed) = (Vec::new(), Vec::new());
+	let mut result = Ok(());
+	for entry in &entries {
+		if applied.entries.contains(entry) {
+			// fingerprints are forgotten unless seen again
+			if kind == ListKind::Fingerprints {
+				lists.fingerprints.insert(entry);
+			}
+			continue;
+		}
+		match lists.add(kind, entry).await {
+			Ok(true) => added.push(entry.clone()),
+			Ok(false) => {}
+			Err(e) => {
+				result = Err(e);
+				break;
+			}
+		}
+	}
+	if result.is_ok() {
+		for entry in applied.entries.difference(&entries) {
+			if let Err(e) = lists.remove(kind, entry).await {
+				result = Err(e);
+				break;
+			}
+			removed.push(entry.clone());
+		}
+	}
+	// keep track of what changed even when not everything could be
+	applied.save(result.is_ok().then_some(serial), &added, &removed).await?;
+	result?;
+	Ok((added.len(), removed.len()))
+}
+
+/// The serial of a verified bundle, and what it lists after that.
+fn serial(signed: &str) -> Result<(i64, &str), BundleError> {
+	let (first, listed) = signed.split_once('\n').unwrap_or((signed, ""));
+	let serial = first.trim_end().strip_prefix(SERIAL_PREFIX).ok_or(BundleError::NoSerial)?;
+	let serial = serial.trim().parse().map_err(|_| BundleError::NoSerial)?;
+	Ok((serial, listed))
+}
+
+/// What a bundle lists, if its signature checks out: everything after a first line of
+/// `ed25519 <base64 signature of everything after that line>`.
+///
+/// Signatures are checked strictly: ones with a non-canonical S, or made for small-order
+/// points, don't verify, so no bundle has another valid signature than the publisher's.
+fn verify(bundle: String, keys: &[VerifyingKey]) -> Result<String, BundleError> {
+	let (first, signed) = bundle.split_once('\n').ok_or(BundleError::Unsigned)?;
+	let signature = first.trim_end().strip_prefix(SIGNATURE_PREFIX);
+	let signature = signature.ok_or(BundleError::Unsigned)?;
+	let signature = STANDARD.decode(signature.trim()).map_err(|_| BundleError::BadSignature)?;
+	let signature = Signature::from_slice(&signature).map_err(|_| BundleError::BadSignature)?;
+	if !keys.iter().any(|key| key.verify_strict(signed.as_bytes(), &signature).is_ok()) {
+		return Err(BundleError::BadSignature);
+	}
+	Ok(signed.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use ed25519_dalek::{Signer, SigningKey};
+
+	use super::*;
+
+	/// TEST 1 and TEST 2 of RFC 8032 section 7.1: public key, message and signature.
+	const VECTORS: &[(&str, &str, &str)] = &[
+		(
+			"d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+			"",
+			"e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+		),
+		(
+			"3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+			"72",
+			"92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+		),
+	];
+	/// Secret key of TEST 2.
+	const SECRET: &str = "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb";
+
+	fn decode(digits: &str) -> Vec<u8> {
+		hex::decode(digits).unwrap_or_default()
+	}
+
+	fn key(digits: &str) -> VerifyingKey {
+		let key = parse_key(&STANDARD.encode(decode(digits)));
+		key.unwrap_or_else(|| panic!("{} is a valid key", digits))
+	}
+
+	fn bundle(signature: &[u8], signed: &str) -> String {
+		format!("{}{}\n{}", SIGNATURE_PREFIX, STANDARD.encode(signature), signed)
+	}
+
+	fn signing_key() -> SigningKey {
+		SigningKey::from_bytes(&decode(SECRET).try_into().unwrap_or_default())
+	}
+
+	#[test]
+	fn verifies_rfc_8032_vectors() {
+		for (public, message, signature) in VECTORS {
+			let message = String::from_utf8(decode(message)).unwrap_or_default();
+			let signed = verify(bundle(&decode(signature), &message), &[key(public)]);
+			assert_eq!(signed.ok(), Some(message));
+		}
+		assert_eq!(signing_key().verifying_key(), key(VECTORS[1].0));
+	}
+
+	#[test]
+	fn applies_bundles_signed_by_any_key() {
+		let signed = "spam.example\nscam.example\n";
+		let signature = signing_key().sign(signed.as_bytes()).to_bytes();
+		let keys = [key(VECTORS[0].0), key(VECTORS[1].0)];
+		assert_eq!(verify(bundle(&signature, signed), &keys).ok().as_deref(), Some(signed));
+	}
+
+	#[test]
+	fn refuses_tampered_bundles() {
+		let keys = [key(VECTORS[1].0)];
+		let signed = "spam.example\n";
+		let signature = signing_key().sign(signed.as_bytes()).to_bytes();
+
+		let tampered = verify(bundle(&signature, "spam.example\nmastodon.social\n"), &keys);
+		assert!(matches!(tampered, Err(BundleError::BadSignature)));
+
+		let mut flipped = signature;
+		flipped[10] ^= 1;
+		assert!(matches!(verify(bundle(&flipped, signed), &keys), Err(BundleError::BadSignature)));
+
+		let other = verify(bundle(&signature, signed), &[key(VECTORS[0].0)]);
+		assert!(matches!(other, Err(BundleError::BadSignature)));
+
+		let short = verify(bundle(&signature[..63], signed), &keys);
+		assert!(matches!(short, Err(BundleError::BadSignature)));
+	}
+
+	#[test]
+	fn refuses_non_canonical_signatures() {
+		// the order of the base point, little-endian
+		let order = decode("edd3f55c1a631258d69cf7a2def9de1400000000000000000000000000000010");
+		let signed = "spam.example\n";
+		let mut signature = signing_key().sign(signed.as_bytes()).to_bytes();
+		// S + order is the same scalar, written differently
+		let mut carry = 0;
+		for (s, l) in signature[32..].iter_mut().zip(order) {
+			let sum = u16::from(*s) + u16::from(l) + carry;
+			*s = sum as u8;
+			carry = sum >> 8;
+		}
+		let non_canonical = verify(bundle(&signature, signed), &[key(VECTORS[1].0)]);
+		assert!(matches!(non_canonical, Err(BundleError::BadSignature)));
+	}
+
+	#[test]
+	fn reads_the_serial() {
+		let read = serial("serial 1760572800\nspam.example\n").unwrap_or_default();
+		assert_eq!(read, (1760572800, "spam.example\n"));
+		assert!(matches!(serial("spam.example\n"), Err(BundleError::NoSerial)));
+		assert!(matches!(serial("serial tomorrow\n"), Err(BundleError::NoSerial)));
+	}
+
+	#[test]
+	fn refuses_unsigned_bundles() {
+		let keys = [key(VECTORS[1].0)];
+		let unsigned = verify("spam.example\n".to_string(), &keys);
+		assert!(matches!(unsigned, Err(BundleError::Unsigned)));
+		let one_line = verify("ed25519 AAAA".to_string(), &keys);
+		assert!(matches!(one_line, Err(BundleError::Unsigned)));
+		let not_base64 = verify(format!("{}!!\nspam.example\n", SIGNATURE_PREFIX), &keys);
+		assert!(matches!(not_base64, Err(BundleError::BadSignature)));
+	}
+
+	#[test]
+	fn refuses_keys_off_the_curve_or_of_small_order() {
+		// the identity, and a point of order 8
+		let weak = [
+			"0100000000000000000000000000000000000000000000000000000000000000",
+			"c7176a703d4dd84fba3c0b760d10670f2a2053fa2c39ccc64ec7fd7792ac03fa",
+		];
+		for digits in weak {
+			assert!(parse_key(&STANDARD.encode(decode(digits))).is_none(), "{}", digits);
+		}
+		// y = 2 has no x on the curve
+		let off = "0200000000000000000000000000000000000000000000000000000000000000";
+		assert!(parse_key(&STANDARD.encode(decode(off))).is_none());
+		assert!(parse_key(&STANDARD.encode([0; 31])).is_none());
+		assert!(parse_key("not base64!").is_none());
+	}
+}
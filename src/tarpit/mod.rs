@@ -0,0 +1,52 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{io::AsyncWriteExt, net::TcpStream, sync::Semaphore, time::Instant};
+use tracing::*;
+
+pub const DEFAULT_CONNECTIONS: usize = 64;
+pub const DEFAULT_INTERVAL_SECS: u64 = 10;
+pub const DEFAULT_DURATION_SECS: u64 = 600;
+
+/// What is dripped to the sender, one byte at a time. Looks like the start of a valid response,
+/// so the sender keeps waiting for the rest.
+const DRIP: &[u8] = b"HTTP/1.1 200 OK\r\nX-Musubi-Tarpit: ";
+
+/// Keeps connections from confirmed spam sources open as long as possible, answering
+/// extremely slowly, so the sender's delivery workers are stuck instead of moving on to the
+/// next target.
+///
+/// At most `connections` are held at once, so the tarpit can't exhaust our own resources.
+#[derive(Debug, Clone)]
+pub struct Tarpit {
+	permits: Arc<Semaphore>,
+	interval: Duration,
+	duration: Duration,
+}
+
+impl Tarpit {
+	pub fn new(connections: usize, interval: Duration, duration: Duration) -> Self {
+		Tarpit { permits: Arc::new(Semaphore::new(connections)), interval, duration }
+	}
+
+	/// Hold the connection, or hand it back if the tarpit is full.
+	pub fn hold(&self, mut stream: TcpStream) -> Result<(), TcpStream> {
+		let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+			return Err(stream);
+		};
+		let (interval, duration) = (self.interval, self.duration);
+		tokio::spawn(async move {
+			let started = Instant::now();
+			let mut drip = DRIP.iter().chain(std::iter::repeat(&b'.'));
+			while started.elapsed() < duration {
+				#[allow(clippy::unwrap_used)] // the drip never runs dry
+				if stream.write_all(&[*drip.next().unwrap()]).await.is_err() {
+					break;
+				}
+				tokio::time::sleep(interval).await;
+			}
+			debug!("Released tarpitted connection after {}s", started.elapsed().as_secs());
+			drop(permit);
+		});
+		Ok(())
+	}
+}
@@ -0,0 +1,145 @@
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex, MutexGuard},
+	time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::*;
+use url::Url;
+
+use crate::http;
+
+const DEFAULT_INTERVAL_SECS: u64 = 300;
+const MIN_INTERVAL_SECS: u64 = 60;
+/// Different spam kept per upload, so a wave can't grow the batch without bound.
+const MAX_BATCH: usize = 10_000;
+/// Version of the upload format, for the endpoint to tell apart.
+const VERSION: u32 = 1;
+
+/// `[telemetry]` in the config file: an opt-in community endpoint to upload hashes of confirmed
+/// spam to, building a shared corpus.
+///
+/// Only salted hashes of the normalized content and the instances spam came from leave the
+/// deployment. Actors, note ids and the content itself don't.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+	/// HTTP(S) URL batches are POSTed to.
+	pub url: String,
+	/// Salt given out by the community, the same for every participant so their hashes match,
+	/// and unknown to anyone trying to guess content from them.
+	pub salt: String,
+	/// Seconds between uploads.
+	#[serde(default = "default_interval_secs")]
+	pub interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+	DEFAULT_INTERVAL_SECS
+}
+
+impl TelemetryConfig {
+	/// Check the URL and salt at startup rather than on the first upload.
+	pub fn validate(&self) -> Result<(), String> {
+		let url = Url::parse(&self.url).map_err(|e| format!("invalid telemetry url: {}", e))?;
+		if !http::supports(&url) {
+			return Err("telemetry url must be an http:// or https:// URL".to_string());
+		}
+		if self.salt.is_empty() {
+			return Err("telemetry salt must not be empty".to_string());
+		}
+		if self.interval_secs < MIN_INTERVAL_SECS {
+			return Err(format!("telemetry interval_secs must be at least {}", MIN_INTERVAL_SECS));
+		}
+		Ok(())
+	}
+
+	/// Port the endpoint is reached on.
+	pub fn url_port(&self) -> Option<u16> {
+		Url::parse(&self.url).ok()?.port_or_known_default()
+	}
+}
+
+/// Collects confirmed spam and uploads it in batches. Uploads that fail are dropped rather than
+/// retried, since the corpus doesn't need every last report.
+#[derive(Debug, Clone)]
+pub struct Telemetry {
+	salt: Arc<str>,
+	batch: Arc<Mutex<HashMap<(String, String), u64>>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Upload {
+	version: u32,
+	reports: Vec<Report>,
+}
+
+/// Copies of a piece of spam seen from an instance since the last upload.
+#[derive(Debug, Serialize)]
+struct Report {
+	hash: String,
+	origin: String,
+	count: u64,
+}
+
+impl Telemetry {
+	pub fn new(config: TelemetryConfig) -> Self {
+		let telemetry = Telemetry { salt: config.salt.as_str().into(), batch: Arc::default() };
+		tokio::spawn(telemetry.clone().upload(config));
+		telemetry
+	}
+
+	/// Count spam with content `fingerprint` confirmed from `origin`.
+	pub fn spam(&self, fingerprint: &str, origin: &str) {
+		let mut hasher = Sha256::new();
+		hasher.update(self.salt.as_bytes());
+		hasher.update(b"\n");
+		hasher.update(fingerprint.as_bytes());
+		let key = (format!("{:x}", hasher.finalize()), origin.to_string());
+
+		let mut batch = self.batch();
+		let len = batch.len();
+		match batch.get_mut(&key) {
+			Some(count) => *count += 1,
+			None if len < MAX_BATCH => {
+				batch.insert(key, 1);
+			}
+			None => {}
+		}
+	}
+
+	async fn upload(self, config: TelemetryConfig) {
+		let Ok(url) = Url::parse(&config.url) else {
+			return;
+		};
+		let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+		// the first tick is immediate, with nothing to upload yet
+		interval.tick().await;
+		loop {
+			interval.tick().await;
+			let batch = std::mem::take(&mut *self.batch());
+			if batch.is_empty() {
+				continue;
+			}
+			let reports = batch
+				.into_iter()
+				.map(|((hash, origin), count)| Report { hash, origin, count })
+				.collect::<Vec<_>>();
+			let len = reports.len();
+			let Ok(body) = sonic_rs::to_string(&Upload { version: VERSION, reports }) else {
+				continue;
+			};
+			match http::post_json(&url, &[], &body).await {
+				Ok(_) => debug!("Uploaded {} spam hashes to {}", len, url),
+				Err(e) => warn!("Could not upload {} spam hashes to {}: {}", len, url, e),
+			}
+		}
+	}
+
+	fn batch(&self) -> MutexGuard<'_, HashMap<(String, String), u64>> {
+		// counts are still good after a panic elsewhere
+		self.batch.lock().unwrap_or_else(|e| e.into_inner())
+	}
+}
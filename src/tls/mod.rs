@@ -0,0 +1,162 @@
+use std::{io, net::IpAddr, path::Path, sync::Arc, time::Duration};
+
+use once_cell::sync::OnceCell;
+use rustls::{
+	crypto::{ring, CryptoProvider},
+	pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+	server::WebPkiClientVerifier,
+	ClientConfig, RootCertStore, ServerConfig,
+};
+use tokio::net::TcpStream;
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tokio_rustls::TlsConnector;
+
+mod stream;
+
+pub use stream::Stream;
+
+static CLIENT: OnceCell<Arc<ClientConfig>> = OnceCell::new();
+
+/// Longest a client may take to complete the TLS handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Protocols spoken over TLS here, offered by ALPN: deliveries over HTTP/1.1, and gRPC over
+/// HTTP/2.
+const HTTP_1_1: &[u8] = b"http/1.1";
+const H2: &[u8] = b"h2";
+
+fn provider() -> Arc<CryptoProvider> {
+	Arc::new(ring::default_provider())
+}
+
+/// The Mozilla root store bundled with the binary.
+fn bundled_roots() -> RootCertStore {
+	RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() }
+}
+
+/// TLS settings for connections spam-musubi opens itself, verifying servers against the
+/// Mozilla root store bundled with the binary.
+pub fn client_config() -> Arc<ClientConfig> {
+	CLIENT
+		.get_or_init(|| {
+			let config = ClientConfig::builder_with_provider(provider())
+				.with_safe_default_protocol_versions()
+				.expect("ring supports the default TLS versions")
+				.with_root_certificates(bundled_roots())
+				.with_no_client_auth();
+			Arc::new(config)
+		})
+		.clone()
+}
+
+/// Start TLS on `stream`, checking the server's certificate is for `host` against the bundled
+/// roots.
+pub async fn connect(host: &str, stream: TcpStream) -> io::Result<Stream> {
+	let tls = TlsConnector::from(client_config()).connect(server_name(host)?, stream).await?;
+	Ok(Stream::Tls(Box::new(tls)))
+}
+
+/// Name a server's certificate must be for: a hostname, or an IP address as in URLs.
+pub fn server_name(host: &str) -> io::Result<ServerName<'static>> {
+	let host = host.trim_start_matches('[').trim_end_matches(']').trim_end_matches('.');
+	let name =
+		ServerName::try_from(host).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+	Ok(name?.to_owned())
+}
+
+/// TLS for connections to Postgres.
+pub fn postgres() -> MakeRustlsConnect {
+	MakeRustlsConnect::new(ClientConfig::clone(&client_config()))
+}
+
+/// Whether a host is this machine, where traffic doesn't cross the network.
+pub fn is_local(host: &str) -> bool {
+	let host = host.trim_start_matches('[').trim_end_matches(']');
+	host.eq_ignore_ascii_case("localhost")
+		|| host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// The certificates in a PEM file, in order.
+fn certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+	let certs = CertificateDer::pem_file_iter(path)
+		.and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+		.map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+	match certs.is_empty() {
+		true => Err(format!("{} has no certificates", path.display())),
+		false => Ok(certs),
+	}
+}
+
+/// The private key in a PEM file.
+fn key(path: &Path) -> Result<PrivateKeyDer<'static>, String> {
+	PrivateKeyDer::from_pem_file(path)
+		.map_err(|e| format!("Could not read a key from {}: {}", path.display(), e))
+}
+
+/// The certificates in a PEM file, as roots to verify the other side by.
+fn roots(path: &Path) -> Result<RootCertStore, String> {
+	let mut roots = RootCertStore::empty();
+	for cert in certs(path)? {
+		roots.add(cert).map_err(|e| format!("{}: {}", path.display(), e))?;
+	}
+	Ok(roots)
+}
+
+/// TLS settings for the listener, serving `cert` with `key`. Clients must present a certificate
+/// signed by one in `client_ca` if given.
+pub fn server_config(
+	cert: &Path, key_file: &Path, client_ca: Option<&Path>,
+) -> Result<Arc<ServerConfig>, String> {
+	server(cert, key_file, client_ca, HTTP_1_1)
+}
+
+/// TLS settings for the gRPC admin API, serving `cert` with `key`.
+pub fn grpc_config(cert: &Path, key_file: &Path) -> Result<Arc<ServerConfig>, String> {
+	server(cert, key_file, None, H2)
+}
+
+fn server(
+	cert: &Path, key_file: &Path, client_ca: Option<&Path>, protocol: &[u8],
+) -> Result<Arc<ServerConfig>, String> {
+	let builder = ServerConfig::builder_with_provider(provider())
+		.with_safe_default_protocol_versions()
+		.map_err(|e| e.to_string())?;
+	let builder = match client_ca {
+		Some(ca) => {
+			let verifier =
+				WebPkiClientVerifier::builder_with_provider(Arc::new(roots(ca)?), provider())
+					.build()
+					.map_err(|e| format!("{}: {}", ca.display(), e))?;
+			builder.with_client_cert_verifier(verifier)
+		}
+		None => builder.with_no_client_auth(),
+	};
+	let mut config = builder
+		.with_single_cert(certs(cert)?, key(key_file)?)
+		.map_err(|e| format!("{}: {}", cert.display(), e))?;
+	config.alpn_protocols = vec![protocol.to_vec()];
+	Ok(Arc::new(config))
+}
+
+/// TLS settings for connections to AP servers, verifying them against `ca` if given and the
+/// bundled roots otherwise. `identity` is a certificate and key to present to them.
+pub fn upstream_config(
+	ca: Option<&Path>, identity: Option<(&Path, &Path)>,
+) -> Result<Arc<ClientConfig>, String> {
+	let roots = match ca {
+		Some(ca) => roots(ca)?,
+		None => bundled_roots(),
+	};
+	let builder = ClientConfig::builder_with_provider(provider())
+		.with_safe_default_protocol_versions()
+		.map_err(|e| e.to_string())?
+		.with_root_certificates(roots);
+	let mut config = match identity {
+		Some((cert, key_file)) => builder
+			.with_client_auth_cert(certs(cert)?, key(key_file)?)
+			.map_err(|e| format!("{}: {}", cert.display(), e))?,
+		None => builder.with_no_client_auth(),
+	};
+	config.alpn_protocols = vec![HTTP_1_1.to_vec()];
+	Ok(Arc::new(config))
+}
@@ -0,0 +1,51 @@
+use std::{
+	io,
+	pin::Pin,
+	task::{Context, Poll},
+};
+
+use tokio::{
+	io::{AsyncRead, AsyncWrite, ReadBuf},
+	net::TcpStream,
+};
+use tokio_rustls::client::TlsStream;
+
+/// A connection spam-musubi opened, to an AP server or anything else it talks to.
+pub enum Stream {
+	Plain(TcpStream),
+	Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Stream {
+	fn poll_read(
+		self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf,
+	) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+			Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+		}
+	}
+}
+
+impl AsyncWrite for Stream {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+		match self.get_mut() {
+			Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+			Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+		}
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+			Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+		}
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+		match self.get_mut() {
+			Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+			Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+		}
+	}
+}
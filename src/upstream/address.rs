@@ -0,0 +1,138 @@
+use std::{
+	fmt, io,
+	net::{IpAddr, SocketAddr, SocketAddrV4},
+	str::FromStr,
+	sync::Arc,
+	time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::{
+	net::{lookup_host, TcpStream},
+	task::JoinSet,
+	time::sleep,
+};
+
+/// How long to give a connection attempt before starting the next one alongside it, RFC 8305's
+/// Connection Attempt Delay.
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Where an AP server listens: an IP address, or a hostname resolved on every connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Address {
+	Ip(SocketAddr),
+	Name(Arc<str>, u16),
+}
+
+impl Address {
+	pub fn new(host: &str, port: u16) -> Result<Self, String> {
+		if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+			return Ok(Address::Ip(SocketAddr::new(ip, port)));
+		}
+		let valid = |label: &str| {
+			!label.is_empty()
+				&& label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+				&& !label.starts_with('-')
+		};
+		if host.len() > 253 || !host.trim_end_matches('.').split('.').all(valid) {
+			return Err(format!("{} is neither an IP address nor a hostname", host));
+		}
+		Ok(Address::Name(host.to_ascii_lowercase().into(), port))
+	}
+
+	pub fn port(&self) -> u16 {
+		match self {
+			Address::Ip(address) => address.port(),
+			Address::Name(_, port) => *port,
+		}
+	}
+
+	/// Connect, trying every address a hostname resolves to the Happy Eyeballs way (RFC 8305):
+	/// IPv6 and IPv4 ones take turns, each started [`ATTEMPT_DELAY`] after the last or once it
+	/// failed, and the first to connect wins.
+	pub async fn connect(&self) -> io::Result<TcpStream> {
+		match self {
+			Address::Ip(address) => TcpStream::connect(address).await,
+			Address::Name(host, port) => {
+				let addresses = interleave(lookup_host((&**host, *port)).await?);
+				race(addresses).await
+			}
+		}
+	}
+}
+
+/// IPv6 and IPv4 addresses taking turns, starting with IPv6, and in the resolver's order
+/// otherwise.
+fn interleave(addresses: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+	let (v6, v4): (Vec<_>, Vec<_>) = addresses.partition(|address| address.is_ipv6());
+	let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+	let mut interleaved = Vec::new();
+	loop {
+		match (v6.next(), v4.next()) {
+			(None, None) => return interleaved,
+			(a, b) => interleaved.extend(a.into_iter().chain(b)),
+		}
+	}
+}
+
+async fn race(addresses: Vec<SocketAddr>) -> io::Result<TcpStream> {
+	let mut addresses = addresses.into_iter();
+	let mut attempts = JoinSet::new();
+	let mut last_error = None;
+	loop {
+		match addresses.next() {
+			Some(address) => {
+				attempts.spawn(TcpStream::connect(address));
+			}
+			None if attempts.is_empty() => {
+				let none = || io::Error::new(io::ErrorKind::NotFound, "no addresses resolved");
+				return Err(last_error.unwrap_or_else(none));
+			}
+			None => {}
+		}
+		// attempts still running are dropped along with the set once one connects
+		tokio::select! {
+			Some(attempt) = attempts.join_next() => match attempt {
+				Ok(Ok(stream)) => return Ok(stream),
+				Ok(Err(e)) => last_error = Some(e),
+				Err(e) => last_error = Some(io::Error::other(e)),
+			},
+			_ = sleep(ATTEMPT_DELAY), if addresses.len() > 0 => {}
+		}
+	}
+}
+
+impl From<SocketAddrV4> for Address {
+	fn from(address: SocketAddrV4) -> Self {
+		Address::Ip(address.into())
+	}
+}
+
+impl FromStr for Address {
+	type Err = String;
+
+	/// `host:port`, with IPv6 addresses in brackets.
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (host, port) = s.rsplit_once(':').ok_or_else(|| format!("{} has no port", s))?;
+		let port = port.parse().map_err(|_| format!("invalid port in {}", s))?;
+		Address::new(host, port)
+	}
+}
+
+impl TryFrom<String> for Address {
+	type Error = String;
+
+	fn try_from(s: String) -> Result<Self, Self::Error> {
+		s.parse()
+	}
+}
+
+impl fmt::Display for Address {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Address::Ip(address) => address.fmt(f),
+			Address::Name(host, port) => write!(f, "{}:{}", host, port),
+		}
+	}
+}
@@ -0,0 +1,163 @@
+use std::{
+	fmt,
+	net::SocketAddrV4,
+	sync::{
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use tracing::*;
+
+use super::Address;
+
+/// How connections are spread across the addresses of an AP server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Balance {
+	/// Each address in turn.
+	#[default]
+	RoundRobin,
+	/// The address with the fewest connections open through spam-musubi.
+	LeastConnections,
+}
+
+/// The addresses an AP server listens on, like one per web worker process.
+#[derive(Debug, Clone)]
+pub struct Backends(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+	addresses: Vec<Address>,
+	/// Connections open to each address.
+	active: Vec<Arc<AtomicUsize>>,
+	balance: Balance,
+	next: AtomicUsize,
+	standby: Option<Standby>,
+	failed_over: AtomicBool,
+}
+
+/// Where to send traffic while every address of an AP server is down, like a maintenance page
+/// server or a secondary node.
+#[derive(Debug)]
+pub struct Standby {
+	pub address: Address,
+	/// How long the AP server must have been down for.
+	pub after: Duration,
+	active: Arc<AtomicUsize>,
+}
+
+impl Standby {
+	pub fn new(address: Address, after: Duration) -> Self {
+		Standby { address, after, active: Arc::default() }
+	}
+}
+
+/// An address picked for a connection, counted as one of its connections until dropped.
+#[derive(Debug)]
+pub struct Picked {
+	pub address: Address,
+	active: Arc<AtomicUsize>,
+}
+
+impl Backends {
+	/// `addresses` must not be empty.
+	pub fn new(addresses: Vec<Address>, balance: Balance, standby: Option<Standby>) -> Self {
+		assert!(!addresses.is_empty(), "an AP server needs an address");
+		let active = addresses.iter().map(|_| Arc::default()).collect();
+		Backends(Arc::new(Inner {
+			addresses,
+			active,
+			balance,
+			next: AtomicUsize::new(0),
+			standby,
+			failed_over: AtomicBool::new(false),
+		}))
+	}
+
+	pub fn standby(&self) -> Option<&Standby> {
+		self.0.standby.as_ref()
+	}
+
+	pub fn addresses(&self) -> &[Address] {
+		&self.0.addresses
+	}
+
+	/// The first address, standing for the AP server in cache keys.
+	pub fn primary(&self) -> &Address {
+		&self.0.addresses[0]
+	}
+
+	/// Pick an address for a new connection, passing over those that are down unless all are.
+	pub fn pick(&self, is_up: impl Fn(&Address) -> bool) -> Picked {
+		let inner = &self.0;
+		let count = inner.addresses.len();
+		let up: Vec<usize> = (0..count).filter(|&i| is_up(&inner.addresses[i])).collect();
+		let candidates = if up.is_empty() { (0..count).collect() } else { up };
+		let i = match inner.balance {
+			Balance::RoundRobin => {
+				candidates[inner.next.fetch_add(1, Ordering::Relaxed) % candidates.len()]
+			}
+			Balance::LeastConnections => {
+				let active = |&i: &usize| inner.active[i].load(Ordering::Relaxed);
+				candidates.iter().copied().min_by_key(active).unwrap_or_default()
+			}
+		};
+		inner.active[i].fetch_add(1, Ordering::Relaxed);
+		Picked { address: inner.addresses[i].clone(), active: inner.active[i].clone() }
+	}
+
+	/// Send connections to the standby, or back to the AP server, logging when that changes.
+	pub fn fail_over(&self, to_standby: bool) {
+		let Some(standby) = &self.0.standby else {
+			return;
+		};
+		if self.0.failed_over.swap(to_standby, Ordering::Relaxed) == to_standby {
+			return;
+		}
+		match to_standby {
+			true => warn!("Failing over from {} to standby {}", self, standby.address),
+			false => info!("Failing back from standby {} to {}", standby.address, self),
+		}
+	}
+
+	/// The standby, for a new connection while failed over.
+	pub fn pick_standby(&self) -> Option<Picked> {
+		let standby = self.0.standby.as_ref()?;
+		standby.active.fetch_add(1, Ordering::Relaxed);
+		Some(Picked { address: standby.address.clone(), active: standby.active.clone() })
+	}
+}
+
+impl Drop for Picked {
+	fn drop(&mut self) {
+		self.active.fetch_sub(1, Ordering::Relaxed);
+	}
+}
+
+impl From<Address> for Backends {
+	fn from(address: Address) -> Self {
+		Backends::new(vec![address], Balance::default(), None)
+	}
+}
+
+impl From<SocketAddrV4> for Backends {
+	fn from(address: SocketAddrV4) -> Self {
+		Address::from(address).into()
+	}
+}
+
+impl fmt::Display for Backends {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (i, address) in self.addresses().iter().enumerate() {
+			if i > 0 {
+				f.write_str(", ")?;
+			}
+			address.fmt(f)?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,124 @@
+use std::{
+	sync::{
+		atomic::{AtomicBool, AtomicU32, Ordering},
+		Arc, Mutex,
+	},
+	time::Duration,
+};
+
+use tokio::{
+	io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+	time::{timeout, Instant},
+};
+use tracing::*;
+
+use super::Address;
+
+/// Checks in a row an AP server must fail to count as down. One success brings it back.
+const FAILURES_TO_DOWN: u32 = 2;
+/// Longest status line read from a health check response.
+const MAX_STATUS_LEN: u64 = 256;
+
+/// Every AP server checked on, to report on.
+static REGISTRY: Mutex<Vec<Arc<Health>>> = Mutex::new(Vec::new());
+
+/// How the AP servers are checked on.
+#[derive(Debug, Clone)]
+pub struct HealthCheck {
+	pub interval: Duration,
+	/// Path to GET, expecting a 2xx or 3xx status. Connecting is enough without it.
+	pub path: Option<String>,
+}
+
+/// Whether an AP server answered its latest checks.
+#[derive(Debug)]
+pub struct Health {
+	pub address: Address,
+	up: AtomicBool,
+	failures: AtomicU32,
+	down_since: Mutex<Option<Instant>>,
+}
+
+impl Health {
+	pub fn is_up(&self) -> bool {
+		self.up.load(Ordering::Relaxed)
+	}
+
+	/// How long the AP server has been down for, if it is.
+	pub fn down_for(&self) -> Option<Duration> {
+		#[allow(clippy::unwrap_used)]
+		self.down_since.lock().unwrap().map(|since| since.elapsed())
+	}
+
+	/// Check on the AP server at `address`, reached as `host`, for as long as the process runs.
+	pub fn watch(address: Address, host: String, check: HealthCheck) -> Arc<Self> {
+		let health = Arc::new(Health {
+			address,
+			up: AtomicBool::new(true),
+			failures: AtomicU32::new(0),
+			down_since: Mutex::new(None),
+		});
+		#[allow(clippy::unwrap_used)]
+		REGISTRY.lock().unwrap().push(health.clone());
+		let watched = health.clone();
+		tokio::spawn(async move {
+			let mut interval = tokio::time::interval(check.interval);
+			loop {
+				interval.tick().await;
+				// a check taking longer than the interval failed
+				let probe = probe(&watched.address, &host, check.path.as_deref());
+				let ok = matches!(timeout(check.interval, probe).await, Ok(true));
+				watched.record(ok);
+			}
+		});
+		health
+	}
+
+	fn record(&self, ok: bool) {
+		if ok {
+			self.failures.store(0, Ordering::Relaxed);
+			if !self.up.swap(true, Ordering::Relaxed) {
+				#[allow(clippy::unwrap_used)]
+				self.down_since.lock().unwrap().take();
+				info!("AP server at {} is back up", self.address);
+			}
+			return;
+		}
+		let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+		if failures >= FAILURES_TO_DOWN && self.up.swap(false, Ordering::Relaxed) {
+			#[allow(clippy::unwrap_used)]
+			self.down_since.lock().unwrap().replace(Instant::now());
+			error!("AP server at {} is down", self.address);
+		}
+	}
+}
+
+async fn probe(address: &Address, host: &str, path: Option<&str>) -> bool {
+	let Ok(mut stream) = address.open().await else {
+		return false;
+	};
+	let Some(path) = path else {
+		return true;
+	};
+	let request = format!(
+		"GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: spam-musubi\r\nConnection: close\r\n\r\n",
+		path, host
+	);
+	if stream.write_all(request.as_bytes()).await.is_err() {
+		return false;
+	}
+	let mut status = String::new();
+	if BufReader::new(stream).take(MAX_STATUS_LEN).read_line(&mut status).await.is_err() {
+		return false;
+	}
+	// HTTP/1.1 200 OK
+	let code = status.split(' ').nth(1).and_then(|code| code.parse::<u16>().ok());
+	code.is_some_and(|code| (200..400).contains(&code))
+}
+
+/// Every AP server checked on, and whether it's up.
+pub fn reports() -> Vec<(String, bool)> {
+	#[allow(clippy::unwrap_used)]
+	let registry = REGISTRY.lock().unwrap();
+	registry.iter().map(|health| (health.address.to_string(), health.is_up())).collect()
+}
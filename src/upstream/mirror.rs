@@ -0,0 +1,56 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	sync::Semaphore,
+	time::timeout,
+};
+use tracing::*;
+
+use super::Address;
+
+/// Longest a mirrored request may take, from connecting to the answer.
+const TIMEOUT_SECS: u64 = 10;
+/// Bytes of the answer read before hanging up. The status line is all that matters.
+const ANSWER_LEN: usize = 1024;
+
+/// Sends copies of admitted deliveries to a second server, like a staging AP server or an
+/// analysis collector. Copies are fire-and-forget: whatever happens to them never touches the
+/// original request.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+	address: Address,
+	/// Copies in flight. Past that, deliveries aren't mirrored until some are done.
+	inflight: Arc<Semaphore>,
+}
+
+impl Mirror {
+	pub fn new(address: Address, max_inflight: usize) -> Self {
+		Mirror { address, inflight: Arc::new(Semaphore::new(max_inflight)) }
+	}
+
+	/// Send a copy of a request, unless too many are in flight already.
+	pub fn send(&self, header: &[u8], body: &[u8]) {
+		let Ok(permit) = self.inflight.clone().try_acquire_owned() else {
+			trace!("Too many requests mirrored to {}, skipping one", self.address);
+			return;
+		};
+		let address = self.address.clone();
+		let request = [header, body].concat();
+		tokio::spawn(async move {
+			let mirror = async {
+				let mut stream = address.connect().await?;
+				stream.write_all(&request).await?;
+				// servers may drop requests whose sender hangs up before they answer
+				let mut answer = [0; ANSWER_LEN];
+				stream.read(&mut answer).await
+			};
+			match timeout(Duration::from_secs(TIMEOUT_SECS), mirror).await {
+				Ok(Ok(_)) => {}
+				Ok(Err(e)) => debug!("Could not mirror request to {}: {}", address, e),
+				Err(_) => debug!("Timed out mirroring request to {}", address),
+			}
+			drop(permit);
+		});
+	}
+}
@@ -0,0 +1,319 @@
+use std::{
+	collections::{HashMap, HashSet},
+	iter,
+	sync::Arc,
+	time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+	cache::CacheConfig,
+	domains,
+	filter::ThresholdsPatch,
+	query::{ApiBackend, Backend, Query, QueryInitError, QueryOpMode},
+};
+
+mod address;
+mod balance;
+pub mod health;
+mod mirror;
+pub mod retry;
+mod stream;
+
+pub use address::Address;
+pub use balance::{Backends, Balance, Picked, Standby};
+use health::{Health, HealthCheck};
+pub use mirror::Mirror;
+pub use stream::use_tls;
+
+const DEFAULT_DB_PORT: u16 = 5432;
+/// Seconds an AP server must have been down for to fail over to its standby.
+pub const DEFAULT_FAILOVER_AFTER_SECS: u64 = 30;
+
+/// An AP server to forward to, and the DB to judge its deliveries by.
+#[derive(Clone)]
+pub struct Upstream {
+	pub backends: Backends,
+	pub query: Arc<dyn Backend>,
+	/// Host the server goes by, if it is routed to by Host header.
+	pub host: Option<String>,
+	pub tenant: Arc<Tenant>,
+}
+
+/// Filter settings of an upstream's own, for protecting servers with different needs.
+#[derive(Debug, Default)]
+pub struct Tenant {
+	/// Thresholds to change from the global ones, as they are at the time.
+	pub thresholds: ThresholdsPatch,
+	/// Instances blocked for this server only, besides those on the blocklist.
+	blocklist: HashSet<String>,
+}
+
+impl Tenant {
+	/// Whether `host` or any domain it is under is blocked for this server.
+	pub fn blocks(&self, host: &str) -> bool {
+		if self.blocklist.is_empty() {
+			return false;
+		}
+		let mut host = host.trim_end_matches('.');
+		loop {
+			if self.blocklist.contains(host) {
+				return true;
+			}
+			match host.split_once('.') {
+				Some((_, parent)) => host = parent,
+				None => return false,
+			}
+		}
+	}
+}
+
+/// Upstream AP server for each Host, for protecting several servers with one spam-musubi.
+///
+/// Requests for hosts without an upstream of their own go to the default one.
+#[derive(Clone)]
+pub struct Routes {
+	default: Upstream,
+	by_host: Arc<HashMap<String, Upstream>>,
+	/// Hosts requests may be for besides the routed ones. Any host if empty.
+	expected: Arc<HashSet<String>>,
+	/// Health of each AP server, if checked on.
+	health: Arc<HashMap<Address, Arc<Health>>>,
+}
+
+/// An upstream as written in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+	/// Host header value routed to this upstream.
+	pub host: String,
+	/// `ip:port` or `hostname:port`, or a list of them to spread connections across.
+	pub address: AddressConfig,
+	#[serde(default)]
+	pub balance: Balance,
+	/// Where to send traffic once every address has been down for `failover_after_secs`,
+	/// until one is back up. Needs health checks.
+	pub standby: Option<Address>,
+	#[serde(default = "default_failover_after_secs")]
+	pub failover_after_secs: u64,
+	#[serde(default = "default_server_type")]
+	pub server_type: QueryOpMode,
+	/// The server's DB, or else `api`.
+	pub db: Option<DbConfig>,
+	/// The server's API, to look up actors and instances through instead of its DB.
+	pub api: Option<ApiConfig>,
+	/// Thresholds for this server's deliveries, changed from the global ones.
+	#[serde(default)]
+	pub thresholds: ThresholdsPatch,
+	/// Instances this server gets nothing from, besides those on the blocklist.
+	#[serde(default)]
+	pub blocklist: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum AddressConfig {
+	One(Address),
+	Many(Vec<Address>),
+}
+
+impl AddressConfig {
+	pub fn addresses(&self) -> &[Address] {
+		match self {
+			AddressConfig::One(address) => std::slice::from_ref(address),
+			AddressConfig::Many(addresses) => addresses,
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DbConfig {
+	pub host: String,
+	#[serde(default = "default_db_port")]
+	pub port: u16,
+	pub user: String,
+	pub password: String,
+	pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiConfig {
+	/// http:// or https:// URL of the server.
+	pub url: String,
+	pub token: Option<String>,
+}
+
+fn default_server_type() -> QueryOpMode {
+	QueryOpMode::Misskey
+}
+
+fn default_failover_after_secs() -> u64 {
+	DEFAULT_FAILOVER_AFTER_SECS
+}
+
+fn default_db_port() -> u16 {
+	DEFAULT_DB_PORT
+}
+
+impl Routes {
+	/// Connect to the DB, or the API, of every configured upstream.
+	pub async fn init(
+		default: Upstream, configs: &[UpstreamConfig], caches: &CacheConfig,
+	) -> Result<Self, QueryInitError> {
+		let mut by_host = HashMap::new();
+		for config in configs {
+			let query: Arc<dyn Backend> = match (&config.db, &config.api) {
+				(Some(db), None) => Arc::new(
+					Query::init(
+						&db.host,
+						db.port,
+						&db.user,
+						&db.password,
+						&db.name,
+						config.server_type.clone(),
+					)
+					.await?,
+				),
+				(None, Some(api)) => Arc::new(
+					ApiBackend::new(config.server_type.clone(), &api.url, api.token.clone())?
+						.cache_budget(caches.lookups_mb),
+				),
+				_ => {
+					return Err(QueryInitError::Api(format!(
+						"upstream {} needs either db or api",
+						config.host
+					)))
+				}
+			};
+			let host = normalize(&config.host);
+			let addresses = config.address.addresses().to_vec();
+			if addresses.is_empty() {
+				return Err(QueryInitError::Api(format!("upstream {} needs an address", host)));
+			}
+			let after = Duration::from_secs(config.failover_after_secs);
+			let standby = config.standby.clone().map(|address| Standby::new(address, after));
+			let backends = Backends::new(addresses, config.balance, standby);
+			let tenant = Tenant {
+				thresholds: config.thresholds.clone(),
+				blocklist: config.blocklist.iter().filter_map(|d| domains::normalize(d)).collect(),
+			};
+			let upstream =
+				Upstream { backends, query, host: Some(host.clone()), tenant: Arc::new(tenant) };
+			by_host.insert(host, upstream);
+		}
+		Ok(Routes {
+			default,
+			by_host: Arc::new(by_host),
+			expected: Arc::default(),
+			health: Arc::default(),
+		})
+	}
+
+	/// Check on every AP server, so deliveries for one that's down are answered with 503.
+	pub fn check_health(mut self, check: HealthCheck) -> Self {
+		let mut health = HashMap::new();
+		for upstream in iter::once(&self.default).chain(self.by_host.values()) {
+			for address in upstream.backends.addresses() {
+				if health.contains_key(address) {
+					continue;
+				}
+				let host = upstream
+					.host
+					.clone()
+					.or_else(|| crate::HOST.get().cloned())
+					.unwrap_or_else(|| address.to_string());
+				let watched = Health::watch(address.clone(), host, check.clone());
+				health.insert(address.clone(), watched);
+			}
+			if let Some(standby) = upstream.backends.standby() {
+				if !health.contains_key(&standby.address) {
+					let host = standby.address.to_string();
+					let watched = Health::watch(standby.address.clone(), host, check.clone());
+					health.insert(standby.address.clone(), watched);
+				}
+			}
+		}
+		self.health = Arc::new(health);
+		self
+	}
+
+	/// Only accept requests for these hosts, and the routed ones.
+	pub fn expect_hosts(mut self, hosts: &[String]) -> Self {
+		self.expected = Arc::new(hosts.iter().map(|h| normalize(h)).collect());
+		self
+	}
+
+	/// Whether requests need their Host header looked at.
+	pub fn by_host(&self) -> bool {
+		!self.by_host.is_empty() || !self.expected.is_empty()
+	}
+
+	/// Whether requests for a Host header value may go through.
+	pub fn accepts(&self, host: Option<&str>) -> bool {
+		if self.expected.is_empty() {
+			return true;
+		}
+		let Some(host) = host.map(normalize) else {
+			return false;
+		};
+		self.expected.contains(&host) || self.by_host.contains_key(&host)
+	}
+
+	/// Upstream for a Host header value.
+	pub fn route(&self, host: Option<&str>) -> &Upstream {
+		host.and_then(|h| self.by_host.get(&normalize(h))).unwrap_or(&self.default)
+	}
+
+	/// Whether any address of an AP server passed its latest health checks, or isn't checked on,
+	/// or else its standby took over.
+	pub fn is_up(&self, upstream: &Upstream) -> bool {
+		upstream.backends.addresses().iter().any(|address| self.address_up(address))
+			|| self.failed_over(upstream)
+	}
+
+	fn address_up(&self, address: &Address) -> bool {
+		self.health.get(address).is_none_or(|health| health.is_up())
+	}
+
+	/// Whether every address of an AP server has been down for long enough to send its traffic
+	/// to the standby, and the standby isn't down too.
+	fn failed_over(&self, upstream: &Upstream) -> bool {
+		let Some(standby) = upstream.backends.standby() else {
+			return false;
+		};
+		let down_for = upstream
+			.backends
+			.addresses()
+			.iter()
+			.map(|address| self.health.get(address).and_then(|health| health.down_for()))
+			.collect::<Option<Vec<_>>>()
+			.and_then(|down_for| down_for.into_iter().min());
+		let failed_over = down_for.is_some_and(|down_for| down_for >= standby.after)
+			&& self.address_up(&standby.address);
+		upstream.backends.fail_over(failed_over);
+		failed_over
+	}
+
+	/// Address of an AP server to open a connection to, among those that are up, or its
+	/// standby's while failed over.
+	pub fn pick(&self, upstream: &Upstream) -> Picked {
+		if self.failed_over(upstream) {
+			if let Some(standby) = upstream.backends.pick_standby() {
+				return standby;
+			}
+		}
+		upstream.backends.pick(|address| self.address_up(address))
+	}
+}
+
+/// Host without port, lowercased.
+fn normalize(host: &str) -> String {
+	let host = match host.rsplit_once(':') {
+		Some((h, port)) if port.bytes().all(|b| b.is_ascii_digit()) => h,
+		_ => host,
+	};
+	host.to_ascii_lowercase()
+}
@@ -0,0 +1,234 @@
+use std::{
+	collections::VecDeque,
+	io,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::{Duration, SystemTime},
+};
+
+use tokio::{
+	fs,
+	io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+	sync::Notify,
+	time::{sleep, timeout},
+};
+use tracing::*;
+
+use super::Routes;
+use crate::filter::headers::Headers;
+
+pub const DEFAULT_SIZE_MB: u64 = 64;
+pub const DEFAULT_MAX_AGE_SECS: u64 = 60 * 60;
+/// Answer to a delivery queued for later, which the sender takes as delivered.
+pub const ACCEPTED: &[u8] =
+	b"HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const FIRST_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_SECS: u64 = 5 * 60;
+/// How long the AP server gets to answer a delivery sent again.
+const ANSWER_TIMEOUT_SECS: u64 = 30;
+const MAX_STATUS_LEN: u64 = 1024;
+const EXTENSION: &str = "req";
+
+/// Deliveries admitted while the AP server refused connections, kept on disk and sent again in
+/// order, backing off exponentially while it's still away, so restarting it loses nothing.
+///
+/// Each one is a file of its own in the directory, holding the request as it would have been
+/// forwarded, so the queue survives restarts of spam-musubi too. Deliveries past the size
+/// limit aren't queued, and ones queued for longer than the max age are dropped, since their
+/// signatures go stale.
+#[derive(Clone)]
+pub struct Retry {
+	inner: Arc<Inner>,
+}
+
+struct Inner {
+	dir: PathBuf,
+	max_bytes: u64,
+	max_age: Duration,
+	queue: Mutex<Queue>,
+	queued: Notify,
+}
+
+#[derive(Default)]
+struct Queue {
+	entries: VecDeque<Entry>,
+	bytes: u64,
+	next: u64,
+}
+
+struct Entry {
+	path: PathBuf,
+	len: u64,
+	queued: SystemTime,
+}
+
+impl Retry {
+	/// Take up the deliveries left in `dir` and start sending them.
+	pub async fn init(
+		dir: &Path, max_bytes: u64, max_age: Duration, routes: Routes,
+	) -> io::Result<Self> {
+		fs::create_dir_all(dir).await?;
+		let mut found = Vec::new();
+		let mut files = fs::read_dir(dir).await?;
+		while let Some(file) = files.next_entry().await? {
+			let path = file.path();
+			let seq = match path.extension() {
+				Some(extension) if extension == EXTENSION => {
+					path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok())
+				}
+				_ => None,
+			};
+			let Some(seq) = seq else {
+				continue;
+			};
+			let metadata = file.metadata().await?;
+			let queued = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+			found.push((seq, Entry { path, len: metadata.len(), queued }));
+		}
+		found.sort_unstable_by_key(|(seq, _)| *seq);
+
+		let next = found.last().map_or(0, |(seq, _)| seq + 1);
+		let mut queue = Queue { next, ..Queue::default() };
+		for (_, entry) in found {
+			queue.bytes += entry.len;
+			queue.entries.push_back(entry);
+		}
+		if !queue.entries.is_empty() {
+			info!("Sending {} deliveries left in {} again", queue.entries.len(), dir.display());
+		}
+
+		let retry = Retry {
+			inner: Arc::new(Inner {
+				dir: dir.to_path_buf(),
+				max_bytes,
+				max_age,
+				queue: Mutex::new(queue),
+				queued: Notify::new(),
+			}),
+		};
+		tokio::spawn(retry.clone().run(routes));
+		Ok(retry)
+	}
+
+	/// Keep a delivery to send again later. Returns whether it was, which it isn't once the
+	/// queue is full or can't be written to.
+	pub async fn queue(&self, header: &[u8], body: &[u8]) -> bool {
+		let len = (header.len() + body.len()) as u64;
+		let seq = {
+			#[allow(clippy::unwrap_used)]
+			let mut queue = self.inner.queue.lock().unwrap();
+			if queue.bytes + len > self.inner.max_bytes {
+				return false;
+			}
+			// held for the file while it's written
+			queue.bytes += len;
+			queue.next += 1;
+			queue.next - 1
+		};
+		let path = self.inner.dir.join(format!("{:020}.{}", seq, EXTENSION));
+		// written whole or not at all, so a crash doesn't leave half a request to send
+		let temporary = path.with_extension("tmp");
+		let written = async {
+			fs::write(&temporary, [header, body].concat()).await?;
+			fs::rename(&temporary, &path).await
+		};
+		if let Err(e) = written.await {
+			warn!("Could not queue delivery in {}: {}", self.inner.dir.display(), e);
+			fs::remove_file(&temporary).await.ok();
+			#[allow(clippy::unwrap_used)]
+			let mut queue = self.inner.queue.lock().unwrap();
+			queue.bytes -= len;
+			return false;
+		}
+		{
+			#[allow(clippy::unwrap_used)]
+			let mut queue = self.inner.queue.lock().unwrap();
+			queue.entries.push_back(Entry { path, len, queued: SystemTime::now() });
+		}
+		self.inner.queued.notify_one();
+		true
+	}
+
+	/// Send queued deliveries in order, waiting longer and longer while the AP server is away.
+	async fn run(self, routes: Routes) {
+		let first = Duration::from_millis(FIRST_BACKOFF_MS);
+		let mut backoff = first;
+		loop {
+			let next = {
+				#[allow(clippy::unwrap_used)]
+				let queue = self.inner.queue.lock().unwrap();
+				queue.entries.front().map(|entry| (entry.path.clone(), entry.queued))
+			};
+			let Some((path, queued)) = next else {
+				self.inner.queued.notified().await;
+				continue;
+			};
+			if queued.elapsed().unwrap_or_default() > self.inner.max_age {
+				warn!("Dropping delivery {} queued for too long", path.display());
+				self.done(&path).await;
+				continue;
+			}
+			let request = match fs::read(&path).await {
+				Ok(request) => request,
+				Err(e) => {
+					warn!("Could not read queued delivery {}: {}", path.display(), e);
+					self.done(&path).await;
+					continue;
+				}
+			};
+			match send(&routes, &request).await {
+				Ok(status) => {
+					info!("Delivered queued {}: {}", path.display(), status);
+					self.done(&path).await;
+					backoff = first;
+				}
+				Err(e) => {
+					debug!(
+						"Could not deliver queued {}, next try in {:?}: {}",
+						path.display(),
+						backoff,
+						e
+					);
+					sleep(backoff).await;
+					backoff = (backoff * 2).min(Duration::from_secs(MAX_BACKOFF_SECS));
+				}
+			}
+		}
+	}
+
+	/// Forget the delivery at the front of the queue, at `path`.
+	async fn done(&self, path: &Path) {
+		if let Err(e) = fs::remove_file(path).await {
+			if e.kind() != io::ErrorKind::NotFound {
+				warn!("Could not remove queued delivery {}: {}", path.display(), e);
+			}
+		}
+		#[allow(clippy::unwrap_used)]
+		let mut queue = self.inner.queue.lock().unwrap();
+		if let Some(entry) = queue.entries.pop_front() {
+			queue.bytes -= entry.len;
+		}
+	}
+}
+
+/// Forward a request to its AP server, and return the status line it answered with. The AP
+/// server failing on it counts as not having answered.
+async fn send(routes: &Routes, request: &[u8]) -> io::Result<String> {
+	let host = Headers::parse(request).ok().and_then(|h| h.get("host").ok().flatten());
+	let picked = routes.pick(routes.route(host));
+	let forward = async {
+		let mut stream = picked.address.open().await?;
+		stream.write_all(request).await?;
+		let mut status = String::new();
+		BufReader::new(stream).take(MAX_STATUS_LEN).read_line(&mut status).await?;
+		io::Result::Ok(status.trim_end().to_string())
+	};
+	let status = timeout(Duration::from_secs(ANSWER_TIMEOUT_SECS), forward)
+		.await
+		.map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no answer in time"))??;
+	match status.split(' ').nth(1) {
+		Some(code) if code.starts_with('5') => Err(io::Error::other(status)),
+		Some(_) => Ok(status),
+		None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "no status line")),
+	}
+}
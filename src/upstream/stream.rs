@@ -0,0 +1,33 @@
+use std::{io, sync::Arc};
+
+use once_cell::sync::OnceCell;
+use rustls::{pki_types::ServerName, ClientConfig};
+use tokio_rustls::TlsConnector;
+
+use super::Address;
+use crate::tls::{self, Stream};
+
+/// TLS to connect to AP servers over, if they're reached that way.
+static TLS: OnceCell<TlsConnector> = OnceCell::new();
+
+/// Connect to AP servers over TLS with `config` from now on, e.g. to present a client
+/// certificate to them.
+pub fn use_tls(config: Arc<ClientConfig>) {
+	TLS.set(TlsConnector::from(config)).ok();
+}
+
+impl Address {
+	/// Connect as [`Address::connect`] does, over TLS if [`use_tls`] was called. The server's
+	/// certificate must be for the hostname or IP address it was reached by.
+	pub async fn open(&self) -> io::Result<Stream> {
+		let stream = self.connect().await?;
+		let Some(tls) = TLS.get() else {
+			return Ok(Stream::Plain(stream));
+		};
+		let name = match self {
+			Address::Ip(address) => ServerName::from(address.ip()),
+			Address::Name(host, _) => tls::server_name(host)?,
+		};
+		Ok(Stream::Tls(Box::new(tls.connect(name, stream).await?)))
+	}
+}
@@ -0,0 +1,103 @@
+//! Experimental io_uring data plane for admitted connections.
+//!
+//! Filtering stays on the tokio runtime. Once a delivery is admitted and its buffered header and
+//! body are forwarded, both sockets are handed to a thread running its own io_uring runtime,
+//! which relays the rest without a read and write syscall per chunk.
+
+use std::{
+	io,
+	net::{Shutdown, TcpStream as StdTcpStream},
+	rc::Rc,
+	sync::atomic::{AtomicUsize, Ordering},
+	thread,
+};
+
+use once_cell::sync::OnceCell;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_uring::{buf::IoBuf, net::TcpStream};
+use tracing::*;
+
+/// Bytes read at once per direction of a connection.
+const BUF_LEN: usize = 16 * 1024;
+
+/// Set once by [`Uring::start`].
+static URING: OnceCell<Uring> = OnceCell::new();
+
+/// A client connection and the AP server connection it is relayed to.
+type Pair = (StdTcpStream, StdTcpStream);
+
+/// Threads relaying connections, taking turns.
+#[derive(Debug)]
+pub struct Uring {
+	threads: Vec<UnboundedSender<Pair>>,
+	next: AtomicUsize,
+}
+
+impl Uring {
+	/// Start `threads` relay threads, failing if io_uring isn't available, as on kernels before
+	/// 5.11 or with it disabled by sysctl. Call it outside of the tokio runtime.
+	pub fn start(threads: usize) -> io::Result<()> {
+		// a throwaway ring, so an unsupported kernel fails here and not in every thread
+		drop(tokio_uring::Runtime::new(&tokio_uring::builder())?);
+
+		let mut senders = Vec::with_capacity(threads);
+		for i in 0..threads {
+			let (sender, receiver) = mpsc::unbounded_channel();
+			thread::Builder::new()
+				.name(format!("uring-{}", i))
+				.spawn(move || tokio_uring::start(relay_all(receiver)))?;
+			senders.push(sender);
+		}
+		info!("Relaying admitted connections through io_uring on {} threads", threads);
+		URING.set(Uring { threads: senders, next: AtomicUsize::new(0) }).ok();
+		Ok(())
+	}
+
+	/// The relay threads, if started.
+	pub fn get() -> Option<&'static Uring> {
+		URING.get()
+	}
+
+	/// Relay between `client` and `server` until both sides are done.
+	pub fn relay(&self, client: StdTcpStream, server: StdTcpStream) -> io::Result<()> {
+		// io_uring waits for the sockets itself
+		client.set_nonblocking(false)?;
+		server.set_nonblocking(false)?;
+		let i = self.next.fetch_add(1, Ordering::Relaxed) % self.threads.len();
+		self.threads[i]
+			.send((client, server))
+			.map_err(|_| io::Error::other(format!("io_uring thread {} is gone", i)))
+	}
+}
+
+async fn relay_all(mut receiver: UnboundedReceiver<Pair>) {
+	while let Some((client, server)) = receiver.recv().await {
+		tokio_uring::spawn(relay(client, server));
+	}
+}
+
+async fn relay(client: StdTcpStream, server: StdTcpStream) {
+	let client = Rc::new(TcpStream::from_std(client));
+	let server = Rc::new(TcpStream::from_std(server));
+	let upload = tokio_uring::spawn(pipe(client.clone(), server.clone()));
+	pipe(server, client).await;
+	upload.await.ok();
+}
+
+/// Copy from `from` to `to` until `from` is closed, then close `to` for writing.
+async fn pipe(from: Rc<TcpStream>, to: Rc<TcpStream>) {
+	let mut buf = vec![0; BUF_LEN];
+	loop {
+		let (read, returned) = from.read(buf).await;
+		let n = match read {
+			Ok(0) | Err(_) => break,
+			Ok(n) => n,
+		};
+		let (written, returned) = to.write_all(returned.slice(..n)).await;
+		buf = returned.into_inner();
+		if written.is_err() {
+			break;
+		}
+	}
+	to.shutdown(Shutdown::Write).ok();
+}
@@ -0,0 +1,49 @@
+//! Fixtures shared by the integration tests.
+
+use std::{fs, path::PathBuf};
+
+use rcgen::{
+	BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+};
+
+/// A CA, and PEM files of it and of certificates it signed, in a directory of their own.
+pub struct Pki {
+	dir: PathBuf,
+	ca: Certificate,
+	ca_key: KeyPair,
+}
+
+impl Pki {
+	pub fn new(name: &str) -> Self {
+		let dir = std::env::temp_dir().join(format!("musubi-{}-{}", std::process::id(), name));
+		fs::create_dir_all(&dir).unwrap();
+		let ca_key = KeyPair::generate().unwrap();
+		let mut params = CertificateParams::new(Vec::new()).unwrap();
+		params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+		let ca = params.self_signed(&ca_key).unwrap();
+		fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+		Pki { dir, ca, ca_key }
+	}
+
+	pub fn path(&self, file: &str) -> PathBuf {
+		self.dir.join(file)
+	}
+
+	/// Sign a certificate for `names` to use for `usage`, and write it and its key to
+	/// `<name>.pem` and `<name>.key`.
+	pub fn issue(&self, name: &str, names: &[&str], usage: ExtendedKeyUsagePurpose) {
+		let key = KeyPair::generate().unwrap();
+		let names = names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+		let mut params = CertificateParams::new(names).unwrap();
+		params.extended_key_usages = vec![usage];
+		let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+		fs::write(self.path(&format!("{}.pem", name)), cert.pem()).unwrap();
+		fs::write(self.path(&format!("{}.key", name)), key.serialize_pem()).unwrap();
+	}
+}
+
+impl Drop for Pki {
+	fn drop(&mut self) {
+		fs::remove_dir_all(&self.dir).ok();
+	}
+}
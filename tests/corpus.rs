@@ -0,0 +1,252 @@
+//! Replays the recorded deliveries in `tests/corpus` through the filter over real TCP
+//! connections, checking that every `ham` request is let through and every `spam` request is
+//! rejected for the reason listed in `SPAM`.
+//!
+//! Corpus files are HTTP requests without `Content-Length`, which is filled in when they are
+//! sent, so bodies can be edited freely. Line endings are converted to CRLF.
+
+use std::{
+	fs,
+	net::{Ipv4Addr, SocketAddrV4},
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::Duration,
+};
+
+use spam_musubi::{
+	attachments::AttachmentList,
+	cache::CacheConfig,
+	filter::{
+		rules::{Action, RuleConfig, RuleSet},
+		Admit, Enforcement, Filter, FilterBuilder, RejectReason,
+	},
+	query::{InstanceStats, MemoryBackend, User},
+	reputation::{Reputation, Subject},
+	upstream::{Routes, Upstream},
+};
+use tokio::{
+	io::AsyncWriteExt,
+	net::{TcpListener, TcpStream},
+};
+
+const LOCAL_HOST: &str = "local.example";
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+/// What the AP server knows about the actors and instances in the corpus.
+fn backend() -> MemoryBackend {
+	MemoryBackend::new()
+		.instance("big.example", InstanceStats { followers: 500, following: 500, notes: 10000 })
+		.instance("tiny.example", InstanceStats { followers: 1, following: 1, notes: 10 })
+		.user("https://big.example/users/alice", user(50, 40, 300, 400 * DAY))
+		.user("https://big.example/users/fresh", user(0, 0, 1, DAY))
+		.user("https://big.example/users/prolific", user(0, 0, 3000, 2 * HOUR))
+		.user("https://tiny.example/users/bot", user(0, 0, 0, DAY))
+		.local_user("me", user(20, 20, 100, 300 * DAY))
+}
+
+fn user(followers: i32, following: i32, notes: i32, age_secs: u64) -> User {
+	User { followers, following, notes, age: Duration::from_secs(age_secs) }
+}
+
+fn load(path: &Path) -> Vec<u8> {
+	let src = fs::read_to_string(path).unwrap();
+	let (head, body) = src.split_once("\n\n").unwrap_or((&src, ""));
+	let body = body.trim_end_matches('\n');
+
+	let mut request = head.lines().collect::<Vec<_>>().join("\r\n");
+	if !body.is_empty() {
+		request.push_str(&format!("\r\nContent-Length: {}", body.len()));
+	}
+	request.push_str("\r\n\r\n");
+	request.push_str(body);
+	request.into_bytes()
+}
+
+/// Filter set up the way corpus requests are judged.
+async fn filter() -> FilterBuilder {
+	let attachments = AttachmentList::init(None).await.unwrap();
+	attachments.add(&["https://files.example/campaign/promo.png".to_string()]).await.unwrap();
+	Filter::builder()
+		.relays(vec!["https://relay.example/actor".to_string()])
+		.attachment_blocklist(attachments)
+		.check_recipients(true)
+		// recorded deliveries only get older
+		.published_skew(Duration::ZERO, Duration::from_secs(HOUR))
+}
+
+/// Why each spam request is rejected, and the signal it's caught by when the filter only
+/// annotates, unless it's rejected before scoring.
+const SPAM: &[(&str, &str, Option<&str>)] = &[
+	("blocklisted-attachment.http", "spam", Some("attachment")),
+	("deeply-nested-json.http", "nested too deep", None),
+	("forged-origin.http", "activity id host doesn't match actor", Some("origin")),
+	("hashtag-stuffing.http", "spam", Some("hashtags")),
+	("malformed-json.http", "malformed JSON", None),
+	("mass-mention.http", "spam", Some("audience")),
+	("mention-from-tiny-instance.http", "spam", Some(SKETCHY)),
+	("mention-from-unknown-instance.http", "spam", Some(SKETCHY)),
+	("obsolete-line-folding.http", "obsolete line folding", None),
+	("personal-inbox-of-nobody.http", "no recipient", None),
+	("poll-mention-from-unknown-instance.http", "spam", Some(SKETCHY)),
+	("prolific-new-account.http", "spam", Some("notes-rate")),
+	("published-in-the-future.http", "spam", Some("published")),
+	("relayed-mention-from-tiny-instance.http", "spam", Some(SKETCHY)),
+	("signed-by-another-host.http", "keyId host doesn't match actor", Some("key-id")),
+	(
+		"signed-by-another-host-with-ld-signature.http",
+		"keyId host doesn't match actor",
+		Some("key-id"),
+	),
+	("transfer-encoding.http", "transfer-encoding not supported", None),
+	("wrong-content-type.http", "content-type not accepted", None),
+];
+/// Signal of the default rule.
+const SKETCHY: &str = "rule:nobody from a sketchy instance";
+
+/// Send a request through `filter`.
+async fn judge(filter: Filter, request: &[u8]) -> Result<Admit, RejectReason> {
+	spam_musubi::HOST.set(LOCAL_HOST.to_string()).ok();
+
+	let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+	let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+	client.write_all(request).await.unwrap();
+	let (stream, _) = listener.accept().await.unwrap();
+
+	let upstream = Upstream {
+		backends: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1).into(),
+		query: Arc::new(backend()),
+		host: None,
+		tenant: Arc::default(),
+	};
+	let routes = Routes::init(upstream, &[], &CacheConfig::default()).await.unwrap();
+	filter.handler(stream, &routes).await.map_err(|rejected| rejected.reason)
+}
+
+/// Send a request through a fresh filter, and return why it was rejected, if it was.
+async fn verdict(request: &[u8]) -> Option<RejectReason> {
+	judge(filter().await.build(), request).await.err()
+}
+
+/// Signals a request scores with a fresh filter that only annotates, or why it's rejected
+/// anyway.
+async fn signals(request: &[u8]) -> Result<Vec<String>, &'static str> {
+	let filter = filter().await.enforcement(Enforcement::Annotate).build();
+	let admit = judge(filter, request).await.map_err(|reason| reason.kind())?;
+	let header = String::from_utf8_lossy(&admit.pending_header);
+	let signals = header.lines().find_map(|line| line.strip_prefix("X-Musubi-Signals: "));
+	Ok(signals.unwrap_or_default().split(", ").map(str::to_string).collect())
+}
+
+fn corpus(path: &str) -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus").join(path)
+}
+
+/// Requests in a corpus directory, with their file names.
+fn requests(dir: &str) -> Vec<(String, Vec<u8>)> {
+	let mut paths: Vec<_> =
+		fs::read_dir(corpus(dir)).unwrap().map(|entry| entry.unwrap().path()).collect();
+	paths.sort();
+	assert!(!paths.is_empty(), "no requests in {}", dir);
+	paths
+		.iter()
+		.map(|path| (path.file_name().unwrap().to_string_lossy().to_string(), load(path)))
+		.collect()
+}
+
+#[tokio::test]
+async fn ham_is_accepted() {
+	let mut wrong = Vec::new();
+	for (name, request) in requests("ham") {
+		if let Some(reason) = verdict(&request).await {
+			wrong.push(format!("{}: rejected ({})", name, reason));
+		}
+	}
+	assert!(wrong.is_empty(), "{}", wrong.join("\n"));
+}
+
+#[tokio::test]
+async fn spam_is_rejected() {
+	let mut wrong = Vec::new();
+	for (name, request) in requests("spam") {
+		let Some(&(_, kind, signal)) = SPAM.iter().find(|(file, ..)| *file == name) else {
+			wrong.push(format!("{}: not listed in SPAM", name));
+			continue;
+		};
+		match verdict(&request).await {
+			None => wrong.push(format!("{}: accepted", name)),
+			Some(reason) if reason.kind() != kind => {
+				wrong.push(format!("{}: rejected as {}, not {}", name, reason.kind(), kind))
+			}
+			Some(_) => {}
+		}
+		let Some(signal) = signal else {
+			continue;
+		};
+		match signals(&request).await {
+			Ok(signals) if signals.iter().any(|s| s == signal) => {}
+			Ok(signals) => wrong.push(format!("{}: signals {:?}, not {}", name, signals, signal)),
+			Err(kind) => wrong.push(format!("{}: rejected as {} while annotating", name, kind)),
+		}
+	}
+	assert!(wrong.is_empty(), "{}", wrong.join("\n"));
+}
+
+#[tokio::test]
+async fn reputation_does_not_outweigh_strong_signals() {
+	// big.example delivers plenty of accepted notes
+	let reputation = Reputation::new(Duration::from_secs(DAY));
+	for _ in 0..200 {
+		reputation.accepted("https://big.example/users/alice", "big.example");
+	}
+	let filter = filter().await.reputation(reputation).build();
+
+	// a brand-new account there stuffing hashtags still scores a strong signal
+	let request = load(&corpus("spam/hashtag-stuffing.http"));
+	let reason = judge(filter, &request).await.err().map(|reason| reason.kind());
+	assert_eq!(reason, Some("spam"));
+}
+
+/// A filter that doesn't drop retried deliveries as repeats, but judges them again.
+async fn judging_retries() -> FilterBuilder {
+	filter().await.duplicate_ttl(Duration::ZERO)
+}
+
+#[tokio::test]
+async fn remembered_notes_still_count_against_throttles() {
+	let throttle = RuleConfig {
+		name: Some("a note a day".to_string()),
+		when: "activity.type == 'Create'".to_string(),
+		action: Action::Throttle,
+		limit: Some(1),
+		window: Some(DAY),
+	};
+	let filter = judging_retries().await.rules(RuleSet::compile(&[throttle]).unwrap()).build();
+	let request = load(&corpus("ham/note-from-known-actor.http"));
+	assert!(judge(filter.clone(), &request).await.is_ok());
+	let reason = judge(filter, &request).await.err().map(|reason| reason.kind());
+	assert_eq!(reason, Some("throttled"));
+}
+
+#[tokio::test]
+async fn remembered_notes_still_add_to_reputation() {
+	let filter = judging_retries().await.build();
+	let reputation = || filter.reputation().get(Subject::Actor, "https://big.example/users/alice");
+	let request = load(&corpus("ham/note-from-known-actor.http"));
+	assert!(judge(filter.clone(), &request).await.is_ok());
+	let once = reputation();
+	assert!(judge(filter.clone(), &request).await.is_ok());
+	assert!(reputation() > once, "{} after one note, {} after two", once, reputation());
+}
+
+#[tokio::test]
+async fn only_deliveries_the_ap_server_took_are_repeats() {
+	let filter = filter().await.build();
+	let request = load(&corpus("ham/note-from-known-actor.http"));
+	let mut admit = judge(filter.clone(), &request).await.unwrap();
+	admit.answered(b"HTTP/1.1 503");
+	let mut admit = judge(filter.clone(), &request).await.unwrap();
+	admit.answered(b"HTTP/1.1 202");
+	let reason = judge(filter, &request).await.err().map(|reason| reason.kind());
+	assert_eq!(reason, Some("duplicate"));
+}
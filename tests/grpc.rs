@@ -0,0 +1,161 @@
+//! Calls the gRPC admin API over TLS, as fleet management tools would, checking that the token
+//! is required and that calls change the running filter.
+
+mod common;
+
+use std::{
+	net::{Ipv4Addr, SocketAddrV4},
+	sync::Arc,
+	time::Instant,
+};
+
+use common::Pki;
+use rcgen::ExtendedKeyUsagePurpose;
+use rustls::{pki_types::ServerName, ClientConfig};
+use spam_musubi::{
+	admin::{
+		grpc::v1::{self, admin_client::AdminClient},
+		Admin,
+	},
+	attachments::AttachmentList,
+	cache::CacheConfig,
+	domains::{DomainList, Kind},
+	filter::Filter,
+	query::MemoryBackend,
+	tls,
+	upstream::{Routes, Upstream},
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tonic::{
+	transport::{Channel, Endpoint, Uri},
+	Code, Request,
+};
+
+const TOKEN: &str = "correct horse battery staple";
+
+/// Serve the API of a fresh filter, and connect to it.
+async fn connect(pki: &Pki) -> AdminClient<Channel> {
+	let upstream = Upstream {
+		backends: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1).into(),
+		query: Arc::new(MemoryBackend::new()),
+		host: None,
+		tenant: Arc::default(),
+	};
+	let admin = Admin {
+		blocklist: DomainList::init(Kind::Block, None).await.unwrap(),
+		allowlist: DomainList::init(Kind::Allow, None).await.unwrap(),
+		tarpit: DomainList::init(Kind::Tarpit, None).await.unwrap(),
+		attachments: AttachmentList::init(None).await.unwrap(),
+		filter: Filter::builder().build(),
+		query: upstream.query.clone(),
+		routes: Routes::init(upstream, &[], &CacheConfig::default()).await.unwrap(),
+		config: None,
+		started: Instant::now(),
+	};
+	pki.issue("server", &["localhost"], ExtendedKeyUsagePurpose::ServerAuth);
+	let (pem, key) = (pki.path("server.pem"), pki.path("server.key"));
+	let acceptor = TlsAcceptor::from(tls::grpc_config(&pem, &key).unwrap());
+	let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+	listener.set_nonblocking(true).unwrap();
+	let address = listener.local_addr().unwrap();
+	admin.serve_grpc(listener, acceptor, TOKEN.to_string()).unwrap();
+
+	let ca = pki.path("ca.pem");
+	let mut config = ClientConfig::clone(&tls::upstream_config(Some(&ca), None).unwrap());
+	config.alpn_protocols = vec![b"h2".to_vec()];
+	let connector = TlsConnector::from(Arc::new(config));
+	let channel = Endpoint::from_static("http://localhost")
+		.connect_with_connector(tower::service_fn(move |_: Uri| {
+			let connector = connector.clone();
+			async move {
+				let stream = TcpStream::connect(address).await?;
+				connector.connect(ServerName::try_from("localhost").unwrap(), stream).await
+			}
+		}))
+		.await
+		.unwrap();
+	AdminClient::new(channel)
+}
+
+/// `message` with the token.
+fn authorized<T>(message: T) -> Request<T> {
+	let mut request = Request::new(message);
+	let token = format!("Bearer {}", TOKEN).parse().unwrap();
+	request.metadata_mut().insert("authorization", token);
+	request
+}
+
+#[tokio::test]
+async fn refuses_calls_without_the_token() {
+	let pki = Pki::new("grpc-token");
+	let mut client = connect(&pki).await;
+
+	let status = client.status(v1::StatusRequest {}).await.unwrap_err();
+	assert_eq!(status.code(), Code::Unauthenticated);
+
+	let mut request = Request::new(v1::StatusRequest {});
+	request.metadata_mut().insert("authorization", "Bearer guess".parse().unwrap());
+	let status = client.status(request).await.unwrap_err();
+	assert_eq!(status.code(), Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn changes_lists_rules_and_thresholds() {
+	let pki = Pki::new("grpc-changes");
+	let mut client = connect(&pki).await;
+
+	let domains = vec!["spam.example".to_string(), "scam.example".to_string()];
+	let blocked = v1::ChangeEntriesRequest { list: v1::List::Blocklist as i32, entries: domains };
+	let changed = client.add_entries(authorized(blocked)).await.unwrap().into_inner();
+	assert_eq!(changed.changed, 2);
+	let list = v1::ListEntriesRequest { list: v1::List::Blocklist as i32 };
+	let mut entries = client.list_entries(authorized(list)).await.unwrap().into_inner().entries;
+	entries.sort();
+	assert_eq!(entries, ["scam.example", "spam.example"]);
+
+	let rule = v1::Rule {
+		name: Some("mention spam".to_string()),
+		when: "content.mentions >= 3".to_string(),
+		action: v1::Action::Quarantine as i32,
+		limit: None,
+		window_secs: None,
+	};
+	let added = v1::AddRuleRequest { rule: Some(rule.clone()), position: Some(0) };
+	let rules = client.add_rule(authorized(added)).await.unwrap().into_inner().rules;
+	assert_eq!(rules.first(), Some(&rule));
+
+	let patch = v1::Thresholds { max_hashtags: Some(3), ..Default::default() };
+	let thresholds = client.set_thresholds(authorized(patch)).await.unwrap().into_inner();
+	assert_eq!(thresholds.max_hashtags, Some(3));
+
+	let overview = client.status(authorized(v1::StatusRequest {})).await.unwrap().into_inner();
+	assert_eq!(overview.blocklist, 2);
+	assert_eq!(overview.rules, rules.len() as u64);
+}
+
+#[tokio::test]
+async fn refuses_bad_calls() {
+	let pki = Pki::new("grpc-bad");
+	let mut client = connect(&pki).await;
+
+	let missing = v1::RemoveRuleRequest { name: "no such rule".to_string() };
+	let status = client.remove_rule(authorized(missing)).await.unwrap_err();
+	assert_eq!(status.code(), Code::NotFound);
+
+	let rule = v1::Rule {
+		when: "content.mentions >=".to_string(),
+		action: v1::Action::Reject as i32,
+		..Default::default()
+	};
+	let broken = v1::AddRuleRequest { rule: Some(rule), position: None };
+	let status = client.add_rule(authorized(broken)).await.unwrap_err();
+	assert_eq!(status.code(), Code::InvalidArgument);
+
+	let list = v1::ListEntriesRequest { list: v1::List::Unspecified as i32 };
+	let status = client.list_entries(authorized(list)).await.unwrap_err();
+	assert_eq!(status.code(), Code::InvalidArgument);
+
+	let status = client.release(authorized(v1::ReleaseRequest { id: 42 })).await.unwrap_err();
+	assert_eq!(status.code(), Code::NotFound);
+}
@@ -0,0 +1,139 @@
+//! Sends requests other than deliveries through the filter over real TCP connections, checking
+//! that they are handed on right away with the headers the AP server needs, WebSocket upgrades
+//! in particular.
+//!
+//! The filter is told to trust no proxy unless a test says otherwise. Requests from a trusted
+//! proxy must be cleaned up the same way.
+
+use std::{
+	net::{Ipv4Addr, SocketAddrV4},
+	sync::Arc,
+	time::Duration,
+};
+
+use spam_musubi::{
+	cache::CacheConfig,
+	filter::{forwarded::Cidr, Admit, Direction, Filter},
+	query::MemoryBackend,
+	upstream::{Routes, Upstream},
+};
+use tokio::{
+	io::AsyncWriteExt,
+	net::{TcpListener, TcpStream},
+	time::timeout,
+};
+
+/// Send a request through a fresh filter, leaving the connection open like a client waiting
+/// for an answer, and return what's to be forwarded.
+async fn admit(request: &str) -> Admit {
+	admit_through(request, Filter::builder().trusted_proxies(Vec::new()).build()).await
+}
+
+async fn admit_through(request: &str, filter: Filter) -> Admit {
+	let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+	let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+	client.write_all(request.as_bytes()).await.unwrap();
+	let (stream, _) = listener.accept().await.unwrap();
+
+	let upstream = Upstream {
+		backends: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1).into(),
+		query: Arc::new(MemoryBackend::new()),
+		host: None,
+		tenant: Arc::default(),
+	};
+	let routes = Routes::init(upstream, &[], &CacheConfig::default()).await.unwrap();
+	let handled = timeout(Duration::from_secs(2), filter.handler(stream, &routes)).await;
+	let admit = match handled.expect("request was held up") {
+		Ok(admit) => admit,
+		Err(rejected) => panic!("request was rejected: {}", rejected.reason),
+	};
+	drop(client);
+	admit
+}
+
+fn header_lines(admit: &Admit) -> Vec<String> {
+	let header = String::from_utf8(admit.pending_header.clone()).unwrap();
+	header.split("\r\n").map(|line| line.to_ascii_lowercase()).collect()
+}
+
+#[tokio::test]
+async fn websocket_upgrade_is_passed_through() {
+	let admit = admit(
+		"GET /streaming?i=token HTTP/1.1\r\nHost: local.example\r\nConnection: Upgrade\r\n\
+		Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\n\
+		Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+	)
+	.await;
+
+	let lines = header_lines(&admit);
+	assert!(lines.contains(&"connection: upgrade".to_string()), "{:?}", lines);
+	assert!(lines.contains(&"upgrade: websocket".to_string()), "{:?}", lines);
+	assert!(lines.iter().any(|l| l.starts_with("sec-websocket-key:")), "{:?}", lines);
+	assert!(!lines.contains(&"connection: close".to_string()), "{:?}", lines);
+	assert!(admit.pending_body.is_empty());
+	assert!(!admit.is_delivery());
+}
+
+#[tokio::test]
+async fn upgrade_to_something_else_is_not() {
+	let admit = admit(
+		"GET /api/meta HTTP/1.1\r\nHost: local.example\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+		Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n",
+	)
+	.await;
+
+	let lines = header_lines(&admit);
+	assert!(lines.contains(&"connection: close".to_string()), "{:?}", lines);
+	assert!(!lines.iter().any(|l| l.starts_with("upgrade:")), "{:?}", lines);
+	assert!(!lines.iter().any(|l| l.starts_with("http2-settings:")), "{:?}", lines);
+}
+
+#[tokio::test]
+async fn keep_alive_is_not_passed_on() {
+	let admit = admit(
+		"GET /nodeinfo/2.0 HTTP/1.1\r\nHost: local.example\r\nConnection: keep-alive\r\n\
+		Keep-Alive: timeout=5\r\nTE: trailers\r\nAccept: application/json\r\n\r\n",
+	)
+	.await;
+
+	let lines = header_lines(&admit);
+	assert!(lines.contains(&"connection: close".to_string()), "{:?}", lines);
+	assert_eq!(lines.iter().filter(|l| l.starts_with("connection:")).count(), 1, "{:?}", lines);
+	assert!(!lines.iter().any(|l| l.starts_with("keep-alive:")), "{:?}", lines);
+	assert!(!lines.iter().any(|l| l.starts_with("te:")), "{:?}", lines);
+	assert!(lines.contains(&"accept: application/json".to_string()), "{:?}", lines);
+}
+
+#[tokio::test]
+async fn requests_through_a_trusted_proxy_are_cleaned_up_too() {
+	let trusted: Cidr = "127.0.0.1/32".parse().unwrap();
+	let admit = admit_through(
+		"GET /nodeinfo/2.0 HTTP/1.1\r\nHost: local.example\r\nConnection: keep-alive\r\n\
+		Keep-Alive: timeout=5\r\nX-Request-Id: from-nginx\r\nX-Musubi-Score: 0\r\n\r\n",
+		Filter::builder().trusted_proxies(vec![trusted]).build(),
+	)
+	.await;
+
+	let lines = header_lines(&admit);
+	assert!(lines.contains(&"connection: close".to_string()), "{:?}", lines);
+	assert!(!lines.iter().any(|l| l.starts_with("keep-alive:")), "{:?}", lines);
+	assert!(lines.contains(&"x-request-id: from-nginx".to_string()), "{:?}", lines);
+	assert_eq!(lines.iter().filter(|l| l.starts_with("x-request-id:")).count(), 1, "{:?}", lines);
+	assert!(!lines.iter().any(|l| l.starts_with("x-musubi-")), "{:?}", lines);
+}
+
+#[tokio::test]
+async fn outbound_tunnels_are_let_through() {
+	let admit = admit_through(
+		"CONNECT remote.example:443 HTTP/1.1\r\nHost: remote.example:443\r\n\
+		Proxy-Connection: keep-alive\r\n\r\n",
+		Filter::builder().trusted_proxies(Vec::new()).direction(Direction::Outbound).build(),
+	)
+	.await;
+
+	let lines = header_lines(&admit);
+	assert_eq!(lines[0], "connect remote.example:443 http/1.1");
+	assert!(!lines.contains(&"connection: close".to_string()), "{:?}", lines);
+	assert!(!lines.iter().any(|l| l.starts_with("proxy-connection:")), "{:?}", lines);
+	assert!(!admit.is_delivery());
+}
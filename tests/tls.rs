@@ -0,0 +1,120 @@
+//! Connects to a listener set up with the TLS settings for the port, as an AP server would be
+//! connected to, checking that client certificates are required and verified both ways.
+
+mod common;
+
+use std::{net::Ipv4Addr, path::Path};
+
+use common::Pki;
+use rcgen::ExtendedKeyUsagePurpose;
+use rustls::pki_types::ServerName;
+use spam_musubi::{
+	tls::{self, Stream},
+	upstream::{self, Address},
+};
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// A listener with the port's TLS settings, requiring client certificates signed by `ca`.
+async fn listen(pki: &Pki, ca: &Path) -> (TcpListener, TlsAcceptor) {
+	pki.issue("server", &["127.0.0.1"], ExtendedKeyUsagePurpose::ServerAuth);
+	let config = tls::server_config(&pki.path("server.pem"), &pki.path("server.key"), Some(ca));
+	let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+	(listener, TlsAcceptor::from(config.unwrap()))
+}
+
+/// Connect to `address` over TLS verified by the CA, presenting `identity` if given, and return
+/// whether the listener let the connection through.
+async fn handshake(
+	pki: &Pki, listener: &TcpListener, acceptor: &TlsAcceptor, identity: Option<&str>,
+) -> bool {
+	let identity = identity
+		.map(|name| (pki.path(&format!("{}.pem", name)), pki.path(&format!("{}.key", name))));
+	let identity = identity.as_ref().map(|(cert, key)| (cert.as_path(), key.as_path()));
+	let config = tls::upstream_config(Some(&pki.path("ca.pem")), identity).unwrap();
+	let address = listener.local_addr().unwrap();
+	let client = async {
+		let stream = TcpStream::connect(address).await?;
+		let name = ServerName::from(address.ip());
+		let mut stream = TlsConnector::from(config).connect(name, stream).await?;
+		stream.write_all(b"ping").await?;
+		stream.flush().await
+	};
+	let server = async {
+		let (stream, _) = listener.accept().await?;
+		let mut stream = acceptor.accept(stream).await?;
+		let mut ping = [0; 4];
+		stream.read_exact(&mut ping).await.map(|_| ping)
+	};
+	let (_, accepted) = tokio::join!(client, server);
+	accepted.is_ok_and(|ping| &ping == b"ping")
+}
+
+#[tokio::test]
+async fn requires_a_client_certificate() {
+	let pki = Pki::new("tls-required");
+	let (listener, acceptor) = listen(&pki, &pki.path("ca.pem")).await;
+	assert!(!handshake(&pki, &listener, &acceptor, None).await);
+}
+
+#[tokio::test]
+async fn accepts_client_certificates_signed_by_the_ca() {
+	let pki = Pki::new("tls-signed");
+	pki.issue("proxy", &["proxy.internal"], ExtendedKeyUsagePurpose::ClientAuth);
+	let (listener, acceptor) = listen(&pki, &pki.path("ca.pem")).await;
+	assert!(handshake(&pki, &listener, &acceptor, Some("proxy")).await);
+}
+
+#[tokio::test]
+async fn refuses_client_certificates_signed_by_another_ca() {
+	let pki = Pki::new("tls-unsigned");
+	let other = Pki::new("tls-other");
+	pki.issue("proxy", &["proxy.internal"], ExtendedKeyUsagePurpose::ClientAuth);
+	let (listener, acceptor) = listen(&pki, &other.path("ca.pem")).await;
+	assert!(!handshake(&pki, &listener, &acceptor, Some("proxy")).await);
+}
+
+#[tokio::test]
+async fn presents_a_client_certificate_to_ap_servers() {
+	let pki = Pki::new("tls-upstream");
+	pki.issue("musubi", &["musubi.internal"], ExtendedKeyUsagePurpose::ClientAuth);
+	let (listener, acceptor) = listen(&pki, &pki.path("ca.pem")).await;
+	let identity = (pki.path("musubi.pem"), pki.path("musubi.key"));
+	let config = tls::upstream_config(
+		Some(&pki.path("ca.pem")),
+		Some((identity.0.as_path(), identity.1.as_path())),
+	);
+	upstream::use_tls(config.unwrap());
+
+	let address = Address::Ip(listener.local_addr().unwrap());
+	let client = async {
+		let mut stream = address.open().await?;
+		assert!(matches!(stream, Stream::Tls(_)));
+		stream.write_all(b"ping").await?;
+		stream.flush().await
+	};
+	let server = async {
+		let (stream, _) = listener.accept().await.unwrap();
+		let mut stream = acceptor.accept(stream).await.unwrap();
+		assert!(stream.get_ref().1.peer_certificates().is_some_and(|certs| !certs.is_empty()));
+		let mut ping = [0; 4];
+		stream.read_exact(&mut ping).await.unwrap();
+		ping
+	};
+	let (sent, ping) = tokio::join!(client, server);
+	sent.unwrap();
+	assert_eq!(&ping, b"ping");
+}
+
+#[test]
+fn reports_unreadable_files() {
+	let pki = Pki::new("tls-missing");
+	let missing = pki.path("missing.pem");
+	let error = tls::server_config(&missing, &missing, None).unwrap_err();
+	assert!(error.contains("missing.pem"), "{}", error);
+	let error = tls::upstream_config(Some(&missing), None).unwrap_err();
+	assert!(error.contains("missing.pem"), "{}", error);
+}